@@ -147,6 +147,7 @@ module ddr3(
         .data(uart_tx_data),
         .enable(uart_tx_enable),
         .ready(uart_tx_ready),
+        .baud_divisor(16'd217), // 100MHz / 460800 baud
 
         .tx(uart_tx));
 
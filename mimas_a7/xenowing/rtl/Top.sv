@@ -23,7 +23,15 @@ module Top(
     output wire logic tx,
     input wire logic rx,
 
-    output wire logic [7:0] leds);
+    output wire logic [7:0] leds,
+
+    // Config flash; its SCLK is the dedicated CCLK pin, driven through STARTUPE2 below
+    output wire logic flash_cs_n,
+    output wire logic flash_mosi,
+    input wire logic flash_miso,
+    // Only single-bit SPI is used, so these are held inactive
+    output wire logic flash_wp_n,
+    output wire logic flash_hold_n);
 
     logic sys_clk_200;
     clk_mmcm clk_mmcm0(
@@ -105,6 +113,38 @@ module Top(
 
         .x_sync(rx_sync));
 
+    logic flash_miso_sync;
+    SyncChain #(.DEFAULT(1'b1)) flash_miso_sync_chain(
+        .reset_n(reset_n),
+        .clk(clk_100),
+
+        .x(flash_miso),
+
+        .x_sync(flash_miso_sync));
+
+    logic flash_sclk;
+    // Note that STARTUPE2 swallows the first 3 USRCCLKO edges after configuration; xw::flash::init burns them
+    STARTUPE2 #(
+        .PROG_USR("FALSE"),
+        .SIM_CCLK_FREQ(0.0))
+    startup(
+        .CFGCLK(),
+        .CFGMCLK(),
+        .EOS(),
+        .PREQ(),
+        .CLK(1'b0),
+        .GSR(1'b0),
+        .GTS(1'b0),
+        .KEYCLEARB(1'b1),
+        .PACK(1'b0),
+        .USRCCLKO(flash_sclk),
+        .USRCCLKTS(1'b0),
+        .USRDONEO(1'b1),
+        .USRDONETS(1'b1));
+
+    assign flash_wp_n = 1'b1;
+    assign flash_hold_n = 1'b1;
+
     logic [23:0] bridge_app_addr;
     Xenowing xenowing(
         .reset_n(reset_n),
@@ -112,9 +152,25 @@ module Top(
 
         .tx(tx),
         .rx(rx_sync),
+        // Only tx/rx have pins assigned in xenowing.xdc, so flow control is left disabled on this board: rts_n goes
+        //  nowhere and cts_n is tied asserted, which means enabling it in software has no effect
+        // TODO: Route rts_n/cts_n out to pins if hardware flow control is needed, with cts_n through a SyncChain like rx
+        .rts_n(),
+        .cts_n(1'b0),
 
         .leds(leds),
 
+        // TODO: Route video_* out to a display connector
+        // TODO: Route audio_* out to an audio jack (through an RC low-pass filter)
+        // The microSD slot isn't wired up on this board yet: sd_sclk/sd_mosi/sd_cs_n are left unconnected and
+        //  sd_miso is tied high, so software always sees an empty slot (xw::sdcard::SdCard::init fails with NoResponse)
+        // TODO: Route sd_* out to the microSD slot, with sd_miso through a SyncChain like rx
+        .sd_miso(1'b1),
+        .flash_sclk(flash_sclk),
+        .flash_mosi(flash_mosi),
+        .flash_miso(flash_miso_sync),
+        .flash_cs_n(flash_cs_n),
+
         .ddr3_init_calib_complete(init_calib_complete),
 
         .ddr3_app_rdy(app_rdy),
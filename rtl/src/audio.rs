@@ -0,0 +1,296 @@
+use crate::buster::*;
+use crate::fifo::*;
+
+use kaze::*;
+
+use rtl_meta::audio::*;
+use rtl_meta::xenowing::*;
+
+pub struct Audio<'a> {
+    pub m: &'a Module<'a>,
+
+    pub reg_port: ReplicaPort<'a>,
+
+    pub mem_port: PrimaryPort<'a>,
+
+    // First-order sigma-delta bitstreams, meant to be low-pass filtered (eg. with an RC filter) off-chip
+    pub left: &'a Output<'a>,
+    pub right: &'a Output<'a>,
+
+    pub irq: &'a Output<'a>,
+}
+
+impl<'a> Audio<'a> {
+    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> Audio<'a> {
+        let m = p.module(instance_name, "Audio");
+
+        let reg_bus_enable = m.input("reg_bus_enable", 1);
+        let reg_bus_addr = m.input("reg_bus_addr", REG_BUS_ADDR_BITS);
+        let reg_bus_write = m.input("reg_bus_write", 1);
+        let reg_bus_write_data = m.input("reg_bus_write_data", 128);
+        let reg_bus_write_byte_enable = m.input("reg_bus_write_byte_enable", 128 / 8);
+        let reg_bus_ready = m.output("reg_bus_ready", m.lit(true, 1));
+        let reg_bus_read_data_valid = m.output(
+            "reg_bus_read_data_valid",
+            (reg_bus_enable & !reg_bus_write).reg_next_with_default("reg_bus_read_data_valid_reg", false),
+        );
+
+        let reg_write = reg_bus_enable & reg_bus_write;
+        let reg_addr = reg_bus_addr.bits(REG_BUS_ADDR_BIT_WIDTH - 1, 0);
+        let reg_write_addr = |addr: u32| reg_write & reg_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH));
+
+        let mem_bus_ready = m.input("mem_bus_ready", 1);
+        let mem_bus_read_data = m.input("mem_bus_read_data", 128);
+        let mem_bus_read_data_valid = m.input("mem_bus_read_data_valid", 1);
+
+        let enable_reg = m.reg("enable_reg", 1);
+        enable_reg.default_value(false);
+        enable_reg.drive_next(if_(reg_write_addr(REG_ENABLE_ADDR), {
+            reg_bus_write_data.bit(0)
+        }).else_({
+            enable_reg
+        }));
+
+        let ring_buffer_base_reg = m.reg("ring_buffer_base_reg", SYSTEM_BUS_ADDR_BITS);
+        ring_buffer_base_reg.default_value(0u32);
+        ring_buffer_base_reg.drive_next(if_(reg_write_addr(REG_RING_BUFFER_BASE_ADDR), {
+            reg_bus_write_data.bits(SYSTEM_BUS_ADDR_BITS + 4 - 1, 4)
+        }).else_({
+            ring_buffer_base_reg
+        }));
+
+        let ring_buffer_num_words_reg = m.reg("ring_buffer_num_words_reg", REG_RING_BUFFER_NUM_WORDS_BITS);
+        ring_buffer_num_words_reg.default_value(0u32);
+        ring_buffer_num_words_reg.drive_next(if_(reg_write_addr(REG_RING_BUFFER_NUM_WORDS_ADDR), {
+            reg_bus_write_data.bits(REG_RING_BUFFER_NUM_WORDS_BITS - 1, 0)
+        }).else_({
+            ring_buffer_num_words_reg
+        }));
+
+        let sample_period_reg = m.reg("sample_period_reg", REG_SAMPLE_PERIOD_BITS);
+        sample_period_reg.default_value(REG_SAMPLE_PERIOD_DEFAULT);
+        sample_period_reg.drive_next(if_(reg_write_addr(REG_SAMPLE_PERIOD_ADDR), {
+            reg_bus_write_data.bits(REG_SAMPLE_PERIOD_BITS - 1, 0)
+        }).else_({
+            sample_period_reg
+        }));
+
+        let interrupt_enable_reg = m.reg("interrupt_enable_reg", REG_INTERRUPT_BITS);
+        interrupt_enable_reg.default_value(0u32);
+        interrupt_enable_reg.drive_next(if_(reg_write_addr(REG_INTERRUPT_ENABLE_ADDR), {
+            reg_bus_write_data.bits(REG_INTERRUPT_BITS - 1, 0)
+        }).else_({
+            interrupt_enable_reg
+        }));
+
+        // Fetch
+        let fifo_depth_bits = 3;
+        let fifo = Fifo::new("fifo", fifo_depth_bits, 128, m);
+        fifo.write_enable.drive(mem_bus_read_data_valid);
+        fifo.write_data.drive(mem_bus_read_data);
+
+        let credit_counter_bits = fifo_depth_bits + 1;
+        let credit_counter = m.reg("credit_counter", credit_counter_bits);
+        credit_counter.default_value(1u32 << fifo_depth_bits);
+
+        let issue = enable_reg & credit_counter.ne(m.lit(0u32, credit_counter_bits));
+        let issue_accepted = issue & mem_bus_ready;
+
+        let ring_buffer_index_next = |index: &'a Register<'a>| {
+            let next = index + m.lit(1u32, REG_RING_BUFFER_NUM_WORDS_BITS);
+            if_(next.eq(ring_buffer_num_words_reg), {
+                m.lit(0u32, REG_RING_BUFFER_NUM_WORDS_BITS)
+            }).else_({
+                next
+            })
+        };
+
+        let fetch_index = m.reg("fetch_index", REG_RING_BUFFER_NUM_WORDS_BITS);
+        fetch_index.default_value(0u32);
+        fetch_index.drive_next(if_(!enable_reg, {
+            m.lit(0u32, REG_RING_BUFFER_NUM_WORDS_BITS)
+        }).else_if(issue_accepted, {
+            ring_buffer_index_next(fetch_index)
+        }).else_({
+            fetch_index
+        }));
+        let fetch_addr = ring_buffer_base_reg + m.lit(0u32, SYSTEM_BUS_ADDR_BITS - REG_RING_BUFFER_NUM_WORDS_BITS).concat(fetch_index);
+
+        // Playback
+        //  The sample timer starts out a whole period away so the fifo has a chance to fill up before the first frame
+        let sample_timer = m.reg("sample_timer", REG_SAMPLE_PERIOD_BITS);
+        sample_timer.default_value(0u32);
+        let sample_tick = enable_reg & sample_timer.eq(m.lit(0u32, REG_SAMPLE_PERIOD_BITS));
+        sample_timer.drive_next(if_(!enable_reg | sample_tick, {
+            sample_period_reg - m.lit(1u32, REG_SAMPLE_PERIOD_BITS)
+        }).else_({
+            sample_timer - m.lit(1u32, REG_SAMPLE_PERIOD_BITS)
+        }));
+
+        let frame_index = m.reg("frame_index", FRAMES_PER_WORD_BITS);
+        frame_index.default_value(0u32);
+        frame_index.drive_next(if_(!enable_reg, {
+            m.lit(0u32, FRAMES_PER_WORD_BITS)
+        }).else_if(sample_tick, {
+            frame_index + m.lit(1u32, FRAMES_PER_WORD_BITS)
+        }).else_({
+            frame_index
+        }));
+
+        // A word is popped for its first frame; anything left in the fifo is discarded while disabled
+        let word_pop = sample_tick & frame_index.eq(m.lit(0u32, FRAMES_PER_WORD_BITS));
+        let fifo_read = word_pop | !enable_reg;
+        fifo.read_enable.drive(fifo_read);
+        let fifo_read_accepted = fifo_read & !fifo.empty;
+        let underrun = word_pop & fifo.empty;
+
+        credit_counter.drive_next(if_(!issue_accepted & fifo_read_accepted, {
+            credit_counter + m.lit(1u32, credit_counter_bits)
+        }).else_if(issue_accepted & !fifo_read_accepted, {
+            credit_counter - m.lit(1u32, credit_counter_bits)
+        }).else_({
+            credit_counter
+        }));
+
+        let play_index = m.reg("play_index", REG_RING_BUFFER_NUM_WORDS_BITS);
+        play_index.default_value(0u32);
+        play_index.drive_next(if_(!enable_reg, {
+            m.lit(0u32, REG_RING_BUFFER_NUM_WORDS_BITS)
+        }).else_if(word_pop, {
+            ring_buffer_index_next(play_index)
+        }).else_({
+            play_index
+        }));
+
+        let word_valid_reg = m.reg("word_valid_reg", 1);
+        word_valid_reg.default_value(false);
+        word_valid_reg.drive_next(if_(word_pop, {
+            !fifo.empty
+        }).else_({
+            word_valid_reg
+        }));
+
+        // The fifo's read data is valid the cycle after a pop, so frames are picked out a cycle after each tick
+        let sample_tick_1 = sample_tick.reg_next_with_default("sample_tick_1", false);
+        let frame_index_1 = frame_index.reg_next("frame_index_1");
+        let mut frame: &dyn Signal<'a> = fifo.read_data.bits(31, 0);
+        for i in 1..1 << FRAMES_PER_WORD_BITS {
+            frame = if_(frame_index_1.eq(m.lit(i, FRAMES_PER_WORD_BITS)), {
+                fifo.read_data.bits(i * 32 + 31, i * 32)
+            }).else_({
+                frame
+            });
+        }
+
+        let sample_reg = |name: &str, sample: &'a dyn Signal<'a>| {
+            let reg = m.reg(name, 16);
+            reg.default_value(0u32);
+            reg.drive_next(if_(!enable_reg, {
+                m.lit(0u32, 16)
+            }).else_if(sample_tick_1, {
+                if_(word_valid_reg, {
+                    sample
+                }).else_({
+                    m.lit(0u32, 16)
+                })
+            }).else_({
+                reg
+            }));
+            reg
+        };
+        let left_sample = sample_reg("left_sample", frame.bits(15, 0));
+        let right_sample = sample_reg("right_sample", frame.bits(31, 16));
+
+        // Interrupts
+        let half_words = m.low().concat(ring_buffer_num_words_reg.bits(REG_RING_BUFFER_NUM_WORDS_BITS - 1, 1));
+        let last_word = ring_buffer_num_words_reg - m.lit(1u32, REG_RING_BUFFER_NUM_WORDS_BITS);
+        let first_half_done = word_pop & play_index.eq(half_words - m.lit(1u32, REG_RING_BUFFER_NUM_WORDS_BITS));
+        let second_half_done = word_pop & play_index.eq(last_word);
+
+        let write_one_to_clear = |reg: &'a Register<'a>, addr: u32, set: &'a dyn Signal<'a>| {
+            let cleared = if_(reg_write_addr(addr), {
+                reg & !reg_bus_write_data.bits(reg.bit_width() - 1, 0)
+            }).else_({
+                reg
+            });
+            reg.drive_next(cleared | set);
+        };
+
+        let interrupt_status_reg = m.reg("interrupt_status_reg", REG_INTERRUPT_BITS);
+        interrupt_status_reg.default_value(0u32);
+        // REG_INTERRUPT_SECOND_HALF_BIT, REG_INTERRUPT_FIRST_HALF_BIT
+        write_one_to_clear(interrupt_status_reg, REG_INTERRUPT_STATUS_ADDR, second_half_done.concat(first_half_done));
+
+        let underrun_reg = m.reg("underrun_reg", 1);
+        underrun_reg.default_value(false);
+        write_one_to_clear(underrun_reg, REG_UNDERRUN_ADDR, underrun);
+
+        let irq = (interrupt_status_reg & interrupt_enable_reg).ne(m.lit(0u32, REG_INTERRUPT_BITS));
+
+        // Sigma-delta modulators
+        //  Samples are offset to unsigned, so silence is a 50% duty cycle
+        let sigma_delta = |name: &str, sample: &'a Register<'a>| {
+            let acc = m.reg(format!("{}_acc", name), 16);
+            acc.default_value(0u32);
+            let sum = m.low().concat(acc) + m.low().concat(!sample.bit(15)).concat(sample.bits(14, 0));
+            acc.drive_next(sum.bits(15, 0));
+            sum.bit(16).reg_next_with_default(format!("{}_out", name), false)
+        };
+        let left = sigma_delta("left", left_sample);
+        let right = sigma_delta("right", right_sample);
+
+        // Reg reads
+        let reg_read_addr = reg_addr.reg_next("reg_read_addr");
+        let read_value = |value: &'a dyn Signal<'a>| m.lit(0u32, 32 - value.bit_width()).concat(value);
+        let readable_regs: [(u32, &'a dyn Signal<'a>); 8] = [
+            (REG_ENABLE_ADDR, enable_reg),
+            (REG_RING_BUFFER_BASE_ADDR, ring_buffer_base_reg.concat(m.lit(0u32, 4))),
+            (REG_RING_BUFFER_NUM_WORDS_ADDR, ring_buffer_num_words_reg),
+            (REG_SAMPLE_PERIOD_ADDR, sample_period_reg),
+            (REG_PLAY_POSITION_ADDR, play_index),
+            (REG_INTERRUPT_STATUS_ADDR, interrupt_status_reg),
+            (REG_INTERRUPT_ENABLE_ADDR, interrupt_enable_reg),
+            (REG_UNDERRUN_ADDR, underrun_reg),
+        ];
+        let mut reg_read_data = m.lit(0u32, 32);
+        for &(addr, value) in readable_regs.iter() {
+            reg_read_data = if_(reg_read_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH)), {
+                read_value(value)
+            }).else_({
+                reg_read_data
+            });
+        }
+        let reg_bus_read_data = m.output("reg_bus_read_data", m.lit(0u32, 96).concat(reg_read_data));
+
+        Audio {
+            m,
+
+            reg_port: ReplicaPort {
+                bus_enable: reg_bus_enable,
+                bus_addr: reg_bus_addr,
+                bus_write: reg_bus_write,
+                bus_write_data: reg_bus_write_data,
+                bus_write_byte_enable: reg_bus_write_byte_enable,
+                bus_ready: reg_bus_ready,
+                bus_read_data: reg_bus_read_data,
+                bus_read_data_valid: reg_bus_read_data_valid,
+            },
+
+            mem_port: PrimaryPort {
+                bus_enable: m.output("mem_bus_enable", issue),
+                bus_addr: m.output("mem_bus_addr", fetch_addr),
+                bus_write: m.output("mem_bus_write", m.low()),
+                bus_write_data: m.output("mem_bus_write_data", m.lit(0u32, 128)),
+                bus_write_byte_enable: m.output("mem_bus_write_byte_enable", m.lit(0u32, 128 / 8)),
+                bus_ready: mem_bus_ready,
+                bus_read_data: mem_bus_read_data,
+                bus_read_data_valid: mem_bus_read_data_valid,
+            },
+
+            left: m.output("left", left),
+            right: m.output("right", right),
+
+            irq: m.output("irq", irq),
+        }
+    }
+}
@@ -34,34 +34,162 @@ impl<'a> BitPusher<'a> {
         let reg_write = reg_bus_enable & reg_bus_write;
         let reg_addr = reg_bus_addr.bits(REG_BUS_ADDR_BIT_WIDTH - 1, 0);
 
+        let sys_bus_write_byte_enable = m.output("sys_bus_write_byte_enable", m.lit(0xffffu32, 16));
+        let sys_bus_ready = m.input("sys_bus_ready", 1);
+        let sys_bus_read_data = m.input("sys_bus_read_data", 128);
+        let sys_bus_read_data_valid = m.input("sys_bus_read_data_valid", 1);
+
+        let mem_bus_ready = m.input("mem_bus_ready", 1);
+        let mem_bus_read_data = m.input("mem_bus_read_data", 128);
+        let mem_bus_read_data_valid = m.input("mem_bus_read_data_valid", 1);
+
+        // Descriptor chains are executed by loading each descriptor into the same regs the reg bus writes, then
+        //  dispatching its transfer exactly as if the start reg was written
+        // TODO: Figure out how to use/describe enums properly in kaze!
+        let chain_state_bit_width = 3;
+        let chain_state_idle = 0u32;
+        let chain_state_fetch = 1u32;
+        let chain_state_start = 2u32;
+        let chain_state_transfer = 3u32;
+        let chain_state_write_status = 4u32;
+        let chain_state = m.reg("chain_state", chain_state_bit_width);
+        chain_state.default_value(chain_state_idle);
+
+        let chain_idle = chain_state.eq(m.lit(chain_state_idle, chain_state_bit_width));
+        let fetching = chain_state.eq(m.lit(chain_state_fetch, chain_state_bit_width));
+        let writing_status = chain_state.eq(m.lit(chain_state_write_status, chain_state_bit_width));
+
+        let start_chain = reg_write & reg_addr.eq(m.lit(REG_DESCRIPTOR_ADDR_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+
+        // Only the first two descriptor words are read; the third is only ever written
+        let fetch_counter_bit_width = 2;
+        let fetch_issue_counter = m.reg("fetch_issue_counter", fetch_counter_bit_width);
+        let fetch_receive_counter = m.reg("fetch_receive_counter", fetch_counter_bit_width);
+        let fetch_issue = fetching & fetch_issue_counter.ne(m.lit(2u32, fetch_counter_bit_width));
+        let fetch_issue_accepted = fetch_issue & mem_bus_ready;
+        let descriptor_word_valid = fetching & mem_bus_read_data_valid;
+        let descriptor_word0_valid = descriptor_word_valid & fetch_receive_counter.eq(m.lit(0u32, fetch_counter_bit_width));
+        let descriptor_word1_valid = descriptor_word_valid & fetch_receive_counter.eq(m.lit(1u32, fetch_counter_bit_width));
+        let descriptor_control = mem_bus_read_data.bits(127, 96);
+
+        let write_status_accepted = writing_status & mem_bus_ready;
+
+        let descriptor_addr_reg = m.reg("descriptor_addr_reg", SYSTEM_BUS_ADDR_BITS);
+        let next_descriptor_addr_reg = m.reg("next_descriptor_addr_reg", SYSTEM_BUS_ADDR_BITS);
+        let last_descriptor_reg = m.reg("last_descriptor_reg", 1);
+        descriptor_addr_reg.drive_next(if_(start_chain, {
+            reg_bus_write_data.bits(SYSTEM_BUS_ADDR_BITS + 4 - 1, 4)
+        }).else_if(write_status_accepted, {
+            next_descriptor_addr_reg
+        }).else_({
+            descriptor_addr_reg
+        }));
+        next_descriptor_addr_reg.drive_next(if_(descriptor_word1_valid, {
+            descriptor_control.bits(SYSTEM_BUS_ADDR_BITS + DESCRIPTOR_CONTROL_NEXT_ADDR_BIT_OFFSET - 1, DESCRIPTOR_CONTROL_NEXT_ADDR_BIT_OFFSET)
+        }).else_({
+            next_descriptor_addr_reg
+        }));
+        last_descriptor_reg.drive_next(if_(descriptor_word1_valid, {
+            descriptor_control.bit(DESCRIPTOR_CONTROL_LAST_BIT)
+        }).else_({
+            last_descriptor_reg
+        }));
+
+        let fetch_next_descriptor = start_chain | (write_status_accepted & !last_descriptor_reg);
+        fetch_issue_counter.drive_next(if_(fetch_next_descriptor, {
+            m.lit(0u32, fetch_counter_bit_width)
+        }).else_if(fetch_issue_accepted, {
+            fetch_issue_counter + m.lit(1u32, fetch_counter_bit_width)
+        }).else_({
+            fetch_issue_counter
+        }));
+        fetch_receive_counter.drive_next(if_(fetch_next_descriptor, {
+            m.lit(0u32, fetch_counter_bit_width)
+        }).else_if(descriptor_word_valid, {
+            fetch_receive_counter + m.lit(1u32, fetch_counter_bit_width)
+        }).else_({
+            fetch_receive_counter
+        }));
+
         let direction_reg = m.reg("direction_reg", REG_DIRECTION_BITS);
         direction_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_DIRECTION_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
             reg_bus_write_data.bits(REG_DIRECTION_BITS - 1, 0)
+        }).else_if(descriptor_word1_valid, {
+            descriptor_control.bits(DESCRIPTOR_CONTROL_DIRECTION_BIT + REG_DIRECTION_BITS - 1, DESCRIPTOR_CONTROL_DIRECTION_BIT)
         }).else_({
             direction_reg
         }));
 
-        let start_transfer = reg_write & reg_addr.eq(m.lit(REG_START_ADDR, REG_BUS_ADDR_BIT_WIDTH));
-        let write_num_words = reg_write & reg_addr.eq(m.lit(REG_NUM_WORDS_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+        // The addr units' span bases are latched on start, so the descriptor's transfer is dispatched the cycle after
+        //  its last word is loaded
+        let start_transfer =
+            (reg_write & reg_addr.eq(m.lit(REG_START_ADDR, REG_BUS_ADDR_BIT_WIDTH))) |
+            chain_state.eq(m.lit(chain_state_start, chain_state_bit_width));
+        let write_num_words = (reg_write & reg_addr.eq(m.lit(REG_NUM_WORDS_ADDR, REG_BUS_ADDR_BIT_WIDTH))) | descriptor_word0_valid;
+        let num_words = if_(descriptor_word0_valid, {
+            mem_bus_read_data.bits(127, 96)
+        }).else_({
+            reg_bus_write_data.bits(31, 0)
+        });
+
+        let fill_value_reg = m.reg("fill_value_reg", 32);
+        fill_value_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_FILL_VALUE_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(31, 0)
+        }).else_if(descriptor_word0_valid, {
+            mem_bus_read_data.bits(31, 0)
+        }).else_({
+            fill_value_reg
+        }));
 
-        let sys_bus_write_byte_enable = m.output("sys_bus_write_byte_enable", m.lit(0xffffu32, 16));
-        let sys_bus_ready = m.input("sys_bus_ready", 1);
-        let sys_bus_read_data = m.input("sys_bus_read_data", 128);
-        let sys_bus_read_data_valid = m.input("sys_bus_read_data_valid", 1);
+        let pixel_op_settings_reg = m.reg("pixel_op_settings_reg", REG_PIXEL_OP_SETTINGS_BITS);
+        pixel_op_settings_reg.default_value(REG_PIXEL_OP_SETTINGS_LOGIC_OP_COPY << REG_PIXEL_OP_SETTINGS_LOGIC_OP_BIT_OFFSET);
+        pixel_op_settings_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_PIXEL_OP_SETTINGS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_PIXEL_OP_SETTINGS_BITS - 1, 0)
+        }).else_({
+            pixel_op_settings_reg
+        }));
 
-        let mem_bus_write_byte_enable = m.output("mem_bus_write_byte_enable", m.lit(0xffffu32, 16));
-        let mem_bus_ready = m.input("mem_bus_ready", 1);
-        let mem_bus_read_data = m.input("mem_bus_read_data", 128);
-        let mem_bus_read_data_valid = m.input("mem_bus_read_data_valid", 1);
+        let color_key_reg = m.reg("color_key_reg", REG_COLOR_KEY_BITS);
+        color_key_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_COLOR_KEY_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_COLOR_KEY_BITS - 1, 0)
+        }).else_({
+            color_key_reg
+        }));
 
+        let mem2sys = direction_reg.eq(m.lit(REG_DIRECTION_MEM2SYS, REG_DIRECTION_BITS));
+        let sys2mem = direction_reg.eq(m.lit(REG_DIRECTION_SYS2MEM, REG_DIRECTION_BITS));
+        let mem2mem = direction_reg.eq(m.lit(REG_DIRECTION_MEM2MEM, REG_DIRECTION_BITS));
+        let fill = direction_reg.eq(m.lit(REG_DIRECTION_FILL, REG_DIRECTION_BITS));
+
+        let pixel_ops = PixelOps::new("pixel_ops", m);
+        pixel_ops.color_key_enable.drive(pixel_op_settings_reg.bit(REG_PIXEL_OP_SETTINGS_COLOR_KEY_ENABLE_BIT));
+        pixel_ops.color_key.drive(color_key_reg);
+        pixel_ops.blend_enable.drive(pixel_op_settings_reg.bit(REG_PIXEL_OP_SETTINGS_BLEND_ENABLE_BIT));
+        pixel_ops.logic_op.drive(pixel_op_settings_reg.bits(
+            REG_PIXEL_OP_SETTINGS_LOGIC_OP_BIT_OFFSET + REG_PIXEL_OP_SETTINGS_LOGIC_OP_BITS - 1,
+            REG_PIXEL_OP_SETTINGS_LOGIC_OP_BIT_OFFSET));
+
+        let pixel_ops_active = mem2mem | fill;
+        let dst_needed = pixel_ops_active & pixel_ops.dst_needed;
+
+        // Source data comes from the sys port for SYS2MEM, and otherwise from the mem port, where it's interleaved with
+        //  destination reads when the pixel ops need them. Returned mem data is tagged with which stream it belongs to
+        //  (in issue order), so it's delayed by a cycle to line up with the tag fifo's read data.
         let fifo_depth_bits = 5;
 
+        let mem_read_data_valid = mem_bus_read_data_valid & !fetching;
+        let read_tag_fifo = Fifo::new("read_tag_fifo", fifo_depth_bits + 1, 1, m);
+        read_tag_fifo.read_enable.drive(mem_read_data_valid);
+        let delayed_mem_read_data_valid = mem_read_data_valid.reg_next_with_default("delayed_mem_read_data_valid", false);
+        let delayed_mem_read_data = mem_bus_read_data.reg_next("delayed_mem_read_data");
+        let delayed_mem_read_data_is_dst = read_tag_fifo.read_data;
+
         let data_fifo = Fifo::new("data_fifo", fifo_depth_bits, 128, m);
-        data_fifo.write_enable.drive(sys_bus_read_data_valid | mem_bus_read_data_valid);
+        data_fifo.write_enable.drive(sys_bus_read_data_valid | (delayed_mem_read_data_valid & !delayed_mem_read_data_is_dst));
         data_fifo.write_data.drive(if_(sys_bus_read_data_valid, {
             sys_bus_read_data
         }).else_({
-            mem_bus_read_data
+            delayed_mem_read_data
         }));
 
         let data_buffer = PeekBuffer::new("data_buffer", 128, m);
@@ -71,54 +199,126 @@ impl<'a> BitPusher<'a> {
             (!data_fifo.empty & data_buffer.ingress_read_enable)
             .reg_next_with_default("data_buffer_ingress_data_valid", false));
 
-        let mem2sys = direction_reg.eq(m.lit(REG_DIRECTION_MEM2SYS, REG_DIRECTION_BITS));
+        let dst_data_fifo = Fifo::new("dst_data_fifo", fifo_depth_bits, 128, m);
+        dst_data_fifo.write_enable.drive(delayed_mem_read_data_valid & delayed_mem_read_data_is_dst);
+        dst_data_fifo.write_data.drive(delayed_mem_read_data);
+
+        let dst_data_buffer = PeekBuffer::new("dst_data_buffer", 128, m);
+        dst_data_fifo.read_enable.drive(dst_data_buffer.ingress_read_enable);
+        dst_data_buffer.ingress_data.drive(dst_data_fifo.read_data);
+        dst_data_buffer.ingress_data_valid.drive(
+            (!dst_data_fifo.empty & dst_data_buffer.ingress_read_enable)
+            .reg_next_with_default("dst_data_buffer_ingress_data_valid", false));
 
+        // Mem port priority is writes, then source reads, then destination reads
         let read_issue = ReadIssue::new("read_issue", fifo_depth_bits, m);
-        read_issue.num_words.drive(reg_bus_write_data.bits(31, 0));
+        let dst_read_issue = ReadIssue::new("dst_read_issue", fifo_depth_bits, m);
+        let write_issue = WriteIssue::new("write_issue", m);
+
+        let src_read_on_mem_bus = (mem2sys | mem2mem) & read_issue.bus_enable;
+
+        read_issue.num_words.drive(num_words);
         read_issue.write_num_words.drive(write_num_words);
-        read_issue.start_transfer.drive(start_transfer);
+        read_issue.start_transfer.drive(start_transfer & !fill);
         read_issue.bus_ready.drive(if_(mem2sys, {
             mem_bus_ready
-        }).else_({
+        }).else_if(sys2mem, {
             sys_bus_ready
+        }).else_({
+            mem_bus_ready & !write_issue.bus_enable
         }));
 
-        let write_issue = WriteIssue::new("write_issue", m);
-        write_issue.num_words.drive(reg_bus_write_data.bits(31, 0));
+        dst_read_issue.num_words.drive(num_words);
+        dst_read_issue.write_num_words.drive(write_num_words);
+        dst_read_issue.start_transfer.drive(start_transfer & dst_needed);
+        dst_read_issue.bus_ready.drive(mem_bus_ready & !write_issue.bus_enable & !src_read_on_mem_bus);
+
+        read_tag_fifo.write_enable.drive((src_read_on_mem_bus & read_issue.issue_accepted) | dst_read_issue.issue_accepted);
+        read_tag_fifo.write_data.drive(dst_read_issue.issue_accepted);
+
+        write_issue.num_words.drive(num_words);
         write_issue.write_num_words.drive(write_num_words);
         write_issue.start_transfer.drive(start_transfer);
-        write_issue.data_ready.drive(data_buffer.egress_ready);
-        data_buffer.egress_read_enable.drive(write_issue.issue_accepted);
+        write_issue.data_ready.drive((fill | data_buffer.egress_ready) & (!dst_needed | dst_data_buffer.egress_ready));
+        data_buffer.egress_read_enable.drive(write_issue.issue_accepted & !fill);
+        dst_data_buffer.egress_read_enable.drive(write_issue.issue_accepted & dst_needed);
         write_issue.bus_ready.drive(if_(mem2sys, {
             sys_bus_ready
         }).else_({
             mem_bus_ready
         }));
-        read_issue.credit_counter_inc.drive(write_issue.issue_accepted);
+        read_issue.credit_counter_inc.drive(write_issue.issue_accepted & !fill);
+        dst_read_issue.credit_counter_inc.drive(write_issue.issue_accepted & dst_needed);
+
+        pixel_ops.src.drive(if_(fill, {
+            fill_value_reg.repeat(4)
+        }).else_({
+            data_buffer.egress_data
+        }));
+        pixel_ops.dst.drive(dst_data_buffer.egress_data);
 
         let sys_bus_enable = m.output("sys_bus_enable", if_(mem2sys, {
             write_issue.bus_enable
-        }).else_({
+        }).else_if(sys2mem, {
             read_issue.bus_enable
+        }).else_({
+            m.lit(false, 1)
         }));
         let sys_bus_write = m.output("sys_bus_write", mem2sys);
         let sys_bus_write_data = m.output("sys_bus_write_data", data_buffer.egress_data);
 
-        let mem_bus_enable = m.output("mem_bus_enable", if_(mem2sys, {
+        let mem_bus_enable = m.output("mem_bus_enable", if_(fetching, {
+            fetch_issue
+        }).else_if(writing_status, {
+            m.lit(true, 1)
+        }).else_if(mem2sys, {
             read_issue.bus_enable
         }).else_({
-            write_issue.bus_enable
+            write_issue.bus_enable | src_read_on_mem_bus | dst_read_issue.bus_enable
+        }));
+        let mem_bus_write = m.output("mem_bus_write", if_(fetching | writing_status, {
+            writing_status
+        }).else_({
+            !mem2sys & write_issue.bus_enable
+        }));
+        let mem_bus_write_data = m.output("mem_bus_write_data", if_(writing_status, {
+            m.lit(DESCRIPTOR_STATUS_COMPLETE, 128)
+        }).else_if(pixel_ops_active, {
+            pixel_ops.data
+        }).else_({
+            data_buffer.egress_data
+        }));
+        let mem_bus_write_byte_enable = m.output("mem_bus_write_byte_enable", if_(pixel_ops_active & !writing_status, {
+            pixel_ops.byte_enable
+        }).else_({
+            m.lit(0xffffu32, 16)
         }));
-        let mem_bus_write = m.output("mem_bus_write", !mem2sys);
-        let mem_bus_write_data = m.output("mem_bus_write_data", data_buffer.egress_data);
 
-        let busy = read_issue.busy | write_issue.busy;
+        let busy = read_issue.busy | dst_read_issue.busy | write_issue.busy;
+
+        chain_state.drive_next(if_(start_chain, {
+            m.lit(chain_state_fetch, chain_state_bit_width)
+        }).else_if(descriptor_word1_valid, {
+            m.lit(chain_state_start, chain_state_bit_width)
+        }).else_if(chain_state.eq(m.lit(chain_state_start, chain_state_bit_width)), {
+            m.lit(chain_state_transfer, chain_state_bit_width)
+        }).else_if(chain_state.eq(m.lit(chain_state_transfer, chain_state_bit_width)) & !busy, {
+            m.lit(chain_state_write_status, chain_state_bit_width)
+        }).else_if(write_status_accepted, {
+            if_(last_descriptor_reg, {
+                m.lit(chain_state_idle, chain_state_bit_width)
+            }).else_({
+                m.lit(chain_state_fetch, chain_state_bit_width)
+            })
+        }).else_({
+            chain_state
+        }));
 
         let sys_addr_unit = AddrUnit::new("sys_addr_unit", m);
-        sys_addr_unit.write_data.drive(reg_bus_write_data.bits(31, 0));
-        sys_addr_unit.write_addr.drive(reg_write & reg_addr.eq(m.lit(REG_SYS_ADDR_ADDR, REG_BUS_ADDR_BIT_WIDTH)));
-        sys_addr_unit.write_words_per_span.drive(reg_write & reg_addr.eq(m.lit(REG_SYS_WORDS_PER_SPAN_ADDR, REG_BUS_ADDR_BIT_WIDTH)));
-        sys_addr_unit.write_span_stride.drive(reg_write & reg_addr.eq(m.lit(REG_SYS_SPAN_STRIDE_ADDR, REG_BUS_ADDR_BIT_WIDTH)));
+        sys_addr_unit.load(descriptor_word0_valid, mem_bus_read_data, reg_bus_write_data.bits(31, 0));
+        sys_addr_unit.write_addr.drive(reg_write & reg_addr.eq(m.lit(REG_SYS_ADDR_ADDR, REG_BUS_ADDR_BIT_WIDTH)) | descriptor_word0_valid);
+        sys_addr_unit.write_words_per_span.drive(reg_write & reg_addr.eq(m.lit(REG_SYS_WORDS_PER_SPAN_ADDR, REG_BUS_ADDR_BIT_WIDTH)) | descriptor_word0_valid);
+        sys_addr_unit.write_span_stride.drive(reg_write & reg_addr.eq(m.lit(REG_SYS_SPAN_STRIDE_ADDR, REG_BUS_ADDR_BIT_WIDTH)) | descriptor_word0_valid);
         sys_addr_unit.start_transfer.drive(start_transfer);
         sys_addr_unit.step.drive(if_(mem2sys, {
             write_issue.issue_accepted
@@ -126,23 +326,40 @@ impl<'a> BitPusher<'a> {
             read_issue.issue_accepted
         }));
 
+        // Destination reads walk the same rect as writes, so the dst addr unit is configured identically to the mem addr unit
         let mem_addr_unit = AddrUnit::new("mem_addr_unit", m);
-        mem_addr_unit.write_data.drive(reg_bus_write_data.bits(31, 0));
-        mem_addr_unit.write_addr.drive(reg_write & reg_addr.eq(m.lit(REG_MEM_ADDR_ADDR, REG_BUS_ADDR_BIT_WIDTH)));
-        mem_addr_unit.write_words_per_span.drive(reg_write & reg_addr.eq(m.lit(REG_MEM_WORDS_PER_SPAN_ADDR, REG_BUS_ADDR_BIT_WIDTH)));
-        mem_addr_unit.write_span_stride.drive(reg_write & reg_addr.eq(m.lit(REG_MEM_SPAN_STRIDE_ADDR, REG_BUS_ADDR_BIT_WIDTH)));
-        mem_addr_unit.start_transfer.drive(start_transfer);
+        let dst_addr_unit = AddrUnit::new("dst_addr_unit", m);
+        for addr_unit in [&mem_addr_unit, &dst_addr_unit] {
+            addr_unit.load(descriptor_word1_valid, mem_bus_read_data, reg_bus_write_data.bits(31, 0));
+            addr_unit.write_addr.drive(reg_write & reg_addr.eq(m.lit(REG_MEM_ADDR_ADDR, REG_BUS_ADDR_BIT_WIDTH)) | descriptor_word1_valid);
+            addr_unit.write_words_per_span.drive(reg_write & reg_addr.eq(m.lit(REG_MEM_WORDS_PER_SPAN_ADDR, REG_BUS_ADDR_BIT_WIDTH)) | descriptor_word1_valid);
+            addr_unit.write_span_stride.drive(reg_write & reg_addr.eq(m.lit(REG_MEM_SPAN_STRIDE_ADDR, REG_BUS_ADDR_BIT_WIDTH)) | descriptor_word1_valid);
+            addr_unit.start_transfer.drive(start_transfer);
+        }
         mem_addr_unit.step.drive(if_(mem2sys, {
             read_issue.issue_accepted
         }).else_({
             write_issue.issue_accepted
         }));
+        dst_addr_unit.step.drive(dst_read_issue.issue_accepted);
 
         let sys_bus_addr = m.output("sys_bus_addr", sys_addr_unit.addr);
 
-        let mem_bus_addr = m.output("mem_bus_addr", mem_addr_unit.addr);
+        let fetch_addr = descriptor_addr_reg + m.lit(0u32, SYSTEM_BUS_ADDR_BITS - fetch_counter_bit_width).concat(fetch_issue_counter);
+        let status_addr = descriptor_addr_reg + m.lit(DESCRIPTOR_STATUS_WORD, SYSTEM_BUS_ADDR_BITS);
+        let mem_bus_addr = m.output("mem_bus_addr", if_(fetching, {
+            fetch_addr
+        }).else_if(writing_status, {
+            status_addr
+        }).else_if(mem2sys | write_issue.bus_enable, {
+            mem_addr_unit.addr
+        }).else_if(src_read_on_mem_bus, {
+            sys_addr_unit.addr
+        }).else_({
+            dst_addr_unit.addr
+        }));
 
-        let reg_bus_read_data = m.output("reg_bus_read_data", m.lit(0u32, 127).concat(busy));
+        let reg_bus_read_data = m.output("reg_bus_read_data", m.lit(0u32, 127).concat(busy | !chain_idle));
 
         BitPusher {
             m,
@@ -335,7 +552,9 @@ impl<'a> WriteIssue<'a> {
 }
 
 struct AddrUnit<'a> {
-    write_data: &'a Input<'a>,
+    addr_data: &'a Input<'a>,
+    words_per_span_data: &'a Input<'a>,
+    span_stride_data: &'a Input<'a>,
     write_addr: &'a Input<'a>,
     write_words_per_span: &'a Input<'a>,
     write_span_stride: &'a Input<'a>,
@@ -350,7 +569,9 @@ impl<'a> AddrUnit<'a> {
     pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> AddrUnit<'a> {
         let m = p.module(instance_name, "AddrUnit");
 
-        let write_data = m.input("write_data", 32);
+        let addr_data = m.input("addr_data", 32);
+        let words_per_span_data = m.input("words_per_span_data", 32);
+        let span_stride_data = m.input("span_stride_data", 32);
         let write_addr = m.input("write_addr", 1);
         let write_words_per_span = m.input("write_words_per_span", 1);
         let write_span_stride = m.input("write_span_stride", 1);
@@ -371,7 +592,7 @@ impl<'a> AddrUnit<'a> {
         let next_span_base = span_base_reg + span_stride_reg;
 
         addr_reg.drive_next(if_(write_addr, {
-            write_data.bits(SYSTEM_BUS_ADDR_BITS + 4 - 1, 4)
+            addr_data.bits(SYSTEM_BUS_ADDR_BITS + 4 - 1, 4)
         }).else_if(step, {
             if_(next_span, {
                 next_span_base
@@ -383,13 +604,13 @@ impl<'a> AddrUnit<'a> {
         }));
 
         words_per_span_reg.drive_next(if_(write_words_per_span, {
-            write_data
+            words_per_span_data
         }).else_({
             words_per_span_reg
         }));
 
         span_stride_reg.drive_next(if_(write_span_stride, {
-            write_data.bits(SYSTEM_BUS_ADDR_BITS - 1, 0)
+            span_stride_data.bits(SYSTEM_BUS_ADDR_BITS - 1, 0)
         }).else_({
             span_stride_reg
         }));
@@ -417,7 +638,9 @@ impl<'a> AddrUnit<'a> {
         let addr = m.output("addr", addr_reg);
 
         AddrUnit {
-            write_data,
+            addr_data,
+            words_per_span_data,
+            span_stride_data,
             write_addr,
             write_words_per_span,
             write_span_stride,
@@ -428,4 +651,110 @@ impl<'a> AddrUnit<'a> {
             addr,
         }
     }
+
+    // Descriptor words hold an addr unit's fields in their low three 32-bit lanes, in the same order as its regs
+    fn load(&self, load_descriptor_word: &'a dyn Signal<'a>, descriptor_word: &'a dyn Signal<'a>, reg_data: &'a dyn Signal<'a>) {
+        let field = |index: u32| if_(load_descriptor_word, {
+            descriptor_word.bits(index * 32 + 31, index * 32)
+        }).else_({
+            reg_data
+        });
+        self.addr_data.drive(field(0));
+        self.words_per_span_data.drive(field(1));
+        self.span_stride_data.drive(field(2));
+    }
+}
+
+struct PixelOps<'a> {
+    color_key_enable: &'a Input<'a>,
+    color_key: &'a Input<'a>,
+    blend_enable: &'a Input<'a>,
+    logic_op: &'a Input<'a>,
+
+    dst_needed: &'a Output<'a>,
+
+    src: &'a Input<'a>,
+    dst: &'a Input<'a>,
+
+    data: &'a Output<'a>,
+    byte_enable: &'a Output<'a>,
+}
+
+impl<'a> PixelOps<'a> {
+    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> PixelOps<'a> {
+        let m = p.module(instance_name, "PixelOps");
+
+        let color_key_enable = m.input("color_key_enable", 1);
+        let color_key = m.input("color_key", REG_COLOR_KEY_BITS);
+        let blend_enable = m.input("blend_enable", 1);
+        let logic_op = m.input("logic_op", REG_PIXEL_OP_SETTINGS_LOGIC_OP_BITS);
+
+        // Logic ops that don't depend on the destination have matching results for both of its values
+        let logic_op_reads_dst = (logic_op.bit(3) ^ logic_op.bit(2)) | (logic_op.bit(1) ^ logic_op.bit(0));
+        let dst_needed = m.output("dst_needed", blend_enable | logic_op_reads_dst);
+
+        let src = m.input("src", 128);
+        let dst = m.input("dst", 128);
+
+        let one = m.high().concat(m.lit(0u32, 8));
+
+        let concat_all = |values: Vec<&'a dyn Signal<'a>>| -> &'a dyn Signal<'a> {
+            values.into_iter().fold(None, |acc: Option<&'a dyn Signal<'a>>, value| {
+                Some(if let Some(acc) = acc {
+                    value.concat(acc)
+                } else {
+                    value
+                })
+            }).unwrap()
+        };
+
+        let (pixels, pixel_byte_enables): (Vec<_>, Vec<_>) = (0..4).map(|x| {
+            let src = src.bits(x * 32 + 31, x * 32);
+            let dst = dst.bits(x * 32 + 31, x * 32);
+
+            let keyed = color_key_enable & src.bits(23, 0).eq(color_key);
+
+            // Same fixed-point convention as ColorThrust's blend factors, where one is 256
+            let src_alpha = m.low().concat(src.bits(31, 24));
+            let one_minus_src_alpha = one - src_alpha;
+            let blend_comp = |bit_offset: u32| -> &'a dyn Signal<'a> {
+                let src_term = src.bits(bit_offset + 7, bit_offset) * src_alpha;
+                let dst_term = dst.bits(bit_offset + 7, bit_offset) * one_minus_src_alpha;
+                (src_term + dst_term).bits(15, 8)
+            };
+            let blended = if_(blend_enable, {
+                blend_comp(24).concat(blend_comp(16)).concat(blend_comp(8)).concat(blend_comp(0))
+            }).else_({
+                src
+            });
+
+            let logic_op_term = |index: u32, src: &'a dyn Signal<'a>, dst: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+                logic_op.bit(index).repeat(32) & src & dst
+            };
+            let pixel =
+                logic_op_term(0, !blended, !dst) |
+                logic_op_term(1, !blended, dst) |
+                logic_op_term(2, blended, !dst) |
+                logic_op_term(3, blended, dst);
+
+            (pixel, (!keyed).repeat(4))
+        }).unzip();
+        let data = m.output("data", concat_all(pixels));
+        let byte_enable = m.output("byte_enable", concat_all(pixel_byte_enables));
+
+        PixelOps {
+            color_key_enable,
+            color_key,
+            blend_enable,
+            logic_op,
+
+            dst_needed,
+
+            src,
+            dst,
+
+            data,
+            byte_enable,
+        }
+    }
 }
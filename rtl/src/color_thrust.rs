@@ -8,6 +8,7 @@ use crate::flow_controlled_pipe::*;
 use crate::word_mem::*;
 
 use rtl_meta::color_thrust::*;
+use rtl_meta::xenowing::*;
 
 use kaze::*;
 
@@ -17,11 +18,29 @@ pub struct ColorThrust<'a> {
     pub reg_port: ReplicaPort<'a>,
     pub color_buffer_port: ReplicaPort<'a>,
     pub depth_buffer_port: ReplicaPort<'a>,
+    pub stencil_buffer_port: ReplicaPort<'a>,
     pub tex_cache_system_port: PrimaryPort<'a>,
 }
 
 impl<'a> ColorThrust<'a> {
-    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> ColorThrust<'a> {
+    pub fn new(instance_name: impl Into<String>, num_pixel_pipes: u32, tile_width_bits: u32, tile_height_bits: u32, p: &'a impl ModuleParent<'a>) -> ColorThrust<'a> {
+        // Pixel pipes work on interleaved pixels within each row, and each one owns a bank of every tile buffer, so
+        //  there can't be more of them than there are pixels in a color buffer word
+        if !num_pixel_pipes.is_power_of_two() || num_pixel_pipes > 4 {
+            panic!("Cannot generate a ColorThrust module with {} pixel pipes; must be 1, 2, or 4.", num_pixel_pipes);
+        }
+        let pixel_pipes_bits = num_pixel_pipes.trailing_zeros();
+
+        // Tiles must be at least as wide as a stencil buffer word, and as tall as a hierarchical z block
+        for &(dim_name, dim_bits) in [("width", tile_width_bits), ("height", tile_height_bits)].iter() {
+            if !(TILE_DIM_BITS_MIN..=TILE_DIM_BITS_MAX).contains(&dim_bits) {
+                panic!("Cannot generate a ColorThrust module with a tile {} of {} pixels; must be between {} and {} pixels.", dim_name, 1u32 << dim_bits, 1u32 << TILE_DIM_BITS_MIN, 1u32 << TILE_DIM_BITS_MAX);
+            }
+        }
+        let tile_pixels_bits = tile_width_bits + tile_height_bits;
+        let tile_pixels_words_bits = tile_pixels_bits - 2;
+        let hiz_tile_blocks_bits = (tile_width_bits - HIZ_BLOCK_DIM_BITS) + (tile_height_bits - HIZ_BLOCK_DIM_BITS);
+
         let m = p.module(instance_name, "ColorThrust");
 
         let reg_bus_ready = m.output("reg_bus_ready", m.high());
@@ -43,6 +62,7 @@ impl<'a> ColorThrust<'a> {
         }));
         let depth_test_enable = reg_depth_settings.bit(REG_DEPTH_TEST_ENABLE_BIT);
         let depth_write_mask_enable = reg_depth_settings.bit(REG_DEPTH_WRITE_MASK_ENABLE_BIT);
+        let depth_func = reg_depth_settings.bits(REG_DEPTH_FUNC_BIT_OFFSET + REG_DEPTH_FUNC_BITS - 1, REG_DEPTH_FUNC_BIT_OFFSET);
 
         let reg_texture_settings = m.reg("texture_settings", REG_TEXTURE_SETTINGS_BITS);
         reg_texture_settings.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_TEXTURE_SETTINGS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
@@ -60,6 +80,21 @@ impl<'a> ColorThrust<'a> {
             reg_texture_base
         }));
 
+        let reg_texture_env = m.reg("texture_env", REG_TEXTURE_ENV_BITS);
+        reg_texture_env.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_TEXTURE_ENV_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_TEXTURE_ENV_BITS - 1, 0)
+        }).else_({
+            reg_texture_env
+        }));
+        let texture_env_mode = reg_texture_env.bits(REG_TEXTURE_ENV_MODE_BIT_OFFSET + REG_TEXTURE_ENV_MODE_BITS - 1, REG_TEXTURE_ENV_MODE_BIT_OFFSET);
+
+        let reg_texture_env_color = m.reg("texture_env_color", REG_TEXTURE_ENV_COLOR_BITS);
+        reg_texture_env_color.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_TEXTURE_ENV_COLOR_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_TEXTURE_ENV_COLOR_BITS - 1, 0)
+        }).else_({
+            reg_texture_env_color
+        }));
+
         let reg_blend_settings = m.reg("blend_settings", REG_BLEND_SETTINGS_BITS);
         reg_blend_settings.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_BLEND_SETTINGS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
             reg_bus_write_data.bits(REG_BLEND_SETTINGS_BITS - 1, 0)
@@ -68,55 +103,478 @@ impl<'a> ColorThrust<'a> {
         }));
         let blend_src_factor = reg_blend_settings.bits(REG_BLEND_SETTINGS_SRC_FACTOR_BIT_OFFSET + REG_BLEND_SETTINGS_SRC_FACTOR_BITS - 1, REG_BLEND_SETTINGS_SRC_FACTOR_BIT_OFFSET);
         let blend_dst_factor = reg_blend_settings.bits(REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET + REG_BLEND_SETTINGS_DST_FACTOR_BITS - 1, REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET);
+        let blend_equation = reg_blend_settings.bits(REG_BLEND_SETTINGS_EQUATION_BIT_OFFSET + REG_BLEND_SETTINGS_EQUATION_BITS - 1, REG_BLEND_SETTINGS_EQUATION_BIT_OFFSET);
+
+        let reg_blend_color = m.reg("blend_color", REG_BLEND_COLOR_BITS);
+        reg_blend_color.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_BLEND_COLOR_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_BLEND_COLOR_BITS - 1, 0)
+        }).else_({
+            reg_blend_color
+        }));
+
+        let reg_alpha_test_settings = m.reg("alpha_test_settings", REG_ALPHA_TEST_SETTINGS_BITS);
+        reg_alpha_test_settings.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_ALPHA_TEST_SETTINGS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_ALPHA_TEST_SETTINGS_BITS - 1, 0)
+        }).else_({
+            reg_alpha_test_settings
+        }));
+        let alpha_test_enable = reg_alpha_test_settings.bit(REG_ALPHA_TEST_ENABLE_BIT);
+        let alpha_test_func = reg_alpha_test_settings.bits(REG_ALPHA_TEST_FUNC_BIT_OFFSET + REG_ALPHA_TEST_FUNC_BITS - 1, REG_ALPHA_TEST_FUNC_BIT_OFFSET);
+        let alpha_test_ref = reg_alpha_test_settings.bits(REG_ALPHA_TEST_REF_BIT_OFFSET + REG_ALPHA_TEST_REF_BITS - 1, REG_ALPHA_TEST_REF_BIT_OFFSET);
+
+        let reg_stencil_settings = m.reg("stencil_settings", REG_STENCIL_SETTINGS_BITS);
+        reg_stencil_settings.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_STENCIL_SETTINGS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_STENCIL_SETTINGS_BITS - 1, 0)
+        }).else_({
+            reg_stencil_settings
+        }));
+        let stencil_test_enable = reg_stencil_settings.bit(REG_STENCIL_TEST_ENABLE_BIT);
+        let stencil_func = reg_stencil_settings.bits(REG_STENCIL_FUNC_BIT_OFFSET + REG_STENCIL_FUNC_BITS - 1, REG_STENCIL_FUNC_BIT_OFFSET);
+        let stencil_sfail_op = reg_stencil_settings.bits(REG_STENCIL_SFAIL_OP_BIT_OFFSET + REG_STENCIL_OP_BITS - 1, REG_STENCIL_SFAIL_OP_BIT_OFFSET);
+        let stencil_zfail_op = reg_stencil_settings.bits(REG_STENCIL_ZFAIL_OP_BIT_OFFSET + REG_STENCIL_OP_BITS - 1, REG_STENCIL_ZFAIL_OP_BIT_OFFSET);
+        let stencil_zpass_op = reg_stencil_settings.bits(REG_STENCIL_ZPASS_OP_BIT_OFFSET + REG_STENCIL_OP_BITS - 1, REG_STENCIL_ZPASS_OP_BIT_OFFSET);
+
+        let reg_stencil_ref = m.reg("stencil_ref", REG_STENCIL_REF_BITS);
+        reg_stencil_ref.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_STENCIL_REF_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_STENCIL_REF_BITS - 1, 0)
+        }).else_({
+            reg_stencil_ref
+        }));
+        let stencil_ref = reg_stencil_ref.bits(REG_STENCIL_REF_VALUE_BIT_OFFSET + REG_STENCIL_REF_VALUE_BITS - 1, REG_STENCIL_REF_VALUE_BIT_OFFSET);
+        let stencil_mask = reg_stencil_ref.bits(REG_STENCIL_REF_MASK_BIT_OFFSET + REG_STENCIL_REF_MASK_BITS - 1, REG_STENCIL_REF_MASK_BIT_OFFSET);
+        let stencil_write_mask = reg_stencil_ref.bits(REG_STENCIL_REF_WRITE_MASK_BIT_OFFSET + REG_STENCIL_REF_WRITE_MASK_BITS - 1, REG_STENCIL_REF_WRITE_MASK_BIT_OFFSET);
+
+        let reg_fog_settings = m.reg("fog_settings", REG_FOG_SETTINGS_BITS);
+        reg_fog_settings.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_FOG_SETTINGS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_FOG_SETTINGS_BITS - 1, 0)
+        }).else_({
+            reg_fog_settings
+        }));
+        let fog_enable = reg_fog_settings.bit(REG_FOG_ENABLE_BIT);
+        let fog_source = reg_fog_settings.bits(REG_FOG_SOURCE_BIT_OFFSET + REG_FOG_SOURCE_BITS - 1, REG_FOG_SOURCE_BIT_OFFSET);
+        let fog_w_shift = reg_fog_settings.bits(REG_FOG_W_SHIFT_BIT_OFFSET + REG_FOG_W_SHIFT_BITS - 1, REG_FOG_W_SHIFT_BIT_OFFSET);
+
+        let reg_fog_color = m.reg("fog_color", REG_FOG_COLOR_BITS);
+        reg_fog_color.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_FOG_COLOR_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_FOG_COLOR_BITS - 1, 0)
+        }).else_({
+            reg_fog_color
+        }));
+
+        let fog_table_write_enable = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_FOG_TABLE_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+        let fog_table_write_index = reg_bus_write_data.bits(REG_FOG_TABLE_INDEX_BIT_OFFSET + REG_FOG_TABLE_INDEX_BITS - 1, REG_FOG_TABLE_INDEX_BIT_OFFSET);
+        let fog_table_write_value = reg_bus_write_data.bits(REG_FOG_TABLE_VALUE_BIT_OFFSET + REG_FOG_TABLE_VALUE_BITS - 1, REG_FOG_TABLE_VALUE_BIT_OFFSET);
+        // Entries are packed into a single signal with entry 0 in the low bits
+        let fog_table = (0..REG_FOG_TABLE_ENTRIES).fold(None, |acc: Option<&'a dyn Signal<'a>>, x| {
+            let entry = m.reg(format!("fog_table_{}", x), REG_FOG_TABLE_VALUE_BITS);
+            entry.drive_next(if_(fog_table_write_enable & fog_table_write_index.eq(m.lit(x, REG_FOG_TABLE_INDEX_BITS)), {
+                fog_table_write_value
+            }).else_({
+                entry
+            }));
+            Some(if let Some(acc) = acc {
+                entry.concat(acc)
+            } else {
+                entry
+            })
+        }).unwrap();
+
+        let reg_aa_settings = m.reg("aa_settings", REG_AA_SETTINGS_BITS);
+        reg_aa_settings.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_AA_SETTINGS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_AA_SETTINGS_BITS - 1, 0)
+        }).else_({
+            reg_aa_settings
+        }));
+        let aa_enable = reg_aa_settings.bit(REG_AA_ENABLE_BIT);
+
+        let reg_clear_color = m.reg("clear_color", REG_CLEAR_COLOR_BITS);
+        reg_clear_color.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_CLEAR_COLOR_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_CLEAR_COLOR_BITS - 1, 0)
+        }).else_({
+            reg_clear_color
+        }));
+
+        let reg_clear_depth_stencil = m.reg("clear_depth_stencil", REG_CLEAR_DEPTH_STENCIL_BITS);
+        reg_clear_depth_stencil.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_CLEAR_DEPTH_STENCIL_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_CLEAR_DEPTH_STENCIL_BITS - 1, 0)
+        }).else_({
+            reg_clear_depth_stencil
+        }));
+        let clear_depth = reg_clear_depth_stencil.bits(REG_CLEAR_DEPTH_BIT_OFFSET + REG_CLEAR_DEPTH_BITS - 1, REG_CLEAR_DEPTH_BIT_OFFSET);
+        let clear_stencil = reg_clear_depth_stencil.bits(REG_CLEAR_STENCIL_BIT_OFFSET + REG_CLEAR_STENCIL_BITS - 1, REG_CLEAR_STENCIL_BIT_OFFSET);
+
+        let clear_tile = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_CLEAR_TILE_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+
+        // Walk the color buffer's word addresses once; the depth and stencil buffers have fewer words, so they're
+        //  cleared during the first half/quarter of the walk
+        let clear_active = m.reg("clear_active", 1);
+        clear_active.default_value(false);
+        let clear_buffers = m.reg("clear_buffers", REG_CLEAR_TILE_BITS);
+        let clear_addr = m.reg("clear_addr", tile_pixels_words_bits);
+        let clear_addr_last = clear_addr.eq(m.lit((1u32 << tile_pixels_words_bits) - 1, tile_pixels_words_bits));
+
+        let (next_clear_active, next_clear_buffers, next_clear_addr) = if_(clear_tile, {
+            let next_clear_active = m.high();
+            let next_clear_buffers = reg_bus_write_data.bits(REG_CLEAR_TILE_BITS - 1, 0);
+            let next_clear_addr = m.lit(0u32, tile_pixels_words_bits);
+
+            (next_clear_active, next_clear_buffers, next_clear_addr)
+        }).else_if(clear_active, {
+            let next_clear_active = !clear_addr_last;
+            let next_clear_addr = clear_addr + m.lit(1u32, tile_pixels_words_bits);
+
+            (next_clear_active, clear_buffers.into(), next_clear_addr)
+        }).else_({
+            (clear_active.into(), clear_buffers.into(), clear_addr.into())
+        });
+
+        clear_active.drive_next(next_clear_active);
+        clear_buffers.drive_next(next_clear_buffers);
+        clear_addr.drive_next(next_clear_addr);
+
+        let clear_color_write_enable = clear_active & clear_buffers.bit(REG_CLEAR_TILE_COLOR_BIT);
+        let clear_depth_write_enable = clear_active & clear_buffers.bit(REG_CLEAR_TILE_DEPTH_BIT) & !clear_addr.bit(tile_pixels_words_bits - 1);
+        let clear_stencil_write_enable = clear_active & clear_buffers.bit(REG_CLEAR_TILE_STENCIL_BIT) & clear_addr.bits(tile_pixels_words_bits - 1, tile_pixels_words_bits - 2).eq(m.lit(0u32, 2));
+
+        // Multisample resolve
+        //  Each resolved color word holds 4 pixels, whose samples span 2 words in each of 2 sample rows. These are read one
+        //  per cycle and accumulated, and the resolved word is written as the last of them arrives. A resolved word's
+        //  address is never past those of the sample words it's made from, so the resolve can happen in place.
+        let aa_resolve = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_AA_RESOLVE_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+
+        let resolve_active = m.reg("resolve_active", 1);
+        resolve_active.default_value(false);
+        let resolve_counter = m.reg("resolve_counter", tile_pixels_words_bits);
+        let resolve_counter_last = resolve_counter.eq(m.lit((1u32 << tile_pixels_words_bits) - 1, tile_pixels_words_bits));
+
+        resolve_active.drive_next(if_(aa_resolve, {
+            m.high()
+        }).else_if(resolve_active, {
+            !resolve_counter_last
+        }).else_({
+            resolve_active
+        }));
+        resolve_counter.drive_next(if_(aa_resolve, {
+            m.lit(0u32, tile_pixels_words_bits)
+        }).else_if(resolve_active, {
+            resolve_counter + m.lit(1u32, tile_pixels_words_bits)
+        }).else_({
+            resolve_counter
+        }));
+
+        //  The counter's low bits select the sample row and word within the resolved word's 2x2 group of sample words
+        let resolve_word_addr = resolve_counter.bits(tile_pixels_words_bits - 1, 2);
+        let resolve_y = resolve_word_addr.bits(tile_pixels_words_bits - 3, tile_width_bits - 3);
+        let resolve_x = resolve_word_addr.bits(tile_width_bits - 4, 0);
+        let resolve_read_addr = resolve_y.concat(resolve_counter.bit(1)).concat(resolve_x).concat(resolve_counter.bit(0));
+
+        let resolve_read_data_valid = resolve_active.reg_next_with_default("resolve_read_data_valid", false);
+        let resolve_read_phase = resolve_counter.bits(1, 0).reg_next("resolve_read_phase");
+        let resolve_write_addr = m.lit(0u32, 2).concat(resolve_word_addr.reg_next("resolve_write_addr"));
+        let resolve_write_enable = resolve_read_data_valid & resolve_read_phase.eq(m.lit(3u32, 2));
+
+        // RGB565 pack/unpack
+        //  Packing reads 2 color words per packed word, writing it as the second arrives. Unpacking walks the unpacked
+        //  words backwards, reading each packed word once for each of its halves. Either way, no word is written before
+        //  it's been read for the last time, so both can happen in place.
+        let color_pack = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_COLOR_PACK_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+        let color_unpack = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_COLOR_UNPACK_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+
+        let pack_active = m.reg("pack_active", 1);
+        pack_active.default_value(false);
+        let pack_resolved = m.reg("pack_resolved", 1);
+        let pack_counter = m.reg("pack_counter", tile_pixels_words_bits);
+        let pack_counter_last = pack_counter.eq(m.lit((1u32 << tile_pixels_words_bits) - 1, tile_pixels_words_bits));
+
+        pack_active.drive_next(if_(color_pack, {
+            m.high()
+        }).else_if(pack_active, {
+            !pack_counter_last
+        }).else_({
+            pack_active
+        }));
+        pack_resolved.drive_next(if_(color_pack, {
+            reg_bus_write_data.bit(REG_COLOR_PACK_RESOLVED_BIT)
+        }).else_({
+            pack_resolved
+        }));
+        pack_counter.drive_next(if_(color_pack, {
+            m.lit(0u32, tile_pixels_words_bits)
+        }).else_if(pack_active, {
+            pack_counter + m.lit(1u32, tile_pixels_words_bits)
+        }).else_({
+            pack_counter
+        }));
+
+        let pack_read_data_valid = pack_active.reg_next_with_default("pack_read_data_valid", false);
+        let pack_read_addr = pack_counter.reg_next("pack_read_addr");
+        let pack_write_addr = m.lit(0u32, 1).concat(pack_read_addr.bits(tile_pixels_words_bits - 1, 1));
+        let pack_write_enable = pack_read_data_valid & pack_read_addr.bit(0);
+        //  Color words are 4 pixels wide, and rows are a whole number of them
+        let pack_dither_y = if_(pack_resolved, {
+            pack_read_addr.bits(tile_width_bits - 2, tile_width_bits - 3)
+        }).else_({
+            pack_read_addr.bits(tile_width_bits - 1, tile_width_bits - 2)
+        });
+
+        let unpack_active = m.reg("unpack_active", 1);
+        unpack_active.default_value(false);
+        let unpack_counter = m.reg("unpack_counter", tile_pixels_words_bits);
+        let unpack_counter_last = unpack_counter.eq(m.lit(0u32, tile_pixels_words_bits));
+
+        unpack_active.drive_next(if_(color_unpack, {
+            m.high()
+        }).else_if(unpack_active, {
+            !unpack_counter_last
+        }).else_({
+            unpack_active
+        }));
+        unpack_counter.drive_next(if_(color_unpack, {
+            m.lit((1u32 << tile_pixels_words_bits) - 1, tile_pixels_words_bits)
+        }).else_if(unpack_active, {
+            unpack_counter - m.lit(1u32, tile_pixels_words_bits)
+        }).else_({
+            unpack_counter
+        }));
+
+        let unpack_read_addr = m.lit(0u32, 1).concat(unpack_counter.bits(tile_pixels_words_bits - 1, 1));
+        let unpack_write_enable = unpack_active.reg_next_with_default("unpack_write_enable", false);
+        let unpack_write_addr = unpack_counter.reg_next("unpack_write_addr");
+
+        // Texture swizzle/unswizzle
+        //  Reads the color words in order, holding the first of each pair. The first converted word is written as the
+        //  second arrives, and the second converted word is held and written in the following cycle. Only words of the
+        //  pair just read are written, so this can happen in place.
+        let color_swizzle = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_COLOR_SWIZZLE_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+
+        let swizzle_active = m.reg("swizzle_active", 1);
+        swizzle_active.default_value(false);
+        let swizzle_inverse = m.reg("swizzle_inverse", 1);
+        let swizzle_counter = m.reg("swizzle_counter", tile_pixels_words_bits);
+        let swizzle_counter_last = swizzle_counter.eq(m.lit((1u32 << tile_pixels_words_bits) - 1, tile_pixels_words_bits));
+
+        swizzle_active.drive_next(if_(color_swizzle, {
+            m.high()
+        }).else_if(swizzle_active, {
+            !swizzle_counter_last
+        }).else_({
+            swizzle_active
+        }));
+        swizzle_inverse.drive_next(if_(color_swizzle, {
+            reg_bus_write_data.bit(REG_COLOR_SWIZZLE_INVERSE_BIT)
+        }).else_({
+            swizzle_inverse
+        }));
+        swizzle_counter.drive_next(if_(color_swizzle, {
+            m.lit(0u32, tile_pixels_words_bits)
+        }).else_if(swizzle_active, {
+            swizzle_counter + m.lit(1u32, tile_pixels_words_bits)
+        }).else_({
+            swizzle_counter
+        }));
+
+        let swizzle_read_data_valid = swizzle_active.reg_next_with_default("swizzle_read_data_valid", false);
+        let swizzle_read_addr = swizzle_counter.reg_next("swizzle_read_addr");
+        let swizzle_pair_arrived = swizzle_read_data_valid & swizzle_read_addr.bit(0);
+        let swizzle_second_write_enable = swizzle_pair_arrived.reg_next_with_default("swizzle_second_write_enable", false);
+        let swizzle_write_enable = swizzle_pair_arrived | swizzle_second_write_enable;
+        let swizzle_write_addr = if_(swizzle_second_write_enable, {
+            swizzle_read_addr.reg_next("swizzle_second_write_addr")
+        }).else_({
+            swizzle_read_addr.bits(tile_pixels_words_bits - 1, 1).concat(m.low())
+        });
+
+        // Hierarchical z
+        let hiz_enable = reg_depth_settings.bit(REG_DEPTH_HIZ_ENABLE_BIT) & depth_test_enable & !stencil_test_enable;
+
+        let reg_z_bounds = m.reg("z_bounds", REG_Z_BOUNDS_BITS);
+        reg_z_bounds.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_Z_BOUNDS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_Z_BOUNDS_BITS - 1, 0)
+        }).else_({
+            reg_z_bounds
+        }));
+        let z_bounds_mirror = m.reg("z_bounds_mirror", REG_Z_BOUNDS_BITS);
+
+        let occlusion_query_begin = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_OCCLUSION_QUERY_BEGIN_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+        let occlusion_query_end = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_OCCLUSION_QUERY_END_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+        let occlusion_query_active = m.reg("occlusion_query_active", 1);
+        occlusion_query_active.default_value(false);
+        occlusion_query_active.drive_next(if_(occlusion_query_begin, {
+            m.high()
+        }).else_if(occlusion_query_end, {
+            m.low()
+        }).else_({
+            occlusion_query_active
+        }));
+
+        let depth_bounds_reset = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_DEPTH_BOUNDS_RESET_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+
+        let hiz_block_bounds = (0..1u32 << hiz_tile_blocks_bits).map(|x| {
+            (m.reg(format!("hiz_block_{}_min", x), 16), m.reg(format!("hiz_block_{}_max", x), 16))
+        }).collect::<Vec<_>>();
+
+        // A primitive can only pass the depth test somewhere in a block if one of the relations enabled in depth_func
+        //  is possible between the primitive's z range and the block's depth bounds
+        let hiz_block_reject = |z_bounds: &'a dyn Signal<'a>, block_min: &'a dyn Signal<'a>, block_max: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            let z_min = z_bounds.bits(REG_Z_BOUNDS_MIN_BIT_OFFSET + REG_Z_BOUNDS_MIN_BITS - 1, REG_Z_BOUNDS_MIN_BIT_OFFSET);
+            let z_max = z_bounds.bits(REG_Z_BOUNDS_MAX_BIT_OFFSET + REG_Z_BOUNDS_MAX_BITS - 1, REG_Z_BOUNDS_MAX_BIT_OFFSET);
+            let less_possible = z_min.lt(block_max);
+            let equal_possible = !block_max.lt(z_min) & !z_max.lt(block_min);
+            let greater_possible = block_min.lt(z_max);
+            !((depth_func.bit(0) & less_possible) | (depth_func.bit(1) & equal_possible) | (depth_func.bit(2) & greater_possible))
+        };
 
         let input_generator_active = m.reg("input_generator_active", 1);
         input_generator_active.default_value(false);
 
-        let tile_x = m.reg("tile_x", TILE_DIM_BITS);
-        let tile_y = m.reg("tile_y", TILE_DIM_BITS);
-        let tile_x_last = tile_x.eq(m.lit(TILE_DIM - 1, TILE_DIM_BITS));
-        let tile_y_last = tile_y.eq(m.lit(TILE_DIM - 1, TILE_DIM_BITS));
+        let tile_x = m.reg("tile_x", tile_width_bits);
+        let tile_y = m.reg("tile_y", tile_height_bits);
+        let tile_x_last = tile_x.eq(m.lit((1u32 << tile_width_bits) - 1, tile_width_bits));
+        let tile_y_last = tile_y.eq(m.lit((1u32 << tile_height_bits) - 1, tile_height_bits));
 
         let start = reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_START_ADDR, REG_BUS_ADDR_BIT_WIDTH));
 
-        let pixel_pipe = PixelPipe::new("pixel_pipe", m);
+        z_bounds_mirror.drive_next(if_(start, {
+            reg_z_bounds
+        }).else_({
+            z_bounds_mirror
+        }));
 
-        pixel_pipe.start.drive(start);
+        //  Reject the whole primitive up front if it can't pass in any block
+        let hiz_primitive_reject = hiz_enable & hiz_block_bounds.iter().fold(m.high(), |acc, &(block_min, block_max)| {
+            acc & hiz_block_reject(reg_z_bounds, block_min, block_max)
+        });
+        let start_rasterization = start & !hiz_primitive_reject;
 
-        pixel_pipe.depth_test_enable.drive(depth_test_enable);
-        pixel_pipe.depth_write_mask_enable.drive(depth_write_mask_enable);
+        //  Otherwise, skip a block's row of pixels when the input generator enters it
+        let hiz_block_index = tile_y.bits(tile_height_bits - 1, HIZ_BLOCK_DIM_BITS).concat(tile_x.bits(tile_width_bits - 1, HIZ_BLOCK_DIM_BITS));
+        let hiz_block_index_reject = hiz_block_bounds.iter().enumerate().fold(m.low(), |acc, (x, &(block_min, block_max))| {
+            if_(hiz_block_index.eq(m.lit(x as u32, hiz_tile_blocks_bits)), {
+                hiz_block_reject(z_bounds_mirror, block_min, block_max)
+            }).else_({
+                acc
+            })
+        });
+        let hiz_block_skip =
+            input_generator_active &
+            hiz_enable &
+            tile_x.bits(HIZ_BLOCK_DIM_BITS - 1, 0).eq(m.lit(0u32, HIZ_BLOCK_DIM_BITS)) &
+            hiz_block_index_reject;
+
+        let pixel_pipes = (0..num_pixel_pipes).map(|i| {
+            PixelPipe::new(format!("pixel_pipe{}", i), num_pixel_pipes, tile_pixels_bits, m)
+        }).collect::<Vec<_>>();
+
+        // The input generator issues a group of horizontally adjacent pixels at a time, one to each pixel pipe. Pipes
+        //  stall independently, so each lane tracks whether its pipe has already taken its pixel from the current group.
+        let lane_accepted = (0..num_pixel_pipes).map(|i| {
+            let lane_accepted = m.reg(format!("lane{}_accepted", i), 1);
+            lane_accepted.default_value(false);
+            lane_accepted
+        }).collect::<Vec<_>>();
+        let lanes_ready = pixel_pipes.iter().zip(lane_accepted.iter()).fold(m.high(), |acc, (pixel_pipe, &lane_accepted)| {
+            acc & (lane_accepted | pixel_pipe.in_ready)
+        });
 
-        pixel_pipe.tex_filter_select.drive(tex_filter_select);
-        pixel_pipe.tex_dim.drive(tex_dim);
-        pixel_pipe.tex_base.drive(reg_texture_base);
+        // Advance by a group of pixels when every pixel pipe has accepted its pixel, or by a whole block row when it's skipped
+        let input_step = hiz_block_skip | lanes_ready;
+        let input_step_x = if_(hiz_block_skip, {
+            m.lit(HIZ_BLOCK_DIM, tile_width_bits)
+        }).else_({
+            m.lit(num_pixel_pipes, tile_width_bits)
+        });
+        let row_last = |step_bits: u32| -> &'a dyn Signal<'a> {
+            if step_bits == 0 {
+                tile_x_last
+            } else {
+                tile_x.bits(tile_width_bits - 1, step_bits).eq(m.lit((1u32 << (tile_width_bits - step_bits)) - 1, tile_width_bits - step_bits))
+            }
+        };
+        let input_step_row_last = if_(hiz_block_skip, {
+            row_last(HIZ_BLOCK_DIM_BITS)
+        }).else_({
+            row_last(pixel_pipes_bits)
+        });
 
-        pixel_pipe.blend_src_factor.drive(blend_src_factor);
-        pixel_pipe.blend_dst_factor.drive(blend_dst_factor);
+        for (lane, (pixel_pipe, &lane_accepted)) in pixel_pipes.iter().zip(lane_accepted.iter()).enumerate() {
+            pixel_pipe.start.drive(start_rasterization);
+            pixel_pipe.hiz_block_skip.drive(hiz_block_skip);
+
+            pixel_pipe.depth_test_enable.drive(depth_test_enable);
+            pixel_pipe.depth_func.drive(depth_func);
+            pixel_pipe.depth_write_mask_enable.drive(depth_write_mask_enable);
+
+            pixel_pipe.stencil_test_enable.drive(stencil_test_enable);
+            pixel_pipe.stencil_func.drive(stencil_func);
+            pixel_pipe.stencil_sfail_op.drive(stencil_sfail_op);
+            pixel_pipe.stencil_zfail_op.drive(stencil_zfail_op);
+            pixel_pipe.stencil_zpass_op.drive(stencil_zpass_op);
+            pixel_pipe.stencil_ref.drive(stencil_ref);
+            pixel_pipe.stencil_mask.drive(stencil_mask);
+            pixel_pipe.stencil_write_mask.drive(stencil_write_mask);
+
+            pixel_pipe.occlusion_query_begin.drive(occlusion_query_begin);
+            pixel_pipe.occlusion_query_active.drive(occlusion_query_active);
+
+            pixel_pipe.tex_filter_select.drive(tex_filter_select);
+            pixel_pipe.tex_dim.drive(tex_dim);
+            pixel_pipe.tex_base.drive(reg_texture_base);
+
+            pixel_pipe.tex_cache_invalidate.drive(tex_cache_invalidate);
+
+            pixel_pipe.texture_env_mode.drive(texture_env_mode);
+            pixel_pipe.texture_env_color.drive(reg_texture_env_color);
+
+            pixel_pipe.blend_src_factor.drive(blend_src_factor);
+            pixel_pipe.blend_dst_factor.drive(blend_dst_factor);
+            pixel_pipe.blend_equation.drive(blend_equation);
+            pixel_pipe.blend_color.drive(reg_blend_color);
+
+            pixel_pipe.alpha_test_enable.drive(alpha_test_enable);
+            pixel_pipe.alpha_test_func.drive(alpha_test_func);
+            pixel_pipe.alpha_test_ref.drive(alpha_test_ref);
+            pixel_pipe.fog_enable.drive(fog_enable);
+            pixel_pipe.fog_source.drive(fog_source);
+            pixel_pipe.fog_w_shift.drive(fog_w_shift);
+            pixel_pipe.fog_color.drive(reg_fog_color);
+            pixel_pipe.fog_table.drive(fog_table);
+
+            let in_valid = input_generator_active & !hiz_block_skip & !lane_accepted;
+            pixel_pipe.in_valid.drive(in_valid);
+            pixel_pipe.in_tile_addr.drive(if pixel_pipes_bits == 0 {
+                tile_y.concat(tile_x)
+            } else {
+                tile_y.concat(tile_x.bits(tile_width_bits - 1, pixel_pipes_bits)).concat(m.lit(lane as u32, pixel_pipes_bits))
+            });
 
-        pixel_pipe.in_valid.drive(input_generator_active);
-        pixel_pipe.in_tile_addr.drive(tile_y.concat(tile_x));
+            lane_accepted.drive_next(if_(start_rasterization | input_step, {
+                m.low()
+            }).else_({
+                lane_accepted | (in_valid & pixel_pipe.in_ready)
+            }));
+        }
 
-        let (next_input_generator_active, next_tile_x, next_tile_y) = if_(start, {
+        let (next_input_generator_active, next_tile_x, next_tile_y) = if_(start_rasterization, {
             let next_input_generator_active = m.high();
 
-            let next_tile_x = m.lit(0u32, TILE_DIM_BITS);
-            let next_tile_y = m.lit(0u32, TILE_DIM_BITS);
+            let next_tile_x = m.lit(0u32, tile_width_bits);
+            let next_tile_y = m.lit(0u32, tile_height_bits);
 
             (next_input_generator_active, next_tile_x, next_tile_y)
-        }).else_if(pixel_pipe.in_ready, {
+        }).else_if(input_step, {
             let next_input_generator_active = input_generator_active;
 
-            let next_tile_x = tile_x + m.lit(1u32, TILE_DIM_BITS);
+            let next_tile_x = tile_x + input_step_x;
             let next_tile_y = tile_y;
 
-            let (next_input_generator_active, next_tile_y) = if_(tile_x_last, {
+            let (next_input_generator_active, next_tile_y) = if_(input_step_row_last, {
                 let next_input_generator_active = if_(tile_y_last, {
                     m.low()
                 }).else_({
                     next_input_generator_active
                 });
 
-                let next_tile_y = tile_y + m.lit(1u32, TILE_DIM_BITS);
+                let next_tile_y = tile_y + m.lit(1u32, tile_height_bits);
 
                 (next_input_generator_active, next_tile_y)
             }).else_({
@@ -133,7 +591,17 @@ impl<'a> ColorThrust<'a> {
         tile_x.drive_next(next_tile_x);
         tile_y.drive_next(next_tile_y);
 
-        let interpolant = |name: &str, num_bits, min_addr: u32, dx_addr: u32, dy_addr: u32| {
+        let shl = |x: &'a dyn Signal<'a>, shift: u32| -> &'a dyn Signal<'a> {
+            if shift == 0 {
+                x
+            } else {
+                x.bits(x.bit_width() - 1 - shift, 0).concat(m.lit(0u32, shift))
+            }
+        };
+
+        // With multisampling, edge functions and z are evaluated per sample, but the rest of the interpolants are
+        //  evaluated once per pixel, so they only step between 2x2 sample groups
+        let interpolant = |name: &str, num_bits, min_addr: u32, dx_addr: u32, dy_addr: u32, per_sample: bool| {
             let min = m.reg(format!("{}_min", name), num_bits);
             min.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(min_addr, REG_BUS_ADDR_BIT_WIDTH)), {
                 reg_bus_write_data.bits(num_bits - 1, 0)
@@ -169,14 +637,51 @@ impl<'a> ColorThrust<'a> {
 
             let value = m.reg(name, num_bits);
 
+            let (dx_step, dy_step, skip_step): (&'a dyn Signal<'a>, &'a dyn Signal<'a>, &'a dyn Signal<'a>) = if per_sample {
+                (shl(dx_mirror, pixel_pipes_bits), dy_mirror, shl(dx_mirror, HIZ_BLOCK_DIM_BITS))
+            } else {
+                let zero = m.lit(0u32, num_bits);
+                let aa_dx_step = if pixel_pipes_bits == 0 {
+                    if_(tile_x.bit(0), {
+                        dx_mirror
+                    }).else_({
+                        zero
+                    })
+                } else {
+                    shl(dx_mirror, pixel_pipes_bits - 1)
+                };
+                (
+                    if_(aa_enable, {
+                        aa_dx_step
+                    }).else_({
+                        shl(dx_mirror, pixel_pipes_bits)
+                    }),
+                    if_(aa_enable & !tile_y.bit(0), {
+                        zero
+                    }).else_({
+                        dy_mirror
+                    }),
+                    if_(aa_enable, {
+                        shl(dx_mirror, HIZ_BLOCK_DIM_BITS - 1)
+                    }).else_({
+                        shl(dx_mirror, HIZ_BLOCK_DIM_BITS)
+                    }),
+                )
+            };
+
             let (next_row, next_value) = if_(start, {
                 (min.into(), min.into())
-            }).else_if(pixel_pipe.in_ready, {
-                if_(tile_x_last, {
-                    let next = row + dy_mirror;
+            }).else_if(input_step, {
+                if_(input_step_row_last, {
+                    let next = row + dy_step;
                     (next, next)
                 }).else_({
-                    (row, value + dx_mirror)
+                    let step = if_(hiz_block_skip, {
+                        skip_step
+                    }).else_({
+                        dx_step
+                    });
+                    (row, value + step)
                 })
             }).else_({
                 (row, value)
@@ -186,42 +691,99 @@ impl<'a> ColorThrust<'a> {
 
             value.drive_next(next_value);
 
-            value
+            // Each lane's pixel is offset from the group's first pixel by lane * dx (or by its pixel's offset with multisampling)
+            let lane_value = |offset: u32| {
+                (0..pixel_pipes_bits).filter(|bit| (offset & (1 << bit)) != 0).fold(value.into(), |acc: &'a dyn Signal<'a>, bit| {
+                    acc + shl(dx_mirror, bit)
+                })
+            };
+            (0..num_pixel_pipes).map(|lane| {
+                if per_sample {
+                    lane_value(lane)
+                } else {
+                    if_(aa_enable, {
+                        lane_value(lane >> 1)
+                    }).else_({
+                        lane_value(lane)
+                    })
+                }
+            }).collect::<Vec<_>>()
         };
 
-        let w0 = interpolant("w0", 32, REG_W0_MIN_ADDR, REG_W0_DX_ADDR, REG_W0_DY_ADDR).bit(31);
-        let w1 = interpolant("w1", 32, REG_W1_MIN_ADDR, REG_W1_DX_ADDR, REG_W1_DY_ADDR).bit(31);
-        let w2 = interpolant("w2", 32, REG_W2_MIN_ADDR, REG_W2_DX_ADDR, REG_W2_DY_ADDR).bit(31);
+        let w0 = interpolant("w0", 32, REG_W0_MIN_ADDR, REG_W0_DX_ADDR, REG_W0_DY_ADDR, true);
+        let w1 = interpolant("w1", 32, REG_W1_MIN_ADDR, REG_W1_DX_ADDR, REG_W1_DY_ADDR, true);
+        let w2 = interpolant("w2", 32, REG_W2_MIN_ADDR, REG_W2_DX_ADDR, REG_W2_DY_ADDR, true);
+
+        let r = interpolant("r", 24, REG_R_MIN_ADDR, REG_R_DX_ADDR, REG_R_DY_ADDR, false);
+        let g = interpolant("g", 24, REG_G_MIN_ADDR, REG_G_DX_ADDR, REG_G_DY_ADDR, false);
+        let b = interpolant("b", 24, REG_B_MIN_ADDR, REG_B_DX_ADDR, REG_B_DY_ADDR, false);
+        let a = interpolant("a", 24, REG_A_MIN_ADDR, REG_A_DX_ADDR, REG_A_DY_ADDR, false);
 
-        let r = interpolant("r", 24, REG_R_MIN_ADDR, REG_R_DX_ADDR, REG_R_DY_ADDR).bits(COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 1, COLOR_FRACT_BITS);
-        let g = interpolant("g", 24, REG_G_MIN_ADDR, REG_G_DX_ADDR, REG_G_DY_ADDR).bits(COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 1, COLOR_FRACT_BITS);
-        let b = interpolant("b", 24, REG_B_MIN_ADDR, REG_B_DX_ADDR, REG_B_DY_ADDR).bits(COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 1, COLOR_FRACT_BITS);
-        let a = interpolant("a", 24, REG_A_MIN_ADDR, REG_A_DX_ADDR, REG_A_DY_ADDR).bits(COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 1, COLOR_FRACT_BITS);
+        let w_inverse = interpolant("w_inverse", 32, REG_W_INVERSE_MIN_ADDR, REG_W_INVERSE_DX_ADDR, REG_W_INVERSE_DY_ADDR, false);
 
-        let w_inverse = interpolant("w_inverse", 32, REG_W_INVERSE_MIN_ADDR, REG_W_INVERSE_DX_ADDR, REG_W_INVERSE_DY_ADDR);
+        let z = interpolant("z", 32, REG_Z_MIN_ADDR, REG_Z_DX_ADDR, REG_Z_DY_ADDR, true);
 
-        let z = interpolant("z", 32, REG_Z_MIN_ADDR, REG_Z_DX_ADDR, REG_Z_DY_ADDR).bits(31, 16);
+        let s = interpolant("s", 32, REG_S_MIN_ADDR, REG_S_DX_ADDR, REG_S_DY_ADDR, false);
+        let t = interpolant("t", 32, REG_T_MIN_ADDR, REG_T_DX_ADDR, REG_T_DY_ADDR, false);
 
-        let s = interpolant("s", 32, REG_S_MIN_ADDR, REG_S_DX_ADDR, REG_S_DY_ADDR).bits(31, RESTORED_W_FRACT_BITS);
-        let t = interpolant("t", 32, REG_T_MIN_ADDR, REG_T_DX_ADDR, REG_T_DY_ADDR).bits(31, RESTORED_W_FRACT_BITS);
+        for (lane, pixel_pipe) in pixel_pipes.iter().enumerate() {
+            pixel_pipe.in_w0.drive(w0[lane].bit(31));
+            pixel_pipe.in_w1.drive(w1[lane].bit(31));
+            pixel_pipe.in_w2.drive(w2[lane].bit(31));
 
-        pixel_pipe.in_w0.drive(w0);
-        pixel_pipe.in_w1.drive(w1);
-        pixel_pipe.in_w2.drive(w2);
+            pixel_pipe.in_r.drive(r[lane].bits(COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 1, COLOR_FRACT_BITS));
+            pixel_pipe.in_g.drive(g[lane].bits(COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 1, COLOR_FRACT_BITS));
+            pixel_pipe.in_b.drive(b[lane].bits(COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 1, COLOR_FRACT_BITS));
+            pixel_pipe.in_a.drive(a[lane].bits(COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 1, COLOR_FRACT_BITS));
 
-        pixel_pipe.in_r.drive(r);
-        pixel_pipe.in_g.drive(g);
-        pixel_pipe.in_b.drive(b);
-        pixel_pipe.in_a.drive(a);
+            pixel_pipe.in_w_inverse.drive(w_inverse[lane]);
 
-        pixel_pipe.in_w_inverse.drive(w_inverse);
+            pixel_pipe.in_z.drive(z[lane].bits(31, 16));
 
-        pixel_pipe.in_z.drive(z);
+            pixel_pipe.in_s.drive(s[lane].bits(31, RESTORED_W_FRACT_BITS));
+            pixel_pipe.in_t.drive(t[lane].bits(31, RESTORED_W_FRACT_BITS));
+        }
 
-        pixel_pipe.in_s.drive(s);
-        pixel_pipe.in_t.drive(t);
+        let hiz_rejected_primitives = m.reg("hiz_rejected_primitives", 32);
+        hiz_rejected_primitives.default_value(0u32);
+        hiz_rejected_primitives.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_HIZ_REJECTED_PRIMITIVES_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            m.lit(0u32, 32)
+        }).else_if(start & hiz_primitive_reject, {
+            hiz_rejected_primitives + m.lit(1u32, 32)
+        }).else_({
+            hiz_rejected_primitives
+        }));
 
-        let reg_bus_read_data = m.output("reg_bus_read_data", m.lit(0u32, 127).concat(input_generator_active | pixel_pipe.active));
+        let hiz_rejected_blocks = m.reg("hiz_rejected_blocks", 32);
+        hiz_rejected_blocks.default_value(0u32);
+        hiz_rejected_blocks.drive_next(if_(reg_bus_write_enable & truncated_reg_bus_addr.eq(m.lit(REG_HIZ_REJECTED_BLOCKS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            m.lit(0u32, 32)
+        }).else_if(hiz_block_skip & tile_y.bits(HIZ_BLOCK_DIM_BITS - 1, 0).eq(m.lit(0u32, HIZ_BLOCK_DIM_BITS)), {
+            // Count each block once, on its first row
+            hiz_rejected_blocks + m.lit(1u32, 32)
+        }).else_({
+            hiz_rejected_blocks
+        }));
+
+        let pixel_pipes_active = pixel_pipes.iter().fold(m.low(), |acc, pixel_pipe| acc | pixel_pipe.active);
+        let occlusion_query_passed_samples = pixel_pipes.iter().skip(1).fold(pixel_pipes[0].occlusion_query_passed_samples as &'a dyn Signal<'a>, |acc, pixel_pipe| acc + pixel_pipe.occlusion_query_passed_samples);
+
+        let reg_bus_read_addr = truncated_reg_bus_addr.reg_next("reg_bus_read_addr");
+        let caps =
+            m.lit(pixel_pipes_bits, REG_CAPS_PIXEL_PIPES_LOG2_BITS)
+            .concat(m.lit(tile_height_bits, REG_CAPS_TILE_HEIGHT_LOG2_BITS))
+            .concat(m.lit(tile_width_bits, REG_CAPS_TILE_WIDTH_LOG2_BITS));
+        let reg_bus_read_data = m.output("reg_bus_read_data", if_(reg_bus_read_addr.eq(m.lit(REG_CAPS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            m.lit(0u32, 128 - REG_CAPS_BITS).concat(caps)
+        }).else_if(reg_bus_read_addr.eq(m.lit(REG_HIZ_REJECTED_PRIMITIVES_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            m.lit(0u32, 96).concat(hiz_rejected_primitives)
+        }).else_if(reg_bus_read_addr.eq(m.lit(REG_HIZ_REJECTED_BLOCKS_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            m.lit(0u32, 96).concat(hiz_rejected_blocks)
+        }).else_if(reg_bus_read_addr.eq(m.lit(REG_OCCLUSION_QUERY_PASSED_SAMPLES_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            m.lit(0u32, 96).concat(occlusion_query_passed_samples)
+        }).else_({
+            m.lit(0u32, 127).concat(input_generator_active | pixel_pipes_active | clear_active | resolve_active | resolve_read_data_valid | pack_active | pack_read_data_valid | unpack_active | unpack_write_enable | swizzle_active | swizzle_read_data_valid | swizzle_second_write_enable)
+        }));
         let reg_bus_read_data_valid = m.output("reg_bus_read_data_valid", (reg_bus_enable & !reg_bus_write).reg_next_with_default("reg_bus_read_data_valid", false));
 
         let color_buffer_bus_ready = m.output("color_buffer_bus_ready", m.high());
@@ -239,40 +801,220 @@ impl<'a> ColorThrust<'a> {
             })
         }).unwrap();
 
-        let color_buffer = WordMem::new(m, "color_buffer", TILE_PIXELS_WORDS_BITS, 32, 4);
+        // Each pixel pipe owns a bank of every tile buffer; bank i holds the pixels whose x coordinate mod num_pixel_pipes is i
         let color_buffer_bus_write_enable = color_buffer_bus_enable & color_buffer_bus_write;
-        color_buffer.write_port(
-            if_(color_buffer_bus_write_enable, {
-                color_buffer_bus_addr.bits(TILE_PIXELS_WORDS_BITS - 1, 0)
-            }).else_({
-                pixel_pipe.color_buffer_write_port_addr
-            }),
-            if_(color_buffer_bus_write_enable, {
-                color_buffer_bus_write_data
-            }).else_({
-                pixel_pipe.color_buffer_write_port_value
-            }),
-            color_buffer_bus_write_enable | pixel_pipe.color_buffer_write_port_enable,
-            if_(color_buffer_bus_write_enable, {
-                color_buffer_bus_write_word_enable
-            }).else_({
-                pixel_pipe.color_buffer_write_port_word_enable
-            }));
-
         let color_buffer_bus_read_enable = color_buffer_bus_enable & !color_buffer_bus_write;
-        let color_buffer_read_port_value = color_buffer.read_port(
-            if_(color_buffer_bus_read_enable, {
-                color_buffer_bus_addr.bits(TILE_PIXELS_WORDS_BITS - 1, 0)
-            }).else_({
-                pixel_pipe.color_buffer_read_port_addr
-            }),
-            color_buffer_bus_read_enable | pixel_pipe.color_buffer_read_port_enable);
+        //  The swizzled window only reorders the word address bits, from (block y, block x, row / 2, word) to
+        //  (row / 2, block y, word, block x)
+        let color_buffer_bus_word_addr = color_buffer_bus_addr.bits(tile_pixels_words_bits - 1, 0);
+        let color_buffer_bus_tile_addr = if_(color_buffer_bus_addr.bit(COLOR_BUFFER_BUS_SWIZZLED_WINDOW_BIT), {
+            let word = color_buffer_bus_word_addr.bits(tile_width_bits - 4, 0);
+            let row = color_buffer_bus_word_addr.bits(tile_pixels_words_bits - 3, tile_width_bits - 3);
+            let block_x = color_buffer_bus_word_addr.bit(tile_pixels_words_bits - 2);
+            let block_y = color_buffer_bus_word_addr.bit(tile_pixels_words_bits - 1);
+            row.concat(block_y).concat(word).concat(block_x)
+        }).else_({
+            color_buffer_bus_word_addr
+        });
+        let color_buffer_banks = pixel_pipes.iter().enumerate().map(|(bank, pixel_pipe)| {
+            let bank = bank as u32;
+            let bank_elements = 4 / num_pixel_pipes;
+            let color_buffer = WordMem::new(m, format!("color_buffer_bank{}", bank), tile_pixels_words_bits, 32, bank_elements);
+
+            let color_buffer_read_port_value = color_buffer.read_port(
+                if_(color_buffer_bus_read_enable, {
+                    color_buffer_bus_tile_addr
+                }).else_if(resolve_active, {
+                    resolve_read_addr
+                }).else_if(pack_active, {
+                    pack_counter
+                }).else_if(unpack_active, {
+                    unpack_read_addr
+                }).else_if(swizzle_active, {
+                    swizzle_counter
+                }).else_({
+                    pixel_pipe.color_buffer_read_port_addr
+                }),
+                color_buffer_bus_read_enable | resolve_active | pack_active | unpack_active | swizzle_active | pixel_pipe.color_buffer_read_port_enable);
 
-        pixel_pipe.color_buffer_read_port_value.drive(color_buffer_read_port_value);
+            pixel_pipe.color_buffer_read_port_value.drive(unbank_word(color_buffer_read_port_value, 32, 4, num_pixel_pipes, bank, m));
 
-        let color_buffer_bus_read_data = m.output("color_buffer_bus_read_data", color_buffer_read_port_value);
+            (color_buffer, color_buffer_read_port_value)
+        }).collect::<Vec<_>>();
+
+        let color_buffer_read_data = unbank_words(&color_buffer_banks.iter().map(|&(_, value)| value).collect::<Vec<_>>(), 32, 4);
+        let color_buffer_bus_read_data = m.output("color_buffer_bus_read_data", color_buffer_read_data);
         let color_buffer_bus_read_data_valid = m.output("color_buffer_bus_read_data_valid", color_buffer_bus_read_enable.reg_next_with_default("color_buffer_bus_read_data_valid", false));
 
+        //  Resolved pixel x's samples are sample pair x & 1 in the sample words for half x >> 1 of the resolved word
+        let resolve_write_value = (0..4).fold(None, |acc: Option<&'a dyn Signal<'a>>, x| {
+            let half_arrived = resolve_read_data_valid & if x >> 1 == 0 { !resolve_read_phase.bit(0) } else { resolve_read_phase.bit(0) };
+            let pair = x & 1;
+            let pixel = (0..4).fold(None, |acc: Option<&'a dyn Signal<'a>>, channel| {
+                let sample = |sample_x: u32| {
+                    let lsb = sample_x * 32 + channel * 8;
+                    m.lit(0u32, 2).concat(color_buffer_read_data.bits(lsb + 7, lsb))
+                };
+                let pair_sum = sample(pair * 2) + sample(pair * 2 + 1);
+
+                let sum = m.reg(format!("resolve_sum_{}_{}", x, channel), 10);
+                sum.drive_next(if_(half_arrived, {
+                    if_(resolve_read_phase.bit(1), {
+                        sum + pair_sum
+                    }).else_({
+                        pair_sum
+                    })
+                }).else_({
+                    sum
+                }));
+                // The second half's last pair arrives in the same cycle the resolved word is written, so it bypasses the sum
+                let total = if x >> 1 == 0 { sum.into() } else { sum + pair_sum };
+                let channel_value = (total + m.lit(2u32, 10)).bits(9, 2);
+
+                Some(if let Some(acc) = acc {
+                    channel_value.concat(acc)
+                } else {
+                    channel_value
+                })
+            }).unwrap();
+
+            Some(if let Some(acc) = acc {
+                pixel.concat(acc)
+            } else {
+                pixel
+            })
+        }).unwrap();
+
+        let concat_all = |values: Vec<&'a dyn Signal<'a>>| -> &'a dyn Signal<'a> {
+            values.into_iter().fold(None, |acc: Option<&'a dyn Signal<'a>>, value| {
+                Some(if let Some(acc) = acc {
+                    value.concat(acc)
+                } else {
+                    value
+                })
+            }).unwrap()
+        };
+
+        //  The low half of each packed word is held until the high half arrives
+        let packed_half = concat_all((0..4).map(|x| {
+            let pixel = color_buffer_read_data.bits(x * 32 + 31, x * 32);
+            let threshold = if_(pack_dither_y.eq(m.lit(0u32, 2)), {
+                m.lit(COLOR_PACK_DITHER_MATRIX[0][x as usize], 4)
+            }).else_if(pack_dither_y.eq(m.lit(1u32, 2)), {
+                m.lit(COLOR_PACK_DITHER_MATRIX[1][x as usize], 4)
+            }).else_if(pack_dither_y.eq(m.lit(2u32, 2)), {
+                m.lit(COLOR_PACK_DITHER_MATRIX[2][x as usize], 4)
+            }).else_({
+                m.lit(COLOR_PACK_DITHER_MATRIX[3][x as usize], 4)
+            });
+            let dither = |lsb: u32, bits: u32| -> &'a dyn Signal<'a> {
+                let truncated_bits = 8 - bits;
+                let sum = m.lit(0u32, 1).concat(pixel.bits(lsb + 7, lsb)) + m.lit(0u32, 9 - truncated_bits).concat(threshold.bits(3, 4 - truncated_bits));
+                if_(sum.bit(8), {
+                    m.lit((1u32 << bits) - 1, bits)
+                }).else_({
+                    sum.bits(7, truncated_bits)
+                })
+            };
+            dither(16, 5).concat(dither(8, 6)).concat(dither(0, 5))
+        }).collect());
+        let pack_low_half = m.reg("pack_low_half", 64);
+        pack_low_half.drive_next(if_(pack_read_data_valid & !pack_read_addr.bit(0), {
+            packed_half
+        }).else_({
+            pack_low_half
+        }));
+        let pack_write_value = packed_half.concat(pack_low_half);
+
+        let unpack_half = if_(unpack_write_addr.bit(0), {
+            color_buffer_read_data.bits(127, 64)
+        }).else_({
+            color_buffer_read_data.bits(63, 0)
+        });
+        let unpack_write_value = concat_all((0..4).map(|x| {
+            let pixel = unpack_half.bits(x * 16 + 15, x * 16);
+            let r = pixel.bits(15, 11);
+            let g = pixel.bits(10, 5);
+            let b = pixel.bits(4, 0);
+            m.lit(0xffu32, 8)
+                .concat(r).concat(r.bits(4, 2))
+                .concat(g).concat(g.bits(5, 4))
+                .concat(b).concat(b.bits(4, 2))
+        }).collect());
+
+        //  Forward, converted word w holds pixels w and w + 2 of each word of the pair; the inverse interleaves the
+        //  converted words' pixels back into place
+        let swizzle_first = m.reg("swizzle_first", 128);
+        swizzle_first.drive_next(if_(swizzle_read_data_valid & !swizzle_read_addr.bit(0), {
+            color_buffer_read_data
+        }).else_({
+            swizzle_first
+        }));
+        let swizzle_pair: [&'a dyn Signal<'a>; 2] = [swizzle_first.into(), color_buffer_read_data];
+        let swizzle_words = (0..2).map(|w| concat_all((0..4).map(|x| {
+            let pixel = |word: u32, index: u32| swizzle_pair[word as usize].bits(index * 32 + 31, index * 32);
+            if_(swizzle_inverse, {
+                pixel(x & 1, w * 2 + (x >> 1))
+            }).else_({
+                pixel(x >> 1, (x & 1) * 2 + w)
+            })
+        }).collect())).collect::<Vec<_>>();
+        let swizzle_second = m.reg("swizzle_second", 128);
+        swizzle_second.drive_next(if_(swizzle_pair_arrived, {
+            swizzle_words[1]
+        }).else_({
+            swizzle_second
+        }));
+        let swizzle_write_value = if_(swizzle_pair_arrived, {
+            swizzle_words[0]
+        }).else_({
+            swizzle_second
+        });
+
+        for (bank, (pixel_pipe, (color_buffer, _))) in pixel_pipes.iter().zip(color_buffer_banks.iter()).enumerate() {
+            let bank = bank as u32;
+            let bank_elements = 4 / num_pixel_pipes;
+            color_buffer.write_port(
+                if_(color_buffer_bus_write_enable, {
+                    color_buffer_bus_tile_addr
+                }).else_if(clear_color_write_enable, {
+                    clear_addr.bits(tile_pixels_words_bits - 1, 0)
+                }).else_if(resolve_write_enable, {
+                    resolve_write_addr
+                }).else_if(pack_write_enable, {
+                    pack_write_addr
+                }).else_if(unpack_write_enable, {
+                    unpack_write_addr
+                }).else_if(swizzle_write_enable, {
+                    swizzle_write_addr
+                }).else_({
+                    pixel_pipe.color_buffer_write_port_addr
+                }),
+                if_(color_buffer_bus_write_enable, {
+                    bank_word(color_buffer_bus_write_data, 32, 4, num_pixel_pipes, bank)
+                }).else_if(clear_color_write_enable, {
+                    reg_clear_color.repeat(bank_elements)
+                }).else_if(resolve_write_enable, {
+                    bank_word(resolve_write_value, 32, 4, num_pixel_pipes, bank)
+                }).else_if(pack_write_enable, {
+                    bank_word(pack_write_value, 32, 4, num_pixel_pipes, bank)
+                }).else_if(unpack_write_enable, {
+                    bank_word(unpack_write_value, 32, 4, num_pixel_pipes, bank)
+                }).else_if(swizzle_write_enable, {
+                    bank_word(swizzle_write_value, 32, 4, num_pixel_pipes, bank)
+                }).else_({
+                    bank_word(pixel_pipe.color_buffer_write_port_value, 32, 4, num_pixel_pipes, bank)
+                }),
+                color_buffer_bus_write_enable | clear_color_write_enable | resolve_write_enable | pack_write_enable | unpack_write_enable | swizzle_write_enable | pixel_pipe.color_buffer_write_port_enable,
+                if_(color_buffer_bus_write_enable, {
+                    bank_word(color_buffer_bus_write_word_enable, 1, 4, num_pixel_pipes, bank)
+                }).else_if(clear_color_write_enable | resolve_write_enable | pack_write_enable | unpack_write_enable | swizzle_write_enable, {
+                    m.lit((1u32 << bank_elements) - 1, bank_elements)
+                }).else_({
+                    bank_word(pixel_pipe.color_buffer_write_port_word_enable, 1, 4, num_pixel_pipes, bank)
+                }));
+        }
+
         let depth_buffer_bus_ready = m.output("depth_buffer_bus_ready", m.high());
         let depth_buffer_bus_enable = m.input("depth_buffer_bus_enable", 1);
         let depth_buffer_bus_addr = m.input("depth_buffer_bus_addr", 20);
@@ -288,42 +1030,183 @@ impl<'a> ColorThrust<'a> {
             })
         }).unwrap();
 
-        let depth_buffer = WordMem::new(m, "depth_buffer", TILE_PIXELS_WORDS_BITS - 1, 16, 8);
         let depth_buffer_bus_write_enable = depth_buffer_bus_enable & depth_buffer_bus_write;
-        depth_buffer.write_port(
-            if_(depth_buffer_bus_write_enable, {
-                depth_buffer_bus_addr.bits(TILE_PIXELS_WORDS_BITS - 1 - 1, 0)
+        let depth_buffer_bus_read_enable = depth_buffer_bus_enable & !depth_buffer_bus_write;
+        let depth_buffer_bank_read_port_values = pixel_pipes.iter().enumerate().map(|(bank, pixel_pipe)| {
+            let bank = bank as u32;
+            let bank_elements = 8 / num_pixel_pipes;
+            let depth_buffer = WordMem::new(m, format!("depth_buffer_bank{}", bank), tile_pixels_words_bits - 1, 16, bank_elements);
+            depth_buffer.write_port(
+                if_(depth_buffer_bus_write_enable, {
+                    depth_buffer_bus_addr.bits(tile_pixels_words_bits - 1 - 1, 0)
+                }).else_if(clear_depth_write_enable, {
+                    clear_addr.bits(tile_pixels_words_bits - 1 - 1, 0)
+                }).else_({
+                    pixel_pipe.depth_buffer_write_port_addr
+                }),
+                if_(depth_buffer_bus_write_enable, {
+                    bank_word(depth_buffer_bus_write_data, 16, 8, num_pixel_pipes, bank)
+                }).else_if(clear_depth_write_enable, {
+                    clear_depth.repeat(bank_elements)
+                }).else_({
+                    bank_word(pixel_pipe.depth_buffer_write_port_value, 16, 8, num_pixel_pipes, bank)
+                }),
+                depth_buffer_bus_write_enable | clear_depth_write_enable | pixel_pipe.depth_buffer_write_port_enable,
+                if_(depth_buffer_bus_write_enable, {
+                    bank_word(depth_buffer_bus_write_word_enable, 1, 8, num_pixel_pipes, bank)
+                }).else_if(clear_depth_write_enable, {
+                    m.lit((1u32 << bank_elements) - 1, bank_elements)
+                }).else_({
+                    bank_word(pixel_pipe.depth_buffer_write_port_word_enable, 1, 8, num_pixel_pipes, bank)
+                }));
+
+            let depth_buffer_read_port_value = depth_buffer.read_port(
+                if_(depth_buffer_bus_read_enable, {
+                    depth_buffer_bus_addr.bits(tile_pixels_words_bits - 1 - 1, 0)
+                }).else_({
+                    pixel_pipe.depth_buffer_read_port_addr
+                }),
+                depth_buffer_bus_read_enable | pixel_pipe.depth_buffer_read_port_enable);
+
+            pixel_pipe.depth_buffer_read_port_value.drive(unbank_word(depth_buffer_read_port_value, 16, 8, num_pixel_pipes, bank, m));
+
+            depth_buffer_read_port_value
+        }).collect::<Vec<_>>();
+
+        let depth_buffer_bus_read_data = m.output("depth_buffer_bus_read_data", unbank_words(&depth_buffer_bank_read_port_values, 16, 8));
+        let depth_buffer_bus_read_data_valid = m.output("depth_buffer_bus_read_data_valid", depth_buffer_bus_read_enable.reg_next_with_default("depth_buffer_bus_read_data_valid", false));
+
+        //  Depth words are as wide as a hierarchical z block, so each one falls within a single block
+        let depth_word_block_index = |addr: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            let block_x_bits = tile_width_bits - HIZ_BLOCK_DIM_BITS;
+            addr.bits(tile_pixels_words_bits - 1 - 1, tile_width_bits).concat(addr.bits(block_x_bits - 1, 0))
+        };
+        let depth_buffer_bus_write_block_index = depth_word_block_index(depth_buffer_bus_addr.bits(tile_pixels_words_bits - 1 - 1, 0));
+        let depth_buffer_bus_write_min = (1..8).fold(depth_buffer_bus_write_data.bits(15, 0), |acc, x| {
+            let value = depth_buffer_bus_write_data.bits(x * 16 + 15, x * 16);
+            if_(value.lt(acc), {
+                value
+            }).else_({
+                acc
+            })
+        });
+        let depth_buffer_bus_write_max = (1..8).fold(depth_buffer_bus_write_data.bits(15, 0), |acc, x| {
+            let value = depth_buffer_bus_write_data.bits(x * 16 + 15, x * 16);
+            if_(acc.lt(value), {
+                value
             }).else_({
-                pixel_pipe.depth_buffer_write_port_addr
-            }),
-            if_(depth_buffer_bus_write_enable, {
-                depth_buffer_bus_write_data
+                acc
+            })
+        });
+        let pixel_pipe_depth_writes = pixel_pipes.iter().map(|pixel_pipe| {
+            (
+                pixel_pipe.depth_buffer_write_port_enable,
+                depth_word_block_index(pixel_pipe.depth_buffer_write_port_addr),
+                pixel_pipe.depth_buffer_write_port_value.bits(15, 0),
+            )
+        }).collect::<Vec<_>>();
+        let clear_tile_depth = clear_tile & reg_bus_write_data.bit(REG_CLEAR_TILE_DEPTH_BIT);
+
+        let widen_bounds = |(min, max): (&'a dyn Signal<'a>, &'a dyn Signal<'a>), write: &'a dyn Signal<'a>, write_min: &'a dyn Signal<'a>, write_max: &'a dyn Signal<'a>| {
+            let next_min = if_(write & write_min.lt(min), {
+                write_min
             }).else_({
-                pixel_pipe.depth_buffer_write_port_value
-            }),
-            depth_buffer_bus_write_enable | pixel_pipe.depth_buffer_write_port_enable,
-            if_(depth_buffer_bus_write_enable, {
-                depth_buffer_bus_write_word_enable
+                min
+            });
+            let next_max = if_(write & max.lt(write_max), {
+                write_max
             }).else_({
-                pixel_pipe.depth_buffer_write_port_word_enable
-            }));
+                max
+            });
+            (next_min, next_max)
+        };
 
-        let depth_buffer_bus_read_enable = depth_buffer_bus_enable & !depth_buffer_bus_write;
-        let depth_buffer_read_port_value = depth_buffer.read_port(
-            if_(depth_buffer_bus_read_enable, {
-                depth_buffer_bus_addr.bits(TILE_PIXELS_WORDS_BITS - 1 - 1, 0)
+        for (x, &(block_min, block_max)) in hiz_block_bounds.iter().enumerate() {
+            let block_index = m.lit(x as u32, hiz_tile_blocks_bits);
+            let bus_write = depth_buffer_bus_write_enable & depth_buffer_bus_write_block_index.eq(block_index);
+            let bounds = widen_bounds((block_min.into(), block_max.into()), bus_write, depth_buffer_bus_write_min, depth_buffer_bus_write_max);
+            let (next_min, next_max) = pixel_pipe_depth_writes.iter().fold(bounds, |bounds, &(enable, block_write_index, value)| {
+                widen_bounds(bounds, enable & block_write_index.eq(block_index), value, value)
+            });
+
+            block_min.drive_next(if_(depth_bounds_reset, {
+                m.lit(0xffffu32, 16)
+            }).else_if(clear_tile_depth, {
+                clear_depth
+            }).else_({
+                next_min
+            }));
+            block_max.drive_next(if_(depth_bounds_reset, {
+                m.lit(0u32, 16)
+            }).else_if(clear_tile_depth, {
+                clear_depth
             }).else_({
-                pixel_pipe.depth_buffer_read_port_addr
-            }),
-            depth_buffer_bus_read_enable | pixel_pipe.depth_buffer_read_port_enable);
+                next_max
+            }));
+        }
 
-        pixel_pipe.depth_buffer_read_port_value.drive(depth_buffer_read_port_value);
+        let stencil_buffer_bus_ready = m.output("stencil_buffer_bus_ready", m.high());
+        let stencil_buffer_bus_enable = m.input("stencil_buffer_bus_enable", 1);
+        let stencil_buffer_bus_addr = m.input("stencil_buffer_bus_addr", 20);
+        let stencil_buffer_bus_write = m.input("stencil_buffer_bus_write", 1);
+        let stencil_buffer_bus_write_data = m.input("stencil_buffer_bus_write_data", 128);
+        let stencil_buffer_bus_write_byte_enable = m.input("stencil_buffer_bus_write_byte_enable", 16);
+
+        let stencil_buffer_bus_write_enable = stencil_buffer_bus_enable & stencil_buffer_bus_write;
+        let stencil_buffer_bus_read_enable = stencil_buffer_bus_enable & !stencil_buffer_bus_write;
+        let stencil_buffer_bank_read_port_values = pixel_pipes.iter().enumerate().map(|(bank, pixel_pipe)| {
+            let bank = bank as u32;
+            let bank_elements = 16 / num_pixel_pipes;
+            let stencil_buffer = WordMem::new(m, format!("stencil_buffer_bank{}", bank), tile_pixels_words_bits - 2, 8, bank_elements);
+            stencil_buffer.write_port(
+                if_(stencil_buffer_bus_write_enable, {
+                    stencil_buffer_bus_addr.bits(tile_pixels_words_bits - 2 - 1, 0)
+                }).else_if(clear_stencil_write_enable, {
+                    clear_addr.bits(tile_pixels_words_bits - 2 - 1, 0)
+                }).else_({
+                    pixel_pipe.stencil_buffer_write_port_addr
+                }),
+                if_(stencil_buffer_bus_write_enable, {
+                    bank_word(stencil_buffer_bus_write_data, 8, 16, num_pixel_pipes, bank)
+                }).else_if(clear_stencil_write_enable, {
+                    clear_stencil.repeat(bank_elements)
+                }).else_({
+                    bank_word(pixel_pipe.stencil_buffer_write_port_value, 8, 16, num_pixel_pipes, bank)
+                }),
+                stencil_buffer_bus_write_enable | clear_stencil_write_enable | pixel_pipe.stencil_buffer_write_port_enable,
+                if_(stencil_buffer_bus_write_enable, {
+                    bank_word(stencil_buffer_bus_write_byte_enable, 1, 16, num_pixel_pipes, bank)
+                }).else_if(clear_stencil_write_enable, {
+                    m.lit((1u32 << bank_elements) - 1, bank_elements)
+                }).else_({
+                    bank_word(pixel_pipe.stencil_buffer_write_port_word_enable, 1, 16, num_pixel_pipes, bank)
+                }));
 
-        let depth_buffer_bus_read_data = m.output("depth_buffer_bus_read_data", depth_buffer_read_port_value);
-        let depth_buffer_bus_read_data_valid = m.output("depth_buffer_bus_read_data_valid", depth_buffer_bus_read_enable.reg_next_with_default("depth_buffer_bus_read_data_valid", false));
+            let stencil_buffer_read_port_value = stencil_buffer.read_port(
+                if_(stencil_buffer_bus_read_enable, {
+                    stencil_buffer_bus_addr.bits(tile_pixels_words_bits - 2 - 1, 0)
+                }).else_({
+                    pixel_pipe.stencil_buffer_read_port_addr
+                }),
+                stencil_buffer_bus_read_enable | pixel_pipe.stencil_buffer_read_port_enable);
+
+            pixel_pipe.stencil_buffer_read_port_value.drive(unbank_word(stencil_buffer_read_port_value, 8, 16, num_pixel_pipes, bank, m));
+
+            stencil_buffer_read_port_value
+        }).collect::<Vec<_>>();
 
-        pixel_pipe.tex_cache_invalidate.drive(tex_cache_invalidate);
-        let tex_cache_system_port = pixel_pipe.tex_cache_system_port.forward("tex_cache_system", m);
+        let stencil_buffer_bus_read_data = m.output("stencil_buffer_bus_read_data", unbank_words(&stencil_buffer_bank_read_port_values, 8, 16));
+        let stencil_buffer_bus_read_data_valid = m.output("stencil_buffer_bus_read_data_valid", stencil_buffer_bus_read_enable.reg_next_with_default("stencil_buffer_bus_read_data_valid", false));
+
+        let tex_cache_system_port = if num_pixel_pipes == 1 {
+            pixel_pipes[0].tex_cache_system_port.forward("tex_cache_system", m)
+        } else {
+            let tex_cache_crossbar = Crossbar::new("tex_cache_crossbar", num_pixel_pipes, 1, SYSTEM_BUS_ADDR_BITS, 0, 128, 5, m);
+            for (i, pixel_pipe) in pixel_pipes.iter().enumerate() {
+                pixel_pipe.tex_cache_system_port.connect(&tex_cache_crossbar.replica_ports[i]);
+            }
+            tex_cache_crossbar.primary_ports[0].forward("tex_cache_system", m)
+        };
 
         ColorThrust {
             m,
@@ -358,16 +1241,59 @@ impl<'a> ColorThrust<'a> {
                 bus_read_data: depth_buffer_bus_read_data,
                 bus_read_data_valid: depth_buffer_bus_read_data_valid,
             },
+            stencil_buffer_port: ReplicaPort {
+                bus_enable: stencil_buffer_bus_enable,
+                bus_addr: stencil_buffer_bus_addr,
+                bus_write: stencil_buffer_bus_write,
+                bus_write_data: stencil_buffer_bus_write_data,
+                bus_write_byte_enable: stencil_buffer_bus_write_byte_enable,
+                bus_ready: stencil_buffer_bus_ready,
+                bus_read_data: stencil_buffer_bus_read_data,
+                bus_read_data_valid: stencil_buffer_bus_read_data_valid,
+            },
             tex_cache_system_port,
         }
     }
 }
 
+// Tile buffer words are banked by element; element x of a full word lives in bank x % num_banks, at element
+//  x / num_banks of that bank's word
+fn bank_word<'a>(word: &'a dyn Signal<'a>, element_bit_width: u32, elements_per_word: u32, num_banks: u32, bank: u32) -> &'a dyn Signal<'a> {
+    (bank..elements_per_word).step_by(num_banks as _).fold(None, |acc: Option<&'a dyn Signal<'a>>, x| {
+        let element = word.bits(x * element_bit_width + element_bit_width - 1, x * element_bit_width);
+        Some(if let Some(acc) = acc {
+            element.concat(acc)
+        } else {
+            element
+        })
+    }).unwrap()
+}
+
+fn unbank_words<'a>(bank_words: &[&'a dyn Signal<'a>], element_bit_width: u32, elements_per_word: u32) -> &'a dyn Signal<'a> {
+    let num_banks = bank_words.len() as u32;
+    (0..elements_per_word).fold(None, |acc: Option<&'a dyn Signal<'a>>, x| {
+        let bank_element = x / num_banks;
+        let element = bank_words[(x % num_banks) as usize].bits(bank_element * element_bit_width + element_bit_width - 1, bank_element * element_bit_width);
+        Some(if let Some(acc) = acc {
+            element.concat(acc)
+        } else {
+            element
+        })
+    }).unwrap()
+}
+
+// Expands a single bank's word to a full word, leaving the other banks' elements zeroed
+fn unbank_word<'a>(word: &'a dyn Signal<'a>, element_bit_width: u32, elements_per_word: u32, num_banks: u32, bank: u32, m: &'a Module<'a>) -> &'a dyn Signal<'a> {
+    let zero = m.lit(0u32, element_bit_width * elements_per_word / num_banks);
+    unbank_words(&(0..num_banks).map(|x| if x == bank { word } else { zero }).collect::<Vec<_>>(), element_bit_width, elements_per_word)
+}
+
 pub struct PixelPipe<'a> {
     pub m: &'a Module<'a>,
 
     // Control
     pub start: &'a Input<'a>,
+    pub hiz_block_skip: &'a Input<'a>,
     pub active: &'a Output<'a>,
 
     // Inputs
@@ -394,17 +1320,44 @@ pub struct PixelPipe<'a> {
 
     // Aux inputs
     pub depth_test_enable: &'a Input<'a>,
+    pub depth_func: &'a Input<'a>,
     pub depth_buffer_read_port_value: &'a Input<'a>,
 
+    pub stencil_test_enable: &'a Input<'a>,
+    pub stencil_func: &'a Input<'a>,
+    pub stencil_sfail_op: &'a Input<'a>,
+    pub stencil_zfail_op: &'a Input<'a>,
+    pub stencil_zpass_op: &'a Input<'a>,
+    pub stencil_ref: &'a Input<'a>,
+    pub stencil_mask: &'a Input<'a>,
+    pub stencil_write_mask: &'a Input<'a>,
+    pub stencil_buffer_read_port_value: &'a Input<'a>,
+
+    pub occlusion_query_begin: &'a Input<'a>,
+    pub occlusion_query_active: &'a Input<'a>,
+
     pub tex_filter_select: &'a Input<'a>,
     pub tex_dim: &'a Input<'a>,
     pub tex_base: &'a Input<'a>,
 
     pub tex_cache_invalidate: &'a Input<'a>,
 
+    pub texture_env_mode: &'a Input<'a>,
+    pub texture_env_color: &'a Input<'a>,
+
     pub depth_write_mask_enable: &'a Input<'a>,
     pub blend_src_factor: &'a Input<'a>,
     pub blend_dst_factor: &'a Input<'a>,
+    pub blend_equation: &'a Input<'a>,
+    pub blend_color: &'a Input<'a>,
+    pub alpha_test_enable: &'a Input<'a>,
+    pub alpha_test_func: &'a Input<'a>,
+    pub alpha_test_ref: &'a Input<'a>,
+    pub fog_enable: &'a Input<'a>,
+    pub fog_source: &'a Input<'a>,
+    pub fog_w_shift: &'a Input<'a>,
+    pub fog_color: &'a Input<'a>,
+    pub fog_table: &'a Input<'a>,
     pub color_buffer_read_port_value: &'a Input<'a>,
 
     // Outputs
@@ -414,6 +1367,16 @@ pub struct PixelPipe<'a> {
     pub depth_buffer_read_port_addr: &'a Output<'a>,
     pub depth_buffer_read_port_enable: &'a Output<'a>,
 
+    pub stencil_buffer_read_port_addr: &'a Output<'a>,
+    pub stencil_buffer_read_port_enable: &'a Output<'a>,
+
+    pub stencil_buffer_write_port_addr: &'a Output<'a>,
+    pub stencil_buffer_write_port_value: &'a Output<'a>,
+    pub stencil_buffer_write_port_enable: &'a Output<'a>,
+    pub stencil_buffer_write_port_word_enable: &'a Output<'a>,
+
+    pub occlusion_query_passed_samples: &'a Output<'a>,
+
     pub color_buffer_read_port_addr: &'a Output<'a>,
     pub color_buffer_read_port_enable: &'a Output<'a>,
 
@@ -429,20 +1392,25 @@ pub struct PixelPipe<'a> {
 }
 
 impl<'a> PixelPipe<'a> {
-    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> PixelPipe<'a> {
+    pub fn new(instance_name: impl Into<String>, num_pixel_pipes: u32, tile_pixels_bits: u32, p: &'a impl ModuleParent<'a>) -> PixelPipe<'a> {
         let m = p.module(instance_name, "PixelPipe");
 
+        // Each pipe only sees every num_pixel_pipes'th pixel of the tile
+        let pipe_pixels = (1u32 << tile_pixels_bits) / num_pixel_pipes;
+
         // Control
         let start = m.input("start", 1);
+        // Set for each cycle where the input generator skips a block's row of pixels instead of issuing a pixel
+        let hiz_block_skip = m.input("hiz_block_skip", 1);
 
         let active = m.reg("active", 1);
         active.default_value(false);
 
-        let finished_pixel_acc = m.reg("finished_pixel_acc", TILE_PIXELS_BITS + 1);
+        let finished_pixel_acc = m.reg("finished_pixel_acc", tile_pixels_bits + 1);
 
         // Inputs
         let in_valid = m.input("in_valid", 1);
-        let in_tile_addr = m.input("in_tile_addr", TILE_PIXELS_BITS);
+        let in_tile_addr = m.input("in_tile_addr", tile_pixels_bits);
 
         let in_w0 = m.input("in_w0", 1);
         let in_w1 = m.input("in_w1", 1);
@@ -493,7 +1461,7 @@ impl<'a> PixelPipe<'a> {
 
         // Depth test pipe
         let depth_test_pipe = m.module("depth_test_pipe", "FlowControlledDepthTestPipe");
-        let depth_test_pipe_inner = DepthTestPipe::new("depth_test_pipe_inner", depth_test_pipe);
+        let depth_test_pipe_inner = DepthTestPipe::new("depth_test_pipe_inner", tile_pixels_bits, depth_test_pipe);
         let mut depth_test_pipe = FlowControlledPipe::new(
             depth_test_pipe,
             2,
@@ -503,6 +1471,8 @@ impl<'a> PixelPipe<'a> {
         //  Aux
         let depth_test_enable = m.input("depth_test_enable", 1);
         depth_test_pipe.aux_input("depth_test_enable", depth_test_pipe_inner.depth_test_enable).drive(depth_test_enable);
+        let depth_func = m.input("depth_func", REG_DEPTH_FUNC_BITS);
+        depth_test_pipe.aux_input("depth_func", depth_test_pipe_inner.depth_func).drive(depth_func);
 
         let depth_buffer_read_port_addr = m.output("depth_buffer_read_port_addr", depth_test_pipe.aux_output("depth_buffer_read_port_addr", depth_test_pipe_inner.depth_buffer_read_port_addr));
         let depth_buffer_read_port_enable = m.output("depth_buffer_read_port_enable", depth_test_pipe.aux_output("depth_buffer_read_port_enable", depth_test_pipe_inner.depth_buffer_read_port_enable));
@@ -510,6 +1480,34 @@ impl<'a> PixelPipe<'a> {
         let depth_buffer_read_port_value = m.input("depth_buffer_read_port_value", 128);
         depth_test_pipe.aux_input("depth_buffer_read_port_value", depth_test_pipe_inner.depth_buffer_read_port_value).drive(depth_buffer_read_port_value);
 
+        let stencil_test_enable = m.input("stencil_test_enable", 1);
+        depth_test_pipe.aux_input("stencil_test_enable", depth_test_pipe_inner.stencil_test_enable).drive(stencil_test_enable);
+        let stencil_func = m.input("stencil_func", REG_STENCIL_FUNC_BITS);
+        depth_test_pipe.aux_input("stencil_func", depth_test_pipe_inner.stencil_func).drive(stencil_func);
+        let stencil_sfail_op = m.input("stencil_sfail_op", REG_STENCIL_OP_BITS);
+        depth_test_pipe.aux_input("stencil_sfail_op", depth_test_pipe_inner.stencil_sfail_op).drive(stencil_sfail_op);
+        let stencil_zfail_op = m.input("stencil_zfail_op", REG_STENCIL_OP_BITS);
+        depth_test_pipe.aux_input("stencil_zfail_op", depth_test_pipe_inner.stencil_zfail_op).drive(stencil_zfail_op);
+        let stencil_zpass_op = m.input("stencil_zpass_op", REG_STENCIL_OP_BITS);
+        depth_test_pipe.aux_input("stencil_zpass_op", depth_test_pipe_inner.stencil_zpass_op).drive(stencil_zpass_op);
+        let stencil_ref = m.input("stencil_ref", REG_STENCIL_REF_VALUE_BITS);
+        depth_test_pipe.aux_input("stencil_ref", depth_test_pipe_inner.stencil_ref).drive(stencil_ref);
+        let stencil_mask = m.input("stencil_mask", REG_STENCIL_REF_MASK_BITS);
+        depth_test_pipe.aux_input("stencil_mask", depth_test_pipe_inner.stencil_mask).drive(stencil_mask);
+        let stencil_write_mask = m.input("stencil_write_mask", REG_STENCIL_REF_WRITE_MASK_BITS);
+        depth_test_pipe.aux_input("stencil_write_mask", depth_test_pipe_inner.stencil_write_mask).drive(stencil_write_mask);
+
+        let stencil_buffer_read_port_addr = m.output("stencil_buffer_read_port_addr", depth_test_pipe.aux_output("stencil_buffer_read_port_addr", depth_test_pipe_inner.stencil_buffer_read_port_addr));
+        let stencil_buffer_read_port_enable = m.output("stencil_buffer_read_port_enable", depth_test_pipe.aux_output("stencil_buffer_read_port_enable", depth_test_pipe_inner.stencil_buffer_read_port_enable));
+
+        let stencil_buffer_read_port_value = m.input("stencil_buffer_read_port_value", 128);
+        depth_test_pipe.aux_input("stencil_buffer_read_port_value", depth_test_pipe_inner.stencil_buffer_read_port_value).drive(stencil_buffer_read_port_value);
+
+        let stencil_buffer_write_port_addr = m.output("stencil_buffer_write_port_addr", depth_test_pipe.aux_output("stencil_buffer_write_port_addr", depth_test_pipe_inner.stencil_buffer_write_port_addr));
+        let stencil_buffer_write_port_value = m.output("stencil_buffer_write_port_value", depth_test_pipe.aux_output("stencil_buffer_write_port_value", depth_test_pipe_inner.stencil_buffer_write_port_value));
+        let stencil_buffer_write_port_enable = m.output("stencil_buffer_write_port_enable", depth_test_pipe.aux_output("stencil_buffer_write_port_enable", depth_test_pipe_inner.stencil_buffer_write_port_enable));
+        let stencil_buffer_write_port_word_enable = m.output("stencil_buffer_write_port_word_enable", depth_test_pipe.aux_output("stencil_buffer_write_port_word_enable", depth_test_pipe_inner.stencil_buffer_write_port_word_enable));
+
         //  Inputs
         depth_test_pipe.in_valid.drive(valid);
 
@@ -549,13 +1547,13 @@ impl<'a> PixelPipe<'a> {
         // TODO: I don't like that these valid signals have moved
         let valid = depth_test_pipe.out_valid.unwrap();
 
-        // Reject pixel if it doesn't pass depth test before entering the next pipe
+        // Reject pixel if it doesn't pass depth/stencil test before entering the next pipe
         let depth_test_reject = valid & !depth_test_result;
         let valid = valid & depth_test_result;
 
         // Front pipe
         let front_pipe = m.module("front_pipe", "FlowControlledFrontPipe");
-        let front_pipe_inner = FrontPipe::new("front_pipe_inner", front_pipe);
+        let front_pipe_inner = FrontPipe::new("front_pipe_inner", tile_pixels_bits, front_pipe);
         let mut front_pipe = FlowControlledPipe::new(
             front_pipe,
             15,
@@ -569,6 +1567,10 @@ impl<'a> PixelPipe<'a> {
         front_pipe.aux_input("tex_dim", front_pipe_inner.tex_dim).drive(tex_dim);
         let tex_base = m.input("tex_base", REG_TEXTURE_BASE_BITS);
         front_pipe.aux_input("tex_base", front_pipe_inner.tex_base).drive(tex_base);
+        let fog_source = m.input("fog_source", REG_FOG_SOURCE_BITS);
+        front_pipe.aux_input("fog_source", front_pipe_inner.fog_source).drive(fog_source);
+        let fog_w_shift = m.input("fog_w_shift", REG_FOG_W_SHIFT_BITS);
+        front_pipe.aux_input("fog_w_shift", front_pipe_inner.fog_w_shift).drive(fog_w_shift);
 
         //  Inputs
         front_pipe.in_valid.drive(valid);
@@ -602,6 +1604,8 @@ impl<'a> PixelPipe<'a> {
         let t_fract = front_pipe.output("out_t_fract", front_pipe_inner.out_t_fract);
         let one_minus_t_fract = front_pipe.output("out_one_minus_t_fract", front_pipe_inner.out_one_minus_t_fract);
 
+        let fog_index = front_pipe.output("out_fog_index", front_pipe_inner.out_fog_index);
+
         for i in 0..4 {
             front_pipe.output(format!("out_tex_buffer{}_read_addr", i), front_pipe_inner.out_tex_buffer_read_addrs[i]);
         }
@@ -612,11 +1616,13 @@ impl<'a> PixelPipe<'a> {
         let valid = front_pipe.out_valid.unwrap();
 
         // Tex cache
-        let tex_cache = TexCache::new("tex_cache", m);
+        let tex_cache = TexCache::new("tex_cache", tile_pixels_bits, m);
 
         //  Aux
         let tex_cache_invalidate = m.input("tex_cache_invalidate", 1);
         tex_cache.invalidate.drive(tex_cache_invalidate);
+        let texture_env_mode = m.input("texture_env_mode", REG_TEXTURE_ENV_MODE_BITS);
+        tex_cache.bypass.drive(texture_env_mode.eq(m.lit(REG_TEXTURE_ENV_MODE_UNTEXTURED, REG_TEXTURE_ENV_MODE_BITS)));
 
         let tex_cache_system_port = tex_cache.system_port.forward("tex_cache_system", m);
 
@@ -638,6 +1644,8 @@ impl<'a> PixelPipe<'a> {
         tex_cache.forward_inputs["t_fract"].drive(t_fract);
         tex_cache.forward_inputs["one_minus_t_fract"].drive(one_minus_t_fract);
 
+        tex_cache.forward_inputs["fog_index"].drive(fog_index);
+
         for i in 0..4 {
             let out_tex_buffer_read_addr = front_pipe.output(format!("out_tex_buffer_read_addr_{}", i), front_pipe_inner.out_tex_buffer_read_addrs[i]);
             tex_cache.in_tex_buffer_read_addrs[i].drive(out_tex_buffer_read_addr);
@@ -659,10 +1667,16 @@ impl<'a> PixelPipe<'a> {
         let t_fract = tex_cache.forward_outputs["t_fract"];
         let one_minus_t_fract = tex_cache.forward_outputs["one_minus_t_fract"];
 
+        let fog_index = tex_cache.forward_outputs["fog_index"];
+
         // Back pipe
-        let back_pipe = BackPipe::new("back_pipe", m);
+        let back_pipe = BackPipe::new("back_pipe", tile_pixels_bits, m);
 
         //  Aux
+        back_pipe.in_texture_env_mode.drive(texture_env_mode);
+        let texture_env_color = m.input("texture_env_color", REG_TEXTURE_ENV_COLOR_BITS);
+        back_pipe.in_texture_env_color.drive(texture_env_color);
+
         let depth_write_mask_enable = m.input("depth_write_mask_enable", 1);
         back_pipe.in_depth_write_mask_enable.drive(depth_write_mask_enable);
 
@@ -670,6 +1684,29 @@ impl<'a> PixelPipe<'a> {
         back_pipe.in_blend_src_factor.drive(blend_src_factor);
         let blend_dst_factor = m.input("blend_dst_factor", REG_BLEND_SETTINGS_DST_FACTOR_BITS);
         back_pipe.in_blend_dst_factor.drive(blend_dst_factor);
+        let blend_equation = m.input("blend_equation", REG_BLEND_SETTINGS_EQUATION_BITS);
+        back_pipe.in_blend_equation.drive(blend_equation);
+        let blend_color = m.input("blend_color", REG_BLEND_COLOR_BITS);
+        back_pipe.in_blend_color.drive(blend_color);
+
+        let alpha_test_enable = m.input("alpha_test_enable", 1);
+        back_pipe.in_alpha_test_enable.drive(alpha_test_enable);
+        let alpha_test_func = m.input("alpha_test_func", REG_ALPHA_TEST_FUNC_BITS);
+        back_pipe.in_alpha_test_func.drive(alpha_test_func);
+        let alpha_test_ref = m.input("alpha_test_ref", REG_ALPHA_TEST_REF_BITS);
+        back_pipe.in_alpha_test_ref.drive(alpha_test_ref);
+
+        let fog_enable = m.input("fog_enable", 1);
+        back_pipe.in_fog_enable.drive(fog_enable);
+        let fog_color = m.input("fog_color", REG_FOG_COLOR_BITS);
+        back_pipe.in_fog_color.drive(fog_color);
+        let fog_table = m.input("fog_table", REG_FOG_TABLE_ENTRIES * REG_FOG_TABLE_VALUE_BITS);
+        back_pipe.in_fog_table.drive(fog_table);
+
+        let occlusion_query_begin = m.input("occlusion_query_begin", 1);
+        back_pipe.in_occlusion_query_begin.drive(occlusion_query_begin);
+        let occlusion_query_active = m.input("occlusion_query_active", 1);
+        back_pipe.in_occlusion_query_active.drive(occlusion_query_active);
 
         let color_buffer_read_port_addr = m.output("color_buffer_read_port_addr", back_pipe.color_buffer_read_port_addr);
         let color_buffer_read_port_enable = m.output("color_buffer_read_port_enable", back_pipe.color_buffer_read_port_enable);
@@ -687,6 +1724,8 @@ impl<'a> PixelPipe<'a> {
         let depth_buffer_write_port_enable = m.output("depth_buffer_write_port_enable", back_pipe.depth_buffer_write_port_enable);
         let depth_buffer_write_port_word_enable = m.output("depth_buffer_write_port_word_enable", back_pipe.depth_buffer_write_port_word_enable);
 
+        let occlusion_query_passed_samples = m.output("occlusion_query_passed_samples", back_pipe.occlusion_query_passed_samples);
+
         //  Inputs
         back_pipe.in_valid.drive(valid);
         back_pipe.in_tile_addr.drive(tile_addr);
@@ -703,6 +1742,8 @@ impl<'a> PixelPipe<'a> {
         back_pipe.in_t_fract.drive(t_fract);
         back_pipe.in_one_minus_t_fract.drive(one_minus_t_fract);
 
+        back_pipe.in_fog_index.drive(fog_index);
+
         for i in 0..4 {
             back_pipe.in_tex_buffer_read_values[i].drive(tex_cache.out_tex_buffer_read_values[i]);
         }
@@ -712,7 +1753,7 @@ impl<'a> PixelPipe<'a> {
 
         active.drive_next(if_(start, {
             m.high()
-        }).else_if(finished_pixel_acc.eq(m.lit(TILE_PIXELS, TILE_PIXELS_BITS + 1)), {
+        }).else_if(finished_pixel_acc.eq(m.lit(pipe_pixels, tile_pixels_bits + 1)), {
             m.low()
         }).else_({
             active
@@ -738,10 +1779,16 @@ impl<'a> PixelPipe<'a> {
             m.lit(3u32, 2)
         });
 
+        let skipped_pixel_count = if_(hiz_block_skip, {
+            m.lit(HIZ_BLOCK_DIM / num_pixel_pipes, tile_pixels_bits + 1)
+        }).else_({
+            m.lit(0u32, tile_pixels_bits + 1)
+        });
+
         finished_pixel_acc.drive_next(if_(start, {
-            m.lit(0u32, TILE_PIXELS_BITS + 1)
+            m.lit(0u32, tile_pixels_bits + 1)
         }).else_({
-            finished_pixel_acc + m.lit(0u32, TILE_PIXELS_BITS - 1).concat(finished_pixel_count)
+            finished_pixel_acc + m.lit(0u32, tile_pixels_bits - 1).concat(finished_pixel_count) + skipped_pixel_count
         }));
 
         PixelPipe {
@@ -749,6 +1796,7 @@ impl<'a> PixelPipe<'a> {
 
             // Control
             start,
+            hiz_block_skip,
             active: m.output("active", active),
 
             // Inputs
@@ -775,17 +1823,44 @@ impl<'a> PixelPipe<'a> {
 
             // Aux inputs
             depth_test_enable,
+            depth_func,
             depth_buffer_read_port_value,
 
+            stencil_test_enable,
+            stencil_func,
+            stencil_sfail_op,
+            stencil_zfail_op,
+            stencil_zpass_op,
+            stencil_ref,
+            stencil_mask,
+            stencil_write_mask,
+            stencil_buffer_read_port_value,
+
+            occlusion_query_begin,
+            occlusion_query_active,
+
             tex_filter_select,
             tex_dim,
             tex_base,
 
             tex_cache_invalidate,
 
+            texture_env_mode,
+            texture_env_color,
+
             depth_write_mask_enable,
             blend_src_factor,
             blend_dst_factor,
+            blend_equation,
+            blend_color,
+            alpha_test_enable,
+            alpha_test_func,
+            alpha_test_ref,
+            fog_enable,
+            fog_source,
+            fog_w_shift,
+            fog_color,
+            fog_table,
             color_buffer_read_port_value,
 
             // Outputs
@@ -795,6 +1870,16 @@ impl<'a> PixelPipe<'a> {
             depth_buffer_read_port_addr,
             depth_buffer_read_port_enable,
 
+            stencil_buffer_read_port_addr,
+            stencil_buffer_read_port_enable,
+
+            stencil_buffer_write_port_addr,
+            stencil_buffer_write_port_value,
+            stencil_buffer_write_port_enable,
+            stencil_buffer_write_port_word_enable,
+
+            occlusion_query_passed_samples,
+
             color_buffer_read_port_addr,
             color_buffer_read_port_enable,
 
@@ -833,13 +1918,32 @@ struct DepthTestPipe<'a> {
 
     // Aux inputs
     pub depth_test_enable: &'a Input<'a>,
+    pub depth_func: &'a Input<'a>,
+
+    pub stencil_test_enable: &'a Input<'a>,
+    pub stencil_func: &'a Input<'a>,
+    pub stencil_sfail_op: &'a Input<'a>,
+    pub stencil_zfail_op: &'a Input<'a>,
+    pub stencil_zpass_op: &'a Input<'a>,
+    pub stencil_ref: &'a Input<'a>,
+    pub stencil_mask: &'a Input<'a>,
+    pub stencil_write_mask: &'a Input<'a>,
 
     pub depth_buffer_read_port_value: &'a Input<'a>,
+    pub stencil_buffer_read_port_value: &'a Input<'a>,
 
     // Outputs
     pub depth_buffer_read_port_addr: &'a Output<'a>,
     pub depth_buffer_read_port_enable: &'a Output<'a>,
 
+    pub stencil_buffer_read_port_addr: &'a Output<'a>,
+    pub stencil_buffer_read_port_enable: &'a Output<'a>,
+
+    pub stencil_buffer_write_port_addr: &'a Output<'a>,
+    pub stencil_buffer_write_port_value: &'a Output<'a>,
+    pub stencil_buffer_write_port_enable: &'a Output<'a>,
+    pub stencil_buffer_write_port_word_enable: &'a Output<'a>,
+
     pub out_valid: &'a Output<'a>,
     pub out_tile_addr: &'a Output<'a>,
 
@@ -859,12 +1963,12 @@ struct DepthTestPipe<'a> {
 }
 
 impl<'a> DepthTestPipe<'a> {
-    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> DepthTestPipe<'a> {
+    pub fn new(instance_name: impl Into<String>, tile_pixels_bits: u32, p: &'a impl ModuleParent<'a>) -> DepthTestPipe<'a> {
         let m = p.module(instance_name, "DepthTestPipe");
 
         // Inputs
         let in_valid = m.input("in_valid", 1);
-        let in_tile_addr = m.input("in_tile_addr", TILE_PIXELS_BITS);
+        let in_tile_addr = m.input("in_tile_addr", tile_pixels_bits);
 
         let in_r = m.input("in_r", COLOR_WHOLE_BITS - 1);
         let in_g = m.input("in_g", COLOR_WHOLE_BITS - 1);
@@ -880,6 +1984,16 @@ impl<'a> DepthTestPipe<'a> {
 
         // Aux inputs
         let depth_test_enable = m.input("depth_test_enable", 1);
+        let depth_func = m.input("depth_func", REG_DEPTH_FUNC_BITS);
+
+        let stencil_test_enable = m.input("stencil_test_enable", 1);
+        let stencil_func = m.input("stencil_func", REG_STENCIL_FUNC_BITS);
+        let stencil_sfail_op = m.input("stencil_sfail_op", REG_STENCIL_OP_BITS);
+        let stencil_zfail_op = m.input("stencil_zfail_op", REG_STENCIL_OP_BITS);
+        let stencil_zpass_op = m.input("stencil_zpass_op", REG_STENCIL_OP_BITS);
+        let stencil_ref = m.input("stencil_ref", REG_STENCIL_REF_VALUE_BITS);
+        let stencil_mask = m.input("stencil_mask", REG_STENCIL_REF_MASK_BITS);
+        let stencil_write_mask = m.input("stencil_write_mask", REG_STENCIL_REF_WRITE_MASK_BITS);
 
         let valid = in_valid;
         let tile_addr = in_tile_addr;
@@ -897,9 +2011,13 @@ impl<'a> DepthTestPipe<'a> {
         let t = in_t;
 
         //  Issue depth buffer read for prev_depth
-        let depth_buffer_read_port_addr = m.output("depth_buffer_read_port_addr", tile_addr.bits(TILE_PIXELS_BITS - 1, 3));
+        let depth_buffer_read_port_addr = m.output("depth_buffer_read_port_addr", tile_addr.bits(tile_pixels_bits - 1, 3));
         let depth_buffer_read_port_enable = m.output("depth_buffer_read_port_enable", valid & depth_test_enable);
 
+        //  Issue stencil buffer read for prev_stencil
+        let stencil_buffer_read_port_addr = m.output("stencil_buffer_read_port_addr", tile_addr.bits(tile_pixels_bits - 1, 4));
+        let stencil_buffer_read_port_enable = m.output("stencil_buffer_read_port_enable", valid & stencil_test_enable);
+
         // Stage 1
         let valid = valid.reg_next_with_default("stage_1_valid", false);
         let tile_addr = tile_addr.reg_next("stage_1_tile_addr");
@@ -937,6 +2055,15 @@ impl<'a> DepthTestPipe<'a> {
             prev_depth.bits(127, 112)
         });
 
+        let stencil_buffer_read_port_value = m.input("stencil_buffer_read_port_value", 128);
+        let prev_stencil = (1u32..16).fold(stencil_buffer_read_port_value.bits(7, 0), |acc, x| {
+            if_(tile_addr.bits(3, 0).eq(m.lit(x, 4)), {
+                stencil_buffer_read_port_value.bits(x * 8 + 7, x * 8)
+            }).else_({
+                acc
+            })
+        });
+
         // Stage 2
         let valid = valid.reg_next_with_default("stage_2_valid", false);
         let tile_addr = tile_addr.reg_next("stage_2_tile_addr");
@@ -954,8 +2081,74 @@ impl<'a> DepthTestPipe<'a> {
         let t = t.reg_next("stage_2_t");
 
         let prev_depth = prev_depth.reg_next("stage_2_prev_depth");
+        let prev_stencil = prev_stencil.reg_next("stage_2_prev_stencil");
+
+        let depth_test_result =
+            (depth_func.bit(0) & z.lt(prev_depth)) |
+            (depth_func.bit(1) & z.eq(prev_depth)) |
+            (depth_func.bit(2) & prev_depth.lt(z)) |
+            !depth_test_enable;
+
+        let masked_stencil_ref = stencil_ref & stencil_mask;
+        let masked_prev_stencil = prev_stencil & stencil_mask;
+        let stencil_test_result =
+            (stencil_func.bit(0) & masked_stencil_ref.lt(masked_prev_stencil)) |
+            (stencil_func.bit(1) & masked_stencil_ref.eq(masked_prev_stencil)) |
+            (stencil_func.bit(2) & masked_prev_stencil.lt(masked_stencil_ref)) |
+            !stencil_test_enable;
+
+        //  Stencil update
+        //   Note that this happens here rather than at the end of the pixel pipe, so fragments
+        //   that are later discarded by the alpha test still update the stencil buffer
+        let stencil_op = if_(!stencil_test_result, {
+            stencil_sfail_op
+        }).else_if(!depth_test_result, {
+            stencil_zfail_op
+        }).else_({
+            stencil_zpass_op
+        });
+        let stencil_max = m.lit(0xffu32, 8);
+        let stencil_min = m.lit(0u32, 8);
+        let stencil_one = m.lit(1u32, 8);
+        let stencil_op_is = |x: u32| stencil_op.eq(m.lit(x, REG_STENCIL_OP_BITS));
+        let next_stencil = if_(stencil_op_is(REG_STENCIL_OP_ZERO), {
+            stencil_min
+        }).else_if(stencil_op_is(REG_STENCIL_OP_REPLACE), {
+            stencil_ref
+        }).else_if(stencil_op_is(REG_STENCIL_OP_INCR), {
+            if_(prev_stencil.eq(stencil_max), {
+                prev_stencil
+            }).else_({
+                prev_stencil + stencil_one
+            })
+        }).else_if(stencil_op_is(REG_STENCIL_OP_DECR), {
+            if_(prev_stencil.eq(stencil_min), {
+                prev_stencil
+            }).else_({
+                prev_stencil - stencil_one
+            })
+        }).else_if(stencil_op_is(REG_STENCIL_OP_INVERT), {
+            !prev_stencil
+        }).else_if(stencil_op_is(REG_STENCIL_OP_INCR_WRAP), {
+            prev_stencil + stencil_one
+        }).else_if(stencil_op_is(REG_STENCIL_OP_DECR_WRAP), {
+            prev_stencil - stencil_one
+        }).else_({
+            prev_stencil
+        });
+        let next_stencil = (next_stencil & stencil_write_mask) | (prev_stencil & !stencil_write_mask);
 
-        let depth_test_result = z.lt(prev_depth) | !depth_test_enable;
+        let stencil_buffer_write_port_addr = m.output("stencil_buffer_write_port_addr", tile_addr.bits(tile_pixels_bits - 1, 4));
+        let stencil_buffer_write_port_value = m.output("stencil_buffer_write_port_value", next_stencil.repeat(16));
+        let stencil_buffer_write_port_enable = m.output("stencil_buffer_write_port_enable", valid & stencil_test_enable);
+        let stencil_buffer_write_port_word_enable = m.output("stencil_buffer_write_port_word_enable", (0u32..16).fold(None, |acc, x| {
+            let word_enable_bit = tile_addr.bits(3, 0).eq(m.lit(x, 4));
+            Some(if let Some(acc) = acc {
+                word_enable_bit.concat(acc)
+            } else {
+                word_enable_bit
+            })
+        }).unwrap());
 
         // Outputs
         let out_valid = m.output("out_valid", valid);
@@ -973,7 +2166,7 @@ impl<'a> DepthTestPipe<'a> {
         let out_s = m.output("out_s", s);
         let out_t = m.output("out_t", t);
 
-        let out_depth_test_result = m.output("out_depth_test_result", depth_test_result);
+        let out_depth_test_result = m.output("out_depth_test_result", depth_test_result & stencil_test_result);
 
         DepthTestPipe {
             m,
@@ -996,13 +2189,32 @@ impl<'a> DepthTestPipe<'a> {
 
             // Aux inputs
             depth_test_enable,
+            depth_func,
+
+            stencil_test_enable,
+            stencil_func,
+            stencil_sfail_op,
+            stencil_zfail_op,
+            stencil_zpass_op,
+            stencil_ref,
+            stencil_mask,
+            stencil_write_mask,
 
             depth_buffer_read_port_value,
+            stencil_buffer_read_port_value,
 
             // Outputs
             depth_buffer_read_port_addr,
             depth_buffer_read_port_enable,
 
+            stencil_buffer_read_port_addr,
+            stencil_buffer_read_port_enable,
+
+            stencil_buffer_write_port_addr,
+            stencil_buffer_write_port_value,
+            stencil_buffer_write_port_enable,
+            stencil_buffer_write_port_word_enable,
+
             out_valid,
             out_tile_addr,
 
@@ -1047,6 +2259,9 @@ pub struct FrontPipe<'a> {
     pub tex_dim: &'a Input<'a>,
     pub tex_base: &'a Input<'a>,
 
+    pub fog_source: &'a Input<'a>,
+    pub fog_w_shift: &'a Input<'a>,
+
     // Outputs
     pub out_valid: &'a Output<'a>,
     pub out_tile_addr: &'a Output<'a>,
@@ -1063,16 +2278,18 @@ pub struct FrontPipe<'a> {
     pub out_t_fract: &'a Output<'a>,
     pub out_one_minus_t_fract: &'a Output<'a>,
 
+    pub out_fog_index: &'a Output<'a>,
+
     pub out_tex_buffer_read_addrs: Vec<&'a Output<'a>>,
 }
 
 impl<'a> FrontPipe<'a> {
-    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> FrontPipe<'a> {
+    pub fn new(instance_name: impl Into<String>, tile_pixels_bits: u32, p: &'a impl ModuleParent<'a>) -> FrontPipe<'a> {
         let m = p.module(instance_name, "FrontPipe");
 
         // Inputs
         let in_valid = m.input("in_valid", 1);
-        let in_tile_addr = m.input("in_tile_addr", TILE_PIXELS_BITS);
+        let in_tile_addr = m.input("in_tile_addr", tile_pixels_bits);
 
         let in_r = m.input("in_r", COLOR_WHOLE_BITS - 1);
         let in_g = m.input("in_g", COLOR_WHOLE_BITS - 1);
@@ -1091,6 +2308,9 @@ impl<'a> FrontPipe<'a> {
         let tex_dim = m.input("tex_dim", 2);
         let tex_base = m.input("tex_base", REG_TEXTURE_BASE_BITS);
 
+        let fog_source = m.input("fog_source", REG_FOG_SOURCE_BITS);
+        let fog_w_shift = m.input("fog_w_shift", REG_FOG_W_SHIFT_BITS);
+
         let mut valid: &dyn Signal<'a> = in_valid;
         let mut tile_addr: &dyn Signal<'a> = in_tile_addr;
 
@@ -1159,6 +2379,34 @@ impl<'a> FrontPipe<'a> {
         let s = s.reg_next("stage_15_s");
         let t = t.reg_next("stage_15_t");
 
+        let w = w.reg_next("stage_15_w");
+
+        //  Fog table index
+        let max_fog_index = m.lit(REG_FOG_TABLE_ENTRIES - 1, REG_FOG_TABLE_INDEX_BITS);
+        let fog_w_index = (0..=32 - REG_FOG_TABLE_INDEX_BITS).fold(max_fog_index, |acc, shift| {
+            let index = w.bits(shift + REG_FOG_TABLE_INDEX_BITS - 1, shift);
+            let index = if shift + REG_FOG_TABLE_INDEX_BITS < 32 {
+                // Saturate if any bits above the selected window are set
+                if_(w.bits(31, shift + REG_FOG_TABLE_INDEX_BITS).eq(m.lit(0u32, 32 - shift - REG_FOG_TABLE_INDEX_BITS)), {
+                    index
+                }).else_({
+                    max_fog_index
+                })
+            } else {
+                index
+            };
+            if_(fog_w_shift.eq(m.lit(shift, REG_FOG_W_SHIFT_BITS)), {
+                index
+            }).else_({
+                acc
+            })
+        });
+        let fog_index = if_(fog_source.eq(m.lit(REG_FOG_SOURCE_W, REG_FOG_SOURCE_BITS)), {
+            fog_w_index
+        }).else_({
+            z.bits(15, 16 - REG_FOG_TABLE_INDEX_BITS)
+        });
+
         let s_floor = s.bits(31, ST_FRACT_BITS);
         let t_floor = t.bits(31, ST_FRACT_BITS);
         let s_fract = m.low().concat(s.bits(ST_FRACT_BITS - 1, ST_FRACT_BITS - ST_FILTER_FRACT_BITS));
@@ -1203,6 +2451,8 @@ impl<'a> FrontPipe<'a> {
         let out_t_fract = m.output("out_t_fract", t_fract);
         let out_one_minus_t_fract = m.output("out_one_minus_t_fract", one_minus_t_fract);
 
+        let out_fog_index = m.output("out_fog_index", fog_index);
+
         let buffer0_s = (s_floor.bits(6, 0) + m.lit(1u32, 7)).bits(6, 1);
         let buffer0_t = (t_floor.bits(6, 0) + m.lit(1u32, 7)).bits(6, 1);
         let buffer1_s = s_floor.bits(6, 1);
@@ -1271,6 +2521,9 @@ impl<'a> FrontPipe<'a> {
             tex_dim,
             tex_base,
 
+            fog_source,
+            fog_w_shift,
+
             // Outputs
             out_valid,
             out_tile_addr,
@@ -1287,6 +2540,8 @@ impl<'a> FrontPipe<'a> {
             out_t_fract,
             out_one_minus_t_fract,
 
+            out_fog_index,
+
             out_tex_buffer_read_addrs,
         }
     }
@@ -1311,11 +2566,29 @@ pub struct BackPipe<'a> {
     in_t_fract: &'a Input<'a>,
     in_one_minus_t_fract: &'a Input<'a>,
 
+    in_fog_index: &'a Input<'a>,
+
     // Aux inputs
+    in_texture_env_mode: &'a Input<'a>,
+    in_texture_env_color: &'a Input<'a>,
+
     in_depth_write_mask_enable: &'a Input<'a>,
 
     in_blend_src_factor: &'a Input<'a>,
     in_blend_dst_factor: &'a Input<'a>,
+    in_blend_equation: &'a Input<'a>,
+    in_blend_color: &'a Input<'a>,
+
+    in_alpha_test_enable: &'a Input<'a>,
+    in_alpha_test_func: &'a Input<'a>,
+    in_alpha_test_ref: &'a Input<'a>,
+
+    in_fog_enable: &'a Input<'a>,
+    in_fog_color: &'a Input<'a>,
+    in_fog_table: &'a Input<'a>,
+
+    in_occlusion_query_begin: &'a Input<'a>,
+    in_occlusion_query_active: &'a Input<'a>,
 
     in_tex_buffer_read_values: Vec<&'a Input<'a>>,
 
@@ -1335,17 +2608,19 @@ pub struct BackPipe<'a> {
     depth_buffer_write_port_enable: &'a Output<'a>,
     depth_buffer_write_port_word_enable: &'a Output<'a>,
 
+    occlusion_query_passed_samples: &'a Output<'a>,
+
     // Outputs
     out_valid: &'a Output<'a>,
 }
 
 impl<'a> BackPipe<'a> {
-    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> BackPipe<'a> {
+    pub fn new(instance_name: impl Into<String>, tile_pixels_bits: u32, p: &'a impl ModuleParent<'a>) -> BackPipe<'a> {
         let m = p.module(instance_name, "BackPipe");
 
         // Inputs
         let in_valid = m.input("in_valid", 1);
-        let in_tile_addr = m.input("in_tile_addr", TILE_PIXELS_BITS);
+        let in_tile_addr = m.input("in_tile_addr", tile_pixels_bits);
 
         let in_r = m.input("in_r", COLOR_WHOLE_BITS - 1);
         let in_g = m.input("in_g", COLOR_WHOLE_BITS - 1);
@@ -1359,16 +2634,34 @@ impl<'a> BackPipe<'a> {
         let in_t_fract = m.input("in_t_fract", ST_FILTER_FRACT_BITS + 1);
         let in_one_minus_t_fract = m.input("in_one_minus_t_fract", ST_FILTER_FRACT_BITS + 1);
 
+        let in_fog_index = m.input("in_fog_index", REG_FOG_TABLE_INDEX_BITS);
+
         let mut in_tex_buffer_read_values = Vec::new();
         for i in 0..4 {
             in_tex_buffer_read_values.push(m.input(format!("in_tex_buffer{}_read_value", i), 32));
         }
 
         // Aux inputs
+        let in_texture_env_mode = m.input("in_texture_env_mode", REG_TEXTURE_ENV_MODE_BITS);
+        let in_texture_env_color = m.input("in_texture_env_color", REG_TEXTURE_ENV_COLOR_BITS);
+
         let in_depth_write_mask_enable = m.input("in_depth_write_mask_enable", 1);
 
         let in_blend_src_factor = m.input("in_blend_src_factor", REG_BLEND_SETTINGS_SRC_FACTOR_BITS);
         let in_blend_dst_factor = m.input("in_blend_dst_factor", REG_BLEND_SETTINGS_DST_FACTOR_BITS);
+        let in_blend_equation = m.input("in_blend_equation", REG_BLEND_SETTINGS_EQUATION_BITS);
+        let in_blend_color = m.input("in_blend_color", REG_BLEND_COLOR_BITS);
+
+        let in_alpha_test_enable = m.input("in_alpha_test_enable", 1);
+        let in_alpha_test_func = m.input("in_alpha_test_func", REG_ALPHA_TEST_FUNC_BITS);
+        let in_alpha_test_ref = m.input("in_alpha_test_ref", REG_ALPHA_TEST_REF_BITS);
+
+        let in_fog_enable = m.input("in_fog_enable", 1);
+        let in_fog_color = m.input("in_fog_color", REG_FOG_COLOR_BITS);
+        let in_fog_table = m.input("in_fog_table", REG_FOG_TABLE_ENTRIES * REG_FOG_TABLE_VALUE_BITS);
+
+        let in_occlusion_query_begin = m.input("in_occlusion_query_begin", 1);
+        let in_occlusion_query_active = m.input("in_occlusion_query_active", 1);
 
         let valid = in_valid;
         let tile_addr = in_tile_addr;
@@ -1385,10 +2678,28 @@ impl<'a> BackPipe<'a> {
         let t_fract = in_t_fract;
         let one_minus_t_fract = in_one_minus_t_fract;
 
+        let fog_index = in_fog_index;
+
+        let texture_env_mode = in_texture_env_mode;
+        let texture_env_color = Texel::new(in_texture_env_color);
+
         let depth_write_mask_enable = in_depth_write_mask_enable;
 
         let blend_src_factor = in_blend_src_factor;
         let blend_dst_factor = in_blend_dst_factor;
+        let blend_equation = in_blend_equation;
+        let blend_color = Texel::new(in_blend_color);
+
+        let alpha_test_enable = in_alpha_test_enable;
+        let alpha_test_func = in_alpha_test_func;
+        let alpha_test_ref = in_alpha_test_ref;
+
+        let fog_enable = in_fog_enable;
+        let fog_color = Texel::new(in_fog_color);
+        let fog_table = in_fog_table;
+
+        let occlusion_query_begin = in_occlusion_query_begin;
+        let occlusion_query_active = in_occlusion_query_active;
 
         // Stage 1
         let valid = valid.reg_next_with_default("stage_1_valid", false);
@@ -1401,6 +2712,8 @@ impl<'a> BackPipe<'a> {
 
         let z = z.reg_next("stage_1_z");
 
+        let fog_index = fog_index.reg_next("stage_1_fog_index");
+
         let s_fract = s_fract.reg_next("stage_1_s_fract");
         let one_minus_s_fract = one_minus_s_fract.reg_next("stage_1_one_minus_s_fract");
         let t_fract = t_fract.reg_next("stage_1_t_fract");
@@ -1479,6 +2792,8 @@ impl<'a> BackPipe<'a> {
 
         let z = z.reg_next("stage_2_z");
 
+        let fog_index = fog_index.reg_next("stage_2_fog_index");
+
         let t_fract = t_fract.reg_next("stage_2_t_fract");
         let one_minus_t_fract = one_minus_t_fract.reg_next("stage_2_one_minus_t_fract");
 
@@ -1498,19 +2813,79 @@ impl<'a> BackPipe<'a> {
 
         let z = z.reg_next("stage_3_z");
 
+        let fog_index = fog_index.reg_next("stage_3_fog_index");
+
         let texel = Texel::new(texel.reg_next("stage_3_texel"));
 
+        //  Texture environment
+        let saturate_comp = |comp: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            if_(comp.bit(9), {
+                m.lit(0x1ffu32, 9)
+            }).else_({
+                comp.bits(8, 0)
+            })
+        };
         let scale_comp = |color_comp: &'a dyn Signal<'a>, texel_comp: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
             (color_comp * texel_comp).bits(16, 8)
         };
+        let add_comp = |color_comp: &'a dyn Signal<'a>, texel_comp: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            saturate_comp(m.low().concat(color_comp) + m.lit(0u32, 2).concat(texel_comp))
+        };
+        // color_comp * (1 - weight) + other_comp * weight
+        let lerp_comp = |color_comp: &'a dyn Signal<'a>, other_comp: &'a dyn Signal<'a>, weight: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            let one = m.high().concat(m.lit(0u32, 8));
+            let weight = m.low().concat(weight);
+            saturate_comp((color_comp * (one - weight) + m.low().concat(other_comp) * weight).bits(17, 8))
+        };
 
-        let r = scale_comp(r, texel.r);
-        let g = scale_comp(g, texel.g);
-        let b = scale_comp(b, texel.b);
-        let a = scale_comp(a, texel.a);
+        let texture_env_mode_is = |mode: u32| -> &'a dyn Signal<'a> {
+            texture_env_mode.eq(m.lit(mode, REG_TEXTURE_ENV_MODE_BITS))
+        };
+        let texel_a = texel.a;
+        let combine_color_comp = |color_comp: &'a dyn Signal<'a>, texel_comp: &'a dyn Signal<'a>, env_color_comp: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            if_(texture_env_mode_is(REG_TEXTURE_ENV_MODE_REPLACE), {
+                m.low().concat(texel_comp)
+            }).else_if(texture_env_mode_is(REG_TEXTURE_ENV_MODE_DECAL), {
+                lerp_comp(color_comp, texel_comp, texel_a)
+            }).else_if(texture_env_mode_is(REG_TEXTURE_ENV_MODE_ADD), {
+                add_comp(color_comp, texel_comp)
+            }).else_if(texture_env_mode_is(REG_TEXTURE_ENV_MODE_BLEND), {
+                lerp_comp(color_comp, env_color_comp, texel_comp)
+            }).else_if(texture_env_mode_is(REG_TEXTURE_ENV_MODE_UNTEXTURED), {
+                color_comp
+            }).else_({
+                scale_comp(color_comp, texel_comp)
+            })
+        };
+
+        let r = combine_color_comp(r, texel.r, texture_env_color.r);
+        let g = combine_color_comp(g, texel.g, texture_env_color.g);
+        let b = combine_color_comp(b, texel.b, texture_env_color.b);
+        let a = if_(texture_env_mode_is(REG_TEXTURE_ENV_MODE_REPLACE), {
+            m.low().concat(texel.a)
+        }).else_if(texture_env_mode_is(REG_TEXTURE_ENV_MODE_DECAL) | texture_env_mode_is(REG_TEXTURE_ENV_MODE_UNTEXTURED), {
+            a
+        }).else_({
+            scale_comp(a, texel.a)
+        });
+
+        //  Fog table lookup
+        let fog_amount = (1..REG_FOG_TABLE_ENTRIES).fold(fog_table.bits(REG_FOG_TABLE_VALUE_BITS - 1, 0), |acc, x| {
+            if_(fog_index.eq(m.lit(x, REG_FOG_TABLE_INDEX_BITS)), {
+                fog_table.bits((x + 1) * REG_FOG_TABLE_VALUE_BITS - 1, x * REG_FOG_TABLE_VALUE_BITS)
+            }).else_({
+                acc
+            })
+        });
+        //   Map [0, 255] to [0, 256] so that a full table entry replaces the color entirely
+        let fog_weight = if_(fog_enable, {
+            m.low().concat(fog_amount) + m.lit(0u32, 8).concat(fog_amount.bit(7))
+        }).else_({
+            m.lit(0u32, 9)
+        });
 
         //  Issue color buffer read for prev_color
-        let color_buffer_read_port_addr = m.output("color_buffer_read_port_addr", tile_addr.bits(TILE_PIXELS_BITS - 1, 2));
+        let color_buffer_read_port_addr = m.output("color_buffer_read_port_addr", tile_addr.bits(tile_pixels_bits - 1, 2));
         let color_buffer_read_port_enable = m.output("color_buffer_read_port_enable", valid);
 
         // Stage 4
@@ -1524,28 +2899,28 @@ impl<'a> BackPipe<'a> {
 
         let z = z.reg_next("stage_4_z");
 
-        let zero = m.lit(0u32, 9);
-        let one = m.high().concat(m.lit(0u32, 8));
+        let fog_weight = fog_weight.reg_next("stage_4_fog_weight");
 
-        let blend_src_factor = if_(blend_src_factor.eq(m.lit(REG_BLEND_SETTINGS_SRC_FACTOR_ZERO, REG_BLEND_SETTINGS_SRC_FACTOR_BITS)), {
-            zero
-        }).else_if(blend_src_factor.eq(m.lit(REG_BLEND_SETTINGS_SRC_FACTOR_ONE, REG_BLEND_SETTINGS_SRC_FACTOR_BITS)), {
-            one
-        }).else_if(blend_src_factor.eq(m.lit(REG_BLEND_SETTINGS_SRC_FACTOR_SRC_ALPHA, REG_BLEND_SETTINGS_SRC_FACTOR_BITS)), {
-            a
-        }).else_({
-            one - a
-        });
+        //  Fog (alpha is left untouched)
+        let fog_comp = |comp: &'a dyn Signal<'a>, fog_color_comp: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            let one = m.high().concat(m.lit(0u32, 8));
+            (comp * (one - fog_weight) + m.low().concat(fog_color_comp) * fog_weight).bits(16, 8)
+        };
+        let r = fog_comp(r, fog_color.r);
+        let g = fog_comp(g, fog_color.g);
+        let b = fog_comp(b, fog_color.b);
 
-        let blend_dst_factor = if_(blend_dst_factor.eq(m.lit(REG_BLEND_SETTINGS_DST_FACTOR_ZERO, REG_BLEND_SETTINGS_DST_FACTOR_BITS)), {
-            zero
-        }).else_if(blend_dst_factor.eq(m.lit(REG_BLEND_SETTINGS_DST_FACTOR_ONE, REG_BLEND_SETTINGS_DST_FACTOR_BITS)), {
-            one
-        }).else_if(blend_dst_factor.eq(m.lit(REG_BLEND_SETTINGS_DST_FACTOR_SRC_ALPHA, REG_BLEND_SETTINGS_DST_FACTOR_BITS)), {
-            a
+        //  Alpha test against the clamped alpha value that will end up in the color buffer
+        let clamped_a = if_(a.bit(8), {
+            m.lit(255u32, 8)
         }).else_({
-            one - a
+            a.bits(7, 0)
         });
+        let alpha_test_result =
+            (alpha_test_func.bit(0) & clamped_a.lt(alpha_test_ref)) |
+            (alpha_test_func.bit(1) & clamped_a.eq(alpha_test_ref)) |
+            (alpha_test_func.bit(2) & alpha_test_ref.lt(clamped_a)) |
+            !alpha_test_enable;
 
         //  Returned from issue in previous stage
         let color_buffer_read_port_value = m.input("color_buffer_read_port_value", 128);
@@ -1560,6 +2935,70 @@ impl<'a> BackPipe<'a> {
             prev_color.bits(127, 96)
         });
 
+        let prev_r = prev_color.bits(23, 16);
+        let prev_g = prev_color.bits(15, 8);
+        let prev_b = prev_color.bits(7, 0);
+        let prev_a = prev_color.bits(31, 24);
+
+        let zero = m.lit(0u32, 9);
+        let one = m.high().concat(m.lit(0u32, 8));
+
+        let select_factor = |factor: &'a dyn Signal<'a>, factor_bits: u32, options: &[(u32, &'a dyn Signal<'a>)]| -> &'a dyn Signal<'a> {
+            options.iter().fold(zero, |acc, &(value, option)| {
+                if_(factor.eq(m.lit(value, factor_bits)), {
+                    option
+                }).else_({
+                    acc
+                })
+            })
+        };
+
+        let blend_src_factor_comp = |comp: &'a dyn Signal<'a>, prev_comp: &'a dyn Signal<'a>, blend_color_comp: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            let prev_comp = m.low().concat(prev_comp);
+            let blend_color_comp = m.low().concat(blend_color_comp);
+            let prev_a = m.low().concat(prev_a);
+            select_factor(blend_src_factor, REG_BLEND_SETTINGS_SRC_FACTOR_BITS, &[
+                (REG_BLEND_SETTINGS_SRC_FACTOR_ONE, one),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_SRC_ALPHA, a),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_ALPHA, one - a),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_DST_COLOR, prev_comp),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_COLOR, one - prev_comp),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_SRC_COLOR, comp),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_COLOR, one - comp),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_DST_ALPHA, prev_a),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_ALPHA, one - prev_a),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_CONSTANT_COLOR, blend_color_comp),
+                (REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_CONSTANT_COLOR, one - blend_color_comp),
+            ])
+        };
+
+        let blend_dst_factor_comp = |comp: &'a dyn Signal<'a>, prev_comp: &'a dyn Signal<'a>, blend_color_comp: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            let prev_comp = m.low().concat(prev_comp);
+            let blend_color_comp = m.low().concat(blend_color_comp);
+            let prev_a = m.low().concat(prev_a);
+            select_factor(blend_dst_factor, REG_BLEND_SETTINGS_DST_FACTOR_BITS, &[
+                (REG_BLEND_SETTINGS_DST_FACTOR_ONE, one),
+                (REG_BLEND_SETTINGS_DST_FACTOR_SRC_ALPHA, a),
+                (REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_ALPHA, one - a),
+                (REG_BLEND_SETTINGS_DST_FACTOR_DST_COLOR, prev_comp),
+                (REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_COLOR, one - prev_comp),
+                (REG_BLEND_SETTINGS_DST_FACTOR_SRC_COLOR, comp),
+                (REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_COLOR, one - comp),
+                (REG_BLEND_SETTINGS_DST_FACTOR_DST_ALPHA, prev_a),
+                (REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_ALPHA, one - prev_a),
+                (REG_BLEND_SETTINGS_DST_FACTOR_CONSTANT_COLOR, blend_color_comp),
+                (REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_CONSTANT_COLOR, one - blend_color_comp),
+            ])
+        };
+
+        let r_src_factor = blend_src_factor_comp(r, prev_r, blend_color.r);
+        let g_src_factor = blend_src_factor_comp(g, prev_g, blend_color.g);
+        let b_src_factor = blend_src_factor_comp(b, prev_b, blend_color.b);
+
+        let r_dst_factor = blend_dst_factor_comp(r, prev_r, blend_color.r);
+        let g_dst_factor = blend_dst_factor_comp(g, prev_g, blend_color.g);
+        let b_dst_factor = blend_dst_factor_comp(b, prev_b, blend_color.b);
+
         // Stage 5
         let valid = valid.reg_next_with_default("stage_5_valid", false);
         let tile_addr = tile_addr.reg_next("stage_5_tile_addr");
@@ -1571,18 +3010,17 @@ impl<'a> BackPipe<'a> {
 
         let z = z.reg_next("stage_5_z");
 
-        let blend_src_factor = blend_src_factor.reg_next("stage_5_blend_src_factor");
-        let blend_dst_factor = blend_dst_factor.reg_next("stage_5_blend_dst_factor");
+        let r_src_factor = r_src_factor.reg_next("stage_5_r_src_factor");
+        let g_src_factor = g_src_factor.reg_next("stage_5_g_src_factor");
+        let b_src_factor = b_src_factor.reg_next("stage_5_b_src_factor");
 
-        let prev_color = prev_color.reg_next("stage_5_prev_color");
+        let r_dst_factor = r_dst_factor.reg_next("stage_5_r_dst_factor");
+        let g_dst_factor = g_dst_factor.reg_next("stage_5_g_dst_factor");
+        let b_dst_factor = b_dst_factor.reg_next("stage_5_b_dst_factor");
 
-        let r = (r * blend_src_factor).bits(17, 8);
-        let g = (g * blend_src_factor).bits(17, 8);
-        let b = (b * blend_src_factor).bits(17, 8);
+        let alpha_test_result = alpha_test_result.reg_next("stage_5_alpha_test_result");
 
-        let prev_r = m.lit(0u32, 2).concat((prev_color.bits(23, 16) * blend_dst_factor).bits(16, 9));
-        let prev_g = m.lit(0u32, 2).concat((prev_color.bits(15, 8) * blend_dst_factor).bits(16, 9));
-        let prev_b = m.lit(0u32, 2).concat((prev_color.bits(7, 0) * blend_dst_factor).bits(16, 9));
+        let prev_color = prev_color.reg_next("stage_5_prev_color");
 
         let clamp_comp = |comp: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
             if_(comp.bits(9, 8).eq(m.lit(0u32, 2)), {
@@ -1592,9 +3030,48 @@ impl<'a> BackPipe<'a> {
             })
         };
 
-        let r = clamp_comp(r + prev_r);
-        let g = clamp_comp(g + prev_g);
-        let b = clamp_comp(b + prev_b);
+        let blend_equation_is = |equation: u32| -> &'a dyn Signal<'a> {
+            blend_equation.eq(m.lit(equation, REG_BLEND_SETTINGS_EQUATION_BITS))
+        };
+        let blend_comp = |comp: &'a dyn Signal<'a>, prev_comp: &'a dyn Signal<'a>, src_factor: &'a dyn Signal<'a>, dst_factor: &'a dyn Signal<'a>| -> &'a dyn Signal<'a> {
+            let src_term = (comp * src_factor).bits(17, 8);
+            let dst_term = m.lit(0u32, 2).concat((prev_comp * dst_factor).bits(16, 9));
+
+            let comp = m.low().concat(comp);
+            let prev_comp = m.lit(0u32, 2).concat(prev_comp);
+
+            clamp_comp(if_(blend_equation_is(REG_BLEND_SETTINGS_EQUATION_SUBTRACT), {
+                if_(src_term.lt(dst_term), {
+                    m.lit(0u32, 10)
+                }).else_({
+                    src_term - dst_term
+                })
+            }).else_if(blend_equation_is(REG_BLEND_SETTINGS_EQUATION_REVERSE_SUBTRACT), {
+                if_(dst_term.lt(src_term), {
+                    m.lit(0u32, 10)
+                }).else_({
+                    dst_term - src_term
+                })
+            }).else_if(blend_equation_is(REG_BLEND_SETTINGS_EQUATION_MIN), {
+                if_(comp.lt(prev_comp), {
+                    comp
+                }).else_({
+                    prev_comp
+                })
+            }).else_if(blend_equation_is(REG_BLEND_SETTINGS_EQUATION_MAX), {
+                if_(prev_comp.lt(comp), {
+                    comp
+                }).else_({
+                    prev_comp
+                })
+            }).else_({
+                src_term + dst_term
+            }))
+        };
+
+        let r = blend_comp(r, prev_color.bits(23, 16), r_src_factor, r_dst_factor);
+        let g = blend_comp(g, prev_color.bits(15, 8), g_src_factor, g_dst_factor);
+        let b = blend_comp(b, prev_color.bits(7, 0), b_src_factor, b_dst_factor);
         let a = clamp_comp(m.low().concat(a));
 
         let color = a.concat(r).concat(g).concat(b);
@@ -1607,9 +3084,11 @@ impl<'a> BackPipe<'a> {
 
         let color = color.reg_next("stage_6_color");
 
-        let color_buffer_write_port_addr = m.output("color_buffer_write_port_addr", tile_addr.bits(TILE_PIXELS_BITS - 1, 2));
+        let alpha_test_result = alpha_test_result.reg_next("stage_6_alpha_test_result");
+
+        let color_buffer_write_port_addr = m.output("color_buffer_write_port_addr", tile_addr.bits(tile_pixels_bits - 1, 2));
         let color_buffer_write_port_value = m.output("color_buffer_write_port_value", color.repeat(4));
-        let color_buffer_write_port_enable = m.output("color_buffer_write_port_enable", valid);
+        let color_buffer_write_port_enable = m.output("color_buffer_write_port_enable", valid & alpha_test_result);
         let color_buffer_write_port_word_enable = m.output("color_buffer_write_port_word_enable", (0u32..4).fold(None, |acc, x| {
             let word_enable_bit = tile_addr.bits(1, 0).eq(m.lit(x, 2));
             Some(if let Some(acc) = acc {
@@ -1619,9 +3098,9 @@ impl<'a> BackPipe<'a> {
             })
         }).unwrap());
 
-        let depth_buffer_write_port_addr = m.output("depth_buffer_write_port_addr", tile_addr.bits(TILE_PIXELS_BITS - 1, 3));
+        let depth_buffer_write_port_addr = m.output("depth_buffer_write_port_addr", tile_addr.bits(tile_pixels_bits - 1, 3));
         let depth_buffer_write_port_value = m.output("depth_buffer_write_port_value", z.repeat(8));
-        let depth_buffer_write_port_enable = m.output("depth_buffer_write_port_enable", valid & depth_write_mask_enable);
+        let depth_buffer_write_port_enable = m.output("depth_buffer_write_port_enable", valid & alpha_test_result & depth_write_mask_enable);
         let depth_buffer_write_port_word_enable = m.output("depth_buffer_write_port_word_enable", (0u32..8).fold(None, |acc, x| {
             let word_enable_bit = tile_addr.bits(2, 0).eq(m.lit(x, 3));
             Some(if let Some(acc) = acc {
@@ -1631,6 +3110,20 @@ impl<'a> BackPipe<'a> {
             })
         }).unwrap());
 
+        //  Occlusion query
+        //   Pixels only reach this pipe once they've passed the depth and stencil tests, so counting them
+        //   here, alongside the color write, leaves out samples discarded by the alpha test as well
+        let passed_samples = m.reg("occlusion_query_passed_samples", 32);
+        passed_samples.default_value(0u32);
+        passed_samples.drive_next(if_(occlusion_query_begin, {
+            m.lit(0u32, 32)
+        }).else_if(valid & occlusion_query_active & alpha_test_result, {
+            passed_samples + m.lit(1u32, 32)
+        }).else_({
+            passed_samples
+        }));
+        let occlusion_query_passed_samples = m.output("occlusion_query_passed_samples", passed_samples);
+
         // Outputs
         let out_valid = m.output("out_valid", valid);
 
@@ -1653,11 +3146,29 @@ impl<'a> BackPipe<'a> {
             in_t_fract,
             in_one_minus_t_fract,
 
+            in_fog_index,
+
             // Aux inputs
+            in_texture_env_mode,
+            in_texture_env_color,
+
             in_depth_write_mask_enable,
 
             in_blend_src_factor,
             in_blend_dst_factor,
+            in_blend_equation,
+            in_blend_color,
+
+            in_alpha_test_enable,
+            in_alpha_test_func,
+            in_alpha_test_ref,
+
+            in_fog_enable,
+            in_fog_color,
+            in_fog_table,
+
+            in_occlusion_query_begin,
+            in_occlusion_query_active,
 
             in_tex_buffer_read_values,
 
@@ -1679,6 +3190,8 @@ impl<'a> BackPipe<'a> {
             depth_buffer_write_port_value,
             depth_buffer_write_port_enable,
             depth_buffer_write_port_word_enable,
+
+            occlusion_query_passed_samples,
         }
     }
 }
@@ -12,6 +12,8 @@ pub struct TexCache<'a> {
     pub m: &'a Module<'a>,
 
     pub invalidate: &'a Input<'a>,
+    // Untextured pixels pass through without issuing any block cache reads
+    pub bypass: &'a Input<'a>,
     pub in_valid: &'a Input<'a>,
     pub in_ready: &'a Output<'a>,
     pub out_valid: &'a Output<'a>,
@@ -26,15 +28,17 @@ pub struct TexCache<'a> {
 }
 
 impl<'a> TexCache<'a> {
-    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> TexCache<'a> {
+    pub fn new(instance_name: impl Into<String>, tile_pixels_bits: u32, p: &'a impl ModuleParent<'a>) -> TexCache<'a> {
         let m = p.module(instance_name, "TexCache");
 
         let invalidate = m.input("invalidate", 1);
+        let bypass = m.input("bypass", 1);
 
         let in_valid = m.input("in_valid", 1);
 
         let issue_buffer_occupied = m.reg("issue_buffer_occupied", 1);
         issue_buffer_occupied.default_value(false);
+        let issue_buffer_bypass = m.reg("issue_buffer_bypass", 1);
 
         let block_cache_crossbar = Crossbar::new("block_cache_crossbar", 4, 1, SYSTEM_BUS_ADDR_BITS, 0, 128, 5, m);
         let system_port = block_cache_crossbar.primary_ports[0].forward("system", m);
@@ -66,7 +70,7 @@ impl<'a> TexCache<'a> {
         }).collect::<Vec<_>>();
         let (caches_in_ready, caches_return_data_valid) = acc.unwrap();
 
-        let out_valid = issue_buffer_occupied & caches_return_data_valid;
+        let out_valid = issue_buffer_occupied & (issue_buffer_bypass | caches_return_data_valid);
 
         //  Note that we exploit implementation details of `ReadCache` - namely that we
         //   know that its `client_bus_ready` output is independent of its
@@ -74,7 +78,7 @@ impl<'a> TexCache<'a> {
         //   connect between the caches on the primary side (which may introduce some
         //   interdepencies), we know that they can be in a state where all of them can
         //   accept reads simultaneously. This simplifies issue logic in this cache.
-        let can_accept_issue = caches_in_ready & (!issue_buffer_occupied | caches_return_data_valid);
+        let can_accept_issue = caches_in_ready & (!issue_buffer_occupied | out_valid);
         let in_ready = m.output("in_ready", can_accept_issue);
 
         let accept_issue = can_accept_issue & in_valid;
@@ -87,14 +91,20 @@ impl<'a> TexCache<'a> {
             issue_buffer_occupied
         }));
 
+        issue_buffer_bypass.drive_next(if_(accept_issue, {
+            bypass
+        }).else_({
+            issue_buffer_bypass
+        }));
+
         for block_cache in block_caches {
-            block_cache.issue.drive(accept_issue);
+            block_cache.issue.drive(accept_issue & !bypass);
         }
 
         let mut forward_inputs = HashMap::new();
         let mut forward_outputs = HashMap::new();
         for &(name, bit_width) in [
-            ("tile_addr", TILE_PIXELS_BITS),
+            ("tile_addr", tile_pixels_bits),
 
             ("r", COLOR_WHOLE_BITS - 1),
             ("g", COLOR_WHOLE_BITS - 1),
@@ -107,6 +117,8 @@ impl<'a> TexCache<'a> {
             ("one_minus_s_fract", ST_FILTER_FRACT_BITS + 1),
             ("t_fract", ST_FILTER_FRACT_BITS + 1),
             ("one_minus_t_fract", ST_FILTER_FRACT_BITS + 1),
+
+            ("fog_index", REG_FOG_TABLE_INDEX_BITS),
         ].iter() {
             let input = m.input(format!("in_{}", name), bit_width);
             let reg = m.reg(format!("{}_forward", name), bit_width);
@@ -124,6 +136,7 @@ impl<'a> TexCache<'a> {
             m,
 
             invalidate,
+            bypass,
             in_valid,
             in_ready,
             out_valid: m.output("out_valid", out_valid),
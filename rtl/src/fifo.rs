@@ -12,6 +12,9 @@ pub struct Fifo<'a> {
     pub empty: &'a Output<'a>,
     pub read_enable: &'a Input<'a>,
     pub read_data: &'a Output<'a>,
+
+    // Number of elements currently held (depth_bit_width + 1 bits)
+    pub count: &'a Output<'a>,
 }
 
 impl<'a> Fifo<'a> {
@@ -79,6 +82,8 @@ impl<'a> Fifo<'a> {
             empty: m.output("empty", empty),
             read_enable,
             read_data,
+
+            count: m.output("count", count),
         }
     }
 }
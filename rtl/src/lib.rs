@@ -1,4 +1,5 @@
 pub mod approx_reciprocal;
+pub mod audio;
 pub mod bit_pusher;
 pub mod boot_rom;
 pub mod buster;
@@ -13,6 +14,8 @@ pub mod marv_system_bridge;
 pub mod mimas_a7;
 pub mod peek_buffer;
 pub mod read_cache;
+pub mod scanout;
+pub mod spi;
 pub mod uart;
 pub mod uart_interface;
 pub mod word_mem;
@@ -1,4 +1,5 @@
 mod approx_reciprocal;
+mod audio;
 mod bit_pusher;
 mod boot_rom;
 mod byte_ram;
@@ -13,6 +14,8 @@ mod marv_system_bridge;
 mod mimas_a7;
 mod peek_buffer;
 mod read_cache;
+mod scanout;
+mod spi;
 mod uart;
 mod uart_interface;
 mod wire;
@@ -35,9 +38,7 @@ fn main() -> Result<()> {
     let xenowing = Xenowing::new("xenowing", &c);
     let lfsr = Lfsr::new("lfsr", &c);
     let uart = Uart::new("uart", &c);
-    let clock_freq = 100000000;
-    let uart_baud_rate = 460800;
-    let uart_tx = UartTx::new("uart_tx", clock_freq, uart_baud_rate, &c);
+    let uart_tx = UartTx::new("uart_tx", &c);
     let buster_mig_ui_bridge = BusterMigUiBridge::new("buster_mig_ui_bridge", 128, 24, &c);
 
     verilog::generate(xenowing.m, stdout())?;
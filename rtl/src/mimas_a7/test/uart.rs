@@ -3,6 +3,8 @@ use super::lfsr::*;
 
 use kaze::*;
 
+use rtl_meta::uart::*;
+
 pub struct Uart<'a> {
     pub m: &'a Module<'a>,
     pub rx: &'a Input<'a>,
@@ -19,11 +21,11 @@ impl<'a> Uart<'a> {
 
         let write_enable = !has_errored;
 
-        let clock_freq = 100000000;
-        let uart_baud_rate = 460800;
+        let baud_divisor = m.lit(REG_BAUD_DIVISOR_DEFAULT, REG_BAUD_DIVISOR_BITS);
 
-        let uart_tx = UartTx::new("uart_tx", clock_freq, uart_baud_rate, m);
+        let uart_tx = UartTx::new("uart_tx", m);
         uart_tx.enable.drive(write_enable);
+        uart_tx.baud_divisor.drive(baud_divisor);
         let tx = m.output("tx", uart_tx.tx);
 
         let tx_lfsr = Lfsr::new("tx_lfsr", m);
@@ -31,8 +33,9 @@ impl<'a> Uart<'a> {
         uart_tx.data.drive(tx_lfsr.value);
 
         let rx = m.input("rx", 1);
-        let uart_rx = UartRx::new("uart_rx", clock_freq, uart_baud_rate, m);
+        let uart_rx = UartRx::new("uart_rx", m);
         uart_rx.rx.drive(rx);
+        uart_rx.baud_divisor.drive(baud_divisor);
         let read_data = uart_rx.data;
         let read_data_valid = uart_rx.data_valid;
 
@@ -0,0 +1,570 @@
+use crate::buster::*;
+use crate::fifo::*;
+
+use kaze::*;
+
+use rtl_meta::scanout::*;
+use rtl_meta::xenowing::*;
+
+pub struct Scanout<'a> {
+    pub m: &'a Module<'a>,
+
+    pub reg_port: ReplicaPort<'a>,
+
+    pub mem_port: PrimaryPort<'a>,
+
+    // High for one cycle per pixel; all video outputs only change on the cycle following it
+    pub pixel_clock_enable: &'a Output<'a>,
+    pub hsync_n: &'a Output<'a>,
+    pub vsync_n: &'a Output<'a>,
+    pub de: &'a Output<'a>,
+    pub rgb: &'a Output<'a>,
+}
+
+impl<'a> Scanout<'a> {
+    // The pixel clock is derived from the system clock by dividing it by 2^pixel_clock_divider_bits
+    pub fn new(instance_name: impl Into<String>, pixel_clock_divider_bits: u32, p: &'a impl ModuleParent<'a>) -> Scanout<'a> {
+        let m = p.module(instance_name, "Scanout");
+
+        let reg_bus_enable = m.input("reg_bus_enable", 1);
+        let reg_bus_addr = m.input("reg_bus_addr", REG_BUS_ADDR_BITS);
+        let reg_bus_write = m.input("reg_bus_write", 1);
+        let reg_bus_write_data = m.input("reg_bus_write_data", 128);
+        let reg_bus_write_byte_enable = m.input("reg_bus_write_byte_enable", 128 / 8);
+        let reg_bus_ready = m.output("reg_bus_ready", m.lit(true, 1));
+        let reg_bus_read_data_valid = m.output(
+            "reg_bus_read_data_valid",
+            (reg_bus_enable & !reg_bus_write).reg_next_with_default("reg_bus_read_data_valid_reg", false),
+        );
+
+        let reg_write = reg_bus_enable & reg_bus_write;
+        let reg_addr = reg_bus_addr.bits(REG_BUS_ADDR_BIT_WIDTH - 1, 0);
+
+        let mem_bus_ready = m.input("mem_bus_ready", 1);
+        let mem_bus_read_data = m.input("mem_bus_read_data", 128);
+        let mem_bus_read_data_valid = m.input("mem_bus_read_data_valid", 1);
+
+        let enable_reg = m.reg("enable_reg", 1);
+        enable_reg.default_value(false);
+        enable_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_ENABLE_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bit(0)
+        }).else_({
+            enable_reg
+        }));
+
+        let write_front_buffer_base = reg_write & reg_addr.eq(m.lit(REG_FRONT_BUFFER_BASE_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+        let front_buffer_base_reg = m.reg("front_buffer_base_reg", SYSTEM_BUS_ADDR_BITS);
+        front_buffer_base_reg.default_value(0u32);
+        front_buffer_base_reg.drive_next(if_(write_front_buffer_base, {
+            reg_bus_write_data.bits(SYSTEM_BUS_ADDR_BITS + 4 - 1, 4)
+        }).else_({
+            front_buffer_base_reg
+        }));
+
+        let line_stride_reg = m.reg("line_stride_reg", SYSTEM_BUS_ADDR_BITS);
+        line_stride_reg.default_value(REG_LINE_STRIDE_DEFAULT);
+        line_stride_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_LINE_STRIDE_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(SYSTEM_BUS_ADDR_BITS - 1, 0)
+        }).else_({
+            line_stride_reg
+        }));
+
+        let format_reg = m.reg("format_reg", REG_FORMAT_BITS);
+        format_reg.default_value(REG_FORMAT_ARGB8888);
+        format_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_FORMAT_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(REG_FORMAT_BITS - 1, 0)
+        }).else_({
+            format_reg
+        }));
+
+        let overlay_enable_reg = m.reg("overlay_enable_reg", 1);
+        overlay_enable_reg.default_value(false);
+        overlay_enable_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_OVERLAY_ENABLE_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bit(0)
+        }).else_({
+            overlay_enable_reg
+        }));
+
+        let overlay_base_reg = m.reg("overlay_base_reg", SYSTEM_BUS_ADDR_BITS);
+        overlay_base_reg.default_value(0u32);
+        overlay_base_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_OVERLAY_BASE_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(SYSTEM_BUS_ADDR_BITS + 4 - 1, 4)
+        }).else_({
+            overlay_base_reg
+        }));
+
+        let overlay_line_stride_reg = m.reg("overlay_line_stride_reg", SYSTEM_BUS_ADDR_BITS);
+        overlay_line_stride_reg.default_value(REG_OVERLAY_LINE_STRIDE_DEFAULT);
+        overlay_line_stride_reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(REG_OVERLAY_LINE_STRIDE_ADDR, REG_BUS_ADDR_BIT_WIDTH)), {
+            reg_bus_write_data.bits(SYSTEM_BUS_ADDR_BITS - 1, 0)
+        }).else_({
+            overlay_line_stride_reg
+        }));
+
+        let palette = m.mem("palette", REG_PALETTE_INDEX_BITS, REG_PALETTE_VALUE_BITS);
+        palette.initial_contents(&(0..REG_PALETTE_ENTRIES).map(|i| (i << 16) | (i << 8) | i).collect::<Vec<_>>());
+        palette.write_port(
+            reg_bus_write_data.bits(REG_PALETTE_INDEX_BIT_OFFSET + REG_PALETTE_INDEX_BITS - 1, REG_PALETTE_INDEX_BIT_OFFSET),
+            reg_bus_write_data.bits(REG_PALETTE_VALUE_BIT_OFFSET + REG_PALETTE_VALUE_BITS - 1, REG_PALETTE_VALUE_BIT_OFFSET),
+            reg_write & reg_addr.eq(m.lit(REG_PALETTE_ADDR, REG_BUS_ADDR_BIT_WIDTH)));
+
+        // One LUT per component, so all three can be looked up at once
+        let gamma_lut_write_enable = reg_write & reg_addr.eq(m.lit(REG_GAMMA_LUT_ADDR, REG_BUS_ADDR_BIT_WIDTH));
+        let gamma_lut_write_index = reg_bus_write_data.bits(REG_GAMMA_LUT_INDEX_BIT_OFFSET + REG_GAMMA_LUT_INDEX_BITS - 1, REG_GAMMA_LUT_INDEX_BIT_OFFSET);
+        let gamma_lut = |name: &str, bit_offset: u32| {
+            let lut = m.mem(name, REG_GAMMA_LUT_INDEX_BITS, 8);
+            lut.initial_contents(&(0..REG_GAMMA_LUT_ENTRIES).collect::<Vec<_>>());
+            lut.write_port(
+                gamma_lut_write_index,
+                reg_bus_write_data.bits(REG_GAMMA_LUT_VALUE_BIT_OFFSET + bit_offset + 7, REG_GAMMA_LUT_VALUE_BIT_OFFSET + bit_offset),
+                gamma_lut_write_enable);
+            lut
+        };
+        let gamma_lut_r = gamma_lut("gamma_lut_r", 16);
+        let gamma_lut_g = gamma_lut("gamma_lut_g", 8);
+        let gamma_lut_b = gamma_lut("gamma_lut_b", 0);
+
+        let timing_reg = |name: &str, addr: u32, default_value: u32| {
+            let reg = m.reg(name, REG_TIMING_BITS);
+            reg.default_value(default_value);
+            reg.drive_next(if_(reg_write & reg_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH)), {
+                reg_bus_write_data.bits(REG_TIMING_BITS - 1, 0)
+            }).else_({
+                reg
+            }));
+            reg
+        };
+        let h_front_porch_reg = timing_reg("h_front_porch_reg", REG_H_FRONT_PORCH_ADDR, REG_H_FRONT_PORCH_DEFAULT);
+        let h_sync_reg = timing_reg("h_sync_reg", REG_H_SYNC_ADDR, REG_H_SYNC_DEFAULT);
+        let h_back_porch_reg = timing_reg("h_back_porch_reg", REG_H_BACK_PORCH_ADDR, REG_H_BACK_PORCH_DEFAULT);
+        let v_front_porch_reg = timing_reg("v_front_porch_reg", REG_V_FRONT_PORCH_ADDR, REG_V_FRONT_PORCH_DEFAULT);
+        let v_sync_reg = timing_reg("v_sync_reg", REG_V_SYNC_ADDR, REG_V_SYNC_DEFAULT);
+        let v_back_porch_reg = timing_reg("v_back_porch_reg", REG_V_BACK_PORCH_ADDR, REG_V_BACK_PORCH_DEFAULT);
+
+        // Timing
+        let pixel_clock_enable = if pixel_clock_divider_bits == 0 {
+            m.high()
+        } else {
+            let pixel_clock_divider = m.reg("pixel_clock_divider", pixel_clock_divider_bits);
+            pixel_clock_divider.default_value(0u32);
+            pixel_clock_divider.drive_next(pixel_clock_divider + m.lit(1u32, pixel_clock_divider_bits));
+            pixel_clock_divider.eq(m.lit((1u32 << pixel_clock_divider_bits) - 1, pixel_clock_divider_bits))
+        };
+
+        let timing = |value: &'a dyn Signal<'a>| m.lit(0u32, TIMING_COUNTER_BITS - value.bit_width()).concat(value);
+
+        let h_sync_start = m.lit(H_ACTIVE, TIMING_COUNTER_BITS) + timing(h_front_porch_reg);
+        let h_sync_end = h_sync_start + timing(h_sync_reg);
+        let h_last = h_sync_end + timing(h_back_porch_reg) - m.lit(1u32, TIMING_COUNTER_BITS);
+        let v_sync_start = m.lit(V_ACTIVE, TIMING_COUNTER_BITS) + timing(v_front_porch_reg);
+        let v_sync_end = v_sync_start + timing(v_sync_reg);
+        let v_last = v_sync_end + timing(v_back_porch_reg) - m.lit(1u32, TIMING_COUNTER_BITS);
+
+        let h_counter = m.reg("h_counter", TIMING_COUNTER_BITS);
+        h_counter.default_value(0u32);
+        let v_counter = m.reg("v_counter", TIMING_COUNTER_BITS);
+        v_counter.default_value(0u32);
+
+        // Compare with ge so that shrinking the blanking intervals mid-frame can't make the counters run away
+        let line_end = pixel_clock_enable & h_counter.ge(h_last);
+        let frame_end = line_end & v_counter.ge(v_last);
+
+        h_counter.drive_next(if_(line_end, {
+            m.lit(0u32, TIMING_COUNTER_BITS)
+        }).else_if(pixel_clock_enable, {
+            h_counter + m.lit(1u32, TIMING_COUNTER_BITS)
+        }).else_({
+            h_counter
+        }));
+        v_counter.drive_next(if_(frame_end, {
+            m.lit(0u32, TIMING_COUNTER_BITS)
+        }).else_if(line_end, {
+            v_counter + m.lit(1u32, TIMING_COUNTER_BITS)
+        }).else_({
+            v_counter
+        }));
+
+        let h_active = h_counter.lt(m.lit(H_ACTIVE, TIMING_COUNTER_BITS));
+        let v_active = v_counter.lt(m.lit(V_ACTIVE, TIMING_COUNTER_BITS));
+        let de = h_active & v_active;
+        let hsync = h_counter.ge(h_sync_start) & h_counter.lt(h_sync_end);
+        let vsync = v_counter.ge(v_sync_start) & v_counter.lt(v_sync_end);
+
+        // Frame setup happens on entry to the vertical back porch, leaving the whole back porch for the line fifo to
+        //  fill up before the first visible line
+        let frame_setup = line_end & (v_counter + m.lit(1u32, TIMING_COUNTER_BITS)).eq(v_sync_end);
+
+        let frame_enable_reg = m.reg("frame_enable_reg", 1);
+        frame_enable_reg.default_value(false);
+        frame_enable_reg.drive_next(if_(frame_setup, {
+            enable_reg
+        }).else_({
+            frame_enable_reg
+        }));
+
+        let frame_line_stride_reg = m.reg("frame_line_stride_reg", SYSTEM_BUS_ADDR_BITS);
+        frame_line_stride_reg.drive_next(if_(frame_setup, {
+            line_stride_reg
+        }).else_({
+            frame_line_stride_reg
+        }));
+
+        let frame_format_reg = m.reg("frame_format_reg", REG_FORMAT_BITS);
+        frame_format_reg.drive_next(if_(frame_setup, {
+            format_reg
+        }).else_({
+            frame_format_reg
+        }));
+        let rgb565 = frame_format_reg.eq(m.lit(REG_FORMAT_RGB565, REG_FORMAT_BITS));
+        let indexed8 = frame_format_reg.eq(m.lit(REG_FORMAT_INDEXED8, REG_FORMAT_BITS));
+
+        // The overlay is only shown while scanout itself is enabled
+        let frame_overlay_enable_reg = m.reg("frame_overlay_enable_reg", 1);
+        frame_overlay_enable_reg.default_value(false);
+        frame_overlay_enable_reg.drive_next(if_(frame_setup, {
+            enable_reg & overlay_enable_reg
+        }).else_({
+            frame_overlay_enable_reg
+        }));
+
+        let frame_overlay_line_stride_reg = m.reg("frame_overlay_line_stride_reg", SYSTEM_BUS_ADDR_BITS);
+        frame_overlay_line_stride_reg.drive_next(if_(frame_setup, {
+            overlay_line_stride_reg
+        }).else_({
+            frame_overlay_line_stride_reg
+        }));
+
+        let flip_pending_reg = m.reg("flip_pending_reg", 1);
+        flip_pending_reg.default_value(false);
+        flip_pending_reg.drive_next(if_(write_front_buffer_base, {
+            m.high()
+        }).else_if(frame_setup, {
+            m.low()
+        }).else_({
+            flip_pending_reg
+        }));
+
+        let frame_count_reg = m.reg("frame_count_reg", REG_FRAME_COUNT_BITS);
+        frame_count_reg.default_value(0u32);
+        frame_count_reg.drive_next(if_(frame_setup, {
+            frame_count_reg + m.lit(1u32, REG_FRAME_COUNT_BITS)
+        }).else_({
+            frame_count_reg
+        }));
+
+        // Line fifos
+        //  Front buffer and overlay words share the mem port, and responses come back in order, so each issued read
+        //  is tagged with the fifo it's headed for. Responses are registered once so the tag is ready by the time they
+        //  reach the fifos.
+        let fifo_depth_bits = 8;
+        let line_fifo = Fifo::new("line_fifo", fifo_depth_bits, 128, m);
+        let overlay_fifo = Fifo::new("overlay_fifo", fifo_depth_bits, 128, m);
+        let response_tag_fifo = Fifo::new("response_tag_fifo", fifo_depth_bits + 1, 1, m);
+        response_tag_fifo.read_enable.drive(mem_bus_read_data_valid);
+
+        let response_valid = mem_bus_read_data_valid.reg_next_with_default("response_valid", false);
+        let response_data = mem_bus_read_data.reg_next("response_data");
+        let response_overlay = response_tag_fifo.read_data;
+        line_fifo.write_enable.drive(response_valid & !response_overlay);
+        line_fifo.write_data.drive(response_data);
+        overlay_fifo.write_enable.drive(response_valid & response_overlay);
+        overlay_fifo.write_data.drive(response_data);
+
+        // A word is popped for the first pixel it covers. If the fetcher ever falls behind, any words left over at the
+        //  end of the frame are discarded during the vertical front porch/sync so the next frame starts out aligned.
+        let word_start = |pixels_per_word_bits: u32| {
+            h_counter.bits(pixels_per_word_bits - 1, 0).eq(m.lit(0u32, pixels_per_word_bits))
+        };
+        let pixel_word_start = if_(rgb565, {
+            word_start(RGB565_PIXELS_PER_WORD_BITS)
+        }).else_if(indexed8, {
+            word_start(INDEXED8_PIXELS_PER_WORD_BITS)
+        }).else_({
+            word_start(ARGB8888_PIXELS_PER_WORD_BITS)
+        });
+        let pixel_fetch = pixel_clock_enable & de & frame_enable_reg & pixel_word_start;
+        let drain = !v_active & v_counter.lt(v_sync_end);
+        let line_fifo_read = pixel_fetch | drain;
+        line_fifo.read_enable.drive(line_fifo_read);
+        let line_fifo_read_accepted = line_fifo_read & !line_fifo.empty;
+
+        let overlay_fetch = pixel_clock_enable & de & frame_overlay_enable_reg & word_start(ARGB8888_PIXELS_PER_WORD_BITS);
+        let overlay_fifo_read = overlay_fetch | drain;
+        overlay_fifo.read_enable.drive(overlay_fifo_read);
+        let overlay_fifo_read_accepted = overlay_fifo_read & !overlay_fifo.empty;
+
+        // Fetch
+        //  Each line is fetched from the front buffer, then (if enabled) from the overlay
+        let fetch_busy_reg = m.reg("fetch_busy_reg", 1);
+        fetch_busy_reg.default_value(false);
+
+        let fetch_overlay_reg = m.reg("fetch_overlay_reg", 1);
+        fetch_overlay_reg.default_value(false);
+
+        let credit_counter_bits = fifo_depth_bits + 1;
+        let credit_counter = |name: &str| {
+            let reg = m.reg(name, credit_counter_bits);
+            reg.default_value(1u32 << fifo_depth_bits);
+            reg
+        };
+        let line_credit_counter = credit_counter("line_credit_counter");
+        let overlay_credit_counter = credit_counter("overlay_credit_counter");
+
+        let issue = fetch_busy_reg & if_(fetch_overlay_reg, {
+            overlay_credit_counter
+        }).else_({
+            line_credit_counter
+        }).ne(m.lit(0u32, credit_counter_bits));
+        let issue_accepted = issue & mem_bus_ready;
+        response_tag_fifo.write_enable.drive(issue_accepted);
+        response_tag_fifo.write_data.drive(fetch_overlay_reg);
+
+        let line_addr_reg = m.reg("line_addr_reg", SYSTEM_BUS_ADDR_BITS);
+        let overlay_line_addr_reg = m.reg("overlay_line_addr_reg", SYSTEM_BUS_ADDR_BITS);
+        let word_addr_reg = m.reg("word_addr_reg", SYSTEM_BUS_ADDR_BITS);
+        let line_words_left = m.reg("line_words_left", TIMING_COUNTER_BITS);
+        let lines_left = m.reg("lines_left", TIMING_COUNTER_BITS);
+
+        let last_line_word = line_words_left.eq(m.lit(1u32, TIMING_COUNTER_BITS));
+        let last_line = lines_left.eq(m.lit(1u32, TIMING_COUNTER_BITS));
+        let next_line_addr = line_addr_reg + frame_line_stride_reg;
+        let next_overlay_line_addr = overlay_line_addr_reg + frame_overlay_line_stride_reg;
+        let line_words = |format: &'a dyn Signal<'a>| if_(format.eq(m.lit(REG_FORMAT_RGB565, REG_FORMAT_BITS)), {
+            m.lit(H_ACTIVE >> RGB565_PIXELS_PER_WORD_BITS, TIMING_COUNTER_BITS)
+        }).else_if(format.eq(m.lit(REG_FORMAT_INDEXED8, REG_FORMAT_BITS)), {
+            m.lit(H_ACTIVE >> INDEXED8_PIXELS_PER_WORD_BITS, TIMING_COUNTER_BITS)
+        }).else_({
+            m.lit(H_ACTIVE >> ARGB8888_PIXELS_PER_WORD_BITS, TIMING_COUNTER_BITS)
+        });
+
+        let segment_end = issue_accepted & last_line_word;
+        let start_overlay_segment = segment_end & !fetch_overlay_reg & frame_overlay_enable_reg;
+        let line_done = segment_end & !start_overlay_segment;
+
+        fetch_busy_reg.drive_next(if_(frame_setup, {
+            enable_reg
+        }).else_if(line_done & last_line, {
+            m.lit(false, 1)
+        }).else_({
+            fetch_busy_reg
+        }));
+        fetch_overlay_reg.drive_next(if_(frame_setup, {
+            m.low()
+        }).else_if(segment_end, {
+            start_overlay_segment
+        }).else_({
+            fetch_overlay_reg
+        }));
+
+        let credit_counter_next = |counter: &'a Register<'a>, issued: &'a dyn Signal<'a>, read: &'a dyn Signal<'a>| {
+            counter.drive_next(if_(!issued & read, {
+                counter + m.lit(1u32, credit_counter_bits)
+            }).else_if(issued & !read, {
+                counter - m.lit(1u32, credit_counter_bits)
+            }).else_({
+                counter
+            }));
+        };
+        credit_counter_next(line_credit_counter, issue_accepted & !fetch_overlay_reg, line_fifo_read_accepted);
+        credit_counter_next(overlay_credit_counter, issue_accepted & fetch_overlay_reg, overlay_fifo_read_accepted);
+
+        line_addr_reg.drive_next(if_(frame_setup, {
+            front_buffer_base_reg
+        }).else_if(segment_end & !fetch_overlay_reg, {
+            next_line_addr
+        }).else_({
+            line_addr_reg
+        }));
+        overlay_line_addr_reg.drive_next(if_(frame_setup, {
+            overlay_base_reg
+        }).else_if(segment_end & fetch_overlay_reg, {
+            next_overlay_line_addr
+        }).else_({
+            overlay_line_addr_reg
+        }));
+        word_addr_reg.drive_next(if_(frame_setup, {
+            front_buffer_base_reg
+        }).else_if(start_overlay_segment, {
+            overlay_line_addr_reg
+        }).else_if(segment_end & fetch_overlay_reg, {
+            // line_addr_reg was already advanced at the end of the front buffer segment
+            line_addr_reg
+        }).else_if(segment_end, {
+            next_line_addr
+        }).else_if(issue_accepted, {
+            word_addr_reg + m.lit(1u32, SYSTEM_BUS_ADDR_BITS)
+        }).else_({
+            word_addr_reg
+        }));
+        line_words_left.drive_next(if_(frame_setup, {
+            line_words(format_reg)
+        }).else_if(start_overlay_segment, {
+            m.lit(H_ACTIVE >> ARGB8888_PIXELS_PER_WORD_BITS, TIMING_COUNTER_BITS)
+        }).else_if(segment_end, {
+            line_words(frame_format_reg)
+        }).else_if(issue_accepted, {
+            line_words_left - m.lit(1u32, TIMING_COUNTER_BITS)
+        }).else_({
+            line_words_left
+        }));
+        lines_left.drive_next(if_(frame_setup, {
+            m.lit(V_ACTIVE, TIMING_COUNTER_BITS)
+        }).else_if(line_done, {
+            lines_left - m.lit(1u32, TIMING_COUNTER_BITS)
+        }).else_({
+            lines_left
+        }));
+
+        // Output pipeline
+        //  Stage 1 lines up the timing signals with the line fifos' read data, which is valid the cycle after a pop
+        //  Stage 2 lines them up with the palette/gamma LUT read data, and blends in the overlay
+        //  Stage 3 registers the outputs
+        let pixel_reg_next = |name: &str, value: &'a dyn Signal<'a>| {
+            let reg = m.reg(name, value.bit_width());
+            reg.default_value(0u32);
+            reg.drive_next(if_(pixel_clock_enable, {
+                value
+            }).else_({
+                reg
+            }));
+            reg
+        };
+
+        let de_1 = pixel_reg_next("de_1", de);
+        let hsync_1 = pixel_reg_next("hsync_1", hsync);
+        let vsync_1 = pixel_reg_next("vsync_1", vsync);
+        let visible_1 = pixel_reg_next("visible_1", de & frame_enable_reg);
+        let overlay_visible_1 = pixel_reg_next("overlay_visible_1", de & frame_overlay_enable_reg);
+        let pixel_index_1 = pixel_reg_next("pixel_index_1", h_counter.bits(MAX_PIXELS_PER_WORD_BITS - 1, 0));
+
+        let select_pixel = |word: &'a dyn Signal<'a>, pixels_per_word_bits: u32, pixel_bits: u32| {
+            let index = pixel_index_1.bits(pixels_per_word_bits - 1, 0);
+            let mut pixel: &dyn Signal<'a> = word.bits(pixel_bits - 1, 0);
+            for i in 1..1 << pixels_per_word_bits {
+                pixel = if_(index.eq(m.lit(i, pixels_per_word_bits)), {
+                    word.bits((i + 1) * pixel_bits - 1, i * pixel_bits)
+                }).else_({
+                    pixel
+                });
+            }
+            pixel
+        };
+        let argb8888_pixel = select_pixel(line_fifo.read_data, ARGB8888_PIXELS_PER_WORD_BITS, 32);
+        let rgb565_pixel = select_pixel(line_fifo.read_data, RGB565_PIXELS_PER_WORD_BITS, 16);
+        let indexed8_pixel = select_pixel(line_fifo.read_data, INDEXED8_PIXELS_PER_WORD_BITS, 8);
+        let overlay_pixel = select_pixel(overlay_fifo.read_data, ARGB8888_PIXELS_PER_WORD_BITS, 32);
+
+        let palette_value = palette.read_port(indexed8_pixel, pixel_clock_enable);
+        let gamma_r = gamma_lut_r.read_port(argb8888_pixel.bits(23, 16), pixel_clock_enable);
+        let gamma_g = gamma_lut_g.read_port(argb8888_pixel.bits(15, 8), pixel_clock_enable);
+        let gamma_b = gamma_lut_b.read_port(argb8888_pixel.bits(7, 0), pixel_clock_enable);
+
+        let r = rgb565_pixel.bits(15, 11);
+        let g = rgb565_pixel.bits(10, 5);
+        let b = rgb565_pixel.bits(4, 0);
+        let rgb565_expanded = r.concat(r.bits(4, 2)).concat(g).concat(g.bits(5, 4)).concat(b).concat(b.bits(4, 2));
+
+        let de_2 = pixel_reg_next("de_2", de_1);
+        let hsync_2 = pixel_reg_next("hsync_2", hsync_1);
+        let vsync_2 = pixel_reg_next("vsync_2", vsync_1);
+        let visible_2 = pixel_reg_next("visible_2", visible_1);
+        let overlay_visible_2 = pixel_reg_next("overlay_visible_2", overlay_visible_1);
+        let rgb565_pixel_2 = pixel_reg_next("rgb565_pixel_2", rgb565_expanded);
+        let overlay_pixel_2 = pixel_reg_next("overlay_pixel_2", overlay_pixel);
+
+        let pixel = if_(rgb565, {
+            rgb565_pixel_2
+        }).else_if(indexed8, {
+            palette_value
+        }).else_({
+            gamma_r.concat(gamma_g).concat(gamma_b)
+        });
+
+        // Same fixed-point convention as BitPusher's blending, where one is 256
+        let overlay_alpha = m.low().concat(overlay_pixel_2.bits(31, 24));
+        let one_minus_overlay_alpha = m.lit(256u32, 9) - overlay_alpha;
+        let blend_comp = |bit_offset: u32| -> &'a dyn Signal<'a> {
+            let overlay_term = overlay_pixel_2.bits(bit_offset + 7, bit_offset) * overlay_alpha;
+            let pixel_term = pixel.bits(bit_offset + 7, bit_offset) * one_minus_overlay_alpha;
+            (overlay_term + pixel_term).bits(15, 8)
+        };
+        let blended_pixel = if_(overlay_visible_2, {
+            blend_comp(16).concat(blend_comp(8)).concat(blend_comp(0))
+        }).else_({
+            pixel
+        });
+
+        let de_3 = pixel_reg_next("de_3", de_2);
+        let hsync_3 = pixel_reg_next("hsync_3", hsync_2);
+        let vsync_3 = pixel_reg_next("vsync_3", vsync_2);
+        let rgb_3 = pixel_reg_next("rgb_3", if_(visible_2, {
+            blended_pixel
+        }).else_({
+            m.lit(0u32, 24)
+        }));
+
+        // Reg reads
+        let reg_read_addr = reg_addr.reg_next("reg_read_addr");
+        let read_value = |value: &'a dyn Signal<'a>| m.lit(0u32, 32 - value.bit_width()).concat(value);
+        let readable_regs: [(u32, &'a dyn Signal<'a>); 16] = [
+            (REG_ENABLE_ADDR, enable_reg),
+            (REG_FRONT_BUFFER_BASE_ADDR, front_buffer_base_reg.concat(m.lit(0u32, 4))),
+            (REG_LINE_STRIDE_ADDR, line_stride_reg),
+            (REG_CURRENT_LINE_ADDR, v_counter),
+            (REG_H_FRONT_PORCH_ADDR, h_front_porch_reg),
+            (REG_H_SYNC_ADDR, h_sync_reg),
+            (REG_H_BACK_PORCH_ADDR, h_back_porch_reg),
+            (REG_V_FRONT_PORCH_ADDR, v_front_porch_reg),
+            (REG_V_SYNC_ADDR, v_sync_reg),
+            (REG_V_BACK_PORCH_ADDR, v_back_porch_reg),
+            // REG_STATUS_FLIP_PENDING_BIT, REG_STATUS_VBLANK_BIT
+            (REG_STATUS_ADDR, flip_pending_reg.concat(!v_active)),
+            (REG_FRAME_COUNT_ADDR, frame_count_reg),
+            (REG_FORMAT_ADDR, format_reg),
+            (REG_OVERLAY_ENABLE_ADDR, overlay_enable_reg),
+            (REG_OVERLAY_BASE_ADDR, overlay_base_reg.concat(m.lit(0u32, 4))),
+            (REG_OVERLAY_LINE_STRIDE_ADDR, overlay_line_stride_reg),
+        ];
+        let mut reg_read_data = m.lit(0u32, 32);
+        for &(addr, value) in readable_regs.iter() {
+            reg_read_data = if_(reg_read_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH)), {
+                read_value(value)
+            }).else_({
+                reg_read_data
+            });
+        }
+        let reg_bus_read_data = m.output("reg_bus_read_data", m.lit(0u32, 96).concat(reg_read_data));
+
+        Scanout {
+            m,
+
+            reg_port: ReplicaPort {
+                bus_enable: reg_bus_enable,
+                bus_addr: reg_bus_addr,
+                bus_write: reg_bus_write,
+                bus_write_data: reg_bus_write_data,
+                bus_write_byte_enable: reg_bus_write_byte_enable,
+                bus_ready: reg_bus_ready,
+                bus_read_data: reg_bus_read_data,
+                bus_read_data_valid: reg_bus_read_data_valid,
+            },
+
+            mem_port: PrimaryPort {
+                bus_enable: m.output("mem_bus_enable", issue),
+                bus_addr: m.output("mem_bus_addr", word_addr_reg),
+                bus_write: m.output("mem_bus_write", m.low()),
+                bus_write_data: m.output("mem_bus_write_data", m.lit(0u32, 128)),
+                bus_write_byte_enable: m.output("mem_bus_write_byte_enable", m.lit(0u32, 128 / 8)),
+                bus_ready: mem_bus_ready,
+                bus_read_data: mem_bus_read_data,
+                bus_read_data_valid: mem_bus_read_data_valid,
+            },
+
+            pixel_clock_enable: m.output("pixel_clock_enable", pixel_clock_enable),
+            hsync_n: m.output("hsync_n", !hsync_3),
+            vsync_n: m.output("vsync_n", !vsync_3),
+            de: m.output("de", de_3),
+            rgb: m.output("rgb", rgb_3),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::buster::*;
+
+use kaze::*;
+
+use rtl_meta::spi::*;
+
+pub struct Spi<'a> {
+    pub m: &'a Module<'a>,
+
+    pub reg_port: ReplicaPort<'a>,
+
+    pub sclk: &'a Output<'a>,
+    pub mosi: &'a Output<'a>,
+    // Requires external sync FF's
+    pub miso: &'a Input<'a>,
+    pub cs_n: &'a Output<'a>,
+}
+
+impl<'a> Spi<'a> {
+    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> Spi<'a> {
+        let m = p.module(instance_name, "Spi");
+
+        let reg_bus_enable = m.input("reg_bus_enable", 1);
+        let reg_bus_addr = m.input("reg_bus_addr", REG_BUS_ADDR_BITS);
+        let reg_bus_write = m.input("reg_bus_write", 1);
+        let reg_bus_write_data = m.input("reg_bus_write_data", 128);
+        let reg_bus_write_byte_enable = m.input("reg_bus_write_byte_enable", 128 / 8);
+        let reg_bus_ready = m.output("reg_bus_ready", m.lit(true, 1));
+        let reg_bus_read_data_valid = m.output(
+            "reg_bus_read_data_valid",
+            (reg_bus_enable & !reg_bus_write).reg_next_with_default("reg_bus_read_data_valid_reg", false),
+        );
+
+        let reg_write = reg_bus_enable & reg_bus_write;
+        let reg_addr = reg_bus_addr.bits(REG_BUS_ADDR_BIT_WIDTH - 1, 0);
+        let reg_write_addr = |addr: u32| reg_write & reg_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH));
+
+        let miso = m.input("miso", 1);
+
+        let clock_divider_reg = m.reg("clock_divider_reg", REG_CLOCK_DIVIDER_BITS);
+        clock_divider_reg.default_value(REG_CLOCK_DIVIDER_DEFAULT);
+        clock_divider_reg.drive_next(if_(reg_write_addr(REG_CLOCK_DIVIDER_ADDR), {
+            reg_bus_write_data.bits(REG_CLOCK_DIVIDER_BITS - 1, 0)
+        }).else_({
+            clock_divider_reg
+        }));
+
+        let chip_select_reg = m.reg("chip_select_reg", 1);
+        chip_select_reg.default_value(false);
+        chip_select_reg.drive_next(if_(reg_write_addr(REG_CHIP_SELECT_ADDR), {
+            reg_bus_write_data.bit(0)
+        }).else_({
+            chip_select_reg
+        }));
+
+        // Transfers
+        let busy_reg = m.reg("busy_reg", 1);
+        busy_reg.default_value(false);
+        let start = reg_write_addr(REG_DATA_ADDR) & !busy_reg;
+
+        let divider_counter = m.reg("divider_counter", REG_CLOCK_DIVIDER_BITS);
+        divider_counter.default_value(0u32);
+        let tick = busy_reg & divider_counter.eq(m.lit(0u32, REG_CLOCK_DIVIDER_BITS));
+        divider_counter.drive_next(if_(start | tick, {
+            clock_divider_reg
+        }).else_if(busy_reg, {
+            divider_counter - m.lit(1u32, REG_CLOCK_DIVIDER_BITS)
+        }).else_({
+            divider_counter
+        }));
+
+        let sclk_reg = m.reg("sclk_reg", 1);
+        sclk_reg.default_value(false);
+        sclk_reg.drive_next(if_(tick, {
+            !sclk_reg
+        }).else_({
+            sclk_reg
+        }));
+        let rising_edge = tick & !sclk_reg;
+        let falling_edge = tick & sclk_reg;
+
+        let miso_sample_reg = m.reg("miso_sample_reg", 1);
+        miso_sample_reg.default_value(false);
+        miso_sample_reg.drive_next(if_(rising_edge, {
+            miso
+        }).else_({
+            miso_sample_reg
+        }));
+
+        // Outgoing bits leave from the top while incoming bits enter at the bottom, so once all 8 falling edges have
+        //  passed, this holds the received byte
+        let shift_reg = m.reg("shift_reg", 8);
+        shift_reg.default_value(0xffu32);
+        shift_reg.drive_next(if_(start, {
+            reg_bus_write_data.bits(7, 0)
+        }).else_if(falling_edge, {
+            shift_reg.bits(6, 0).concat(miso_sample_reg)
+        }).else_({
+            shift_reg
+        }));
+
+        let bit_counter = m.reg("bit_counter", 3);
+        bit_counter.default_value(0u32);
+        bit_counter.drive_next(if_(falling_edge, {
+            bit_counter + m.lit(1u32, 3)
+        }).else_({
+            bit_counter
+        }));
+
+        busy_reg.drive_next(if_(start, {
+            m.high()
+        }).else_if(falling_edge & bit_counter.eq(m.lit(7u32, 3)), {
+            m.low()
+        }).else_({
+            busy_reg
+        }));
+
+        // Reg reads
+        let reg_read_addr = reg_addr.reg_next("reg_read_addr");
+        let read_value = |value: &'a dyn Signal<'a>| m.lit(0u32, 32 - value.bit_width()).concat(value);
+        let readable_regs: [(u32, &'a dyn Signal<'a>); 4] = [
+            (REG_CLOCK_DIVIDER_ADDR, clock_divider_reg),
+            (REG_CHIP_SELECT_ADDR, chip_select_reg),
+            (REG_DATA_ADDR, shift_reg),
+            // REG_STATUS_BUSY_BIT
+            (REG_STATUS_ADDR, busy_reg),
+        ];
+        let mut reg_read_data = m.lit(0u32, 32);
+        for &(addr, value) in readable_regs.iter() {
+            reg_read_data = if_(reg_read_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH)), {
+                read_value(value)
+            }).else_({
+                reg_read_data
+            });
+        }
+        let reg_bus_read_data = m.output("reg_bus_read_data", m.lit(0u32, 96).concat(reg_read_data));
+
+        Spi {
+            m,
+
+            reg_port: ReplicaPort {
+                bus_enable: reg_bus_enable,
+                bus_addr: reg_bus_addr,
+                bus_write: reg_bus_write,
+                bus_write_data: reg_bus_write_data,
+                bus_write_byte_enable: reg_bus_write_byte_enable,
+                bus_ready: reg_bus_ready,
+                bus_read_data: reg_bus_read_data,
+                bus_read_data_valid: reg_bus_read_data_valid,
+            },
+
+            sclk: m.output("sclk", sclk_reg),
+            mosi: m.output("mosi", shift_reg.bit(7)),
+            miso,
+            cs_n: m.output("cs_n", !chip_select_reg),
+        }
+    }
+}
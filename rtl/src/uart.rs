@@ -1,27 +1,45 @@
 use kaze::*;
 
+use rtl_meta::uart::*;
+
 pub struct UartRx<'a> {
     pub m: &'a Module<'a>,
     pub rx: &'a Input<'a>,
+    pub baud_divisor: &'a Input<'a>,
     pub data: &'a Output<'a>,
     pub data_valid: &'a Output<'a>,
+    // Valid when data_valid is high
+    pub framing_error: &'a Output<'a>,
 }
 
 impl<'a> UartRx<'a> {
-    pub fn new(instance_name: impl Into<String>, clock_freq: u32, baud_rate: u32, p: &'a impl ModuleParent<'a>) -> UartRx<'a> {
+    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> UartRx<'a> {
         let m = p.module(instance_name, "UartRx");
 
         // Requires external sync FF's
         let rx = m.input("rx", 1);
 
-        // Sample at 4x baud_rate
+        // Clocks per bit
+        let baud_divisor = m.input("baud_divisor", REG_BAUD_DIVISOR_BITS);
+
+        // Sample at 4x baud rate
         //  We should technically only need 2x due to nyquist/shannon, but due to slight rate differences we want some headroom, so we go for 4 instead
-        let tick_rate = baud_rate * 4;
-        let clocks_per_tick = clock_freq / tick_rate;
-        let tick_counter = m.reg("tick_counter", 32);
-        tick_counter.default_value(0u32);
-        let tick = tick_counter.eq(m.lit(clocks_per_tick - 1, 32));
-        tick_counter.drive_next(tick.mux(m.lit(0u32, 32), tick_counter + m.lit(1u32, 32)));
+        //  The divisor isn't necessarily a multiple of 4, so ticks come from an accumulator that gains 4 each cycle and wraps at the divisor,
+        //  which gives exactly 4 ticks per bit with at most a cycle of jitter on each
+        let tick_accumulator_bit_width = REG_BAUD_DIVISOR_BITS + 1;
+        let tick_accumulator = m.reg("tick_accumulator", tick_accumulator_bit_width);
+        tick_accumulator.default_value(0u32);
+        let next_tick_accumulator = tick_accumulator + m.lit(4u32, tick_accumulator_bit_width);
+        let wide_baud_divisor = m.low().concat(baud_divisor);
+        let tick = next_tick_accumulator.ge(wide_baud_divisor);
+        let wrapped_tick_accumulator = next_tick_accumulator - wide_baud_divisor;
+        tick_accumulator.drive_next(if_(tick, {
+            // If the divisor was just lowered, the accumulator can be left far above it, and would otherwise tick every
+            //  cycle until it worked its way back down
+            wrapped_tick_accumulator.ge(wide_baud_divisor).mux(m.lit(0u32, tick_accumulator_bit_width), wrapped_tick_accumulator)
+        }).else_({
+            next_tick_accumulator
+        }));
 
         let wait_counter_bit_width = 2;
         let wait_counter = m.reg("wait_counter", wait_counter_bit_width);
@@ -32,6 +50,8 @@ impl<'a> UartRx<'a> {
         let data_valid = m.reg("data_valid", 1);
         data_valid.default_value(false);
         let next_data_valid = m.low();
+        let framing_error = m.reg("framing_error", 1);
+        let next_framing_error = framing_error;
 
         let bit_counter = m.reg("bit_counter", 3);
         bit_counter.default_value(0u32);
@@ -45,17 +65,17 @@ impl<'a> UartRx<'a> {
         let state = m.reg("state", state_bit_width);
         state.default_value(state_idle);
         let next_state = state;
-        let (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state) = if_(tick, {
+        let (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state) = if_(tick, {
             let next_wait_counter = wait_counter + m.lit(1u32, wait_counter_bit_width);
 
-            let (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state) = if_(state.eq(m.lit(state_idle, state_bit_width)), {
+            let (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state) = if_(state.eq(m.lit(state_idle, state_bit_width)), {
                 if_(!rx, {
                     let next_wait_counter = m.lit(0u32, wait_counter_bit_width);
                     let next_state = m.lit(state_start_bit_wait, state_bit_width);
 
-                    (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state)
+                    (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state)
                 }).else_({
-                    (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state)
+                    (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state)
                 })
             }).else_if(state.eq(m.lit(state_start_bit_wait, state_bit_width)), {
                 let (next_wait_counter, next_state) = if_(wait_counter.eq(m.lit(1u32, wait_counter_bit_width)), {
@@ -74,14 +94,13 @@ impl<'a> UartRx<'a> {
                     next_state
                 });
 
-                (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state)
+                (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state)
             }).else_if(state.eq(m.lit(state_input_bit, state_bit_width)), {
                 let (next_data, next_data_valid, next_bit_counter, next_state) = if_(wait_counter.eq(m.lit(3u32, wait_counter_bit_width)), {
                     let next_data = rx.concat(data.bits(7, 1));
                     let next_bit_counter = bit_counter + m.lit(1u32, 3);
 
                     if_(bit_counter.eq(m.lit(7u32, 3)), {
-                        let next_data_valid = m.high();
                         let next_state = m.lit(state_stop_bit_wait, state_bit_width);
 
                         (next_data, next_data_valid, next_bit_counter, next_state)
@@ -92,26 +111,32 @@ impl<'a> UartRx<'a> {
                     (next_data, next_data_valid, next_bit_counter, next_state)
                 });
 
-                (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state)
+                (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state)
             }).else_({
-                let next_state = if_(wait_counter.eq(m.lit(3u32, wait_counter_bit_width)), {
-                    m.lit(state_idle, state_bit_width)
+                // Bytes are delivered in the middle of the stop bit, which should be high
+                let (next_data_valid, next_framing_error, next_state) = if_(wait_counter.eq(m.lit(3u32, wait_counter_bit_width)), {
+                    let next_data_valid = m.high();
+                    let next_framing_error = !rx;
+                    let next_state = m.lit(state_idle, state_bit_width);
+
+                    (next_data_valid, next_framing_error, next_state)
                 }).else_({
-                    next_state
+                    (next_data_valid, next_framing_error, next_state)
                 });
 
-                (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state)
+                (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state)
             });
 
-            (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state)
+            (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state)
         }).else_({
-            (next_wait_counter, next_data, next_data_valid, next_bit_counter, next_state)
+            (next_wait_counter, next_data, next_data_valid, next_framing_error, next_bit_counter, next_state)
         });
 
         wait_counter.drive_next(next_wait_counter);
 
         data.drive_next(next_data);
         data_valid.drive_next(next_data_valid);
+        framing_error.drive_next(next_framing_error);
 
         bit_counter.drive_next(next_bit_counter);
 
@@ -120,8 +145,10 @@ impl<'a> UartRx<'a> {
         UartRx {
             m,
             rx,
+            baud_divisor,
             data: m.output("data", data),
             data_valid: m.output("data_valid", data_valid),
+            framing_error: m.output("framing_error", framing_error),
         }
     }
 }
@@ -133,10 +160,11 @@ pub struct UartTx<'a> {
     pub tx: &'a Output<'a>,
     pub enable: &'a Input<'a>,
     pub data: &'a Input<'a>,
+    pub baud_divisor: &'a Input<'a>,
 }
 
 impl<'a> UartTx<'a> {
-    pub fn new(instance_name: impl Into<String>, clock_freq: u32, baud_rate: u32, p: &'a impl ModuleParent<'a>) -> UartTx<'a> {
+    pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> UartTx<'a> {
         let m = p.module(instance_name, "UartTx");
 
         let state_bit_width = 2;
@@ -160,13 +188,15 @@ impl<'a> UartTx<'a> {
         let data_latch = m.reg("data_latch", 8);
         let next_data_latch = data_latch;
 
-        let tick_rate = baud_rate;
-        let clocks_per_tick = clock_freq / tick_rate;
-        let tick_counter = m.reg("tick_counter", 32);
+        // Clocks per bit
+        let baud_divisor = m.input("baud_divisor", REG_BAUD_DIVISOR_BITS);
+
+        let tick_counter = m.reg("tick_counter", REG_BAUD_DIVISOR_BITS);
         tick_counter.default_value(0u32);
-        let tick = tick_counter.eq(m.lit(clocks_per_tick - 1, 32));
+        // Compare with ge rather than eq so that lowering the divisor can't leave the counter stranded above it
+        let tick = tick_counter.ge(baud_divisor - m.lit(1u32, REG_BAUD_DIVISOR_BITS));
         // TODO: Reset this counter if we're in the idle state and we accept a new write
-        let next_tick_counter = tick.mux(m.lit(0u32, 32), tick_counter + m.lit(1u32, 32));
+        let next_tick_counter = tick.mux(m.lit(0u32, REG_BAUD_DIVISOR_BITS), tick_counter + m.lit(1u32, REG_BAUD_DIVISOR_BITS));
 
         let bit_counter = m.reg("bit_counter", 3);
         bit_counter.default_value(0u32);
@@ -177,7 +207,7 @@ impl<'a> UartTx<'a> {
                 let next_state = m.lit(state_start_bit, state_bit_width);
                 let next_tx = m.low();
                 let next_data_latch = data;
-                let next_tick_counter = m.lit(0u32, 32);
+                let next_tick_counter = m.lit(0u32, REG_BAUD_DIVISOR_BITS);
 
                 (next_state, next_tx, next_data_latch, next_tick_counter, next_bit_counter)
             }).else_({
@@ -231,6 +261,7 @@ impl<'a> UartTx<'a> {
             tx: m.output("tx", tx),
             enable,
             data,
+            baud_divisor,
         }
     }
 }
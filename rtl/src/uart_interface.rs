@@ -3,53 +3,130 @@ use crate::fifo::*;
 
 use kaze::*;
 
+use rtl_meta::uart::*;
+
 pub struct UartInterface<'a> {
     pub m: &'a Module<'a>,
     pub client_port: ReplicaPort<'a>,
     pub rx_ready: &'a Output<'a>,
     pub rx_data: &'a Input<'a>,
     pub rx_data_valid: &'a Input<'a>,
+    pub rx_framing_error: &'a Input<'a>,
     pub tx_ready: &'a Input<'a>,
     pub tx_data: &'a Output<'a>,
     pub tx_enable: &'a Output<'a>,
+    pub baud_divisor: &'a Output<'a>,
+    pub rts: &'a Output<'a>,
+    pub cts: &'a Input<'a>,
 }
 
 impl<'a> UartInterface<'a> {
     pub fn new(instance_name: impl Into<String>, p: &'a impl ModuleParent<'a>) -> UartInterface<'a> {
         let m = p.module(instance_name, "UartInterface");
 
-        let rx_fifo = Fifo::new("rx_fifo", 8, 8, m);
+        let bus_enable = m.input("bus_enable", 1);
+        let bus_addr = m.input("bus_addr", REG_BUS_ADDR_BITS);
+        let bus_write = m.input("bus_write", 1);
+        let bus_write_data = m.input("bus_write_data", 128);
+        let bus_write_byte_enable = m.input("bus_write_byte_enable", 16);
+        let bus_ready = m.output("bus_ready", m.high());
+        let bus_read_data_valid = m.output("bus_read_data_valid", (bus_enable & !bus_write).reg_next_with_default("bus_read_data_valid", false));
+
+        let reg_addr = bus_addr.bits(REG_BUS_ADDR_BIT_WIDTH - 1, 0);
+        let reg_write_addr = |addr: u32| bus_enable & bus_write & reg_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH));
+        let reg_read_addr = |addr: u32| bus_enable & !bus_write & reg_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH));
+
+        let baud_divisor_reg = m.reg("baud_divisor_reg", REG_BAUD_DIVISOR_BITS);
+        baud_divisor_reg.default_value(REG_BAUD_DIVISOR_DEFAULT);
+        baud_divisor_reg.drive_next(if_(reg_write_addr(REG_BAUD_DIVISOR_ADDR), {
+            // Clamp so RX always gets its 4 samples per bit
+            let baud_divisor = bus_write_data.bits(REG_BAUD_DIVISOR_BITS - 1, 0);
+            let min_baud_divisor = m.lit(REG_BAUD_DIVISOR_MIN, REG_BAUD_DIVISOR_BITS);
+            baud_divisor.lt(min_baud_divisor).mux(min_baud_divisor, baud_divisor)
+        }).else_({
+            baud_divisor_reg
+        }));
+        let baud_divisor = m.output("baud_divisor", baud_divisor_reg);
+
+        let flow_control_enable_reg = m.reg("flow_control_enable_reg", 1);
+        flow_control_enable_reg.default_value(false);
+        flow_control_enable_reg.drive_next(if_(reg_write_addr(REG_FLOW_CONTROL_ADDR), {
+            bus_write_data.bit(REG_FLOW_CONTROL_ENABLE_BIT)
+        }).else_({
+            flow_control_enable_reg
+        }));
+
+        // RX
+        let rx_fifo = Fifo::new("rx_fifo", FIFO_DEPTH_BITS, 8, m);
         let rx_ready = m.output("rx_ready", !rx_fifo.full);
         let rx_data = m.input("rx_data", 8);
         let rx_data_valid = m.input("rx_data_valid", 1);
+        let rx_framing_error = m.input("rx_framing_error", 1);
         rx_fifo.write_enable.drive(rx_data_valid);
         rx_fifo.write_data.drive(rx_data);
+        rx_fifo.read_enable.drive(reg_read_addr(REG_RX_DATA_ADDR));
+
+        let rts = m.output("rts", !flow_control_enable_reg | rx_fifo.count.lt(m.lit(FIFO_DEPTH - RTS_THRESHOLD, REG_LEVEL_BITS)));
+
+        // TX
+        let tx_fifo = Fifo::new("tx_fifo", FIFO_DEPTH_BITS, 8, m);
+        let tx_write = reg_write_addr(REG_TX_DATA_ADDR);
+        tx_fifo.write_enable.drive(tx_write);
+        tx_fifo.write_data.drive(bus_write_data.bits(7, 0));
 
         let tx_ready = m.input("tx_ready", 1);
+        let cts = m.input("cts", 1);
 
-        let bus_enable = m.input("bus_enable", 1);
-        let bus_addr = m.input("bus_addr", 20);
-        let bus_write = m.input("bus_write", 1);
-        let bus_write_data = m.input("bus_write_data", 128);
-        let bus_write_byte_enable = m.input("bus_write_byte_enable", 16);
-        let bus_ready = m.output("bus_ready", m.high());
+        // Popped data shows up on the FIFO's read port a cycle later, so the transmitter is enabled one cycle after each
+        //  pop. It's still idle during that cycle, so we hold off popping again until it's had a chance to go busy.
+        let tx_enable_reg = m.reg("tx_enable_reg", 1);
+        tx_enable_reg.default_value(false);
+        let tx_pop = tx_ready & !tx_enable_reg & !tx_fifo.empty & (!flow_control_enable_reg | cts);
+        tx_fifo.read_enable.drive(tx_pop);
+        tx_enable_reg.drive_next(tx_pop);
+        let tx_data = m.output("tx_data", tx_fifo.read_data);
+        let tx_enable = m.output("tx_enable", tx_enable_reg);
 
-        rx_fifo.read_enable.drive(bus_enable & !bus_write & bus_addr.bits(1, 0).eq(m.lit(3u32, 2)));
+        let tx_idle = tx_ready & !tx_enable_reg & tx_fifo.empty;
 
-        let bus_read_return_addr = bus_addr.reg_next("bus_read_return_addr");
-        let bus_read_data = m.output("bus_read_data", if_(bus_read_return_addr.bits(1, 0).eq(m.lit(0u32, 2)), {
-            m.lit(0u32, 127).concat(tx_ready)
-        }).else_if(bus_read_return_addr.bits(1, 0).eq(m.lit(1u32, 2)), {
-            m.lit(0u32, 128)
-        }).else_if(bus_read_return_addr.bits(1, 0).eq(m.lit(2u32, 2)), {
-            m.lit(0u32, 127).concat(!rx_fifo.empty)
+        // Errors
+        let error_reg = m.reg("error_reg", REG_ERROR_BITS);
+        error_reg.default_value(0u32);
+        let error_clear = if_(reg_write_addr(REG_ERROR_ADDR), {
+            bus_write_data.bits(REG_ERROR_BITS - 1, 0)
         }).else_({
-            m.lit(0u32, 120).concat(rx_fifo.read_data)
-        }));
-        let bus_read_data_valid = m.output("bus_read_data_valid", (bus_enable & !bus_write).reg_next_with_default("bus_read_data_valid", false));
+            m.lit(0u32, REG_ERROR_BITS)
+        });
+        // REG_ERROR_TX_OVERFLOW_BIT, REG_ERROR_OVERRUN_BIT, REG_ERROR_FRAMING_BIT
+        let error_set = (tx_write & tx_fifo.full)
+            .concat(rx_data_valid & rx_fifo.full)
+            .concat(rx_data_valid & rx_framing_error);
+        error_reg.drive_next((error_reg & !error_clear) | error_set);
 
-        let tx_data = m.output("tx_data", bus_write_data.bits(7, 0));
-        let tx_enable = m.output("tx_enable", bus_enable & bus_write);
+        // Reg reads
+        let bus_read_return_addr = reg_addr.reg_next("bus_read_return_addr");
+        let read_value = |value: &'a dyn Signal<'a>| m.lit(0u32, 32 - value.bit_width()).concat(value);
+        let readable_regs: [(u32, &'a dyn Signal<'a>); 8] = [
+            // REG_TX_STATUS_IDLE_BIT, REG_TX_STATUS_READY_BIT
+            (REG_TX_STATUS_ADDR, tx_idle.concat(!tx_fifo.full)),
+            // REG_RX_STATUS_READY_BIT
+            (REG_RX_STATUS_ADDR, !rx_fifo.empty),
+            (REG_RX_DATA_ADDR, rx_fifo.read_data),
+            (REG_BAUD_DIVISOR_ADDR, baud_divisor_reg),
+            (REG_FLOW_CONTROL_ADDR, flow_control_enable_reg),
+            (REG_TX_LEVEL_ADDR, tx_fifo.count),
+            (REG_RX_LEVEL_ADDR, rx_fifo.count),
+            (REG_ERROR_ADDR, error_reg),
+        ];
+        let mut read_data = m.lit(0u32, 32);
+        for &(addr, value) in readable_regs.iter() {
+            read_data = if_(bus_read_return_addr.eq(m.lit(addr, REG_BUS_ADDR_BIT_WIDTH)), {
+                read_value(value)
+            }).else_({
+                read_data
+            });
+        }
+        let bus_read_data = m.output("bus_read_data", m.lit(0u32, 96).concat(read_data));
 
         UartInterface {
             m,
@@ -66,9 +143,13 @@ impl<'a> UartInterface<'a> {
             rx_ready,
             rx_data,
             rx_data_valid,
+            rx_framing_error,
             tx_ready,
             tx_data,
             tx_enable,
+            baud_divisor,
+            rts,
+            cts,
         }
     }
 }
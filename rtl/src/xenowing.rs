@@ -1,3 +1,4 @@
+use crate::audio::*;
 use crate::bit_pusher::*;
 use crate::boot_rom::*;
 use crate::buster::*;
@@ -7,11 +8,15 @@ use crate::led_interface::*;
 use crate::marv::*;
 use crate::marv_system_bridge::*;
 use crate::read_cache::*;
+use crate::scanout::*;
+use crate::spi::*;
 use crate::uart::*;
 use crate::uart_interface::*;
 
 use kaze::*;
 
+use rtl_meta::color_thrust::TILE_DIM_BITS_DEFAULT;
+
 pub struct Xenowing<'a> {
     pub m: &'a Module<'a>,
 
@@ -19,6 +24,27 @@ pub struct Xenowing<'a> {
 
     pub tx: &'a Output<'a>,
     pub rx: &'a Input<'a>,
+    pub rts_n: &'a Output<'a>,
+    pub cts_n: &'a Input<'a>,
+
+    pub video_pixel_clock_enable: &'a Output<'a>,
+    pub video_hsync_n: &'a Output<'a>,
+    pub video_vsync_n: &'a Output<'a>,
+    pub video_de: &'a Output<'a>,
+    pub video_rgb: &'a Output<'a>,
+
+    pub audio_left: &'a Output<'a>,
+    pub audio_right: &'a Output<'a>,
+
+    pub sd_sclk: &'a Output<'a>,
+    pub sd_mosi: &'a Output<'a>,
+    pub sd_miso: &'a Input<'a>,
+    pub sd_cs_n: &'a Output<'a>,
+
+    pub flash_sclk: &'a Output<'a>,
+    pub flash_mosi: &'a Output<'a>,
+    pub flash_miso: &'a Input<'a>,
+    pub flash_cs_n: &'a Output<'a>,
 
     pub ddr3: MigUiPort<'a>,
 }
@@ -31,21 +57,34 @@ impl<'a> Xenowing<'a> {
 
         let leds = m.output("leds", inner.leds);
 
-        let clock_freq = 100000000;
-        let uart_baud_rate = 460800;
-
-        let uart_tx = UartTx::new("uart_tx", clock_freq, uart_baud_rate, m);
+        let uart_tx = UartTx::new("uart_tx", m);
         let tx = m.output("tx", uart_tx.tx);
 
-        let uart_rx = UartRx::new("uart_rx", clock_freq, uart_baud_rate, m);
+        let uart_rx = UartRx::new("uart_rx", m);
         let rx = m.input("rx", 1);
         uart_rx.rx.drive(rx);
 
         uart_tx.data.drive(inner.uart_tx_data);
         uart_tx.enable.drive(inner.uart_tx_enable);
+        uart_tx.baud_divisor.drive(inner.uart_baud_divisor);
         inner.uart_tx_ready.drive(uart_tx.ready);
+        uart_rx.baud_divisor.drive(inner.uart_baud_divisor);
         inner.uart_rx_data.drive(uart_rx.data);
         inner.uart_rx_data_valid.drive(uart_rx.data_valid);
+        inner.uart_rx_framing_error.drive(uart_rx.framing_error);
+
+        let rts_n = m.output("rts_n", !inner.uart_rts);
+        // Requires external sync FF's
+        let cts_n = m.input("cts_n", 1);
+        inner.uart_cts.drive(!cts_n);
+
+        // Requires external sync FF's
+        let sd_miso = m.input("sd_miso", 1);
+        inner.sd_miso.drive(sd_miso);
+
+        // Requires external sync FF's
+        let flash_miso = m.input("flash_miso", 1);
+        inner.flash_miso.drive(flash_miso);
 
         Xenowing {
             m,
@@ -54,6 +93,27 @@ impl<'a> Xenowing<'a> {
 
             tx,
             rx,
+            rts_n,
+            cts_n,
+
+            video_pixel_clock_enable: m.output("video_pixel_clock_enable", inner.video_pixel_clock_enable),
+            video_hsync_n: m.output("video_hsync_n", inner.video_hsync_n),
+            video_vsync_n: m.output("video_vsync_n", inner.video_vsync_n),
+            video_de: m.output("video_de", inner.video_de),
+            video_rgb: m.output("video_rgb", inner.video_rgb),
+
+            audio_left: m.output("audio_left", inner.audio_left),
+            audio_right: m.output("audio_right", inner.audio_right),
+
+            sd_sclk: m.output("sd_sclk", inner.sd_sclk),
+            sd_mosi: m.output("sd_mosi", inner.sd_mosi),
+            sd_miso,
+            sd_cs_n: m.output("sd_cs_n", inner.sd_cs_n),
+
+            flash_sclk: m.output("flash_sclk", inner.flash_sclk),
+            flash_mosi: m.output("flash_mosi", inner.flash_mosi),
+            flash_miso,
+            flash_cs_n: m.output("flash_cs_n", inner.flash_cs_n),
 
             ddr3: inner.ddr3.forward("ddr3", m),
         }
@@ -71,7 +131,30 @@ pub struct XenowingInner<'a> {
     pub uart_tx_ready: &'a Input<'a>,
     pub uart_rx_data: &'a Input<'a>,
     pub uart_rx_data_valid: &'a Input<'a>,
+    pub uart_rx_framing_error: &'a Input<'a>,
     pub uart_rx_ready: &'a Output<'a>,
+    pub uart_baud_divisor: &'a Output<'a>,
+    pub uart_rts: &'a Output<'a>,
+    pub uart_cts: &'a Input<'a>,
+
+    pub video_pixel_clock_enable: &'a Output<'a>,
+    pub video_hsync_n: &'a Output<'a>,
+    pub video_vsync_n: &'a Output<'a>,
+    pub video_de: &'a Output<'a>,
+    pub video_rgb: &'a Output<'a>,
+
+    pub audio_left: &'a Output<'a>,
+    pub audio_right: &'a Output<'a>,
+
+    pub sd_sclk: &'a Output<'a>,
+    pub sd_mosi: &'a Output<'a>,
+    pub sd_miso: &'a Input<'a>,
+    pub sd_cs_n: &'a Output<'a>,
+
+    pub flash_sclk: &'a Output<'a>,
+    pub flash_mosi: &'a Output<'a>,
+    pub flash_miso: &'a Input<'a>,
+    pub flash_cs_n: &'a Output<'a>,
 
     pub ddr3: MigUiPort<'a>,
 }
@@ -96,12 +179,46 @@ impl<'a> XenowingInner<'a> {
         uart_interface.rx_data.drive(uart_rx_data);
         let uart_rx_data_valid = m.input("uart_rx_data_valid", 1);
         uart_interface.rx_data_valid.drive(uart_rx_data_valid);
+        let uart_rx_framing_error = m.input("uart_rx_framing_error", 1);
+        uart_interface.rx_framing_error.drive(uart_rx_framing_error);
         let uart_rx_ready = m.output("uart_rx_ready", uart_interface.rx_ready);
+        let uart_baud_divisor = m.output("uart_baud_divisor", uart_interface.baud_divisor);
+        let uart_rts = m.output("uart_rts", uart_interface.rts);
+        let uart_cts = m.input("uart_cts", 1);
+        uart_interface.cts.drive(uart_cts);
 
-        let color_thrust = ColorThrust::new("color_thrust", m);
+        let color_thrust = ColorThrust::new("color_thrust", 1, TILE_DIM_BITS_DEFAULT, TILE_DIM_BITS_DEFAULT, m);
 
         let bit_pusher = BitPusher::new("bit_pusher", m);
 
+        // 100MHz / 4 = 25MHz pixel clock, which is close enough to 640x480@60's nominal 25.175MHz for any display
+        let scanout = Scanout::new("scanout", 2, m);
+        let video_pixel_clock_enable = m.output("video_pixel_clock_enable", scanout.pixel_clock_enable);
+        let video_hsync_n = m.output("video_hsync_n", scanout.hsync_n);
+        let video_vsync_n = m.output("video_vsync_n", scanout.vsync_n);
+        let video_de = m.output("video_de", scanout.de);
+        let video_rgb = m.output("video_rgb", scanout.rgb);
+
+        let audio = Audio::new("audio", m);
+        let audio_left = m.output("audio_left", audio.left);
+        let audio_right = m.output("audio_right", audio.right);
+        // TODO: Route audio.irq to the CPU once it has interrupt support; until then, drivers poll its status reg
+
+        let sd_spi = Spi::new("sd_spi", m);
+        let sd_sclk = m.output("sd_sclk", sd_spi.sclk);
+        let sd_mosi = m.output("sd_mosi", sd_spi.mosi);
+        let sd_miso = m.input("sd_miso", 1);
+        sd_spi.miso.drive(sd_miso);
+        let sd_cs_n = m.output("sd_cs_n", sd_spi.cs_n);
+
+        // Same master as the SD card's; flash commands are sequenced in sw
+        let flash_spi = Spi::new("flash_spi", m);
+        let flash_sclk = m.output("flash_sclk", flash_spi.sclk);
+        let flash_mosi = m.output("flash_mosi", flash_spi.mosi);
+        let flash_miso = m.input("flash_miso", 1);
+        flash_spi.miso.drive(flash_miso);
+        let flash_cs_n = m.output("flash_cs_n", flash_spi.cs_n);
+
         let ddr3_bridge = BusterMigUiBridge::new("ddr3_bridge", 128, 24, m);
 
         // Interconnect
@@ -116,13 +233,15 @@ impl<'a> XenowingInner<'a> {
         marv.data_port.connect(&marv_data_bridge.marv_port);
         marv_data_bridge.system_port.connect(&cpu_crossbar.replica_ports[1]);
 
-        let mem_crossbar = Crossbar::new("mem_crossbar", 3, 1, 24, 0, 128, 5, m);
+        let mem_crossbar = Crossbar::new("mem_crossbar", 5, 1, 24, 0, 128, 5, m);
         cpu_crossbar.primary_ports[1].connect(&mem_crossbar.replica_ports[0]);
         color_thrust.tex_cache_system_port.connect(&mem_crossbar.replica_ports[1]);
         bit_pusher.mem_port.connect(&mem_crossbar.replica_ports[2]);
+        scanout.mem_port.connect(&mem_crossbar.replica_ports[3]);
+        audio.mem_port.connect(&mem_crossbar.replica_ports[4]);
         mem_crossbar.primary_ports[0].connect(&ddr3_bridge.client_port);
 
-        let sys_crossbar = Crossbar::new("sys_crossbar", 2, 7, 24, 4, 128, 5, m);
+        let sys_crossbar = Crossbar::new("sys_crossbar", 2, 12, 24, 4, 128, 5, m);
         cpu_crossbar.primary_ports[0].connect(&sys_crossbar.replica_ports[0]);
         bit_pusher.sys_port.connect(&sys_crossbar.replica_ports[1]);
         sys_crossbar.primary_ports[0].connect(&boot_rom.client_port);
@@ -132,6 +251,11 @@ impl<'a> XenowingInner<'a> {
         sys_crossbar.primary_ports[4].connect(&color_thrust.color_buffer_port);
         sys_crossbar.primary_ports[5].connect(&color_thrust.depth_buffer_port);
         sys_crossbar.primary_ports[6].connect(&bit_pusher.reg_port);
+        sys_crossbar.primary_ports[7].connect(&color_thrust.stencil_buffer_port);
+        sys_crossbar.primary_ports[8].connect(&scanout.reg_port);
+        sys_crossbar.primary_ports[9].connect(&audio.reg_port);
+        sys_crossbar.primary_ports[10].connect(&sd_spi.reg_port);
+        sys_crossbar.primary_ports[11].connect(&flash_spi.reg_port);
 
         XenowingInner {
             m,
@@ -143,7 +267,30 @@ impl<'a> XenowingInner<'a> {
             uart_tx_ready,
             uart_rx_data,
             uart_rx_data_valid,
+            uart_rx_framing_error,
             uart_rx_ready,
+            uart_baud_divisor,
+            uart_rts,
+            uart_cts,
+
+            video_pixel_clock_enable,
+            video_hsync_n,
+            video_vsync_n,
+            video_de,
+            video_rgb,
+
+            audio_left,
+            audio_right,
+
+            sd_sclk,
+            sd_mosi,
+            sd_miso,
+            sd_cs_n,
+
+            flash_sclk,
+            flash_mosi,
+            flash_miso,
+            flash_cs_n,
 
             ddr3: ddr3_bridge.ui_port.forward("ddr3", m),
         }
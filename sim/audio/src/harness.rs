@@ -0,0 +1,108 @@
+use crate::modules::*;
+
+use mem_model::*;
+
+use rtl_meta::audio::*;
+
+// Cycles at either end of each sample period that aren't considered when recovering frames, which covers the latency
+//  from each sample tick to the outputs
+pub const SAMPLE_GUARD_CYCLES: u32 = 8;
+
+// Recovers frames from the bitstreams by counting ones over each sample period. This is a crude box filter compared
+//  to the RC filter on the board, but plenty to listen to or check against. Periods are counted from the last enable
+//  call, since the first frame is played a full period after that.
+pub struct Harness {
+    pub m: Audio,
+    pub mem: ReadOnlyMem,
+
+    pub frames: Vec<(i16, i16)>,
+
+    sample_period: u32,
+    cycle_index: u32,
+    num_ones: (u32, u32),
+}
+
+impl Harness {
+    pub fn new(mem: Vec<u128>, sample_period: u32) -> Harness {
+        let mut m = Audio::new();
+
+        m.reset();
+        m.reg_bus_enable = false;
+        m.reg_bus_addr = 0;
+        m.reg_bus_write = false;
+        m.reg_bus_write_data = 0;
+        m.reg_bus_write_byte_enable = 0xffff;
+        m.mem_bus_ready = false;
+        m.mem_bus_read_data = 0;
+        m.mem_bus_read_data_valid = false;
+
+        Harness {
+            m,
+            mem: ReadOnlyMem::new(mem),
+
+            frames: Vec::new(),
+
+            sample_period,
+            cycle_index: 0,
+            num_ones: (0, 0),
+        }
+    }
+
+    pub fn step(&mut self) {
+        let mem_inputs = self.mem.port_inputs();
+        self.m.mem_bus_ready = mem_inputs.ready;
+        self.m.mem_bus_read_data = mem_inputs.read_data;
+        self.m.mem_bus_read_data_valid = mem_inputs.read_data_valid;
+
+        self.m.prop();
+
+        self.mem.posedge_clk(mem_inputs.ready, self.m.mem_bus_enable, self.m.mem_bus_addr, self.m.mem_bus_write);
+
+        if self.cycle_index >= SAMPLE_GUARD_CYCLES && self.cycle_index < self.sample_period - SAMPLE_GUARD_CYCLES {
+            self.num_ones.0 += self.m.left as u32;
+            self.num_ones.1 += self.m.right as u32;
+        }
+        self.cycle_index += 1;
+        if self.cycle_index == self.sample_period {
+            let window = self.sample_period - SAMPLE_GUARD_CYCLES * 2;
+            let recover = |num_ones: u32| ((num_ones as i64 * 65536 / window as i64) - 32768) as i16;
+            self.frames.push((recover(self.num_ones.0), recover(self.num_ones.1)));
+            self.num_ones = (0, 0);
+            self.cycle_index = 0;
+        }
+
+        self.m.posedge_clk();
+    }
+
+    pub fn write_reg(&mut self, addr: u32, data: u32) {
+        self.m.reg_bus_enable = true;
+        self.m.reg_bus_addr = addr;
+        self.m.reg_bus_write = true;
+        self.m.reg_bus_write_data = data as _;
+        self.step();
+        self.m.reg_bus_enable = false;
+    }
+
+    pub fn read_reg(&mut self, addr: u32) -> u32 {
+        self.m.reg_bus_enable = true;
+        self.m.reg_bus_addr = addr;
+        self.m.reg_bus_write = false;
+        self.step();
+        self.m.reg_bus_enable = false;
+        self.m.prop();
+        assert_eq!(self.m.reg_bus_read_data_valid, true);
+        self.m.reg_bus_read_data as _
+    }
+
+    pub fn irq(&mut self) -> bool {
+        self.m.prop();
+        self.m.irq
+    }
+
+    pub fn enable(&mut self) {
+        self.write_reg(REG_ENABLE_ADDR, 1);
+        // Any partial period recovered so far is dropped
+        self.cycle_index = 0;
+        self.num_ones = (0, 0);
+    }
+}
@@ -0,0 +1,133 @@
+mod harness;
+mod modules {
+    include!(concat!(env!("OUT_DIR"), "/modules.rs"));
+}
+
+#[cfg(test)]
+mod tests;
+
+use harness::*;
+
+use rtl_meta::audio::*;
+use rtl_meta::xenowing::SYSTEM_CLOCK_FREQ;
+
+use std::env;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+const RING_BUFFER_NUM_WORDS: u32 = 512;
+const RING_BUFFER_HALF_NUM_WORDS: u32 = RING_BUFFER_NUM_WORDS / 2;
+const FRAMES_PER_WORD: u32 = 1 << FRAMES_PER_WORD_BITS;
+
+const TONE_FREQS: (f64, f64) = (440.0, 660.0);
+const TONE_AMPLITUDE: f64 = 0.5;
+
+struct ToneGenerator {
+    sample_rate: f64,
+    frame_index: u64,
+}
+
+impl ToneGenerator {
+    fn next_frame(&mut self) -> (i16, i16) {
+        let t = self.frame_index as f64 / self.sample_rate;
+        self.frame_index += 1;
+        let tone = |freq: f64| ((2.0 * PI * freq * t).sin() * TONE_AMPLITUDE * 32767.0) as i16;
+        (tone(TONE_FREQS.0), tone(TONE_FREQS.1))
+    }
+
+    // Fills the given words the way a driver would, packing frames in play order
+    fn fill(&mut self, words: &mut [u128]) {
+        for word in words.iter_mut() {
+            *word = 0;
+            for i in 0..FRAMES_PER_WORD {
+                let (left, right) = self.next_frame();
+                *word |= (((right as u16 as u128) << 16) | (left as u16 as u128)) << (i * 32);
+            }
+        }
+    }
+}
+
+fn write_wav(path: &str, sample_rate: u32, frames: &[(i16, i16)]) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+
+    let num_channels = 2u16;
+    let bits_per_sample = 16u16;
+    let block_align = num_channels * bits_per_sample / 8;
+    let data_size = frames.len() as u32 * block_align as u32;
+
+    w.write_all(b"RIFF")?;
+    w.write_all(&(36 + data_size).to_le_bytes())?;
+    w.write_all(b"WAVE")?;
+
+    w.write_all(b"fmt ")?;
+    w.write_all(&16u32.to_le_bytes())?;
+    w.write_all(&1u16.to_le_bytes())?; // PCM
+    w.write_all(&num_channels.to_le_bytes())?;
+    w.write_all(&sample_rate.to_le_bytes())?;
+    w.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    w.write_all(&block_align.to_le_bytes())?;
+    w.write_all(&bits_per_sample.to_le_bytes())?;
+
+    w.write_all(b"data")?;
+    w.write_all(&data_size.to_le_bytes())?;
+    for &(left, right) in frames {
+        w.write_all(&left.to_le_bytes())?;
+        w.write_all(&right.to_le_bytes())?;
+    }
+
+    w.flush()
+}
+
+fn main() {
+    let output_path = env::args().skip(1).nth(0).expect("output path not specified");
+    let duration: f64 = env::args().skip(1).nth(1).map(|arg| arg.parse().expect("Couldn't parse duration")).unwrap_or(0.25);
+
+    let sample_period = REG_SAMPLE_PERIOD_DEFAULT;
+    let sample_rate = SYSTEM_CLOCK_FREQ / sample_period;
+    let num_frames = (duration * sample_rate as f64) as usize;
+
+    println!("Capturing {} frames ({}s at {}Hz) to {}", num_frames, duration, sample_rate, output_path);
+
+    let mut tones = ToneGenerator {
+        sample_rate: sample_rate as _,
+        frame_index: 0,
+    };
+
+    // The ring buffer lives at the bottom of our little memory
+    let mut mem = vec![0; RING_BUFFER_NUM_WORDS as usize];
+    tones.fill(&mut mem);
+
+    let mut h = Harness::new(mem, sample_period);
+
+    h.write_reg(REG_RING_BUFFER_BASE_ADDR, 0);
+    h.write_reg(REG_RING_BUFFER_NUM_WORDS_ADDR, RING_BUFFER_NUM_WORDS);
+    h.write_reg(REG_SAMPLE_PERIOD_ADDR, sample_period);
+    h.write_reg(REG_INTERRUPT_ENABLE_ADDR, (1 << REG_INTERRUPT_FIRST_HALF_BIT) | (1 << REG_INTERRUPT_SECOND_HALF_BIT));
+    h.enable();
+
+    while h.frames.len() < num_frames {
+        h.step();
+
+        // Refill whichever half just finished, like an interrupt handler would
+        if h.irq() {
+            let status = h.read_reg(REG_INTERRUPT_STATUS_ADDR);
+            let half_num_words = RING_BUFFER_HALF_NUM_WORDS as usize;
+            if (status & (1 << REG_INTERRUPT_FIRST_HALF_BIT)) != 0 {
+                tones.fill(&mut h.mem.words[..half_num_words]);
+            }
+            if (status & (1 << REG_INTERRUPT_SECOND_HALF_BIT)) != 0 {
+                tones.fill(&mut h.mem.words[half_num_words..]);
+            }
+            h.write_reg(REG_INTERRUPT_STATUS_ADDR, status);
+        }
+    }
+
+    if h.read_reg(REG_UNDERRUN_ADDR) != 0 {
+        println!("Warning: ring buffer underran during capture");
+    }
+
+    write_wav(&output_path, sample_rate, &h.frames[..num_frames]).expect("Couldn't write WAV file");
+
+    println!("Done");
+}
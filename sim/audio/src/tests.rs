@@ -0,0 +1,184 @@
+use crate::harness::*;
+
+use rtl_meta::audio::*;
+
+const MEM_NUM_WORDS: usize = 1 << 12;
+
+const RING_BUFFER_BASE_ADDR: u32 = 0x100 * 16;
+const RING_BUFFER_NUM_WORDS: u32 = 8;
+const RING_BUFFER_NUM_FRAMES: u32 = RING_BUFFER_NUM_WORDS << FRAMES_PER_WORD_BITS;
+
+// Long enough to recover frames from the bitstreams with reasonable precision
+const SAMPLE_PERIOD: u32 = 512;
+
+fn start(h: &mut Harness) {
+    h.write_reg(REG_RING_BUFFER_BASE_ADDR, RING_BUFFER_BASE_ADDR);
+    h.write_reg(REG_RING_BUFFER_NUM_WORDS_ADDR, RING_BUFFER_NUM_WORDS);
+    h.write_reg(REG_SAMPLE_PERIOD_ADDR, SAMPLE_PERIOD);
+    h.enable();
+}
+
+// Steps until the next frame has been recovered
+fn next_frame(h: &mut Harness) -> (i16, i16) {
+    let num_frames = h.frames.len();
+    while h.frames.len() == num_frames {
+        h.step();
+    }
+    h.frames[num_frames]
+}
+
+// A first-order modulator's output over a window is within one pulse of the ideal
+fn assert_frame_eq(actual: (i16, i16), expected: (i16, i16), frame_index: u32) {
+    let tolerance = 65536 / (SAMPLE_PERIOD - SAMPLE_GUARD_CYCLES * 2) as i32 + 1;
+    assert!(
+        (actual.0 as i32 - expected.0 as i32).abs() <= tolerance && (actual.1 as i32 - expected.1 as i32).abs() <= tolerance,
+        "frame {} mismatch: expected {:?}, got {:?}", frame_index, expected, actual);
+}
+
+fn frame(index: u32) -> (i16, i16) {
+    // Arbitrary, but spread across the whole range
+    let x = index.wrapping_mul(0x9e3779b1);
+    ((x >> 16) as i16, x as i16)
+}
+
+fn build_mem() -> Vec<u128> {
+    let mut mem = vec![0; MEM_NUM_WORDS];
+    for i in 0..RING_BUFFER_NUM_FRAMES {
+        let (left, right) = frame(i);
+        let word = &mut mem[(RING_BUFFER_BASE_ADDR / 16 + (i >> FRAMES_PER_WORD_BITS)) as usize];
+        *word |= (((right as u16 as u128) << 16) | (left as u16 as u128)) << ((i & ((1 << FRAMES_PER_WORD_BITS) - 1)) * 32);
+    }
+    mem
+}
+
+#[test]
+fn plays_ring_buffer() {
+    let mut h = Harness::new(build_mem(), SAMPLE_PERIOD);
+
+    start(&mut h);
+
+    // Nothing plays until the first sample period has passed
+    assert_frame_eq(next_frame(&mut h), (0, 0), 0);
+
+    // Loop around the ring buffer a couple times
+    for i in 0..RING_BUFFER_NUM_FRAMES * 2 {
+        assert_frame_eq(next_frame(&mut h), frame(i % RING_BUFFER_NUM_FRAMES), i);
+    }
+    assert_eq!(h.read_reg(REG_UNDERRUN_ADDR), 0);
+}
+
+#[test]
+fn half_buffer_interrupts() {
+    let mut h = Harness::new(build_mem(), SAMPLE_PERIOD);
+
+    h.write_reg(REG_INTERRUPT_ENABLE_ADDR, 1 << REG_INTERRUPT_SECOND_HALF_BIT);
+    start(&mut h);
+
+    let wait_for_status = |h: &mut Harness| loop {
+        let status = h.read_reg(REG_INTERRUPT_STATUS_ADDR);
+        if status != 0 {
+            break status;
+        }
+    };
+
+    for _ in 0..2 {
+        // The first half is done once its last word is taken, at which point the second half is up next
+        assert_eq!(wait_for_status(&mut h), 1 << REG_INTERRUPT_FIRST_HALF_BIT);
+        assert_eq!(h.read_reg(REG_PLAY_POSITION_ADDR), RING_BUFFER_NUM_WORDS / 2);
+        // .. but only the second half's interrupt is enabled
+        assert!(!h.irq());
+        h.write_reg(REG_INTERRUPT_STATUS_ADDR, 1 << REG_INTERRUPT_FIRST_HALF_BIT);
+        assert_eq!(h.read_reg(REG_INTERRUPT_STATUS_ADDR), 0);
+
+        assert_eq!(wait_for_status(&mut h), 1 << REG_INTERRUPT_SECOND_HALF_BIT);
+        assert_eq!(h.read_reg(REG_PLAY_POSITION_ADDR), 0);
+        assert!(h.irq());
+        h.write_reg(REG_INTERRUPT_STATUS_ADDR, 1 << REG_INTERRUPT_SECOND_HALF_BIT);
+        assert!(!h.irq());
+    }
+}
+
+#[test]
+fn underrun_plays_silence() {
+    let mut mem = build_mem();
+    for word in mem.iter_mut() {
+        // Full scale, so it'd be obvious if any of it got through
+        *word = 0x7fff7fff_7fff7fff_7fff7fff_7fff7fff;
+    }
+    let mut h = Harness::new(mem, SAMPLE_PERIOD);
+    h.mem.stalled = true;
+
+    start(&mut h);
+
+    for i in 0..4 {
+        assert_frame_eq(next_frame(&mut h), (0, 0), i);
+    }
+    assert_eq!(h.read_reg(REG_UNDERRUN_ADDR), 1);
+    h.write_reg(REG_UNDERRUN_ADDR, 1);
+    assert_eq!(h.read_reg(REG_UNDERRUN_ADDR), 0);
+}
+
+#[test]
+fn disabled_is_silent() {
+    let mut h = Harness::new(build_mem(), SAMPLE_PERIOD);
+
+    h.write_reg(REG_RING_BUFFER_BASE_ADDR, RING_BUFFER_BASE_ADDR);
+    h.write_reg(REG_RING_BUFFER_NUM_WORDS_ADDR, RING_BUFFER_NUM_WORDS);
+    h.write_reg(REG_SAMPLE_PERIOD_ADDR, SAMPLE_PERIOD);
+
+    for i in 0..4 {
+        assert_frame_eq(next_frame(&mut h), (0, 0), i);
+    }
+    assert_eq!(h.mem.num_reads, 0);
+}
+
+#[test]
+fn restart_plays_from_beginning() {
+    let mut h = Harness::new(build_mem(), SAMPLE_PERIOD);
+
+    start(&mut h);
+    for _ in 0..RING_BUFFER_NUM_FRAMES / 2 + 3 {
+        next_frame(&mut h);
+    }
+
+    h.write_reg(REG_ENABLE_ADDR, 0);
+    for _ in 0..SAMPLE_PERIOD {
+        h.step();
+    }
+    assert_eq!(h.read_reg(REG_PLAY_POSITION_ADDR), 0);
+
+    h.enable();
+    assert_frame_eq(next_frame(&mut h), (0, 0), 0);
+    for i in 0..RING_BUFFER_NUM_FRAMES {
+        assert_frame_eq(next_frame(&mut h), frame(i), i);
+    }
+}
+
+#[test]
+fn reg_reads() {
+    let mut h = Harness::new(vec![0; MEM_NUM_WORDS], SAMPLE_PERIOD);
+
+    assert_eq!(h.read_reg(REG_SAMPLE_PERIOD_ADDR), REG_SAMPLE_PERIOD_DEFAULT);
+
+    h.write_reg(REG_RING_BUFFER_BASE_ADDR, RING_BUFFER_BASE_ADDR);
+    h.write_reg(REG_RING_BUFFER_NUM_WORDS_ADDR, RING_BUFFER_NUM_WORDS);
+    h.write_reg(REG_SAMPLE_PERIOD_ADDR, SAMPLE_PERIOD);
+    h.write_reg(REG_INTERRUPT_ENABLE_ADDR, 3);
+
+    assert_eq!(h.read_reg(REG_ENABLE_ADDR), 0);
+    assert_eq!(h.read_reg(REG_RING_BUFFER_BASE_ADDR), RING_BUFFER_BASE_ADDR);
+    assert_eq!(h.read_reg(REG_RING_BUFFER_NUM_WORDS_ADDR), RING_BUFFER_NUM_WORDS);
+    assert_eq!(h.read_reg(REG_SAMPLE_PERIOD_ADDR), SAMPLE_PERIOD);
+    assert_eq!(h.read_reg(REG_INTERRUPT_ENABLE_ADDR), 3);
+    assert_eq!(h.read_reg(REG_INTERRUPT_STATUS_ADDR), 0);
+    assert_eq!(h.read_reg(REG_PLAY_POSITION_ADDR), 0);
+    assert_eq!(h.read_reg(REG_UNDERRUN_ADDR), 0);
+
+    h.enable();
+    assert_eq!(h.read_reg(REG_ENABLE_ADDR), 1);
+    for _ in 0..SAMPLE_PERIOD * 5 {
+        h.step();
+    }
+    // One word's been taken for frames 0-3, and the second is playing frame 4
+    assert_eq!(h.read_reg(REG_PLAY_POSITION_ADDR), 2);
+}
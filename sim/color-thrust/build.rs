@@ -0,0 +1,35 @@
+use kaze::*;
+use rtl::color_thrust::*;
+
+use rtl_meta::color_thrust::*;
+
+use std::env;
+use std::fs::File;
+use std::io::{Result, Write};
+use std::path::Path;
+
+// Tests compare these against each other, so they only differ in their pixel pipe count
+fn generate_top<'a>(c: &'a Context<'a>, name: &str, num_pixel_pipes: u32, w: impl Write) -> Result<()> {
+    let m = c.module(name, name);
+
+    let color_thrust = ColorThrust::new("color_thrust", num_pixel_pipes, TILE_DIM_BITS_DEFAULT, TILE_DIM_BITS_DEFAULT, m);
+    color_thrust.reg_port.forward("reg", m);
+    color_thrust.color_buffer_port.forward("color_buffer", m);
+    color_thrust.depth_buffer_port.forward("depth_buffer", m);
+    color_thrust.stencil_buffer_port.forward("stencil_buffer", m);
+    color_thrust.tex_cache_system_port.forward("tex_cache_system", m);
+
+    sim::generate(m, sim::GenerationOptions::default(), w)
+}
+
+fn main() -> Result<()> {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("modules.rs");
+    let mut file = File::create(&dest_path).unwrap();
+
+    let c = Context::new();
+
+    generate_top(&c, "OnePipeTop", 1, &mut file)?;
+    generate_top(&c, "TwoPipeTop", 2, &mut file)?;
+    generate_top(&c, "FourPipeTop", 4, file)
+}
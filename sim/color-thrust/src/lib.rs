@@ -0,0 +1,274 @@
+#[cfg(test)]
+mod tests {
+    mod modules {
+        include!(concat!(env!("OUT_DIR"), "/modules.rs"));
+    }
+
+    use modules::*;
+
+    use rtl_meta::color_thrust::*;
+
+    const TILE_DIM: u32 = 1 << TILE_DIM_BITS_DEFAULT;
+    const TILE_COLOR_BUFFER_WORDS: u32 = TILE_DIM * TILE_DIM / 4;
+
+    const CLEAR_COLOR: u32 = 0xff202020;
+
+    trait Harness {
+        fn new() -> Self;
+        fn write_reg(&mut self, addr: u32, data: u32);
+        fn read_reg(&mut self, addr: u32) -> u32;
+        fn read_color_buffer_word(&mut self, addr: u32) -> u128;
+        fn cycles(&self) -> u64;
+    }
+
+    // The generated tops only differ in name, so they share a bus driver
+    macro_rules! harness {
+        ($name:ident, $top:ident) => {
+            struct $name {
+                top: $top,
+                cycles: u64,
+            }
+
+            impl $name {
+                fn posedge_clk(&mut self) {
+                    self.top.posedge_clk();
+                    self.top.prop();
+                    self.cycles += 1;
+                }
+            }
+
+            impl Harness for $name {
+                fn new() -> $name {
+                    let mut top = $top::new();
+                    top.reset();
+                    top.reg_bus_enable = false;
+                    top.color_buffer_bus_enable = false;
+                    top.depth_buffer_bus_enable = false;
+                    top.stencil_buffer_bus_enable = false;
+                    // Untextured pixels never reach the tex cache, so its system port is left unserviced
+                    top.tex_cache_system_bus_ready = false;
+                    top.tex_cache_system_bus_read_data = 0;
+                    top.tex_cache_system_bus_read_data_valid = false;
+                    top.prop();
+
+                    $name {
+                        top,
+                        cycles: 0,
+                    }
+                }
+
+                fn write_reg(&mut self, addr: u32, data: u32) {
+                    self.top.reg_bus_addr = addr;
+                    self.top.reg_bus_enable = true;
+                    self.top.reg_bus_write = true;
+                    self.top.reg_bus_write_data = data as _;
+                    self.top.prop();
+                    loop {
+                        let ready = self.top.reg_bus_ready;
+                        self.posedge_clk();
+                        if ready {
+                            break;
+                        }
+                    }
+                    self.top.reg_bus_enable = false;
+                    self.top.prop();
+                }
+
+                fn read_reg(&mut self, addr: u32) -> u32 {
+                    self.top.reg_bus_addr = addr;
+                    self.top.reg_bus_enable = true;
+                    self.top.reg_bus_write = false;
+                    self.top.prop();
+                    loop {
+                        let ready = self.top.reg_bus_ready;
+                        self.posedge_clk();
+                        if ready {
+                            break;
+                        }
+                    }
+                    self.top.reg_bus_enable = false;
+                    self.top.prop();
+                    while !self.top.reg_bus_read_data_valid {
+                        self.posedge_clk();
+                    }
+                    self.top.reg_bus_read_data as _
+                }
+
+                fn read_color_buffer_word(&mut self, addr: u32) -> u128 {
+                    self.top.color_buffer_bus_addr = addr;
+                    self.top.color_buffer_bus_enable = true;
+                    self.top.color_buffer_bus_write = false;
+                    self.top.prop();
+                    loop {
+                        let ready = self.top.color_buffer_bus_ready;
+                        self.posedge_clk();
+                        if ready {
+                            break;
+                        }
+                    }
+                    self.top.color_buffer_bus_enable = false;
+                    self.top.prop();
+                    while !self.top.color_buffer_bus_read_data_valid {
+                        self.posedge_clk();
+                    }
+                    self.top.color_buffer_bus_read_data
+                }
+
+                fn cycles(&self) -> u64 {
+                    self.cycles
+                }
+            }
+        };
+    }
+
+    harness!(OnePipeHarness, OnePipeTop);
+    harness!(TwoPipeHarness, TwoPipeTop);
+    harness!(FourPipeHarness, FourPipeTop);
+
+    // Edge functions are given as (a, b, c) for a * x + b * y + c, in whole pixels; color and z are (min, dx, dy)
+    struct Triangle {
+        edges: [(i32, i32, i32); 3],
+        r: (u32, u32, u32),
+        g: (u32, u32, u32),
+        b: (u32, u32, u32),
+        z: (u32, u32, u32),
+    }
+
+    fn wait_for_idle<H: Harness>(h: &mut H) {
+        while h.read_reg(REG_STATUS_ADDR) != 0 {
+            // Do nothing
+        }
+    }
+
+    fn draw<H: Harness>(h: &mut H, triangle: &Triangle) {
+        let edge_regs = [
+            (REG_W0_MIN_ADDR, REG_W0_DX_ADDR, REG_W0_DY_ADDR),
+            (REG_W1_MIN_ADDR, REG_W1_DX_ADDR, REG_W1_DY_ADDR),
+            (REG_W2_MIN_ADDR, REG_W2_DX_ADDR, REG_W2_DY_ADDR),
+        ];
+        for (&(min_addr, dx_addr, dy_addr), &(a, b, c)) in edge_regs.iter().zip(triangle.edges.iter()) {
+            h.write_reg(min_addr, (c << EDGE_FRACT_BITS) as _);
+            h.write_reg(dx_addr, (a << EDGE_FRACT_BITS) as _);
+            h.write_reg(dy_addr, (b << EDGE_FRACT_BITS) as _);
+        }
+        let attribute_regs = [
+            (REG_R_MIN_ADDR, REG_R_DX_ADDR, REG_R_DY_ADDR, triangle.r),
+            (REG_G_MIN_ADDR, REG_G_DX_ADDR, REG_G_DY_ADDR, triangle.g),
+            (REG_B_MIN_ADDR, REG_B_DX_ADDR, REG_B_DY_ADDR, triangle.b),
+            (REG_A_MIN_ADDR, REG_A_DX_ADDR, REG_A_DY_ADDR, (0xff << COLOR_FRACT_BITS, 0, 0)),
+            (REG_W_INVERSE_MIN_ADDR, REG_W_INVERSE_DX_ADDR, REG_W_INVERSE_DY_ADDR, (1 << W_INVERSE_FRACT_BITS, 0, 0)),
+            (REG_Z_MIN_ADDR, REG_Z_DX_ADDR, REG_Z_DY_ADDR, triangle.z),
+            (REG_S_MIN_ADDR, REG_S_DX_ADDR, REG_S_DY_ADDR, (0, 0, 0)),
+            (REG_T_MIN_ADDR, REG_T_DX_ADDR, REG_T_DY_ADDR, (0, 0, 0)),
+        ];
+        for &(min_addr, dx_addr, dy_addr, (min, dx, dy)) in attribute_regs.iter() {
+            h.write_reg(min_addr, min);
+            h.write_reg(dx_addr, dx);
+            h.write_reg(dy_addr, dy);
+        }
+
+        wait_for_idle(h);
+        h.write_reg(REG_START_ADDR, 1);
+    }
+
+    // Returns the tile's color buffer and the number of cycles spent rasterizing
+    fn render_scene<H: Harness>(h: &mut H) -> (Vec<u128>, u64) {
+        h.write_reg(
+            REG_DEPTH_SETTINGS_ADDR,
+            (1 << REG_DEPTH_TEST_ENABLE_BIT) |
+            (1 << REG_DEPTH_WRITE_MASK_ENABLE_BIT) |
+            (REG_DEPTH_FUNC_LESS << REG_DEPTH_FUNC_BIT_OFFSET));
+        h.write_reg(REG_TEXTURE_ENV_ADDR, REG_TEXTURE_ENV_MODE_UNTEXTURED << REG_TEXTURE_ENV_MODE_BIT_OFFSET);
+        h.write_reg(
+            REG_BLEND_SETTINGS_ADDR,
+            (REG_BLEND_SETTINGS_SRC_FACTOR_ONE << REG_BLEND_SETTINGS_SRC_FACTOR_BIT_OFFSET) |
+            (REG_BLEND_SETTINGS_DST_FACTOR_ZERO << REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET) |
+            (REG_BLEND_SETTINGS_EQUATION_ADD << REG_BLEND_SETTINGS_EQUATION_BIT_OFFSET));
+
+        h.write_reg(REG_CLEAR_COLOR_ADDR, CLEAR_COLOR);
+        h.write_reg(REG_CLEAR_DEPTH_STENCIL_ADDR, 0xffff << REG_CLEAR_DEPTH_BIT_OFFSET);
+        h.write_reg(REG_CLEAR_TILE_ADDR, (1 << REG_CLEAR_TILE_COLOR_BIT) | (1 << REG_CLEAR_TILE_DEPTH_BIT));
+        wait_for_idle(h);
+
+        let start_cycles = h.cycles();
+
+        // x >= 2, y >= 1, x + y <= 28, with a horizontal red gradient
+        draw(h, &Triangle {
+            edges: [(1, 0, -2), (0, 1, -1), (-1, -1, 28)],
+            r: (0x40 << COLOR_FRACT_BITS, 6 << COLOR_FRACT_BITS, 0),
+            g: (0x10 << COLOR_FRACT_BITS, 0, 0),
+            b: (0x10 << COLOR_FRACT_BITS, 0, 0),
+            z: (0x8000 << Z_FRACT_BITS, 0, 0),
+        });
+        // x <= 29, y <= 30, x + y >= 12, in front of the first triangle towards the top right, and behind it towards
+        //  the bottom left, with a vertical blue gradient
+        draw(h, &Triangle {
+            edges: [(-1, 0, 29), (0, -1, 30), (1, 1, -12)],
+            r: (0x10 << COLOR_FRACT_BITS, 0, 0),
+            g: (0x10 << COLOR_FRACT_BITS, 0, 0),
+            b: (0x20 << COLOR_FRACT_BITS, 0, 7 << COLOR_FRACT_BITS),
+            z: (0xc000 << Z_FRACT_BITS, (0x400u32 << Z_FRACT_BITS).wrapping_neg(), (0x400u32 << Z_FRACT_BITS).wrapping_neg()),
+        });
+        wait_for_idle(h);
+
+        let cycles = h.cycles() - start_cycles;
+
+        (read_color_buffer(h, 0), cycles)
+    }
+
+    fn read_color_buffer<H: Harness>(h: &mut H, base_addr: u32) -> Vec<u128> {
+        (0..TILE_COLOR_BUFFER_WORDS).map(|addr| h.read_color_buffer_word(base_addr + addr)).collect()
+    }
+
+    #[test]
+    fn pixel_pipe_counts_render_identically() {
+        let (one_pipe_buffer, one_pipe_cycles) = render_scene(&mut OnePipeHarness::new());
+        let (two_pipe_buffer, two_pipe_cycles) = render_scene(&mut TwoPipeHarness::new());
+        let (four_pipe_buffer, four_pipe_cycles) = render_scene(&mut FourPipeHarness::new());
+
+        // Make sure both triangles actually landed, so the comparisons below aren't trivially true
+        let pixels = one_pipe_buffer.iter().flat_map(|&word| (0..4).map(move |x| (word >> (x * 32)) as u32)).collect::<Vec<_>>();
+        assert!(pixels.contains(&CLEAR_COLOR));
+        assert!(pixels.iter().any(|&pixel| ((pixel >> 16) & 0xff) > 0x40));
+        assert!(pixels.iter().any(|&pixel| (pixel & 0xff) > 0x20));
+
+        assert_eq!(two_pipe_buffer, one_pipe_buffer);
+        assert_eq!(four_pipe_buffer, one_pipe_buffer);
+
+        assert!(two_pipe_cycles < one_pipe_cycles, "2 pipes took {} cycles, 1 pipe took {}", two_pipe_cycles, one_pipe_cycles);
+        assert!(four_pipe_cycles < two_pipe_cycles, "4 pipes took {} cycles, 2 pipes took {}", four_pipe_cycles, two_pipe_cycles);
+    }
+
+    fn color_swizzle_round_trips<H: Harness>() {
+        let mut h = H::new();
+        let (linear, _) = render_scene(&mut h);
+        let pixel = |x: u32, y: u32| (linear[((y * TILE_DIM + x) / 4) as usize] >> ((x % 4) * 32)) as u32;
+
+        h.write_reg(REG_COLOR_SWIZZLE_ADDR, 0);
+        wait_for_idle(&mut h);
+
+        // Each window word holds 4 texels of a texture chunk row, which are every other pixel of 8 in a tile row
+        let words_per_chunk_row = TILE_DIM / 8;
+        let chunk_rows = TILE_DIM / 2;
+        let expected = (0..TILE_COLOR_BUFFER_WORDS).map(|addr| {
+            let word = addr % words_per_chunk_row;
+            let row = addr / words_per_chunk_row % chunk_rows;
+            let block_x = addr / words_per_chunk_row / chunk_rows % 2;
+            let block_y = addr / words_per_chunk_row / chunk_rows / 2;
+            (0..4).fold(0, |acc, i| acc | ((pixel(word * 8 + i * 2 + block_x, row * 2 + block_y) as u128) << (i * 32)))
+        }).collect::<Vec<_>>();
+        assert_eq!(read_color_buffer(&mut h, 1 << COLOR_BUFFER_BUS_SWIZZLED_WINDOW_BIT), expected);
+
+        h.write_reg(REG_COLOR_SWIZZLE_ADDR, 1 << REG_COLOR_SWIZZLE_INVERSE_BIT);
+        wait_for_idle(&mut h);
+
+        assert_eq!(read_color_buffer(&mut h, 0), linear);
+    }
+
+    #[test]
+    fn color_swizzle_round_trips_for_each_pixel_pipe_count() {
+        color_swizzle_round_trips::<OnePipeHarness>();
+        color_swizzle_round_trips::<TwoPipeHarness>();
+        color_swizzle_round_trips::<FourPipeHarness>();
+    }
+}
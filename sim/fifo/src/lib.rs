@@ -156,4 +156,47 @@ mod tests {
         assert_eq!(m.empty, false);
         assert_eq!(m.read_data, 0);
     }
+
+    #[test]
+    fn count_tracks_writes_and_reads() {
+        let mut m = Fifo::new();
+
+        m.reset();
+        m.prop();
+
+        assert_eq!(m.count, 0);
+
+        m.read_enable = false;
+
+        for i in 0..16 {
+            m.write_enable = true;
+            m.write_data = i;
+            m.prop();
+            assert_eq!(m.count, i);
+            m.posedge_clk();
+        }
+
+        m.write_enable = false;
+        m.prop();
+        assert_eq!(m.count, 16);
+        assert_eq!(m.full, true);
+
+        // Simultaneous write and read leaves the count unchanged
+        m.read_enable = true;
+        m.prop();
+        m.posedge_clk();
+        m.write_enable = true;
+        m.prop();
+        assert_eq!(m.count, 15);
+        m.posedge_clk();
+        m.prop();
+        assert_eq!(m.count, 15);
+
+        m.write_enable = false;
+        for i in (0..15).rev() {
+            m.posedge_clk();
+            m.prop();
+            assert_eq!(m.count, i);
+        }
+    }
 }
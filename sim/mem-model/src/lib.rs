@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+// Roughly what a DDR3 read through the crossbar and MIG bridge costs
+pub const READ_LATENCY: u64 = 24;
+
+// Read-only system memory for sims of modules that fetch over a primary port. The port isn't ready every cycle, so
+//  modules can't count on their requests always being accepted right away.
+pub struct ReadOnlyMem {
+    pub words: Vec<u128>,
+    // Refuses all requests while set, eg. to starve a module
+    pub stalled: bool,
+    pub num_reads: u32,
+
+    reads: VecDeque<(u64, u128)>,
+    time_stamp: u64,
+}
+
+// Port inputs for a single cycle
+pub struct PortInputs {
+    pub ready: bool,
+    pub read_data: u128,
+    pub read_data_valid: bool,
+}
+
+impl ReadOnlyMem {
+    pub fn new(words: Vec<u128>) -> ReadOnlyMem {
+        ReadOnlyMem {
+            words,
+            stalled: false,
+            num_reads: 0,
+
+            reads: VecDeque::new(),
+            time_stamp: 0,
+        }
+    }
+
+    // Should be driven before the module's outputs are propagated
+    pub fn port_inputs(&mut self) -> PortInputs {
+        let (read_data, read_data_valid) = match self.reads.front() {
+            Some(&(ready_time_stamp, data)) if ready_time_stamp <= self.time_stamp => {
+                self.reads.pop_front();
+                (data, true)
+            }
+            _ => (0, false)
+        };
+
+        PortInputs {
+            ready: !self.stalled && self.time_stamp % 7 != 0,
+            read_data,
+            read_data_valid,
+        }
+    }
+
+    // Takes the module's propagated port outputs, along with the ready value it was given this cycle
+    pub fn posedge_clk(&mut self, ready: bool, bus_enable: bool, bus_addr: u32, bus_write: bool) {
+        if bus_enable && ready {
+            assert_eq!(bus_write, false);
+            let data = self.words[bus_addr as usize];
+            self.reads.push_back((self.time_stamp + READ_LATENCY, data));
+            self.num_reads += 1;
+        }
+
+        self.time_stamp += 1;
+    }
+}
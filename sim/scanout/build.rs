@@ -0,0 +1,21 @@
+use kaze::*;
+use rtl::scanout::*;
+
+use std::env;
+use std::fs::File;
+use std::io::Result;
+use std::path::Path;
+
+fn main() -> Result<()> {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("modules.rs");
+    let file = File::create(&dest_path).unwrap();
+
+    let c = Context::new();
+
+    // TODO: Expose this to test driver somehow so we don't have to duplicate it
+    let pixel_clock_divider_bits = 1;
+
+    let scanout = Scanout::new("scanout", pixel_clock_divider_bits, &c);
+    sim::generate(scanout.m, sim::GenerationOptions::default(), file)
+}
@@ -0,0 +1,484 @@
+#[cfg(test)]
+mod tests {
+    mod modules {
+        include!(concat!(env!("OUT_DIR"), "/modules.rs"));
+    }
+
+    use modules::*;
+
+    use mem_model::*;
+
+    use rtl_meta::scanout::*;
+
+    const MEM_NUM_WORDS: usize = 1 << 18;
+
+    const FRONT_BUFFER_BASE_ADDR: u32 = 0x1000 * 16;
+
+    const LINE_WORDS: u32 = H_ACTIVE >> ARGB8888_PIXELS_PER_WORD_BITS;
+
+    struct Timing {
+        h_front_porch: u32,
+        h_sync: u32,
+        h_back_porch: u32,
+        v_front_porch: u32,
+        v_sync: u32,
+        v_back_porch: u32,
+    }
+
+    impl Timing {
+        fn h_total(&self) -> u32 {
+            H_ACTIVE + self.h_front_porch + self.h_sync + self.h_back_porch
+        }
+
+        fn v_total(&self) -> u32 {
+            V_ACTIVE + self.v_front_porch + self.v_sync + self.v_back_porch
+        }
+    }
+
+    const DEFAULT_TIMING: Timing = Timing {
+        h_front_porch: REG_H_FRONT_PORCH_DEFAULT,
+        h_sync: REG_H_SYNC_DEFAULT,
+        h_back_porch: REG_H_BACK_PORCH_DEFAULT,
+        v_front_porch: REG_V_FRONT_PORCH_DEFAULT,
+        v_sync: REG_V_SYNC_DEFAULT,
+        v_back_porch: REG_V_BACK_PORCH_DEFAULT,
+    };
+
+    #[derive(Clone, Copy)]
+    struct Sample {
+        hsync: bool,
+        vsync: bool,
+        de: bool,
+        rgb: u32,
+    }
+
+    struct Harness {
+        m: Scanout,
+        mem: ReadOnlyMem,
+    }
+
+    impl Harness {
+        fn new(mem: Vec<u128>) -> Harness {
+            let mut m = Scanout::new();
+
+            m.reset();
+            m.reg_bus_enable = false;
+            m.reg_bus_addr = 0;
+            m.reg_bus_write = false;
+            m.reg_bus_write_data = 0;
+            m.reg_bus_write_byte_enable = 0xffff;
+            m.mem_bus_ready = false;
+            m.mem_bus_read_data = 0;
+            m.mem_bus_read_data_valid = false;
+
+            Harness {
+                m,
+                mem: ReadOnlyMem::new(mem),
+            }
+        }
+
+        // Returns the video outputs if this cycle's pixel clock enable is high
+        fn step(&mut self) -> Option<Sample> {
+            let mem_inputs = self.mem.port_inputs();
+            self.m.mem_bus_ready = mem_inputs.ready;
+            self.m.mem_bus_read_data = mem_inputs.read_data;
+            self.m.mem_bus_read_data_valid = mem_inputs.read_data_valid;
+
+            self.m.prop();
+
+            self.mem.posedge_clk(mem_inputs.ready, self.m.mem_bus_enable, self.m.mem_bus_addr, self.m.mem_bus_write);
+
+            let sample = if self.m.pixel_clock_enable {
+                Some(Sample {
+                    hsync: !self.m.hsync_n,
+                    vsync: !self.m.vsync_n,
+                    de: self.m.de,
+                    rgb: self.m.rgb,
+                })
+            } else {
+                None
+            };
+
+            self.m.posedge_clk();
+
+            sample
+        }
+
+        fn next_sample(&mut self) -> Sample {
+            loop {
+                if let Some(sample) = self.step() {
+                    return sample;
+                }
+            }
+        }
+
+        fn write_reg(&mut self, addr: u32, data: u32) {
+            self.m.reg_bus_enable = true;
+            self.m.reg_bus_addr = addr;
+            self.m.reg_bus_write = true;
+            self.m.reg_bus_write_data = data as _;
+            self.step();
+            self.m.reg_bus_enable = false;
+        }
+
+        fn read_reg(&mut self, addr: u32) -> u32 {
+            self.m.reg_bus_enable = true;
+            self.m.reg_bus_addr = addr;
+            self.m.reg_bus_write = false;
+            self.step();
+            self.m.reg_bus_enable = false;
+            self.m.prop();
+            assert_eq!(self.m.reg_bus_read_data_valid, true);
+            self.m.reg_bus_read_data as _
+        }
+
+        fn write_timing(&mut self, timing: &Timing) {
+            self.write_reg(REG_H_FRONT_PORCH_ADDR, timing.h_front_porch);
+            self.write_reg(REG_H_SYNC_ADDR, timing.h_sync);
+            self.write_reg(REG_H_BACK_PORCH_ADDR, timing.h_back_porch);
+            self.write_reg(REG_V_FRONT_PORCH_ADDR, timing.v_front_porch);
+            self.write_reg(REG_V_SYNC_ADDR, timing.v_sync);
+            self.write_reg(REG_V_BACK_PORCH_ADDR, timing.v_back_porch);
+        }
+
+        // Syncs to the start of a vsync pulse like a display would, and captures everything up until the next one
+        fn capture_frame(&mut self) -> Vec<Sample> {
+            let mut last_vsync = self.next_sample().vsync;
+            let first_sample = loop {
+                let sample = self.next_sample();
+                if sample.vsync && !last_vsync {
+                    break sample;
+                }
+                last_vsync = sample.vsync;
+            };
+
+            let mut frame = vec![first_sample];
+            loop {
+                let sample = self.next_sample();
+                if sample.vsync && !frame.last().unwrap().vsync {
+                    break;
+                }
+                frame.push(sample);
+            }
+
+            frame
+        }
+    }
+
+    fn pixel(x: u32, y: u32) -> u32 {
+        // Arbitrary, but distinct enough per pixel to catch misordering (alpha included to make sure it's dropped)
+        let x = x.wrapping_mul(0x9e3779b1);
+        let y = y.wrapping_mul(0x85ebca6b);
+        (x ^ y ^ (x >> 15) ^ (y >> 13)).wrapping_mul(0xc2b2ae35)
+    }
+
+    // Different enough from pixel that mixing the two up would show
+    fn overlay_pixel(x: u32, y: u32) -> u32 {
+        pixel(y + 0x1234, x + 0x5678)
+    }
+
+    // Lays out an image with its top line at base_addr, stepping by line_stride words per line
+    fn write_image(mem: &mut [u128], base_addr: u32, line_stride: i32, pixels_per_word_bits: u32, image_pixel: impl Fn(u32, u32) -> u32) {
+        let pixel_bits = 128 >> pixels_per_word_bits;
+        for y in 0..V_ACTIVE {
+            let line_addr = ((base_addr / 16) as i32 + y as i32 * line_stride) as usize;
+            for word_index in 0..H_ACTIVE >> pixels_per_word_bits {
+                let mut word = 0;
+                for i in 0..1 << pixels_per_word_bits {
+                    let x = (word_index << pixels_per_word_bits) + i;
+                    word |= ((image_pixel(x, y) as u128) & ((1 << pixel_bits) - 1)) << (i * pixel_bits);
+                }
+                mem[line_addr + word_index as usize] = word;
+            }
+        }
+    }
+
+    fn build_mem(base_addr: u32, line_stride: i32) -> Vec<u128> {
+        let mut mem = vec![0; MEM_NUM_WORDS];
+        write_image(&mut mem, base_addr, line_stride, ARGB8888_PIXELS_PER_WORD_BITS, pixel);
+        mem
+    }
+
+    fn build_rgb565_mem(base_addr: u32, line_stride: i32) -> Vec<u128> {
+        let mut mem = vec![0; MEM_NUM_WORDS];
+        write_image(&mut mem, base_addr, line_stride, RGB565_PIXELS_PER_WORD_BITS, pixel);
+        mem
+    }
+
+    fn rgb565_to_rgb888(rgb565: u16) -> u32 {
+        let rgb565 = rgb565 as u32;
+        let r = (rgb565 >> 11) & 0x1f;
+        let g = (rgb565 >> 5) & 0x3f;
+        let b = rgb565 & 0x1f;
+        (((r << 3) | (r >> 2)) << 16) | (((g << 2) | (g >> 4)) << 8) | ((b << 3) | (b >> 2))
+    }
+
+    // Same as BitPusher's blending
+    fn blend(src: u32, dst: u32) -> u32 {
+        let alpha = src >> 24;
+        let blend_comp = |bit_offset: u32| {
+            let src = (src >> bit_offset) & 0xff;
+            let dst = (dst >> bit_offset) & 0xff;
+            ((src * alpha + dst * (256 - alpha)) >> 8) << bit_offset
+        };
+        blend_comp(16) | blend_comp(8) | blend_comp(0)
+    }
+
+    fn check_frame(frame: &[Sample], timing: &Timing, expected_pixel: impl Fn(u32, u32) -> u32) {
+        let h_total = timing.h_total();
+        let v_total = timing.v_total();
+        assert_eq!(frame.len() as u32, h_total * v_total);
+
+        let h_sync_start = H_ACTIVE + timing.h_front_porch;
+        let h_sync_end = h_sync_start + timing.h_sync;
+        let v_sync_start = V_ACTIVE + timing.v_front_porch;
+        let v_sync_end = v_sync_start + timing.v_sync;
+
+        for (line_index, line) in frame.chunks(h_total as usize).enumerate() {
+            // Capture starts at the first line of the vsync pulse
+            let y = (v_sync_start + line_index as u32) % v_total;
+            for (x, sample) in line.iter().enumerate() {
+                let x = x as u32;
+                let de = x < H_ACTIVE && y < V_ACTIVE;
+                assert_eq!(sample.de, de, "de mismatch at ({}, {})", x, y);
+                assert_eq!(sample.hsync, x >= h_sync_start && x < h_sync_end, "hsync mismatch at ({}, {})", x, y);
+                assert_eq!(sample.vsync, y >= v_sync_start && y < v_sync_end, "vsync mismatch at ({}, {})", x, y);
+                let rgb = if de { expected_pixel(x, y) & 0xffffff } else { 0 };
+                assert_eq!(sample.rgb, rgb, "pixel mismatch at ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn default_timing_frame() {
+        let mut h = Harness::new(build_mem(FRONT_BUFFER_BASE_ADDR, LINE_WORDS as _));
+
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, FRONT_BUFFER_BASE_ADDR);
+        h.write_reg(REG_ENABLE_ADDR, 1);
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, pixel);
+    }
+
+    #[test]
+    fn custom_timing_flipped_frame() {
+        // Bottom-up buffer; the top line is stored last
+        let base_addr = FRONT_BUFFER_BASE_ADDR + (V_ACTIVE - 1) * LINE_WORDS * 16;
+        let line_stride = -(LINE_WORDS as i32);
+        let mut h = Harness::new(build_mem(base_addr, line_stride));
+
+        let timing = Timing {
+            h_front_porch: 8,
+            h_sync: 40,
+            h_back_porch: 24,
+            v_front_porch: 3,
+            v_sync: 4,
+            v_back_porch: 12,
+        };
+        h.write_timing(&timing);
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, base_addr);
+        h.write_reg(REG_LINE_STRIDE_ADDR, line_stride as _);
+        h.write_reg(REG_ENABLE_ADDR, 1);
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &timing, pixel);
+    }
+
+    #[test]
+    fn rgb565_frame() {
+        let line_stride = (H_ACTIVE >> RGB565_PIXELS_PER_WORD_BITS) as i32;
+        let mut h = Harness::new(build_rgb565_mem(FRONT_BUFFER_BASE_ADDR, line_stride));
+
+        h.write_reg(REG_FORMAT_ADDR, REG_FORMAT_RGB565);
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, FRONT_BUFFER_BASE_ADDR);
+        h.write_reg(REG_LINE_STRIDE_ADDR, line_stride as _);
+        h.write_reg(REG_ENABLE_ADDR, 1);
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, |x, y| rgb565_to_rgb888(pixel(x, y) as _));
+    }
+
+    #[test]
+    fn indexed8_frame() {
+        let line_stride = (H_ACTIVE >> INDEXED8_PIXELS_PER_WORD_BITS) as i32;
+        let mut mem = vec![0; MEM_NUM_WORDS];
+        write_image(&mut mem, FRONT_BUFFER_BASE_ADDR, line_stride, INDEXED8_PIXELS_PER_WORD_BITS, pixel);
+        let mut h = Harness::new(mem);
+
+        let palette_entry = |index: u32| pixel(index, 0xabcd) & 0xffffff;
+        // Leave the last entry at its default to make sure it's a grayscale ramp
+        for index in 0..REG_PALETTE_ENTRIES - 1 {
+            h.write_reg(REG_PALETTE_ADDR, (index << REG_PALETTE_INDEX_BIT_OFFSET) | (palette_entry(index) << REG_PALETTE_VALUE_BIT_OFFSET));
+        }
+        h.write_reg(REG_FORMAT_ADDR, REG_FORMAT_INDEXED8);
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, FRONT_BUFFER_BASE_ADDR);
+        h.write_reg(REG_LINE_STRIDE_ADDR, line_stride as _);
+        h.write_reg(REG_ENABLE_ADDR, 1);
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, |x, y| {
+            let index = pixel(x, y) & 0xff;
+            if index == REG_PALETTE_ENTRIES - 1 {
+                0xffffff
+            } else {
+                palette_entry(index)
+            }
+        });
+    }
+
+    #[test]
+    fn gamma_lut_frame() {
+        let mut h = Harness::new(build_mem(FRONT_BUFFER_BASE_ADDR, LINE_WORDS as _));
+
+        let gamma_r = |c: u32| 255 - c;
+        let gamma_g = |c: u32| c * c / 255;
+        let gamma_b = |c: u32| c / 2;
+        for index in 0..REG_GAMMA_LUT_ENTRIES {
+            let value = (gamma_r(index) << 16) | (gamma_g(index) << 8) | gamma_b(index);
+            h.write_reg(REG_GAMMA_LUT_ADDR, (index << REG_GAMMA_LUT_INDEX_BIT_OFFSET) | (value << REG_GAMMA_LUT_VALUE_BIT_OFFSET));
+        }
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, FRONT_BUFFER_BASE_ADDR);
+        h.write_reg(REG_ENABLE_ADDR, 1);
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, |x, y| {
+            let pixel = pixel(x, y);
+            (gamma_r((pixel >> 16) & 0xff) << 16) | (gamma_g((pixel >> 8) & 0xff) << 8) | gamma_b(pixel & 0xff)
+        });
+    }
+
+    #[test]
+    fn overlay_frame() {
+        // Overlay goes bottom-up over an RGB565 front buffer, to make sure the two fetch streams don't get mixed up
+        let line_stride = (H_ACTIVE >> RGB565_PIXELS_PER_WORD_BITS) as i32;
+        let overlay_base_addr = FRONT_BUFFER_BASE_ADDR + V_ACTIVE * LINE_WORDS * 16 + (V_ACTIVE - 1) * LINE_WORDS * 16;
+        let overlay_line_stride = -(LINE_WORDS as i32);
+        let mut mem = build_rgb565_mem(FRONT_BUFFER_BASE_ADDR, line_stride);
+        write_image(&mut mem, overlay_base_addr, overlay_line_stride, ARGB8888_PIXELS_PER_WORD_BITS, overlay_pixel);
+        let mut h = Harness::new(mem);
+
+        h.write_reg(REG_FORMAT_ADDR, REG_FORMAT_RGB565);
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, FRONT_BUFFER_BASE_ADDR);
+        h.write_reg(REG_LINE_STRIDE_ADDR, line_stride as _);
+        h.write_reg(REG_OVERLAY_BASE_ADDR, overlay_base_addr);
+        h.write_reg(REG_OVERLAY_LINE_STRIDE_ADDR, overlay_line_stride as _);
+        h.write_reg(REG_OVERLAY_ENABLE_ADDR, 1);
+        h.write_reg(REG_ENABLE_ADDR, 1);
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, |x, y| blend(overlay_pixel(x, y), rgb565_to_rgb888(pixel(x, y) as _)));
+
+        // Turning the overlay off takes effect from the next frame on
+        h.write_reg(REG_OVERLAY_ENABLE_ADDR, 0);
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, |x, y| rgb565_to_rgb888(pixel(x, y) as _));
+    }
+
+    #[test]
+    fn disabled_frame_is_black() {
+        let mut h = Harness::new(build_mem(FRONT_BUFFER_BASE_ADDR, LINE_WORDS as _));
+
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, FRONT_BUFFER_BASE_ADDR);
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, |_, _| 0);
+        assert_eq!(h.mem.num_reads, 0);
+    }
+
+    #[test]
+    fn base_addr_latched_per_frame() {
+        let other_base_addr = FRONT_BUFFER_BASE_ADDR + V_ACTIVE * LINE_WORDS * 16;
+        let mut mem = build_mem(FRONT_BUFFER_BASE_ADDR, LINE_WORDS as _);
+        for (i, word) in mem.iter_mut().skip((other_base_addr / 16) as usize).take((V_ACTIVE * LINE_WORDS) as usize).enumerate() {
+            *word = !(i as u128);
+        }
+        let mut h = Harness::new(mem);
+
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, FRONT_BUFFER_BASE_ADDR);
+        h.write_reg(REG_ENABLE_ADDR, 1);
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, pixel);
+
+        // Sync to the next frame's first visible pixel, then switch buffers; the rest of the frame shouldn't change
+        //  (the reg write's cycle falls between pixel clock enables, so no pixel is skipped)
+        while !h.next_sample().de {}
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, other_base_addr);
+        let mut y = 0;
+        let mut x = 1;
+        while y < V_ACTIVE {
+            let sample = h.next_sample();
+            if sample.de {
+                assert_eq!(sample.rgb, pixel(x, y) & 0xffffff, "pixel mismatch at ({}, {})", x, y);
+                x += 1;
+                if x == H_ACTIVE {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+
+        let frame = h.capture_frame();
+        check_frame(&frame, &DEFAULT_TIMING, |x, y| {
+            let word = !((y * LINE_WORDS + (x >> ARGB8888_PIXELS_PER_WORD_BITS)) as u128);
+            (word >> ((x & ((1 << ARGB8888_PIXELS_PER_WORD_BITS) - 1)) * 32)) as u32
+        });
+    }
+
+    #[test]
+    fn reg_reads() {
+        let mut h = Harness::new(vec![0; MEM_NUM_WORDS]);
+
+        h.write_reg(REG_FRONT_BUFFER_BASE_ADDR, FRONT_BUFFER_BASE_ADDR);
+        h.write_reg(REG_LINE_STRIDE_ADDR, -(LINE_WORDS as i32) as _);
+        h.write_reg(REG_ENABLE_ADDR, 1);
+        h.write_reg(REG_V_BACK_PORCH_ADDR, 20);
+
+        assert_eq!(h.read_reg(REG_FRONT_BUFFER_BASE_ADDR), FRONT_BUFFER_BASE_ADDR);
+        assert_eq!(h.read_reg(REG_LINE_STRIDE_ADDR), (1 << 24) - LINE_WORDS);
+        assert_eq!(h.read_reg(REG_ENABLE_ADDR), 1);
+        assert_eq!(h.read_reg(REG_H_SYNC_ADDR), REG_H_SYNC_DEFAULT);
+        assert_eq!(h.read_reg(REG_V_BACK_PORCH_ADDR), 20);
+        assert_eq!(h.read_reg(REG_OVERLAY_ENABLE_ADDR), 0);
+        assert_eq!(h.read_reg(REG_OVERLAY_LINE_STRIDE_ADDR), REG_OVERLAY_LINE_STRIDE_DEFAULT);
+        h.write_reg(REG_OVERLAY_BASE_ADDR, FRONT_BUFFER_BASE_ADDR + 16);
+        assert_eq!(h.read_reg(REG_OVERLAY_BASE_ADDR), FRONT_BUFFER_BASE_ADDR + 16);
+
+        // Nothing's in mem, so there's no point fetching it
+        h.write_reg(REG_ENABLE_ADDR, 0);
+
+        // Base writes stay pending until they're latched in the vertical back porch
+        let frame_count = h.read_reg(REG_FRAME_COUNT_ADDR);
+        assert_eq!(h.read_reg(REG_STATUS_ADDR) & (1 << REG_STATUS_FLIP_PENDING_BIT), 1 << REG_STATUS_FLIP_PENDING_BIT);
+        while h.read_reg(REG_FRAME_COUNT_ADDR) == frame_count {}
+        let status = h.read_reg(REG_STATUS_ADDR);
+        assert_eq!(status & (1 << REG_STATUS_FLIP_PENDING_BIT), 0);
+        assert_eq!(status & (1 << REG_STATUS_VBLANK_BIT), 1 << REG_STATUS_VBLANK_BIT);
+        while !h.next_sample().de {}
+        assert_eq!(h.read_reg(REG_STATUS_ADDR) & (1 << REG_STATUS_VBLANK_BIT), 0);
+
+        // Current line should track the vsync pulse
+        let mut last_vsync = h.next_sample().vsync;
+        loop {
+            let sample = h.next_sample();
+            if sample.vsync && !last_vsync {
+                break;
+            }
+            last_vsync = sample.vsync;
+        }
+        assert_eq!(h.read_reg(REG_CURRENT_LINE_ADDR), V_ACTIVE + REG_V_FRONT_PORCH_DEFAULT);
+
+        // .. and wrap around to 0 after the back porch
+        let mut last_line = h.read_reg(REG_CURRENT_LINE_ADDR);
+        loop {
+            let line = h.read_reg(REG_CURRENT_LINE_ADDR);
+            if line < last_line {
+                assert_eq!(last_line, V_ACTIVE + REG_V_FRONT_PORCH_DEFAULT + REG_V_SYNC_DEFAULT + 20 - 1);
+                assert_eq!(line, 0);
+                break;
+            }
+            assert!(line == last_line || line == last_line + 1);
+            last_line = line;
+        }
+    }
+}
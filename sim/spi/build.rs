@@ -0,0 +1,18 @@
+use kaze::*;
+use rtl::spi::*;
+
+use std::env;
+use std::fs::File;
+use std::io::Result;
+use std::path::Path;
+
+fn main() -> Result<()> {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("modules.rs");
+    let file = File::create(&dest_path).unwrap();
+
+    let c = Context::new();
+
+    let spi = Spi::new("spi", &c);
+    sim::generate(spi.m, sim::GenerationOptions::default(), file)
+}
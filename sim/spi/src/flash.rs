@@ -0,0 +1,174 @@
+// Pin-level behavioral model of a SPI NOR flash, covering the basic command set xw::flash uses. Programs and erases
+//  complete instantly, but report busy for a few status reads so polling gets exercised.
+
+pub const PAGE_SIZE: usize = 256;
+pub const SECTOR_SIZE: usize = 4096;
+
+// Arbitrary, but real (Micron N25Q128)
+pub const ID: u32 = 0x20ba18;
+
+const CMD_READ: u8 = 0x03;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_WRITE_DISABLE: u8 = 0x04;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_READ_ID: u8 = 0x9f;
+
+const STATUS_WRITE_IN_PROGRESS: u8 = 0x01;
+const STATUS_WRITE_ENABLE_LATCH: u8 = 0x02;
+
+const PROGRAM_BUSY_POLLS: u32 = 2;
+const ERASE_BUSY_POLLS: u32 = 5;
+
+pub struct Flash {
+    contents: Vec<u8>,
+
+    is_write_enabled: bool,
+    num_busy_polls: u32,
+
+    // Pins
+    last_cs_n: bool,
+    last_sclk: bool,
+    miso: bool,
+
+    in_shift: u8,
+    in_num_bits: u32,
+    out_shift: u8,
+
+    // Everything received since CS was asserted
+    command: Vec<u8>,
+}
+
+impl Flash {
+    // Size must be a power of two
+    pub fn new(contents: Vec<u8>) -> Flash {
+        assert!(contents.len().is_power_of_two());
+
+        Flash {
+            contents,
+
+            is_write_enabled: false,
+            num_busy_polls: 0,
+
+            last_cs_n: true,
+            last_sclk: false,
+            miso: true,
+
+            in_shift: 0,
+            in_num_bits: 0,
+            out_shift: 0xff,
+
+            command: Vec::new(),
+        }
+    }
+
+    // Call once per cycle with the current pin states; returns the new MISO state. MISO floats (and is pulled high)
+    //  while deselected, and changes on falling SCLK edges.
+    pub fn step(&mut self, cs_n: bool, sclk: bool, mosi: bool) -> bool {
+        let rising_edge = sclk && !self.last_sclk;
+        let falling_edge = !sclk && self.last_sclk;
+        self.last_sclk = sclk;
+
+        if cs_n {
+            if !self.last_cs_n {
+                // Only whole bytes count
+                self.in_num_bits = 0;
+                let command = std::mem::take(&mut self.command);
+                self.execute_command(&command);
+            }
+            self.last_cs_n = true;
+            self.miso = true;
+            return self.miso;
+        }
+
+        if self.last_cs_n {
+            self.last_cs_n = false;
+            self.load_out_byte();
+        }
+
+        if rising_edge {
+            self.in_shift = (self.in_shift << 1) | mosi as u8;
+            self.in_num_bits += 1;
+        }
+
+        if falling_edge {
+            if self.in_num_bits == 8 {
+                self.in_num_bits = 0;
+                self.command.push(self.in_shift);
+                self.load_out_byte();
+            } else {
+                self.out_shift <<= 1;
+                self.miso = (self.out_shift & 0x80) != 0;
+            }
+        }
+
+        self.miso
+    }
+
+    fn load_out_byte(&mut self) {
+        self.out_shift = self.next_out_byte();
+        self.miso = (self.out_shift & 0x80) != 0;
+    }
+
+    // Called as each byte starts shifting out, once all of the bytes before it have been received
+    fn next_out_byte(&mut self) -> u8 {
+        if self.command.is_empty() {
+            return 0xff;
+        }
+
+        match self.command[0] {
+            CMD_READ_STATUS => {
+                let mut status = 0;
+                if self.num_busy_polls > 0 {
+                    status |= STATUS_WRITE_IN_PROGRESS;
+                    self.num_busy_polls -= 1;
+                }
+                if self.is_write_enabled {
+                    status |= STATUS_WRITE_ENABLE_LATCH;
+                }
+                status
+            }
+            _ if self.num_busy_polls > 0 => 0xff,
+            CMD_READ if self.command.len() >= 4 => {
+                let addr = addr(&self.command) + self.command.len() - 4;
+                self.contents[addr & (self.contents.len() - 1)]
+            }
+            CMD_READ_ID if self.command.len() <= 3 => (ID >> ((3 - self.command.len()) * 8)) as u8,
+            _ => 0xff,
+        }
+    }
+
+    // Programs and erases happen when CS is deasserted
+    fn execute_command(&mut self, command: &[u8]) {
+        if command.is_empty() || self.num_busy_polls > 0 {
+            return;
+        }
+
+        match command[0] {
+            CMD_WRITE_ENABLE => self.is_write_enabled = true,
+            CMD_WRITE_DISABLE => self.is_write_enabled = false,
+            CMD_PAGE_PROGRAM if self.is_write_enabled && command.len() >= 4 => {
+                let addr = addr(command) & (self.contents.len() - 1);
+                let page_addr = addr & !(PAGE_SIZE - 1);
+                // Data wraps around within the page
+                for (i, &byte) in command[4..].iter().enumerate() {
+                    self.contents[page_addr + ((addr + i) & (PAGE_SIZE - 1))] &= byte;
+                }
+                self.is_write_enabled = false;
+                self.num_busy_polls = PROGRAM_BUSY_POLLS;
+            }
+            CMD_SECTOR_ERASE if self.is_write_enabled && command.len() == 4 => {
+                let sector_addr = addr(command) & (self.contents.len() - 1) & !(SECTOR_SIZE - 1);
+                self.contents[sector_addr..sector_addr + SECTOR_SIZE].fill(0xff);
+                self.is_write_enabled = false;
+                self.num_busy_polls = ERASE_BUSY_POLLS;
+            }
+            _ => (),
+        }
+    }
+}
+
+fn addr(command: &[u8]) -> usize {
+    ((command[1] as usize) << 16) | ((command[2] as usize) << 8) | command[3] as usize
+}
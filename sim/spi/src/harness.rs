@@ -0,0 +1,98 @@
+use crate::modules::*;
+use crate::sd_card::SdCard;
+
+#[cfg(test)]
+use crate::flash::Flash;
+
+use std::io::{Read, Seek, Write};
+
+// Matches the sync FF's in front of miso on the board
+const MISO_SYNC_STAGES: usize = 2;
+
+// Anything on the other end of the pins
+pub trait Slave {
+    // Called once per cycle with the current pin states; returns the new MISO state
+    fn step(&mut self, cs_n: bool, sclk: bool, mosi: bool) -> bool;
+}
+
+impl<S: Slave + ?Sized> Slave for Box<S> {
+    fn step(&mut self, cs_n: bool, sclk: bool, mosi: bool) -> bool {
+        (**self).step(cs_n, sclk, mosi)
+    }
+}
+
+impl<S: Read + Write + Seek> Slave for SdCard<S> {
+    fn step(&mut self, cs_n: bool, sclk: bool, mosi: bool) -> bool {
+        SdCard::step(self, cs_n, sclk, mosi)
+    }
+}
+
+#[cfg(test)]
+impl Slave for Flash {
+    fn step(&mut self, cs_n: bool, sclk: bool, mosi: bool) -> bool {
+        Flash::step(self, cs_n, sclk, mosi)
+    }
+}
+
+pub struct Harness<S: Slave> {
+    pub m: Spi,
+    pub slave: S,
+    miso_sync: [bool; MISO_SYNC_STAGES],
+    pub time_stamp: u64,
+}
+
+impl<S: Slave> Harness<S> {
+    pub fn new(slave: S) -> Harness<S> {
+        let mut m = Spi::new();
+
+        m.reset();
+        m.reg_bus_enable = false;
+        m.reg_bus_addr = 0;
+        m.reg_bus_write = false;
+        m.reg_bus_write_data = 0;
+        m.reg_bus_write_byte_enable = 0xffff;
+        m.miso = true;
+
+        Harness {
+            m,
+            slave,
+            miso_sync: [true; MISO_SYNC_STAGES],
+            time_stamp: 0,
+        }
+    }
+
+    pub fn step(&mut self) {
+        self.m.miso = self.miso_sync[MISO_SYNC_STAGES - 1];
+
+        self.m.prop();
+
+        let miso = self.slave.step(self.m.cs_n, self.m.sclk, self.m.mosi);
+        for i in (1..MISO_SYNC_STAGES).rev() {
+            self.miso_sync[i] = self.miso_sync[i - 1];
+        }
+        self.miso_sync[0] = miso;
+
+        self.m.posedge_clk();
+        self.time_stamp += 1;
+    }
+
+    pub fn write_reg(&mut self, addr: u32, data: u32) {
+        self.m.reg_bus_enable = true;
+        self.m.reg_bus_addr = addr;
+        self.m.reg_bus_write = true;
+        self.m.reg_bus_write_data = data as _;
+        self.step();
+        self.m.reg_bus_enable = false;
+    }
+
+    pub fn read_reg(&mut self, addr: u32) -> u32 {
+        self.m.reg_bus_enable = true;
+        self.m.reg_bus_addr = addr;
+        self.m.reg_bus_write = false;
+        self.step();
+        self.m.reg_bus_enable = false;
+        self.m.prop();
+        assert!(self.m.reg_bus_read_data_valid);
+        self.m.reg_bus_read_data as _
+    }
+}
@@ -0,0 +1,68 @@
+mod harness;
+mod modules {
+    include!(concat!(env!("OUT_DIR"), "/modules.rs"));
+}
+mod regs;
+mod sd_card;
+#[cfg(test)]
+mod flash;
+#[cfg(test)]
+mod tests;
+
+// xw's drivers, built against the reg shim in regs.rs so they run on the generated master. Not everything in them
+//  is used outside of tests.
+#[allow(dead_code)]
+#[path = "../../../sw/xw/src/spi.rs"]
+mod spi;
+#[allow(dead_code)]
+#[path = "../../../sw/xw/src/sdcard.rs"]
+mod xw_sdcard;
+#[cfg(test)]
+#[path = "../../../sw/xw/src/flash.rs"]
+mod xw_flash;
+
+use harness::*;
+
+use sd_card::*;
+
+use fat32::{BlockDevice, FileSystem};
+
+use rtl_meta::xenowing::SYSTEM_CLOCK_FREQ;
+
+use std::env;
+use std::fs::{self, OpenOptions};
+
+// Same as xw::fs
+impl BlockDevice for xw_sdcard::SdCard {
+    type Error = xw_sdcard::Error;
+
+    fn read_block(&mut self, index: u32, data: &mut [u8; fat32::BLOCK_SIZE]) -> Result<(), xw_sdcard::Error> {
+        xw_sdcard::SdCard::read_block(self, index, data)
+    }
+}
+
+fn main() {
+    let image_path = env::args().skip(1).nth(0).expect("image path not specified");
+    let file_path = env::args().skip(1).nth(1).expect("file path not specified");
+    let output_path = env::args().skip(1).nth(2).expect("output path not specified");
+
+    let image = OpenOptions::new().read(true).write(true).open(&image_path).expect("Couldn't open image");
+    regs::attach(Harness::new(Box::new(SdCard::new(image, CardKind::HighCapacity))));
+
+    println!("Initializing card");
+    let card = xw_sdcard::SdCard::init().expect("Couldn't initialize card");
+
+    println!("Mounting {}", image_path);
+    let mut fs = FileSystem::mount(card).expect("Couldn't mount file system");
+    let mut file = fs.open(&file_path).expect("Couldn't open file");
+    let mut contents = vec![0; file.size() as usize];
+    let num_bytes_read = fs.read(&mut file, &mut contents).expect("Couldn't read file");
+    assert_eq!(num_bytes_read, contents.len());
+
+    let h = regs::detach();
+    println!("Read {} bytes from {} in {} cycles ({:.3}s)", num_bytes_read, file_path, h.time_stamp, h.time_stamp as f64 / SYSTEM_CLOCK_FREQ as f64);
+
+    fs::write(&output_path, &contents).expect("Couldn't write output file");
+
+    println!("Done");
+}
@@ -0,0 +1,39 @@
+// Stands in for xw's regs module, so xw's SPI drivers can be built into the sim as-is. Every reg block maps to the
+//  harness attached on the calling thread, so each test gets its own.
+
+use crate::harness::{Harness, Slave};
+
+use std::cell::RefCell;
+
+thread_local! {
+    static HARNESS: RefCell<Option<Harness<Box<dyn Slave>>>> = RefCell::new(None);
+}
+
+pub fn attach(h: Harness<Box<dyn Slave>>) {
+    HARNESS.with(|harness| *harness.borrow_mut() = Some(h));
+}
+
+pub fn detach() -> Harness<Box<dyn Slave>> {
+    HARNESS.with(|harness| harness.borrow_mut().take()).expect("No harness attached")
+}
+
+fn with_harness<T>(f: impl FnOnce(&mut Harness<Box<dyn Slave>>) -> T) -> T {
+    HARNESS.with(|harness| f(harness.borrow_mut().as_mut().expect("No harness attached")))
+}
+
+#[derive(Clone, Copy)]
+pub struct RegBlock;
+
+impl RegBlock {
+    pub const fn new(_base_addr: u32) -> RegBlock {
+        RegBlock
+    }
+
+    pub fn write(&self, addr: u32, data: u32) {
+        with_harness(|h| h.write_reg(addr, data));
+    }
+
+    pub fn read(&self, addr: u32) -> u32 {
+        with_harness(|h| h.read_reg(addr))
+    }
+}
@@ -0,0 +1,335 @@
+// Pin-level behavioral model of an SD card in SPI mode, backed by a disk image. Only what a simple driver needs is
+//  modeled: the init sequence, single-block reads and writes, and the R1 error bits those can produce.
+
+use std::collections::VecDeque;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub const BLOCK_SIZE: usize = 512;
+
+// How many ACMD41's it takes before the card reports it's done initializing
+const NUM_INIT_ATTEMPTS: u32 = 3;
+// Bytes of 0xff before a read's data token and bytes of busy after a write, standing in for access times
+const READ_LATENCY: usize = 4;
+const WRITE_BUSY_LEN: usize = 4;
+
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+const R1_COMMAND_CRC_ERROR: u8 = 0x08;
+const R1_ADDRESS_ERROR: u8 = 0x20;
+const R1_PARAMETER_ERROR: u8 = 0x40;
+
+const DATA_START_TOKEN: u8 = 0xfe;
+const DATA_RESPONSE_ACCEPTED: u8 = 0x05;
+const DATA_ERROR_OUT_OF_RANGE: u8 = 0x08;
+
+const OCR_POWER_UP_DONE: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+// 2.7-3.6V
+const OCR_VOLTAGE_WINDOW: u32 = 0x00ff8000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CardKind {
+    // Version 1.x, byte-addressed, doesn't know CMD8
+    StandardCapacityV1,
+    // Version 2.0+ SDHC, block-addressed
+    HighCapacity,
+}
+
+enum DataState {
+    None,
+    // CMD24 was accepted; holds the byte address
+    WaitingForToken(u64),
+    ReceivingBlock(u64, Vec<u8>),
+}
+
+pub struct SdCard<S: Read + Write + Seek> {
+    image: S,
+    num_blocks: u64,
+    kind: CardKind,
+
+    is_spi_mode: bool,
+    is_idle: bool,
+    is_app_command: bool,
+    num_init_attempts: u32,
+
+    // Pins
+    last_cs_n: bool,
+    last_sclk: bool,
+    miso: bool,
+
+    in_shift: u8,
+    in_num_bits: u32,
+    out_shift: u8,
+    out_queue: VecDeque<u8>,
+
+    command: Vec<u8>,
+    data_state: DataState,
+}
+
+impl<S: Read + Write + Seek> SdCard<S> {
+    pub fn new(mut image: S, kind: CardKind) -> SdCard<S> {
+        let num_blocks = image.seek(SeekFrom::End(0)).expect("Couldn't seek image") / BLOCK_SIZE as u64;
+
+        SdCard {
+            image,
+            num_blocks,
+            kind,
+
+            is_spi_mode: false,
+            is_idle: true,
+            is_app_command: false,
+            num_init_attempts: 0,
+
+            last_cs_n: true,
+            last_sclk: false,
+            miso: true,
+
+            in_shift: 0,
+            in_num_bits: 0,
+            out_shift: 0xff,
+            out_queue: VecDeque::new(),
+
+            command: Vec::new(),
+            data_state: DataState::None,
+        }
+    }
+
+    // Call once per cycle with the current pin states; returns the new MISO state. Like a real card, MISO floats
+    //  (and is pulled high) while deselected, and changes on falling SCLK edges.
+    pub fn step(&mut self, cs_n: bool, sclk: bool, mosi: bool) -> bool {
+        let rising_edge = sclk && !self.last_sclk;
+        let falling_edge = !sclk && self.last_sclk;
+        self.last_sclk = sclk;
+
+        if cs_n {
+            if !self.last_cs_n {
+                // Anything in flight is abandoned
+                self.in_num_bits = 0;
+                self.out_queue.clear();
+                self.command.clear();
+                self.data_state = DataState::None;
+            }
+            self.last_cs_n = true;
+            self.miso = true;
+            return self.miso;
+        }
+
+        if self.last_cs_n {
+            self.last_cs_n = false;
+            self.load_out_byte();
+        }
+
+        if rising_edge {
+            self.in_shift = (self.in_shift << 1) | mosi as u8;
+            self.in_num_bits += 1;
+        }
+
+        if falling_edge {
+            if self.in_num_bits == 8 {
+                self.in_num_bits = 0;
+                let byte = self.in_shift;
+                self.receive_byte(byte);
+                self.load_out_byte();
+            } else {
+                self.out_shift <<= 1;
+                self.miso = (self.out_shift & 0x80) != 0;
+            }
+        }
+
+        self.miso
+    }
+
+    fn load_out_byte(&mut self) {
+        self.out_shift = self.out_queue.pop_front().unwrap_or(0xff);
+        self.miso = (self.out_shift & 0x80) != 0;
+    }
+
+    fn receive_byte(&mut self, byte: u8) {
+        match std::mem::replace(&mut self.data_state, DataState::None) {
+            DataState::None => (),
+            DataState::WaitingForToken(addr) => {
+                self.data_state = if byte == DATA_START_TOKEN {
+                    DataState::ReceivingBlock(addr, Vec::with_capacity(BLOCK_SIZE + 2))
+                } else {
+                    DataState::WaitingForToken(addr)
+                };
+                return;
+            }
+            DataState::ReceivingBlock(addr, mut data) => {
+                data.push(byte);
+                if data.len() < BLOCK_SIZE + 2 {
+                    self.data_state = DataState::ReceivingBlock(addr, data);
+                } else {
+                    // CRC is off, so the trailing CRC bytes are ignored
+                    self.image.seek(SeekFrom::Start(addr)).expect("Couldn't seek image");
+                    self.image.write_all(&data[..BLOCK_SIZE]).expect("Couldn't write image");
+                    self.out_queue.push_back(DATA_RESPONSE_ACCEPTED);
+                    self.out_queue.extend([0x00; WRITE_BUSY_LEN]);
+                }
+                return;
+            }
+        }
+
+        if self.command.is_empty() && (byte & 0xc0) != 0x40 {
+            // Not a command start; hosts clock out 0xff between commands
+            return;
+        }
+        self.command.push(byte);
+        if self.command.len() == 6 {
+            let command = std::mem::take(&mut self.command);
+            self.execute_command(&command);
+        }
+    }
+
+    fn execute_command(&mut self, command: &[u8]) {
+        let index = command[0] & 0x3f;
+        let arg = u32::from_be_bytes([command[1], command[2], command[3], command[4]]);
+        let is_crc_valid = command[5] == ((crc7(&command[..5]) << 1) | 1);
+
+        if !self.is_spi_mode {
+            // Cards power up in SD mode, and only switch to SPI mode on a CMD0 with CS asserted and a valid CRC
+            if index != 0 || !is_crc_valid {
+                return;
+            }
+            self.is_spi_mode = true;
+        }
+
+        let is_app_command = self.is_app_command;
+        self.is_app_command = false;
+
+        // Ncr
+        self.out_queue.push_back(0xff);
+
+        // CRC checking is off by default in SPI mode, except for these
+        if (index == 0 || index == 8) && !is_crc_valid {
+            self.push_r1(R1_COMMAND_CRC_ERROR);
+            return;
+        }
+
+        match (is_app_command, index) {
+            (_, 0) => {
+                self.is_idle = true;
+                self.num_init_attempts = 0;
+                self.push_r1(0);
+            }
+            (_, 8) if self.kind == CardKind::HighCapacity => {
+                self.push_r1(0);
+                // Echo the voltage (if accepted) and check pattern
+                let voltage = if ((arg >> 8) & 0xf) == 1 { 1 } else { 0 };
+                self.out_queue.extend([0x00, 0x00, voltage, arg as u8]);
+            }
+            (_, 16) => {
+                if arg as usize == BLOCK_SIZE {
+                    self.push_r1(0);
+                } else {
+                    self.push_r1(R1_PARAMETER_ERROR);
+                }
+            }
+            (_, 17) if !self.is_idle => {
+                match self.block_addr(arg) {
+                    Ok(addr) => {
+                        self.push_r1(0);
+                        self.out_queue.extend([0xff; READ_LATENCY]);
+                        if addr / BLOCK_SIZE as u64 >= self.num_blocks {
+                            self.out_queue.push_back(DATA_ERROR_OUT_OF_RANGE);
+                            return;
+                        }
+                        let mut data = [0; BLOCK_SIZE];
+                        self.image.seek(SeekFrom::Start(addr)).expect("Couldn't seek image");
+                        self.image.read_exact(&mut data).expect("Couldn't read image");
+                        self.out_queue.push_back(DATA_START_TOKEN);
+                        self.out_queue.extend(data);
+                        self.out_queue.extend(crc16(&data).to_be_bytes());
+                    }
+                    Err(r1) => self.push_r1(r1),
+                }
+            }
+            (_, 24) if !self.is_idle => {
+                match self.block_addr(arg) {
+                    Ok(addr) if addr / (BLOCK_SIZE as u64) < self.num_blocks => {
+                        self.push_r1(0);
+                        self.data_state = DataState::WaitingForToken(addr);
+                    }
+                    Ok(_) => self.push_r1(R1_ADDRESS_ERROR),
+                    Err(r1) => self.push_r1(r1),
+                }
+            }
+            (_, 55) => {
+                self.is_app_command = true;
+                self.push_r1(0);
+            }
+            (_, 58) => {
+                self.push_r1(0);
+                let mut ocr = OCR_VOLTAGE_WINDOW;
+                if !self.is_idle {
+                    ocr |= OCR_POWER_UP_DONE;
+                    if self.kind == CardKind::HighCapacity {
+                        ocr |= OCR_HIGH_CAPACITY;
+                    }
+                }
+                self.out_queue.extend(ocr.to_be_bytes());
+            }
+            (_, 59) => {
+                self.push_r1(0);
+            }
+            (true, 41) => {
+                // High capacity cards never finish initializing for hosts that don't say they support them
+                let host_supports_high_capacity = (arg & OCR_HIGH_CAPACITY) != 0;
+                if self.kind == CardKind::StandardCapacityV1 || host_supports_high_capacity {
+                    self.num_init_attempts += 1;
+                    if self.num_init_attempts >= NUM_INIT_ATTEMPTS {
+                        self.is_idle = false;
+                    }
+                }
+                self.push_r1(0);
+            }
+            _ => {
+                self.push_r1(R1_ILLEGAL_COMMAND);
+            }
+        }
+    }
+
+    // The idle bit is added automatically
+    fn push_r1(&mut self, r1: u8) {
+        self.out_queue.push_back(r1 | if self.is_idle { R1_IDLE } else { 0 });
+    }
+
+    fn block_addr(&self, arg: u32) -> Result<u64, u8> {
+        match self.kind {
+            CardKind::HighCapacity => Ok(arg as u64 * BLOCK_SIZE as u64),
+            CardKind::StandardCapacityV1 => {
+                if (arg as usize & (BLOCK_SIZE - 1)) != 0 {
+                    return Err(R1_ADDRESS_ERROR);
+                }
+                Ok(arg as _)
+            }
+        }
+    }
+}
+
+// x^7 + x^3 + 1, MSB first
+fn crc7(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        for i in (0..8).rev() {
+            let bit = ((byte >> i) & 1) ^ (crc >> 6);
+            crc = (crc << 1) & 0x7f;
+            if bit != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+// CCITT (x^16 + x^12 + x^5 + 1), MSB first
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if (crc & 0x8000) != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
@@ -0,0 +1,278 @@
+use crate::flash::{self, Flash};
+use crate::harness::*;
+use crate::regs;
+use crate::sd_card::*;
+use crate::xw_flash;
+use crate::xw_sdcard;
+
+use boot_image::*;
+
+use rtl_meta::spi::*;
+
+use std::convert::TryInto;
+use std::io::Cursor;
+
+// MOSI is wired straight back to MISO. Also records pin activity since the last transfer started.
+#[derive(Default)]
+struct Loopback {
+    num_rising_edges: u32,
+    mosi_at_rising_edges: u32,
+    is_selected_at_all_edges: bool,
+    last_sclk: bool,
+    num_cycles: u32,
+}
+
+impl Slave for Loopback {
+    fn step(&mut self, cs_n: bool, sclk: bool, mosi: bool) -> bool {
+        if sclk != self.last_sclk {
+            self.is_selected_at_all_edges &= !cs_n;
+            if sclk {
+                self.num_rising_edges += 1;
+                self.mosi_at_rising_edges = (self.mosi_at_rising_edges << 1) | mosi as u32;
+            }
+        }
+        self.last_sclk = sclk;
+        self.num_cycles += 1;
+
+        mosi
+    }
+}
+
+// Nothing in the slot; MISO is just pulled high
+struct NoCard;
+
+impl Slave for NoCard {
+    fn step(&mut self, _cs_n: bool, _sclk: bool, _mosi: bool) -> bool {
+        true
+    }
+}
+
+impl Harness<Loopback> {
+    fn is_busy(&mut self) -> bool {
+        (self.read_reg(REG_STATUS_ADDR) & (1 << REG_STATUS_BUSY_BIT)) != 0
+    }
+
+    fn start_transfer(&mut self, data: u8) {
+        self.slave = Loopback {
+            is_selected_at_all_edges: true,
+            last_sclk: self.slave.last_sclk,
+            ..Default::default()
+        };
+        self.write_reg(REG_DATA_ADDR, data as _);
+    }
+
+    fn transfer(&mut self, data: u8) -> u8 {
+        self.start_transfer(data);
+        while self.is_busy() {}
+        self.read_reg(REG_DATA_ADDR) as _
+    }
+}
+
+fn build_image(num_blocks: usize) -> Vec<u8> {
+    (0..num_blocks * BLOCK_SIZE).map(|i| (i as u32).wrapping_mul(0x9e3779b1) as u8 ^ (i / BLOCK_SIZE) as u8).collect()
+}
+
+#[test]
+fn loopback() {
+    let mut h = Harness::new(Loopback::default());
+
+    for &divider in [REG_CLOCK_DIVIDER_DEFAULT, REG_CLOCK_DIVIDER_MIN].iter() {
+        h.write_reg(REG_CLOCK_DIVIDER_ADDR, divider);
+        assert_eq!(h.read_reg(REG_CLOCK_DIVIDER_ADDR), divider);
+
+        for &data in [0x00, 0xff, 0xa5, 0x5a, 0x01, 0x80].iter() {
+            assert_eq!(h.transfer(data), data);
+        }
+    }
+}
+
+#[test]
+fn bit_order_and_timing() {
+    let mut h = Harness::new(Loopback::default());
+
+    h.write_reg(REG_CHIP_SELECT_ADDR, 1);
+    assert_eq!(h.read_reg(REG_CHIP_SELECT_ADDR), 1);
+    h.m.prop();
+    assert_eq!(h.m.cs_n, false);
+
+    for &divider in [REG_CLOCK_DIVIDER_DEFAULT, 9, REG_CLOCK_DIVIDER_MIN].iter() {
+        h.write_reg(REG_CLOCK_DIVIDER_ADDR, divider);
+
+        h.start_transfer(0xa5);
+        assert_eq!(h.is_busy(), true);
+        while h.is_busy() {}
+        // Each bit takes a full SCLK period
+        let num_transfer_cycles = h.slave.num_cycles;
+        let expected_num_transfer_cycles = 16 * (divider + 1);
+        assert!(
+            num_transfer_cycles >= expected_num_transfer_cycles && num_transfer_cycles <= expected_num_transfer_cycles + 4,
+            "expected ~{} cycles, took {}", expected_num_transfer_cycles, num_transfer_cycles);
+
+        // Mode 0, MSB first
+        assert_eq!(h.slave.num_rising_edges, 8);
+        assert_eq!(h.slave.mosi_at_rising_edges, 0xa5);
+        assert_eq!(h.slave.is_selected_at_all_edges, true);
+        h.m.prop();
+        assert_eq!(h.m.sclk, false);
+    }
+
+    h.write_reg(REG_CHIP_SELECT_ADDR, 0);
+    assert_eq!(h.read_reg(REG_CHIP_SELECT_ADDR), 0);
+    h.m.prop();
+    assert_eq!(h.m.cs_n, true);
+}
+
+#[test]
+fn write_while_busy_is_ignored() {
+    let mut h = Harness::new(Loopback::default());
+
+    h.start_transfer(0x3c);
+    h.write_reg(REG_DATA_ADDR, 0xc3);
+    while h.is_busy() {}
+    assert_eq!(h.read_reg(REG_DATA_ADDR), 0x3c);
+    assert_eq!(h.slave.num_rising_edges, 8);
+}
+
+// The rest drive the models through xw's drivers
+
+fn attach_card(image: Vec<u8>, kind: CardKind) {
+    regs::attach(Harness::new(Box::new(SdCard::new(Cursor::new(image), kind))));
+}
+
+fn sd_card_read_write(kind: CardKind) {
+    const NUM_BLOCKS: usize = 16;
+
+    let image = build_image(NUM_BLOCKS);
+    attach_card(image.clone(), kind);
+
+    let mut card = xw_sdcard::SdCard::init().unwrap_or_else(|e| panic!("Couldn't initialize card: {:?}", e));
+    assert_eq!(card.is_high_capacity(), kind == CardKind::HighCapacity);
+
+    let mut block = [0; BLOCK_SIZE];
+    for &index in [0, 1, NUM_BLOCKS - 1].iter() {
+        assert_eq!(card.read_block(index as _, &mut block), Ok(()));
+        assert_eq!(&block[..], &image[index * BLOCK_SIZE..(index + 1) * BLOCK_SIZE]);
+    }
+
+    let mut data = [0; BLOCK_SIZE];
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte = !(i as u8);
+    }
+    assert_eq!(card.write_block(3, &data), Ok(()));
+    assert_eq!(card.read_block(3, &mut block), Ok(()));
+    assert_eq!(block, data);
+    // Neighbors are untouched
+    assert_eq!(card.read_block(2, &mut block), Ok(()));
+    assert_eq!(&block[..], &image[2 * BLOCK_SIZE..3 * BLOCK_SIZE]);
+    assert_eq!(card.read_block(4, &mut block), Ok(()));
+    assert_eq!(&block[..], &image[4 * BLOCK_SIZE..5 * BLOCK_SIZE]);
+}
+
+#[test]
+fn sd_card_high_capacity() {
+    sd_card_read_write(CardKind::HighCapacity);
+}
+
+#[test]
+fn sd_card_standard_capacity() {
+    sd_card_read_write(CardKind::StandardCapacityV1);
+}
+
+#[test]
+fn sd_card_out_of_range() {
+    for &kind in [CardKind::HighCapacity, CardKind::StandardCapacityV1].iter() {
+        attach_card(build_image(4), kind);
+
+        let mut card = xw_sdcard::SdCard::init().unwrap_or_else(|e| panic!("Couldn't initialize card: {:?}", e));
+        let mut block = [0; BLOCK_SIZE];
+        // Out of range reads are only caught once the card goes to fetch the data
+        assert_eq!(card.read_block(4, &mut block), Err(xw_sdcard::Error::ReadFailed(0x08)));
+        assert_eq!(card.write_block(4, &block), Err(xw_sdcard::Error::Command(24, 0x20)));
+        // The card is still usable afterwards
+        assert_eq!(card.read_block(3, &mut block), Ok(()));
+    }
+}
+
+#[test]
+fn sd_card_missing() {
+    regs::attach(Harness::new(Box::new(NoCard)));
+
+    assert_eq!(xw_sdcard::SdCard::init().err(), Some(xw_sdcard::Error::NoResponse));
+}
+
+fn attach_flash(contents: Vec<u8>) {
+    regs::attach(Harness::new(Box::new(Flash::new(contents))));
+    xw_flash::init();
+}
+
+fn flash_read_vec(addr: u32, len: usize) -> Vec<u8> {
+    let mut ret = vec![0; len];
+    xw_flash::read(addr, &mut ret);
+    ret
+}
+
+#[test]
+fn flash_read() {
+    let contents = build_image(64 * 1024 / BLOCK_SIZE);
+    attach_flash(contents.clone());
+
+    assert_eq!(xw_flash::read_id(), flash::ID);
+
+    // Reads stream across page and sector boundaries
+    for &(addr, len) in [(0, 16), (250, 20), (4090, 300), (0x1234, 1)].iter() {
+        assert_eq!(flash_read_vec(addr as _, len), &contents[addr..addr + len]);
+    }
+}
+
+#[test]
+fn flash_program_and_erase() {
+    attach_flash(vec![0xff; 64 * 1024]);
+
+    let data = (0..flash::PAGE_SIZE).map(|i| i as u8).collect::<Vec<_>>();
+    xw_flash::program_page(0x1000, &data);
+    assert_eq!(flash_read_vec(0x1000, flash::PAGE_SIZE), data);
+
+    // Programming can only clear bits
+    xw_flash::program_page(0x1010, &[0x0f, 0xf0]);
+    assert_eq!(flash_read_vec(0x1010, 2), vec![0x10 & 0x0f, 0x11 & 0xf0]);
+
+    // Erasing only touches the sector containing the address
+    xw_flash::program_page(0x2000, &[0x5a]);
+    xw_flash::erase_sector(0x1abc);
+    assert_eq!(flash_read_vec(0x1000, flash::SECTOR_SIZE), vec![0xff; flash::SECTOR_SIZE]);
+    assert_eq!(flash_read_vec(0x2000, 1), vec![0x5a]);
+
+    // Multi-sector writes erase everything they touch first
+    let data = build_image(3 * flash::SECTOR_SIZE / BLOCK_SIZE);
+    xw_flash::write(0x3000, &data[..2 * flash::SECTOR_SIZE + 100]);
+    assert_eq!(flash_read_vec(0x3000, 2 * flash::SECTOR_SIZE + 100), &data[..2 * flash::SECTOR_SIZE + 100]);
+    xw_flash::write(0x4000, &data[..10]);
+    assert_eq!(flash_read_vec(0x4000, 10), &data[..10]);
+    assert_eq!(flash_read_vec(0x400a, 10), vec![0xff; 10]);
+}
+
+// boot-rom is a bare-metal binary, so its load_from_flash and program_flash can't be linked in here. This goes
+//  through the same driver calls and boot_image checks they make, but changes to boot-rom itself aren't covered.
+#[test]
+fn flash_boot_image() {
+    const FLASH_SIZE: usize = 8 * 1024 * 1024;
+    const LOAD_ADDR: u32 = 0x10000000;
+
+    let program = build_image(3).into_iter().take(1000).collect::<Vec<_>>();
+
+    // Erased flash doesn't have an image
+    attach_flash(vec![0xff; FLASH_SIZE]);
+    let header_bytes = flash_read_vec(FLASH_OFFSET, HEADER_SIZE);
+    assert_eq!(Header::parse(header_bytes[..].try_into().unwrap()), None);
+
+    let mut image = Header::new(&program, LOAD_ADDR).to_bytes().to_vec();
+    image.extend(&program);
+    xw_flash::write(FLASH_OFFSET, &image);
+
+    let header_bytes = flash_read_vec(FLASH_OFFSET, HEADER_SIZE);
+    let header = Header::parse(header_bytes[..].try_into().unwrap()).unwrap();
+    assert_eq!(header.size as usize, program.len());
+    assert_eq!(header.load_addr, LOAD_ADDR);
+    let read_program = flash_read_vec(FLASH_OFFSET + HEADER_SIZE as u32, header.size as _);
+    assert!(header.matches(&read_program));
+}
@@ -0,0 +1,25 @@
+use kaze::*;
+use rtl::uart::*;
+use rtl::uart_interface::*;
+
+use std::env;
+use std::fs::File;
+use std::io::Result;
+use std::path::Path;
+
+fn main() -> Result<()> {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("modules.rs");
+    let mut file = File::create(&dest_path).unwrap();
+
+    let c = Context::new();
+
+    let uart_interface = UartInterface::new("uart_interface", &c);
+    sim::generate(uart_interface.m, sim::GenerationOptions::default(), &mut file)?;
+
+    let uart_tx = UartTx::new("uart_tx", &c);
+    sim::generate(uart_tx.m, sim::GenerationOptions::default(), &mut file)?;
+
+    let uart_rx = UartRx::new("uart_rx", &c);
+    sim::generate(uart_rx.m, sim::GenerationOptions::default(), file)
+}
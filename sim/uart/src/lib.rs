@@ -0,0 +1,431 @@
+#[cfg(test)]
+mod tests {
+    mod modules {
+        include!(concat!(env!("OUT_DIR"), "/modules.rs"));
+    }
+
+    use modules::*;
+
+    use rtl_meta::uart::*;
+
+    use std::collections::VecDeque;
+
+    // Matches the sync FF's in front of rx on the board
+    const RX_SYNC_STAGES: usize = 2;
+
+    enum Line {
+        // tx is wired straight back to rx
+        Loopback,
+        // Levels to drive rx with, one per cycle; the line idles high once they run out
+        Host(VecDeque<bool>),
+    }
+
+    enum Cts {
+        Asserted,
+        Deasserted,
+        // cts is wired straight back to rts
+        Loopback,
+    }
+
+    struct Harness {
+        interface: UartInterface,
+        tx: UartTx,
+        rx: UartRx,
+        line: Line,
+        cts: Cts,
+        rx_sync: [bool; RX_SYNC_STAGES],
+    }
+
+    impl Harness {
+        fn new(line: Line) -> Harness {
+            let mut interface = UartInterface::new();
+            let mut tx = UartTx::new();
+            let mut rx = UartRx::new();
+
+            interface.reset();
+            interface.bus_enable = false;
+            interface.bus_addr = 0;
+            interface.bus_write = false;
+            interface.bus_write_data = 0;
+            interface.bus_write_byte_enable = 0xffff;
+            tx.reset();
+            rx.reset();
+
+            Harness {
+                interface,
+                tx,
+                rx,
+                line,
+                cts: Cts::Asserted,
+                rx_sync: [true; RX_SYNC_STAGES],
+            }
+        }
+
+        fn step(&mut self) {
+            // Outputs of all three modules only depend on their own regs, so one pass is enough to settle everything
+            self.interface.prop();
+            self.tx.enable = self.interface.tx_enable;
+            self.tx.data = self.interface.tx_data;
+            self.tx.baud_divisor = self.interface.baud_divisor;
+            self.tx.prop();
+            self.rx.rx = self.rx_sync[RX_SYNC_STAGES - 1];
+            self.rx.baud_divisor = self.interface.baud_divisor;
+            self.rx.prop();
+
+            self.interface.tx_ready = self.tx.ready;
+            self.interface.rx_data = self.rx.data;
+            self.interface.rx_data_valid = self.rx.data_valid;
+            self.interface.rx_framing_error = self.rx.framing_error;
+            self.interface.cts = match self.cts {
+                Cts::Asserted => true,
+                Cts::Deasserted => false,
+                Cts::Loopback => self.interface.rts,
+            };
+            self.interface.prop();
+
+            let line = match &mut self.line {
+                Line::Loopback => self.tx.tx,
+                Line::Host(levels) => levels.pop_front().unwrap_or(true),
+            };
+            for i in (1..RX_SYNC_STAGES).rev() {
+                self.rx_sync[i] = self.rx_sync[i - 1];
+            }
+            self.rx_sync[0] = line;
+
+            self.interface.posedge_clk();
+            self.tx.posedge_clk();
+            self.rx.posedge_clk();
+        }
+
+        fn write_reg(&mut self, addr: u32, data: u32) {
+            self.interface.bus_enable = true;
+            self.interface.bus_addr = addr;
+            self.interface.bus_write = true;
+            self.interface.bus_write_data = data as _;
+            self.step();
+            self.interface.bus_enable = false;
+        }
+
+        fn read_reg(&mut self, addr: u32) -> u32 {
+            self.interface.bus_enable = true;
+            self.interface.bus_addr = addr;
+            self.interface.bus_write = false;
+            self.step();
+            self.interface.bus_enable = false;
+            self.interface.prop();
+            assert_eq!(self.interface.bus_read_data_valid, true);
+            self.interface.bus_read_data as _
+        }
+
+        fn tx_status(&mut self, bit: u32) -> bool {
+            (self.read_reg(REG_TX_STATUS_ADDR) & (1 << bit)) != 0
+        }
+
+        // Same as the sw driver's write_u8; blocks only while the TX FIFO is full
+        fn write_u8(&mut self, data: u8) {
+            while !self.tx_status(REG_TX_STATUS_READY_BIT) {}
+            self.write_reg(REG_TX_DATA_ADDR, data as _);
+        }
+
+        fn read_u8(&mut self) -> u8 {
+            while (self.read_reg(REG_RX_STATUS_ADDR) & (1 << REG_RX_STATUS_READY_BIT)) == 0 {}
+            self.read_reg(REG_RX_DATA_ADDR) as _
+        }
+
+        fn flush(&mut self) {
+            while !self.tx_status(REG_TX_STATUS_IDLE_BIT) {}
+        }
+
+        // Runs until nothing has been received for a few frames' worth of cycles
+        fn wait_for_quiet_line(&mut self) {
+            let quiet_cycles = self.read_reg(REG_BAUD_DIVISOR_ADDR) * 10 * 3;
+            let mut rx_level = self.read_reg(REG_RX_LEVEL_ADDR);
+            let mut num_quiet_cycles = 0;
+            while num_quiet_cycles < quiet_cycles {
+                self.step();
+                let new_rx_level = self.read_reg(REG_RX_LEVEL_ADDR);
+                if new_rx_level != rx_level {
+                    rx_level = new_rx_level;
+                    num_quiet_cycles = 0;
+                } else {
+                    num_quiet_cycles += 2;
+                }
+            }
+        }
+    }
+
+    // An 8N1 frame as a host with the given bit period would send it, followed by a bit period of idle
+    fn frame(data: u8, stop_bit: bool, clocks_per_bit: f64) -> VecDeque<bool> {
+        let mut bits = vec![false];
+        bits.extend((0..8).map(|i| ((data >> i) & 1) != 0));
+        bits.push(stop_bit);
+        bits.push(true);
+
+        let num_cycles = (bits.len() as f64 * clocks_per_bit) as usize;
+        (0..num_cycles).map(|cycle| bits[(cycle as f64 / clocks_per_bit) as usize]).collect()
+    }
+
+    fn loopback(baud_divisor: u32, bytes: &[u8]) {
+        let mut h = Harness::new(Line::Loopback);
+
+        h.write_reg(REG_BAUD_DIVISOR_ADDR, baud_divisor);
+        assert_eq!(h.read_reg(REG_BAUD_DIVISOR_ADDR), baud_divisor);
+
+        for &b in bytes {
+            h.write_u8(b);
+        }
+        h.flush();
+        assert_eq!(h.read_reg(REG_TX_LEVEL_ADDR), 0);
+
+        h.wait_for_quiet_line();
+        assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), bytes.len() as u32);
+        for &b in bytes {
+            assert_eq!(h.read_u8(), b);
+        }
+        assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), 0);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+    }
+
+    #[test]
+    fn baud_divisor_is_clamped() {
+        let mut h = Harness::new(Line::Loopback);
+
+        for &baud_divisor in [0, 1, REG_BAUD_DIVISOR_MIN - 1].iter() {
+            h.write_reg(REG_BAUD_DIVISOR_ADDR, baud_divisor);
+            assert_eq!(h.read_reg(REG_BAUD_DIVISOR_ADDR), REG_BAUD_DIVISOR_MIN);
+        }
+
+        // .. and RX can still keep up with TX
+        h.write_u8(0xa5);
+        assert_eq!(h.read_u8(), 0xa5);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+    }
+
+    #[test]
+    fn reset_state() {
+        let mut h = Harness::new(Line::Loopback);
+
+        assert_eq!(h.read_reg(REG_BAUD_DIVISOR_ADDR), REG_BAUD_DIVISOR_DEFAULT);
+        assert_eq!(h.read_reg(REG_FLOW_CONTROL_ADDR), 0);
+        assert_eq!(h.tx_status(REG_TX_STATUS_READY_BIT), true);
+        assert_eq!(h.tx_status(REG_TX_STATUS_IDLE_BIT), true);
+        assert_eq!(h.read_reg(REG_RX_STATUS_ADDR), 0);
+        assert_eq!(h.read_reg(REG_TX_LEVEL_ADDR), 0);
+        assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), 0);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+        h.interface.prop();
+        assert_eq!(h.interface.rts, true);
+    }
+
+    #[test]
+    fn loopback_default_divisor() {
+        loopback(REG_BAUD_DIVISOR_DEFAULT, b"Hello, xenowing!");
+    }
+
+    #[test]
+    fn loopback_other_divisors() {
+        // Includes divisors that aren't multiples of 4, so RX ticks aren't evenly spaced
+        for &baud_divisor in [REG_BAUD_DIVISOR_MIN, 9, 10, 11, 27, 100, 1000].iter() {
+            loopback(baud_divisor, &[0x00, 0xff, 0x55, 0xaa, 0x01, 0x80, 0x3c]);
+        }
+    }
+
+    #[test]
+    fn loopback_full_fifos() {
+        let bytes = (0..FIFO_DEPTH).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        loopback(REG_BAUD_DIVISOR_MIN, &bytes);
+    }
+
+    #[test]
+    fn tx_is_back_to_back() {
+        let baud_divisor = 20;
+        let num_bytes = 16;
+
+        let mut h = Harness::new(Line::Loopback);
+
+        h.write_reg(REG_BAUD_DIVISOR_ADDR, baud_divisor);
+        // Fill the FIFO before anything goes out, so there's always a byte waiting
+        h.cts = Cts::Deasserted;
+        h.write_reg(REG_FLOW_CONTROL_ADDR, 1 << REG_FLOW_CONTROL_ENABLE_BIT);
+        for i in 0..num_bytes {
+            h.write_reg(REG_TX_DATA_ADDR, i);
+        }
+        assert_eq!(h.read_reg(REG_TX_LEVEL_ADDR), num_bytes);
+        h.cts = Cts::Asserted;
+
+        let mut num_cycles = 0;
+        while !h.tx_status(REG_TX_STATUS_IDLE_BIT) {
+            num_cycles += 1;
+        }
+
+        // Start + 8 data + stop, with at most a couple of cycles of overhead per byte to get the next one going
+        let frame_cycles = baud_divisor * 10;
+        assert!(num_cycles <= num_bytes * (frame_cycles + 2));
+    }
+
+    #[test]
+    fn host_baud_rate_tolerance() {
+        let baud_divisor = 217;
+        let bytes = [0x00, 0xff, 0x55, 0xaa, 0x0f, 0xf0];
+
+        // 4x sampling leaves about +/- 1/4 bit of slack over a frame
+        for &error in [-0.02, -0.01, 0.0, 0.01, 0.02].iter() {
+            let clocks_per_bit = baud_divisor as f64 * (1.0 + error);
+            let mut levels = VecDeque::new();
+            for &b in bytes.iter() {
+                levels.extend(frame(b, true, clocks_per_bit));
+            }
+
+            let mut h = Harness::new(Line::Host(levels));
+            h.wait_for_quiet_line();
+
+            assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), bytes.len() as u32);
+            for &b in bytes.iter() {
+                assert_eq!(h.read_u8(), b);
+            }
+            assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+        }
+    }
+
+    #[test]
+    fn framing_error() {
+        let clocks_per_bit = REG_BAUD_DIVISOR_DEFAULT as f64;
+        let mut levels = frame(0x5a, false, clocks_per_bit);
+        levels.extend(frame(0xa5, true, clocks_per_bit));
+
+        let mut h = Harness::new(Line::Host(levels));
+        h.wait_for_quiet_line();
+
+        // Both bytes are kept, but the flag sticks around after the good one
+        assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), 2);
+        assert_eq!(h.read_u8(), 0x5a);
+        assert_eq!(h.read_u8(), 0xa5);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 1 << REG_ERROR_FRAMING_BIT);
+
+        // Writing 0 bits doesn't clear anything
+        h.write_reg(REG_ERROR_ADDR, !(1 << REG_ERROR_FRAMING_BIT));
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 1 << REG_ERROR_FRAMING_BIT);
+        h.write_reg(REG_ERROR_ADDR, 1 << REG_ERROR_FRAMING_BIT);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+    }
+
+    #[test]
+    fn overrun() {
+        let mut h = Harness::new(Line::Loopback);
+
+        h.write_reg(REG_BAUD_DIVISOR_ADDR, REG_BAUD_DIVISOR_MIN);
+
+        let num_bytes = FIFO_DEPTH + 3;
+        for i in 0..num_bytes {
+            h.write_u8(i as _);
+        }
+        h.flush();
+        h.wait_for_quiet_line();
+
+        h.interface.prop();
+        assert_eq!(h.interface.rx_ready, false);
+        assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), FIFO_DEPTH);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 1 << REG_ERROR_OVERRUN_BIT);
+
+        // The oldest bytes survive; the ones that arrived while full are gone
+        for i in 0..FIFO_DEPTH {
+            assert_eq!(h.read_u8(), i as u8);
+        }
+        assert_eq!(h.read_reg(REG_RX_STATUS_ADDR), 0);
+
+        h.write_reg(REG_ERROR_ADDR, 1 << REG_ERROR_OVERRUN_BIT);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+    }
+
+    #[test]
+    fn tx_overflow() {
+        let mut h = Harness::new(Line::Loopback);
+
+        // Hold everything in the FIFO
+        h.cts = Cts::Deasserted;
+        h.write_reg(REG_FLOW_CONTROL_ADDR, 1 << REG_FLOW_CONTROL_ENABLE_BIT);
+
+        for i in 0..FIFO_DEPTH {
+            assert_eq!(h.tx_status(REG_TX_STATUS_READY_BIT), true);
+            h.write_reg(REG_TX_DATA_ADDR, i);
+            assert_eq!(h.read_reg(REG_TX_LEVEL_ADDR), i + 1);
+        }
+        assert_eq!(h.tx_status(REG_TX_STATUS_READY_BIT), false);
+        assert_eq!(h.tx_status(REG_TX_STATUS_IDLE_BIT), false);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+
+        h.write_reg(REG_TX_DATA_ADDR, 0xff);
+        assert_eq!(h.read_reg(REG_TX_LEVEL_ADDR), FIFO_DEPTH);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 1 << REG_ERROR_TX_OVERFLOW_BIT);
+        h.write_reg(REG_ERROR_ADDR, 1 << REG_ERROR_TX_OVERFLOW_BIT);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+
+        // Nothing has gone out yet
+        assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), 0);
+
+        // Dropped byte isn't sent
+        h.write_reg(REG_BAUD_DIVISOR_ADDR, REG_BAUD_DIVISOR_MIN);
+        h.cts = Cts::Asserted;
+        h.flush();
+        h.wait_for_quiet_line();
+        assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), FIFO_DEPTH);
+        for i in 0..FIFO_DEPTH {
+            assert_eq!(h.read_u8(), i as u8);
+        }
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+    }
+
+    #[test]
+    fn cts_ignored_when_flow_control_disabled() {
+        let mut h = Harness::new(Line::Loopback);
+
+        h.cts = Cts::Deasserted;
+        h.write_reg(REG_BAUD_DIVISOR_ADDR, REG_BAUD_DIVISOR_MIN);
+        h.write_u8(0x42);
+        h.flush();
+        h.wait_for_quiet_line();
+
+        assert_eq!(h.read_u8(), 0x42);
+    }
+
+    #[test]
+    fn rts_cts_flow_control() {
+        let mut h = Harness::new(Line::Loopback);
+
+        h.cts = Cts::Loopback;
+        h.write_reg(REG_BAUD_DIVISOR_ADDR, REG_BAUD_DIVISOR_MIN);
+        h.write_reg(REG_FLOW_CONTROL_ADDR, 1 << REG_FLOW_CONTROL_ENABLE_BIT);
+        assert_eq!(h.read_reg(REG_FLOW_CONTROL_ADDR), 1 << REG_FLOW_CONTROL_ENABLE_BIT);
+
+        // Send more than the RX FIFO can hold without reading anything; RTS should stop TX before the RX FIFO overflows
+        let num_bytes = FIFO_DEPTH + 50;
+        for i in 0..FIFO_DEPTH {
+            h.write_u8(i as _);
+        }
+        h.wait_for_quiet_line();
+
+        // A byte that was already on its way when RTS went low may still land
+        let rx_level = h.read_reg(REG_RX_LEVEL_ADDR);
+        assert!((FIFO_DEPTH - RTS_THRESHOLD..FIFO_DEPTH).contains(&rx_level));
+        assert_eq!(h.read_reg(REG_TX_LEVEL_ADDR), FIFO_DEPTH - rx_level);
+        h.interface.prop();
+        assert_eq!(h.interface.rts, false);
+
+        // Draining RX lets TX resume
+        let mut num_sent = FIFO_DEPTH;
+        for i in 0..num_bytes {
+            assert_eq!(h.read_u8(), i as u8);
+            if num_sent < num_bytes {
+                h.write_u8(num_sent as _);
+                num_sent += 1;
+            }
+        }
+        h.flush();
+        h.wait_for_quiet_line();
+
+        assert_eq!(h.read_reg(REG_RX_LEVEL_ADDR), 0);
+        assert_eq!(h.read_reg(REG_ERROR_ADDR), 0);
+        h.interface.prop();
+        assert_eq!(h.interface.rts, true);
+    }
+}
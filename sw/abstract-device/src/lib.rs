@@ -16,6 +16,11 @@ pub trait Device {
     fn color_thrust_read_color_buffer_word(&mut self, addr: u32) -> u128;
     fn color_thrust_write_depth_buffer_word(&mut self, addr: u32, data: u128);
     fn color_thrust_read_depth_buffer_word(&mut self, addr: u32) -> u128;
+    fn color_thrust_write_stencil_buffer_word(&mut self, addr: u32, data: u128);
+    fn color_thrust_read_stencil_buffer_word(&mut self, addr: u32) -> u128;
+
+    fn scanout_write_reg(&mut self, addr: u32, data: u32);
+    fn scanout_read_reg(&mut self, addr: u32) -> u32;
 }
 
 impl<D: Device + ?Sized> Device for &mut D {
@@ -78,4 +83,24 @@ impl<D: Device + ?Sized> Device for &mut D {
     fn color_thrust_read_depth_buffer_word(&mut self, addr: u32) -> u128 {
         (**self).color_thrust_read_depth_buffer_word(addr)
     }
+
+    #[inline]
+    fn color_thrust_write_stencil_buffer_word(&mut self, addr: u32, data: u128) {
+        (**self).color_thrust_write_stencil_buffer_word(addr, data);
+    }
+
+    #[inline]
+    fn color_thrust_read_stencil_buffer_word(&mut self, addr: u32) -> u128 {
+        (**self).color_thrust_read_stencil_buffer_word(addr)
+    }
+
+    #[inline]
+    fn scanout_write_reg(&mut self, addr: u32, data: u32) {
+        (**self).scanout_write_reg(addr, data);
+    }
+
+    #[inline]
+    fn scanout_read_reg(&mut self, addr: u32) -> u32 {
+        (**self).scanout_read_reg(addr)
+    }
 }
@@ -0,0 +1,120 @@
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+// Program images in flash are a header followed immediately by the program itself. All header fields are little
+//  endian.
+
+pub const MAGIC: [u8; 4] = *b"XWPI";
+pub const HEADER_SIZE: usize = 16;
+
+// The FPGA bitstream lives at the start of the same flash, so images start far enough in to leave room for it
+pub const FLASH_OFFSET: u32 = 0x00400000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Header {
+    // In bytes, not including the header
+    pub size: u32,
+    // Also the program's entry point
+    pub load_addr: u32,
+    // CRC-32 (IEEE) of the program
+    pub crc: u32,
+}
+
+impl Header {
+    pub fn new(program: &[u8], load_addr: u32) -> Header {
+        Header {
+            size: program.len() as _,
+            load_addr,
+            crc: crc32(program),
+        }
+    }
+
+    // Returns None if the magic doesn't match, eg. for erased flash
+    pub fn parse(bytes: &[u8; HEADER_SIZE]) -> Option<Header> {
+        if bytes[0..4] != MAGIC {
+            return None;
+        }
+
+        let read_u32 = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        Some(Header {
+            size: read_u32(4),
+            load_addr: read_u32(8),
+            crc: read_u32(12),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut ret = [0; HEADER_SIZE];
+        ret[0..4].copy_from_slice(&MAGIC);
+        ret[4..8].copy_from_slice(&self.size.to_le_bytes());
+        ret[8..12].copy_from_slice(&self.load_addr.to_le_bytes());
+        ret[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        ret
+    }
+
+    pub fn matches(&self, program: &[u8]) -> bool {
+        program.len() == self.size as usize && crc32(program) == self.crc
+    }
+}
+
+// Table-driven, since the boot ROM checks whole programs with this and the bitwise version is painfully slow on marv
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if (crc & 1) != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::vec::Vec;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let program = (0..1000u32).map(|i| i.wrapping_mul(0x9e3779b1) as u8).collect::<Vec<_>>();
+        let header = Header::new(&program, 0x10000000);
+
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[0..4], b"XWPI");
+        let parsed = Header::parse(&bytes).unwrap();
+        assert_eq!(parsed, header);
+        assert!(parsed.matches(&program));
+
+        let mut corrupted = program.clone();
+        corrupted[500] ^= 0x10;
+        assert!(!parsed.matches(&corrupted));
+        assert!(!parsed.matches(&program[..999]));
+    }
+
+    #[test]
+    fn erased_flash_is_not_an_image() {
+        assert_eq!(Header::parse(&[0xff; HEADER_SIZE]), None);
+    }
+}
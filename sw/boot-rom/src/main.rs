@@ -1,20 +1,85 @@
 #![no_main]
 #![no_std]
 
-use xw::{uart, stdio};
+use boot_image::*;
+
+use xw::{flash, marv, uart, stdio};
 
 use core::fmt::Write;
 use core::mem;
+use core::slice;
+
+// Replies to the boot mode query
+const BOOT_MODE_FLASH: u8 = 0x00;
+const BOOT_MODE_UART: u8 = 0x01;
+const BOOT_MODE_PROGRAM_FLASH: u8 = 0x02;
+
+// Plenty of time for xw-blaster to answer, without holding up standalone boots noticeably
+const BOOT_MODE_QUERY_TIMEOUT_CYCLES: u64 = 100000000 / 10;
+
+extern "C" {
+    static mut _sprogram: u8;
+    static _max_program_size: u8;
+}
 
 #[no_mangle]
 fn main() -> ! {
     writeln!(stdio::stdout(), "xw online").unwrap();
 
-    extern "C" {
-        static mut _sprogram: u8;
-        static _max_program_size: u8;
+    flash::init();
+
+    let program_entry_addr = match query_boot_mode() {
+        BOOT_MODE_UART => {
+            load_from_uart();
+            program_ram()
+        }
+        BOOT_MODE_PROGRAM_FLASH => {
+            let program_size = load_from_uart();
+            program_flash(unsafe { slice::from_raw_parts(program_ram(), program_size as _) });
+            program_ram()
+        }
+        _ => match load_from_flash() {
+            Some(program_entry_addr) => program_entry_addr,
+            None => {
+                writeln!(stdio::stdout(), "no valid program image in flash, falling back to UART").unwrap();
+                load_from_uart();
+                program_ram()
+            }
+        }
+    };
+
+    let program_entry = unsafe {
+        mem::transmute::<_, extern "C" fn() -> !>(program_entry_addr)
+    };
+    program_entry()
+}
+
+fn program_ram() -> *mut u8 {
+    unsafe { &mut _sprogram as *mut _ }
+}
+
+fn max_program_size() -> u32 {
+    // TODO: Is there a better way to get this symbol value?
+    unsafe { &_max_program_size as *const _ as u32 }
+}
+
+// Asks xw-blaster how to boot. If it isn't there to answer, we're running standalone, so we boot from flash.
+fn query_boot_mode() -> u8 {
+    // TODO: Proper command
+    uart::write_u8(0x04);
+
+    let start_cycles = marv::cycles();
+    while marv::cycles() - start_cycles < BOOT_MODE_QUERY_TIMEOUT_CYCLES {
+        if let Some(boot_mode) = uart::try_read_u8() {
+            return boot_mode;
+        }
     }
 
+    BOOT_MODE_FLASH
+}
+
+// Loads into the start of program RAM, which is also the entry point. Returns the program's size.
+fn load_from_uart() -> u32 {
     // TODO: Proper command
     uart::write_u8(0x01);
     // TODO: Proper filename
@@ -24,13 +89,12 @@ fn main() -> ! {
     }
     uart::write_u8(0);
     let program_size = uart::read_u32_le();
-    // TODO: Is there a better way to get this symbol value?
-    let max_program_size = unsafe { &_max_program_size as *const _ as u32 };
+    let max_program_size = max_program_size();
     if program_size > max_program_size {
         panic!("program size ({} bytes) must not be larger than {} bytes", program_size, max_program_size);
     }
 
-    let program_ram = unsafe { &mut _sprogram } as *mut u8;
+    let program_ram = program_ram();
     for i in 0..program_size {
         let b = uart::read_u8();
         unsafe {
@@ -40,8 +104,70 @@ fn main() -> ! {
 
     writeln!(stdio::stdout(), "program read successful").unwrap();
 
-    let program_entry = unsafe {
-        mem::transmute::<_, extern "C" fn() -> !>(program_ram)
+    program_size
+}
+
+// Returns the program's entry point, or None if there's no valid image
+fn load_from_flash() -> Option<*mut u8> {
+    let mut header_bytes = [0; HEADER_SIZE];
+    flash::read(FLASH_OFFSET, &mut header_bytes);
+    let header = Header::parse(&header_bytes)?;
+
+    // The image has to fit in program RAM wherever it asks to be loaded
+    let room = header.load_addr.checked_sub(program_ram() as u32).and_then(|offset| max_program_size().checked_sub(offset))?;
+    if header.size > room {
+        writeln!(stdio::stdout(), "program image in flash doesn't fit in program RAM").unwrap();
+        return None;
+    }
+
+    let program = unsafe { slice::from_raw_parts_mut(header.load_addr as *mut u8, header.size as _) };
+    flash::read(FLASH_OFFSET + HEADER_SIZE as u32, program);
+    if !header.matches(program) {
+        writeln!(stdio::stdout(), "program image in flash is corrupt").unwrap();
+        return None;
+    }
+
+    writeln!(stdio::stdout(), "program read from flash successful").unwrap();
+
+    Some(header.load_addr as _)
+}
+
+// Writes a program that was loaded into program RAM to flash, so it'll be booted next time
+fn program_flash(program: &[u8]) {
+    let header_bytes = Header::new(program, program_ram() as u32).to_bytes();
+    let image_size = HEADER_SIZE as u32 + program.len() as u32;
+    let image_byte = |i: u32| {
+        let i = i as usize;
+        if i < HEADER_SIZE { header_bytes[i] } else { program[i - HEADER_SIZE] }
     };
-    program_entry()
+
+    writeln!(stdio::stdout(), "programming flash ({} bytes)", image_size).unwrap();
+
+    for sector_index in 0..image_size.div_ceil(flash::SECTOR_SIZE) {
+        flash::erase_sector(FLASH_OFFSET + sector_index * flash::SECTOR_SIZE);
+    }
+
+    // The page with the header goes last, so an interrupted write can't leave something that looks like a valid image
+    let num_pages = image_size.div_ceil(flash::PAGE_SIZE);
+    let mut page = [0; flash::PAGE_SIZE as usize];
+    for page_index in (1..num_pages).chain(0..1) {
+        let start = page_index * flash::PAGE_SIZE;
+        let len = (image_size - start).min(flash::PAGE_SIZE);
+        for i in 0..len {
+            page[i as usize] = image_byte(start + i);
+        }
+        flash::program_page(FLASH_OFFSET + start, &page[..len as usize]);
+    }
+
+    for page_index in 0..num_pages {
+        let start = page_index * flash::PAGE_SIZE;
+        let len = (image_size - start).min(flash::PAGE_SIZE);
+        flash::read(FLASH_OFFSET + start, &mut page[..len as usize]);
+        if (0..len).any(|i| page[i as usize] != image_byte(start + i)) {
+            writeln!(stdio::stdout(), "flash verify failed at offset {}", start).unwrap();
+            return;
+        }
+    }
+
+    writeln!(stdio::stdout(), "flash programming successful").unwrap();
 }
@@ -0,0 +1,693 @@
+#![no_std]
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+pub const BLOCK_SIZE: usize = 512;
+
+const BOOT_SIGNATURE_OFFSET: usize = 510;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+
+const PARTITION_TABLE_OFFSET: usize = 446;
+const PARTITION_ENTRY_LEN: usize = 16;
+const NUM_PARTITION_ENTRIES: usize = 4;
+const PARTITION_TYPE_FAT32_CHS: u8 = 0x0b;
+const PARTITION_TYPE_FAT32_LBA: u8 = 0x0c;
+
+const FS_TYPE_OFFSET: usize = 82;
+const FS_TYPE: &[u8] = b"FAT32   ";
+
+const DIR_ENTRY_LEN: usize = 32;
+const DIR_ENTRY_END: u8 = 0x00;
+const DIR_ENTRY_DELETED: u8 = 0xe5;
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+const LONG_NAME_LAST_ENTRY: u8 = 0x40;
+const LONG_NAME_CHARS_PER_ENTRY: usize = 13;
+// Offsets of each UCS-2 char within a long name entry
+const LONG_NAME_CHAR_OFFSETS: [usize; LONG_NAME_CHARS_PER_ENTRY] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+const MAX_LONG_NAME_ENTRIES: usize = 20;
+const MAX_LONG_NAME_LEN: usize = 255;
+
+const CLUSTER_MASK: u32 = 0x0fffffff;
+const MIN_CLUSTER: u32 = 2;
+const END_OF_CHAIN: u32 = 0x0ffffff8;
+
+pub trait BlockDevice {
+    type Error;
+
+    fn read_block(&mut self, index: u32, data: &mut [u8; BLOCK_SIZE]) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Error<E> {
+    Device(E),
+    // No FAT32 volume was found, or it's malformed
+    InvalidFileSystem,
+    NotFound,
+    NotAFile,
+    NotADirectory,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(e: E) -> Self {
+        Error::Device(e)
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+fn short_name_checksum(short_name: &[u8]) -> u8 {
+    short_name.iter().fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+fn long_name_char_matches(c: u16, name_char: char) -> bool {
+    // Surrogates (and so anything outside the BMP) never match
+    char::from_u32(c as _).map(|c| c.eq_ignore_ascii_case(&name_char)).unwrap_or(false)
+}
+
+struct DirEntry {
+    first_cluster: u32,
+    size: u32,
+    is_directory: bool,
+}
+
+struct LongName {
+    chars: [u16; MAX_LONG_NAME_ENTRIES * LONG_NAME_CHARS_PER_ENTRY],
+    len: usize,
+    checksum: u8,
+    // Cleared whenever an entry doesn't follow on from the previous one, so stale names never match
+    is_valid: bool,
+    next_sequence_number: u8,
+}
+
+impl LongName {
+    fn new() -> LongName {
+        LongName {
+            chars: [0; MAX_LONG_NAME_ENTRIES * LONG_NAME_CHARS_PER_ENTRY],
+            len: 0,
+            checksum: 0,
+            is_valid: false,
+            next_sequence_number: 0,
+        }
+    }
+
+    // Long name entries are stored last part first, just before the short entry they belong to
+    fn push(&mut self, entry: &[u8]) {
+        let sequence_number = entry[0] & !LONG_NAME_LAST_ENTRY;
+        if sequence_number == 0 || sequence_number as usize > MAX_LONG_NAME_ENTRIES {
+            self.is_valid = false;
+            return;
+        }
+        if (entry[0] & LONG_NAME_LAST_ENTRY) != 0 {
+            self.is_valid = true;
+            self.checksum = entry[13];
+            self.len = sequence_number as usize * LONG_NAME_CHARS_PER_ENTRY;
+        } else if !self.is_valid || sequence_number != self.next_sequence_number || entry[13] != self.checksum {
+            self.is_valid = false;
+            return;
+        }
+        self.next_sequence_number = sequence_number - 1;
+
+        let start = (sequence_number as usize - 1) * LONG_NAME_CHARS_PER_ENTRY;
+        for (i, &offset) in LONG_NAME_CHAR_OFFSETS.iter().enumerate() {
+            let c = read_u16(entry, offset);
+            // Names are null-terminated unless they exactly fill their last entry
+            if c == 0x0000 {
+                self.len = self.len.min(start + i);
+            }
+            self.chars[start + i] = c;
+        }
+        self.len = self.len.min(MAX_LONG_NAME_LEN);
+    }
+
+    fn matches(&self, short_name: &[u8], name: &str) -> bool {
+        self.is_valid
+            && self.next_sequence_number == 0
+            && self.checksum == short_name_checksum(short_name)
+            && self.len == name.chars().count()
+            && self.chars[..self.len].iter().zip(name.chars()).all(|(&c, name_char)| long_name_char_matches(c, name_char))
+    }
+}
+
+fn trim_short_name_part(part: &[u8]) -> &[u8] {
+    let len = part.iter().rposition(|&c| c != b' ').map(|i| i + 1).unwrap_or(0);
+    &part[..len]
+}
+
+fn short_name_matches(short_name: &[u8], name: &str) -> bool {
+    let (base, ext) = short_name.split_at(8);
+    let base = trim_short_name_part(base);
+    let ext = trim_short_name_part(ext);
+
+    let (name_base, name_ext) = match name.rfind('.') {
+        // "." and ".." are stored as-is, without an extension
+        Some(i) if name != "." && name != ".." => (&name[..i], &name[i + 1..]),
+        _ => (name, ""),
+    };
+    // 0x05 stands in for a leading 0xe5, which would otherwise mark the entry as deleted
+    let base_char = |i: usize| if i == 0 && base[0] == 0x05 { 0xe5 } else { base[i] };
+    base.len() == name_base.len()
+        && ext.len() == name_ext.len()
+        && (0..base.len()).all(|i| base_char(i).eq_ignore_ascii_case(&name_base.as_bytes()[i]))
+        && ext.eq_ignore_ascii_case(name_ext.as_bytes())
+}
+
+pub struct File {
+    first_cluster: u32,
+    size: u32,
+    position: u32,
+    // The cluster containing position, and its index in the file's chain
+    cluster: u32,
+    cluster_index: u32,
+}
+
+impl File {
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn position(&self) -> u32 {
+        self.position
+    }
+
+    // Positions past the end are clamped to the end
+    pub fn seek(&mut self, position: u32) {
+        self.position = position.min(self.size);
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.position == self.size
+    }
+}
+
+// Read-only. A single block is cached, so reading sequentially in small chunks doesn't hit the device for every chunk.
+pub struct FileSystem<D: BlockDevice> {
+    device: D,
+
+    block: [u8; BLOCK_SIZE],
+    block_index: Option<u32>,
+
+    fat_start: u32,
+    data_start: u32,
+    blocks_per_cluster_bits: u32,
+    root_cluster: u32,
+}
+
+impl<D: BlockDevice> FileSystem<D> {
+    // Accepts either a disk with an MBR (the first FAT32 partition is used) or a bare volume
+    pub fn mount(device: D) -> Result<FileSystem<D>, Error<D::Error>> {
+        let mut ret = FileSystem {
+            device,
+
+            block: [0; BLOCK_SIZE],
+            block_index: None,
+
+            fat_start: 0,
+            data_start: 0,
+            blocks_per_cluster_bits: 0,
+            root_cluster: 0,
+        };
+
+        ret.load_block(0)?;
+        if ret.block[BOOT_SIGNATURE_OFFSET..BOOT_SIGNATURE_OFFSET + 2] != BOOT_SIGNATURE {
+            return Err(Error::InvalidFileSystem);
+        }
+        let volume_start = if &ret.block[FS_TYPE_OFFSET..FS_TYPE_OFFSET + FS_TYPE.len()] == FS_TYPE {
+            0
+        } else {
+            (0..NUM_PARTITION_ENTRIES)
+                .map(|i| &ret.block[PARTITION_TABLE_OFFSET + i * PARTITION_ENTRY_LEN..][..PARTITION_ENTRY_LEN])
+                .find(|entry| entry[4] == PARTITION_TYPE_FAT32_CHS || entry[4] == PARTITION_TYPE_FAT32_LBA)
+                .map(|entry| read_u32(entry, 8))
+                .ok_or(Error::InvalidFileSystem)?
+        };
+
+        ret.load_block(volume_start)?;
+        let b = &ret.block;
+        let bytes_per_block = read_u16(b, 11);
+        let blocks_per_cluster = b[13];
+        let num_reserved_blocks = read_u16(b, 14);
+        let num_fats = b[16];
+        let fat_size_16 = read_u16(b, 22);
+        let fat_size = read_u32(b, 36);
+        let root_cluster = read_u32(b, 44);
+        if b[BOOT_SIGNATURE_OFFSET..BOOT_SIGNATURE_OFFSET + 2] != BOOT_SIGNATURE
+            || bytes_per_block as usize != BLOCK_SIZE
+            || !blocks_per_cluster.is_power_of_two()
+            || num_fats == 0
+            || fat_size_16 != 0
+            || root_cluster < MIN_CLUSTER
+        {
+            return Err(Error::InvalidFileSystem);
+        }
+
+        ret.fat_start = volume_start + num_reserved_blocks as u32;
+        ret.data_start = ret.fat_start + num_fats as u32 * fat_size;
+        ret.blocks_per_cluster_bits = blocks_per_cluster.trailing_zeros();
+        ret.root_cluster = root_cluster;
+
+        Ok(ret)
+    }
+
+    pub fn into_device(self) -> D {
+        self.device
+    }
+
+    // Paths are relative to the root directory, with components separated by '/'. Names are matched against both long
+    //  and short (8.3) names, ignoring ASCII case.
+    pub fn open(&mut self, path: &str) -> Result<File, Error<D::Error>> {
+        let mut entry = DirEntry {
+            first_cluster: self.root_cluster,
+            size: 0,
+            is_directory: true,
+        };
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if !entry.is_directory {
+                return Err(Error::NotADirectory);
+            }
+            entry = self.find_entry(entry.first_cluster, name)?;
+        }
+        if entry.is_directory {
+            return Err(Error::NotAFile);
+        }
+
+        Ok(File {
+            first_cluster: entry.first_cluster,
+            size: entry.size,
+            position: 0,
+            cluster: entry.first_cluster,
+            cluster_index: 0,
+        })
+    }
+
+    // Returns the number of bytes read, which is only less than buf.len() at the end of the file
+    pub fn read(&mut self, file: &mut File, buf: &mut [u8]) -> Result<usize, Error<D::Error>> {
+        let cluster_bits = self.blocks_per_cluster_bits + BLOCK_SIZE.trailing_zeros();
+
+        let len = buf.len().min((file.size - file.position) as usize);
+        let mut num_bytes_read = 0;
+        while num_bytes_read < len {
+            // Seeking backwards means walking the chain again from the start
+            let cluster_index = file.position >> cluster_bits;
+            if cluster_index < file.cluster_index {
+                file.cluster = file.first_cluster;
+                file.cluster_index = 0;
+            }
+            while file.cluster_index < cluster_index {
+                file.cluster = self.next_cluster(file.cluster)?.ok_or(Error::InvalidFileSystem)?;
+                file.cluster_index += 1;
+            }
+
+            let cluster_offset = file.position & ((1 << cluster_bits) - 1);
+            let block_index = self.cluster_block(file.cluster)? + (cluster_offset / BLOCK_SIZE as u32);
+            let block_offset = cluster_offset as usize % BLOCK_SIZE;
+            let chunk_len = (BLOCK_SIZE - block_offset).min(len - num_bytes_read);
+            let dest = &mut buf[num_bytes_read..num_bytes_read + chunk_len];
+            if chunk_len == BLOCK_SIZE {
+                // Whole blocks can skip the cache
+                self.device.read_block(block_index, dest.try_into().unwrap())?;
+            } else {
+                self.load_block(block_index)?;
+                dest.copy_from_slice(&self.block[block_offset..block_offset + chunk_len]);
+            }
+
+            file.position += chunk_len as u32;
+            num_bytes_read += chunk_len;
+        }
+
+        Ok(num_bytes_read)
+    }
+
+    fn load_block(&mut self, index: u32) -> Result<(), Error<D::Error>> {
+        if self.block_index != Some(index) {
+            // Invalidate first in case the read fails partway through
+            self.block_index = None;
+            self.device.read_block(index, &mut self.block)?;
+            self.block_index = Some(index);
+        }
+        Ok(())
+    }
+
+    fn cluster_block(&self, cluster: u32) -> Result<u32, Error<D::Error>> {
+        if cluster < MIN_CLUSTER {
+            return Err(Error::InvalidFileSystem);
+        }
+        Ok(self.data_start + ((cluster - MIN_CLUSTER) << self.blocks_per_cluster_bits))
+    }
+
+    // None at the end of the chain
+    fn next_cluster(&mut self, cluster: u32) -> Result<Option<u32>, Error<D::Error>> {
+        let entries_per_block = (BLOCK_SIZE / 4) as u32;
+        self.load_block(self.fat_start + cluster / entries_per_block)?;
+        let next = read_u32(&self.block, (cluster % entries_per_block) as usize * 4) & CLUSTER_MASK;
+        if next >= END_OF_CHAIN {
+            Ok(None)
+        } else if next < MIN_CLUSTER {
+            Err(Error::InvalidFileSystem)
+        } else {
+            Ok(Some(next))
+        }
+    }
+
+    fn find_entry(&mut self, dir_cluster: u32, name: &str) -> Result<DirEntry, Error<D::Error>> {
+        let mut long_name = LongName::new();
+        let mut cluster = dir_cluster;
+        loop {
+            let first_block = self.cluster_block(cluster)?;
+            for block_index in first_block..first_block + (1 << self.blocks_per_cluster_bits) {
+                self.load_block(block_index)?;
+                for entry in self.block.chunks_exact(DIR_ENTRY_LEN) {
+                    let attr = entry[11];
+                    match entry[0] {
+                        DIR_ENTRY_END => return Err(Error::NotFound),
+                        DIR_ENTRY_DELETED => long_name.is_valid = false,
+                        _ if attr == ATTR_LONG_NAME => long_name.push(entry),
+                        _ if (attr & ATTR_VOLUME_ID) != 0 => long_name.is_valid = false,
+                        _ => {
+                            let short_name = &entry[..11];
+                            if long_name.matches(short_name, name) || short_name_matches(short_name, name) {
+                                let first_cluster = ((read_u16(entry, 20) as u32) << 16) | read_u16(entry, 26) as u32;
+                                let is_directory = (attr & ATTR_DIRECTORY) != 0;
+                                return Ok(DirEntry {
+                                    // ".." entries use 0 to refer to the root directory
+                                    first_cluster: if is_directory && first_cluster == 0 { self.root_cluster } else { first_cluster },
+                                    size: read_u32(entry, 28),
+                                    is_directory,
+                                });
+                            }
+                            long_name.is_valid = false;
+                        }
+                    }
+                }
+            }
+
+            cluster = match self.next_cluster(cluster)? {
+                Some(next) => next,
+                None => return Err(Error::NotFound),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::string::String;
+    use std::vec::Vec;
+
+    const NUM_CLUSTERS: u32 = 512;
+    const NUM_RESERVED_BLOCKS: u32 = 4;
+    const NUM_FATS: u32 = 2;
+    const MBR_VOLUME_START: u32 = 8;
+
+    impl BlockDevice for Vec<u8> {
+        type Error = ();
+
+        fn read_block(&mut self, index: u32, data: &mut [u8; BLOCK_SIZE]) -> Result<(), ()> {
+            let offset = index as usize * BLOCK_SIZE;
+            data.copy_from_slice(self.get(offset..offset + BLOCK_SIZE).ok_or(())?);
+            Ok(())
+        }
+    }
+
+    // Just enough of a formatter to lay out test volumes, including fragmented files and long names
+    struct ImageBuilder {
+        image: Vec<u8>,
+        volume_start: u32,
+        blocks_per_cluster: u32,
+        fat_size: u32,
+        data_start: u32,
+        is_cluster_used: Vec<bool>,
+    }
+
+    impl ImageBuilder {
+        fn new(with_mbr: bool, blocks_per_cluster: u32) -> ImageBuilder {
+            let volume_start = if with_mbr { MBR_VOLUME_START } else { 0 };
+            let fat_size = ((NUM_CLUSTERS + MIN_CLUSTER) * 4).div_ceil(BLOCK_SIZE as u32);
+            let data_start = volume_start + NUM_RESERVED_BLOCKS + NUM_FATS * fat_size;
+            let num_blocks = data_start + NUM_CLUSTERS * blocks_per_cluster;
+            let mut is_cluster_used = vec![false; (NUM_CLUSTERS + MIN_CLUSTER) as usize];
+            is_cluster_used[0] = true;
+            is_cluster_used[1] = true;
+            let mut ret = ImageBuilder {
+                image: vec![0; num_blocks as usize * BLOCK_SIZE],
+                volume_start,
+                blocks_per_cluster,
+                fat_size,
+                data_start,
+                is_cluster_used,
+            };
+
+            if with_mbr {
+                let entry = &mut ret.image[PARTITION_TABLE_OFFSET + PARTITION_ENTRY_LEN..][..PARTITION_ENTRY_LEN];
+                entry[4] = PARTITION_TYPE_FAT32_LBA;
+                entry[8..12].copy_from_slice(&volume_start.to_le_bytes());
+                entry[12..16].copy_from_slice(&(num_blocks - volume_start).to_le_bytes());
+                ret.image[BOOT_SIGNATURE_OFFSET..BOOT_SIGNATURE_OFFSET + 2].copy_from_slice(&BOOT_SIGNATURE);
+            }
+
+            ret
+        }
+
+        fn set_fat_entry(&mut self, cluster: u32, value: u32) {
+            for i in 0..NUM_FATS {
+                let offset = ((self.volume_start + NUM_RESERVED_BLOCKS + i * self.fat_size) as usize * BLOCK_SIZE) + cluster as usize * 4;
+                self.image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        // Where the next chain will start
+        fn first_free_cluster(&self) -> u32 {
+            (MIN_CLUSTER..).find(|&c| !self.is_cluster_used[c as usize]).unwrap()
+        }
+
+        // Each cluster after the first is at least step clusters after the previous one, so step > 1 fragments files
+        fn write_chain(&mut self, data: &[u8], step: u32) -> u32 {
+            let cluster_len = self.blocks_per_cluster as usize * BLOCK_SIZE;
+            let num_clusters = data.len().div_ceil(cluster_len).max(1);
+            let mut chain: Vec<u32> = Vec::new();
+            for _ in 0..num_clusters {
+                let cluster = match chain.last() {
+                    Some(&prev) => (prev + step..).find(|&c| !self.is_cluster_used[c as usize]).unwrap(),
+                    _ => self.first_free_cluster(),
+                };
+                self.is_cluster_used[cluster as usize] = true;
+                chain.push(cluster);
+            }
+            for (i, &cluster) in chain.iter().enumerate() {
+                let next = chain.get(i + 1).copied().unwrap_or(CLUSTER_MASK);
+                self.set_fat_entry(cluster, next);
+
+                let chunk = data.chunks(cluster_len).nth(i).unwrap_or(&[]);
+                let offset = (self.data_start + (cluster - MIN_CLUSTER) * self.blocks_per_cluster) as usize * BLOCK_SIZE;
+                self.image[offset..offset + chunk.len()].copy_from_slice(chunk);
+            }
+            chain[0]
+        }
+
+        fn add_entry(entries: &mut Vec<u8>, long_name: Option<&str>, short_name: &[u8; 11], attr: u8, first_cluster: u32, size: u32) {
+            if let Some(long_name) = long_name {
+                let chars = long_name.encode_utf16().collect::<Vec<_>>();
+                let num_entries = chars.len().div_ceil(LONG_NAME_CHARS_PER_ENTRY);
+                for sequence_number in (1..=num_entries).rev() {
+                    let mut entry = [0; DIR_ENTRY_LEN];
+                    entry[0] = sequence_number as u8 | if sequence_number == num_entries { LONG_NAME_LAST_ENTRY } else { 0 };
+                    entry[11] = ATTR_LONG_NAME;
+                    entry[13] = short_name_checksum(short_name);
+                    for (i, &offset) in LONG_NAME_CHAR_OFFSETS.iter().enumerate() {
+                        let index = (sequence_number - 1) * LONG_NAME_CHARS_PER_ENTRY + i;
+                        // Null-terminated, then padded with 0xffff
+                        let c = match index {
+                            _ if index < chars.len() => chars[index],
+                            _ if index == chars.len() => 0x0000,
+                            _ => 0xffff,
+                        };
+                        entry[offset..offset + 2].copy_from_slice(&c.to_le_bytes());
+                    }
+                    entries.extend_from_slice(&entry);
+                }
+            }
+
+            let mut entry = [0; DIR_ENTRY_LEN];
+            entry[..11].copy_from_slice(short_name);
+            entry[11] = attr;
+            entry[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+            entry[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+            entry[28..32].copy_from_slice(&size.to_le_bytes());
+            entries.extend_from_slice(&entry);
+        }
+
+        fn finish(mut self, root_entries: &[u8]) -> Vec<u8> {
+            let root_cluster = self.write_chain(root_entries, 1);
+
+            let b = &mut self.image[self.volume_start as usize * BLOCK_SIZE..][..BLOCK_SIZE];
+            b[0..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+            b[11..13].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes());
+            b[13] = self.blocks_per_cluster as _;
+            b[14..16].copy_from_slice(&(NUM_RESERVED_BLOCKS as u16).to_le_bytes());
+            b[16] = NUM_FATS as _;
+            b[36..40].copy_from_slice(&self.fat_size.to_le_bytes());
+            b[44..48].copy_from_slice(&root_cluster.to_le_bytes());
+            b[FS_TYPE_OFFSET..FS_TYPE_OFFSET + FS_TYPE.len()].copy_from_slice(FS_TYPE);
+            b[BOOT_SIGNATURE_OFFSET..BOOT_SIGNATURE_OFFSET + 2].copy_from_slice(&BOOT_SIGNATURE);
+
+            // Media descriptor and end of chain markers
+            self.set_fat_entry(0, 0x0ffffff8);
+            self.set_fat_entry(1, CLUSTER_MASK);
+
+            self.image
+        }
+    }
+
+    fn file_data(len: usize, seed: u32) -> Vec<u8> {
+        (0..len as u32).map(|i| (i.wrapping_mul(0x9e3779b1).wrapping_add(seed) >> 24) as u8).collect()
+    }
+
+    const HELLO_DATA: &[u8] = b"Hello, world!";
+    const LONG_NAME: &str = "A long file name.bin";
+    const LONG_NAME_LEN: usize = 5000;
+    const MUSIC_LEN: usize = 1234;
+    const EMPTY_NAME: &str = "empty";
+
+    // /HELLO.TXT, /A long file name.bin (fragmented), /empty, /assets/music.mod, and some entries that should be skipped
+    fn build_image(with_mbr: bool, blocks_per_cluster: u32) -> Vec<u8> {
+        let mut b = ImageBuilder::new(with_mbr, blocks_per_cluster);
+
+        let hello_cluster = b.write_chain(HELLO_DATA, 1);
+        let long_name_cluster = b.write_chain(&file_data(LONG_NAME_LEN, 0), 3);
+        let music_cluster = b.write_chain(&file_data(MUSIC_LEN, 1), 1);
+
+        let mut assets_entries = Vec::new();
+        ImageBuilder::add_entry(&mut assets_entries, None, b".          ", ATTR_DIRECTORY, b.first_free_cluster(), 0);
+        ImageBuilder::add_entry(&mut assets_entries, None, b"..         ", ATTR_DIRECTORY, 0, 0);
+        ImageBuilder::add_entry(&mut assets_entries, Some("music.mod"), b"MUSIC   MOD", 0x20, music_cluster, MUSIC_LEN as _);
+        let assets_cluster = b.write_chain(&assets_entries, 1);
+
+        let mut root_entries = Vec::new();
+        ImageBuilder::add_entry(&mut root_entries, None, b"TEST VOLUME", ATTR_VOLUME_ID, 0, 0);
+        // A deleted long name shouldn't attach itself to the entry after it
+        let deleted_start = root_entries.len();
+        ImageBuilder::add_entry(&mut root_entries, Some("deleted.txt"), b"DELETED TXT", 0x20, hello_cluster, 0);
+        for entry in root_entries[deleted_start..].chunks_exact_mut(DIR_ENTRY_LEN) {
+            entry[0] = DIR_ENTRY_DELETED;
+        }
+        ImageBuilder::add_entry(&mut root_entries, None, b"HELLO   TXT", 0x20, hello_cluster, HELLO_DATA.len() as _);
+        ImageBuilder::add_entry(&mut root_entries, Some(LONG_NAME), b"ALONGF~1BIN", 0x20, long_name_cluster, LONG_NAME_LEN as _);
+        ImageBuilder::add_entry(&mut root_entries, Some(EMPTY_NAME), b"EMPTY      ", 0x20, 0, 0);
+        ImageBuilder::add_entry(&mut root_entries, None, b"ASSETS     ", ATTR_DIRECTORY, assets_cluster, 0);
+        // Enough filler entries to spill the root directory over into more clusters
+        for i in 0..40 {
+            let short_name = format!("FILLER{:02}   ", i);
+            ImageBuilder::add_entry(&mut root_entries, Some(&format!("filler file {}", i)), short_name.as_bytes().try_into().unwrap(), 0x20, 0, 0);
+        }
+        ImageBuilder::add_entry(&mut root_entries, None, b"LAST    TXT", 0x20, hello_cluster, HELLO_DATA.len() as _);
+
+        b.finish(&root_entries)
+    }
+
+    fn read_to_end<D: BlockDevice>(fs: &mut FileSystem<D>, file: &mut File, chunk_len: usize) -> Vec<u8> where D::Error: core::fmt::Debug {
+        let mut ret = Vec::new();
+        let mut chunk = vec![0; chunk_len];
+        loop {
+            let len = fs.read(file, &mut chunk).unwrap();
+            ret.extend_from_slice(&chunk[..len]);
+            if len < chunk_len {
+                assert!(file.is_eof());
+                break ret;
+            }
+        }
+    }
+
+    fn read_files(with_mbr: bool, blocks_per_cluster: u32) {
+        let mut fs = FileSystem::mount(build_image(with_mbr, blocks_per_cluster)).unwrap();
+
+        let mut file = fs.open("HELLO.TXT").unwrap();
+        assert_eq!(file.size(), HELLO_DATA.len() as u32);
+        assert_eq!(read_to_end(&mut fs, &mut file, 5), HELLO_DATA);
+
+        // Short names match regardless of case, and paths can be absolute
+        let mut file = fs.open("/hello.txt").unwrap();
+        assert_eq!(read_to_end(&mut fs, &mut file, 512), HELLO_DATA);
+
+        // Odd chunk lengths cross block and cluster boundaries at different points
+        for chunk_len in [1, 37, 512, 1000, LONG_NAME_LEN * 2] {
+            let mut file = fs.open(LONG_NAME).unwrap();
+            assert_eq!(read_to_end(&mut fs, &mut file, chunk_len), file_data(LONG_NAME_LEN, 0));
+        }
+        let mut file = fs.open("a LONG file NAME.BIN").unwrap();
+        assert_eq!(read_to_end(&mut fs, &mut file, 100), file_data(LONG_NAME_LEN, 0));
+        let mut file = fs.open("ALONGF~1.BIN").unwrap();
+        assert_eq!(read_to_end(&mut fs, &mut file, 100), file_data(LONG_NAME_LEN, 0));
+
+        let mut file = fs.open("assets/music.mod").unwrap();
+        assert_eq!(read_to_end(&mut fs, &mut file, 300), file_data(MUSIC_LEN, 1));
+        let mut file = fs.open("/ASSETS/../assets/./MUSIC.MOD").unwrap();
+        assert_eq!(read_to_end(&mut fs, &mut file, 300), file_data(MUSIC_LEN, 1));
+
+        let mut file = fs.open(EMPTY_NAME).unwrap();
+        assert_eq!(file.size(), 0);
+        assert!(read_to_end(&mut fs, &mut file, 16).is_empty());
+
+        // Found by walking the directory's cluster chain
+        let mut file = fs.open("last.txt").unwrap();
+        assert_eq!(read_to_end(&mut fs, &mut file, 16), HELLO_DATA);
+    }
+
+    #[test]
+    fn read_files_mbr() {
+        read_files(true, 1);
+    }
+
+    #[test]
+    fn read_files_bare_volume() {
+        read_files(false, 4);
+    }
+
+    #[test]
+    fn seek() {
+        let mut fs = FileSystem::mount(build_image(true, 1)).unwrap();
+        let expected = file_data(LONG_NAME_LEN, 0);
+
+        let mut file = fs.open(LONG_NAME).unwrap();
+        let mut buf = [0; 100];
+        for position in [4000, 10, 2047, 2048, 0, LONG_NAME_LEN as u32 - 50] {
+            file.seek(position);
+            let len = fs.read(&mut file, &mut buf).unwrap();
+            let expected = &expected[position as usize..(position as usize + buf.len()).min(LONG_NAME_LEN)];
+            assert_eq!(&buf[..len], expected);
+        }
+
+        file.seek(LONG_NAME_LEN as u32 + 1000);
+        assert_eq!(file.position(), LONG_NAME_LEN as u32);
+        assert_eq!(fs.read(&mut file, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(FileSystem::mount(vec![0; BLOCK_SIZE * 16]).err(), Some(Error::InvalidFileSystem));
+        assert_eq!(FileSystem::mount(Vec::new()).err(), Some(Error::Device(())));
+
+        let mut fs = FileSystem::mount(build_image(true, 1)).unwrap();
+        assert_eq!(fs.open("nope.txt").err(), Some(Error::NotFound));
+        assert_eq!(fs.open("deleted.txt").err(), Some(Error::NotFound));
+        assert_eq!(fs.open("assets/hello.txt").err(), Some(Error::NotFound));
+        assert_eq!(fs.open("assets").err(), Some(Error::NotAFile));
+        assert_eq!(fs.open("").err(), Some(Error::NotAFile));
+        assert_eq!(fs.open("hello.txt/nope.txt").err(), Some(Error::NotADirectory));
+        // Partial names don't match
+        assert_eq!(fs.open("A long file").err(), Some(Error::NotFound));
+        assert_eq!(fs.open(&(String::from(LONG_NAME) + "x")).err(), Some(Error::NotFound));
+    }
+}
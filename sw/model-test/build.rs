@@ -3,7 +3,9 @@ use kaze::*;
 use rtl::buster::*;
 use rtl::byte_ram::*;
 use rtl::color_thrust::*;
+use rtl::scanout::*;
 
+use rtl_meta::color_thrust::*;
 use rtl_meta::xenowing::*;
 
 use std::env;
@@ -20,7 +22,35 @@ fn main() -> Result<()> {
 
     let m = p.module("top", "Top");
 
-    let color_thrust = ColorThrust::new("color_thrust", m);
+    // Allows comparing throughput between pixel pipe configurations, eg. `COLOR_THRUST_PIXEL_PIPES=1 cargo run --release sim`
+    println!("cargo:rerun-if-env-changed=COLOR_THRUST_PIXEL_PIPES");
+    let num_pixel_pipes = env::var("COLOR_THRUST_PIXEL_PIPES").map(|x| x.parse().expect("Invalid COLOR_THRUST_PIXEL_PIPES value")).unwrap_or(2);
+
+    // Likewise for tile sizes, eg. `COLOR_THRUST_TILE_WIDTH=64 COLOR_THRUST_TILE_HEIGHT=32 cargo run --release sim`
+    let tile_dim_bits = |var_name| {
+        println!("cargo:rerun-if-env-changed={}", var_name);
+        let dim_bits = env::var(var_name).map(|x| {
+            let dim: u32 = x.parse().unwrap_or_else(|_| panic!("Invalid {} value", var_name));
+            if !dim.is_power_of_two() {
+                panic!("Invalid {} value; must be a power of two", var_name);
+            }
+            dim.trailing_zeros()
+        }).unwrap_or(TILE_DIM_BITS_DEFAULT);
+        if !(TILE_DIM_BITS_MIN..=TILE_DIM_BITS_MAX).contains(&dim_bits) {
+            panic!("Invalid {} value; must be between {} and {}", var_name, 1 << TILE_DIM_BITS_MIN, 1 << TILE_DIM_BITS_MAX);
+        }
+        // The model device is built with the same tile size as the sim device
+        println!("cargo:rustc-env={}_BITS={}", var_name, dim_bits);
+        dim_bits
+    };
+    let tile_width_bits = tile_dim_bits("COLOR_THRUST_TILE_WIDTH");
+    let tile_height_bits = tile_dim_bits("COLOR_THRUST_TILE_HEIGHT");
+
+    let color_thrust = ColorThrust::new("color_thrust", num_pixel_pipes, tile_width_bits, tile_height_bits, m);
+
+    // Same pixel clock divider as xenowing, so flips land at the rate they would on hw. The video outputs are left
+    //  unconnected; frames are shown by reading the back buffer back instead.
+    let scanout = Scanout::new("scanout", 2, m);
 
     let mem = ByteRam::new("mem", SYSTEM_BUS_ADDR_BITS, SYSTEM_BUS_ADDR_BITS, m);
 
@@ -28,11 +58,14 @@ fn main() -> Result<()> {
     color_thrust.reg_port.forward("reg", m);
     color_thrust.color_buffer_port.forward("color_buffer", m);
     color_thrust.depth_buffer_port.forward("depth_buffer", m);
+    color_thrust.stencil_buffer_port.forward("stencil_buffer", m);
+    scanout.reg_port.forward("scanout_reg", m);
 
-    let mem_crossbar = Crossbar::new("mem_crossbar", 2, 1, SYSTEM_BUS_ADDR_BITS, 0, 128, 5, m);
+    let mem_crossbar = Crossbar::new("mem_crossbar", 3, 1, SYSTEM_BUS_ADDR_BITS, 0, 128, 5, m);
 
     mem_crossbar.replica_ports[0].forward("mem", m);
     color_thrust.tex_cache_system_port.connect(&mem_crossbar.replica_ports[1]);
+    scanout.mem_port.connect(&mem_crossbar.replica_ports[2]);
 
     mem_crossbar.primary_ports[0].connect(&mem.client_port);
 
@@ -21,9 +21,13 @@ use std::env;
 fn main() {
     let device_type = env::args().skip(1).nth(0).expect("No device type argument provided");
 
-    let mut device: Box<dyn Device> = match device_type.as_str() {
-        "model" => Box::new(model_device::ModelDevice::new()),
-        "sim" => Box::new(sim_device::SimDevice::new()),
+    let (mut device, env): (Box<dyn Device>, _) = match device_type.as_str() {
+        "model" => (Box::new(model_device::ModelDevice::new()), ModelEnvironment::new()),
+        "sim" => {
+            let device = sim_device::SimDevice::new();
+            let env = ModelEnvironment::with_cycles(device.cycles());
+            (Box::new(device), env)
+        }
         _ => panic!("Invalid device type argument")
     };
 
@@ -34,7 +38,6 @@ fn main() {
     }).unwrap();
 
     let mut c = Context::new(&mut *device);
-    let env = ModelEnvironment::new();
     let mut strugl_test = StruglTest::new(&mut c, &env);
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
@@ -46,5 +49,7 @@ fn main() {
             flipped_buffer.extend_from_slice(&back_buffer[(y * WIDTH) as usize..((y + 1) * WIDTH) as usize]);
         }
         window.update_with_buffer(&flipped_buffer, WIDTH as _, HEIGHT as _).unwrap();
+
+        c.present();
     }
 }
@@ -1,8 +1,10 @@
 mod bit_pusher;
 mod color_thrust;
+mod scanout;
 
 use bit_pusher::*;
 use color_thrust::*;
+use scanout::*;
 
 use crate::mem_allocator::*;
 
@@ -11,6 +13,7 @@ use abstract_device::*;
 pub struct ModelDevice {
     bit_pusher: BitPusher,
     color_thrust: ColorThrust,
+    scanout: Scanout,
 
     mem: Box<[u128]>,
     mem_allocator: MemAllocator,
@@ -20,7 +23,11 @@ impl ModelDevice {
     pub fn new() -> ModelDevice {
         ModelDevice {
             bit_pusher: BitPusher::new(),
-            color_thrust: ColorThrust::new(),
+            color_thrust: ColorThrust::new(
+                env!("COLOR_THRUST_TILE_WIDTH_BITS").parse().unwrap(),
+                env!("COLOR_THRUST_TILE_HEIGHT_BITS").parse().unwrap(),
+            ),
+            scanout: Scanout::new(),
 
             mem: vec![0; MEM_NUM_WORDS as usize].into_boxed_slice(),
             mem_allocator: MemAllocator::new(),
@@ -82,4 +89,20 @@ impl Device for ModelDevice {
     fn color_thrust_read_depth_buffer_word(&mut self, addr: u32) -> u128 {
         self.color_thrust.read_depth_buffer_word(addr)
     }
+
+    fn color_thrust_write_stencil_buffer_word(&mut self, addr: u32, data: u128) {
+        self.color_thrust.write_stencil_buffer_word(addr, data);
+    }
+
+    fn color_thrust_read_stencil_buffer_word(&mut self, addr: u32) -> u128 {
+        self.color_thrust.read_stencil_buffer_word(addr)
+    }
+
+    fn scanout_write_reg(&mut self, addr: u32, data: u32) {
+        self.scanout.write_reg(addr, data);
+    }
+
+    fn scanout_read_reg(&mut self, addr: u32) -> u32 {
+        self.scanout.read_reg(addr)
+    }
 }
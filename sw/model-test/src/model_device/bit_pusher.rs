@@ -6,6 +6,12 @@ pub struct BitPusher {
     direction: Direction,
     num_words: u32,
 
+    fill_value: u32,
+    color_key_enable: bool,
+    blend_enable: bool,
+    logic_op: u32,
+    color_key: u32,
+
     sys_addr_unit: AddrUnit,
     mem_addr_unit: AddrUnit,
 }
@@ -16,6 +22,12 @@ impl BitPusher {
             direction: Direction::Mem2Sys,
             num_words: 0,
 
+            fill_value: 0,
+            color_key_enable: false,
+            blend_enable: false,
+            logic_op: REG_PIXEL_OP_SETTINGS_LOGIC_OP_COPY,
+            color_key: 0,
+
             sys_addr_unit: AddrUnit::new(),
             mem_addr_unit: AddrUnit::new(),
         }
@@ -25,11 +37,7 @@ impl BitPusher {
         match addr {
             REG_START_ADDR => self.transfer(mem, color_thrust),
             REG_DIRECTION_ADDR => {
-                self.direction = match data & REG_DIRECTION_BITS {
-                    REG_DIRECTION_MEM2SYS => Direction::Mem2Sys,
-                    REG_DIRECTION_SYS2MEM => Direction::Sys2Mem,
-                    _ => unreachable!()
-                }
+                self.direction = Direction::from_bits(data);
             }
             REG_NUM_WORDS_ADDR => {
                 self.num_words = data;
@@ -52,6 +60,18 @@ impl BitPusher {
             REG_MEM_SPAN_STRIDE_ADDR => {
                 self.mem_addr_unit.span_stride = data;
             }
+            REG_DESCRIPTOR_ADDR_ADDR => self.execute_chain(data >> 4, mem, color_thrust),
+            REG_FILL_VALUE_ADDR => {
+                self.fill_value = data;
+            }
+            REG_PIXEL_OP_SETTINGS_ADDR => {
+                self.color_key_enable = (data & (1 << REG_PIXEL_OP_SETTINGS_COLOR_KEY_ENABLE_BIT)) != 0;
+                self.blend_enable = (data & (1 << REG_PIXEL_OP_SETTINGS_BLEND_ENABLE_BIT)) != 0;
+                self.logic_op = (data >> REG_PIXEL_OP_SETTINGS_LOGIC_OP_BIT_OFFSET) & ((1 << REG_PIXEL_OP_SETTINGS_LOGIC_OP_BITS) - 1);
+            }
+            REG_COLOR_KEY_ADDR => {
+                self.color_key = data & ((1 << REG_COLOR_KEY_BITS) - 1);
+            }
             _ => panic!("Unrecognized addr: {}", addr)
         }
     }
@@ -63,6 +83,35 @@ impl BitPusher {
         0
     }
 
+    fn execute_chain(&mut self, mut descriptor_addr: u32, mem: &mut [u128], color_thrust: &mut ColorThrust) {
+        loop {
+            let word0 = mem[descriptor_addr as usize];
+            let word1 = mem[(descriptor_addr + 1) as usize];
+            let field = |word: u128, index: u32| (word >> (index * 32)) as u32;
+
+            self.sys_addr_unit.addr = field(word0, 0) >> 4;
+            self.fill_value = field(word0, 0);
+            self.sys_addr_unit.words_per_span = field(word0, 1);
+            self.sys_addr_unit.span_stride = field(word0, 2);
+            self.num_words = field(word0, 3);
+
+            self.mem_addr_unit.addr = field(word1, 0) >> 4;
+            self.mem_addr_unit.words_per_span = field(word1, 1);
+            self.mem_addr_unit.span_stride = field(word1, 2);
+            let control = field(word1, 3);
+            self.direction = Direction::from_bits(control >> DESCRIPTOR_CONTROL_DIRECTION_BIT);
+
+            self.transfer(mem, color_thrust);
+
+            mem[(descriptor_addr + DESCRIPTOR_STATUS_WORD) as usize] = DESCRIPTOR_STATUS_COMPLETE as _;
+
+            if (control & (1 << DESCRIPTOR_CONTROL_LAST_BIT)) != 0 {
+                break;
+            }
+            descriptor_addr = control >> DESCRIPTOR_CONTROL_NEXT_ADDR_BIT_OFFSET;
+        }
+    }
+
     fn transfer(&mut self, mem: &mut [u128], color_thrust: &mut ColorThrust) {
         self.sys_addr_unit.start_transfer();
         self.mem_addr_unit.start_transfer();
@@ -78,6 +127,7 @@ impl BitPusher {
                     match sys_base {
                         0x04 => color_thrust.write_color_buffer_word(sys_offset, word), // TODO: Proper constant
                         0x05 => color_thrust.write_depth_buffer_word(sys_offset, word), // TODO: Proper constant
+                        0x07 => color_thrust.write_stencil_buffer_word(sys_offset, word), // TODO: Proper constant
                         _ => panic!("Unrecognized sys addr: 0x{:08x}", self.sys_addr_unit.addr << 4)
                     }
                 }
@@ -85,11 +135,20 @@ impl BitPusher {
                     let word = match sys_base {
                         0x04 => color_thrust.read_color_buffer_word(sys_offset), // TODO: Proper constant
                         0x05 => color_thrust.read_depth_buffer_word(sys_offset), // TODO: Proper constant
+                        0x07 => color_thrust.read_stencil_buffer_word(sys_offset), // TODO: Proper constant
                         _ => panic!("Unrecognized sys addr: 0x{:08x}", self.sys_addr_unit.addr << 4)
                     };
 
                     mem[self.mem_addr_unit.addr as usize] = word;
                 }
+                Direction::Mem2Mem | Direction::Fill => {
+                    let src = match self.direction {
+                        Direction::Fill => (0..4).fold(0, |acc, i| acc | ((self.fill_value as u128) << (i * 32))),
+                        _ => mem[self.sys_addr_unit.addr as usize],
+                    };
+                    let dst = &mut mem[self.mem_addr_unit.addr as usize];
+                    *dst = self.apply_pixel_ops(src, *dst);
+                }
             }
 
             self.sys_addr_unit.step();
@@ -101,11 +160,56 @@ impl BitPusher {
             }
         }
     }
+
+    fn apply_pixel_ops(&self, src: u128, dst: u128) -> u128 {
+        let mut ret = 0;
+        for i in 0..4 {
+            let src_pixel = (src >> (i * 32)) as u32;
+            let dst_pixel = (dst >> (i * 32)) as u32;
+
+            let pixel = if self.color_key_enable && (src_pixel & 0xffffff) == self.color_key {
+                dst_pixel
+            } else {
+                let blended = if self.blend_enable {
+                    let src_alpha = src_pixel >> 24;
+                    (0..4).fold(0, |acc, comp| {
+                        let src_comp = (src_pixel >> (comp * 8)) & 0xff;
+                        let dst_comp = (dst_pixel >> (comp * 8)) & 0xff;
+                        acc | (((src_comp * src_alpha + dst_comp * (256 - src_alpha)) >> 8) << (comp * 8))
+                    })
+                } else {
+                    src_pixel
+                };
+
+                (0..32).fold(0, |acc, bit| {
+                    let index = (((blended >> bit) & 1) << 1) | ((dst_pixel >> bit) & 1);
+                    acc | (((self.logic_op >> index) & 1) << bit)
+                })
+            };
+
+            ret |= (pixel as u128) << (i * 32);
+        }
+        ret
+    }
 }
 
 enum Direction {
     Mem2Sys,
     Sys2Mem,
+    Mem2Mem,
+    Fill,
+}
+
+impl Direction {
+    fn from_bits(bits: u32) -> Direction {
+        match bits & ((1 << REG_DIRECTION_BITS) - 1) {
+            REG_DIRECTION_MEM2SYS => Direction::Mem2Sys,
+            REG_DIRECTION_SYS2MEM => Direction::Sys2Mem,
+            REG_DIRECTION_MEM2MEM => Direction::Mem2Mem,
+            REG_DIRECTION_FILL => Direction::Fill,
+            _ => unreachable!()
+        }
+    }
 }
 
 struct AddrUnit {
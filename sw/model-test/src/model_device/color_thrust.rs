@@ -1,5 +1,93 @@
 use rtl_meta::color_thrust::*;
 
+enum TextureEnvMode {
+    Modulate,
+    Replace,
+    Decal,
+    Add,
+    Blend,
+    Untextured,
+}
+
+enum DepthFunc {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+enum AlphaFunc {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+enum StencilFunc {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+#[derive(Clone, Copy)]
+enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    Incr,
+    Decr,
+    Invert,
+    IncrWrap,
+    DecrWrap,
+}
+
+impl StencilOp {
+    fn from_reg(value: u32) -> StencilOp {
+        match value & ((1 << REG_STENCIL_OP_BITS) - 1) {
+            REG_STENCIL_OP_KEEP => StencilOp::Keep,
+            REG_STENCIL_OP_ZERO => StencilOp::Zero,
+            REG_STENCIL_OP_REPLACE => StencilOp::Replace,
+            REG_STENCIL_OP_INCR => StencilOp::Incr,
+            REG_STENCIL_OP_DECR => StencilOp::Decr,
+            REG_STENCIL_OP_INVERT => StencilOp::Invert,
+            REG_STENCIL_OP_INCR_WRAP => StencilOp::IncrWrap,
+            REG_STENCIL_OP_DECR_WRAP => StencilOp::DecrWrap,
+            _ => unreachable!()
+        }
+    }
+
+    fn to_reg(self) -> u32 {
+        match self {
+            StencilOp::Keep => REG_STENCIL_OP_KEEP,
+            StencilOp::Zero => REG_STENCIL_OP_ZERO,
+            StencilOp::Replace => REG_STENCIL_OP_REPLACE,
+            StencilOp::Incr => REG_STENCIL_OP_INCR,
+            StencilOp::Decr => REG_STENCIL_OP_DECR,
+            StencilOp::Invert => REG_STENCIL_OP_INVERT,
+            StencilOp::IncrWrap => REG_STENCIL_OP_INCR_WRAP,
+            StencilOp::DecrWrap => REG_STENCIL_OP_DECR_WRAP,
+        }
+    }
+}
+
+enum FogSource {
+    Z,
+    W,
+}
+
 enum TextureFilter {
     Nearest,
     Bilinear,
@@ -17,6 +105,14 @@ enum BlendSrcFactor {
     One,
     SrcAlpha,
     OneMinusSrcAlpha,
+    DstColor,
+    OneMinusDstColor,
+    SrcColor,
+    OneMinusSrcColor,
+    DstAlpha,
+    OneMinusDstAlpha,
+    ConstantColor,
+    OneMinusConstantColor,
 }
 
 enum BlendDstFactor {
@@ -24,21 +120,81 @@ enum BlendDstFactor {
     One,
     SrcAlpha,
     OneMinusSrcAlpha,
+    DstColor,
+    OneMinusDstColor,
+    SrcColor,
+    OneMinusSrcColor,
+    DstAlpha,
+    OneMinusDstAlpha,
+    ConstantColor,
+    OneMinusConstantColor,
+}
+
+enum BlendEquation {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
 }
 
 pub struct ColorThrust {
+    tile_width_bits: u32,
+    tile_height_bits: u32,
+
     color_buffer: Box<[u32]>,
     depth_buffer: Box<[u16]>,
+    stencil_buffer: Box<[u8]>,
 
     depth_test_enable: bool,
     depth_write_mask_enable: bool,
+    depth_func: DepthFunc,
+    hiz_enable: bool,
 
     texture_filter: TextureFilter,
     texture_dim: TextureDim,
     texture_base: u32,
 
+    texture_env_mode: TextureEnvMode,
+    texture_env_color: u32,
+
     blend_src_factor: BlendSrcFactor,
     blend_dst_factor: BlendDstFactor,
+    blend_equation: BlendEquation,
+    blend_color: u32,
+
+    alpha_test_enable: bool,
+    alpha_test_func: AlphaFunc,
+    alpha_test_ref: u32,
+
+    stencil_test_enable: bool,
+    stencil_func: StencilFunc,
+    stencil_sfail_op: StencilOp,
+    stencil_zfail_op: StencilOp,
+    stencil_zpass_op: StencilOp,
+    stencil_ref: u8,
+    stencil_mask: u8,
+    stencil_write_mask: u8,
+
+    fog_enable: bool,
+    fog_source: FogSource,
+    fog_w_shift: u32,
+    fog_color: u32,
+    fog_table: [u8; REG_FOG_TABLE_ENTRIES as usize],
+
+    aa_enable: bool,
+
+    clear_color: u32,
+    clear_depth: u16,
+    clear_stencil: u8,
+
+    z_bounds: u32,
+    depth_bounds: Box<[(u16, u16)]>,
+    hiz_rejected_primitives: u32,
+    hiz_rejected_blocks: u32,
+
+    occlusion_query_active: bool,
+    occlusion_query_passed_samples: u32,
 
     w0_min: u32,
     w0_dx: u32,
@@ -76,20 +232,67 @@ pub struct ColorThrust {
 }
 
 impl ColorThrust {
-    pub fn new() -> ColorThrust {
+    pub fn new(tile_width_bits: u32, tile_height_bits: u32) -> ColorThrust {
+        let tile_pixels = 1 << (tile_width_bits + tile_height_bits);
+        let hiz_tile_blocks = tile_pixels >> (HIZ_BLOCK_DIM_BITS * 2);
+
         ColorThrust {
-            color_buffer: vec![0; TILE_PIXELS as usize].into_boxed_slice(),
-            depth_buffer: vec![0; TILE_PIXELS as usize].into_boxed_slice(),
+            tile_width_bits,
+            tile_height_bits,
+
+            color_buffer: vec![0; tile_pixels].into_boxed_slice(),
+            depth_buffer: vec![0; tile_pixels].into_boxed_slice(),
+            stencil_buffer: vec![0; tile_pixels].into_boxed_slice(),
 
             depth_test_enable: false,
             depth_write_mask_enable: false,
+            depth_func: DepthFunc::Less,
+            hiz_enable: false,
 
             texture_filter: TextureFilter::Nearest,
             texture_dim: TextureDim::X16,
             texture_base: 0,
 
+            texture_env_mode: TextureEnvMode::Modulate,
+            texture_env_color: 0,
+
             blend_src_factor: BlendSrcFactor::One,
             blend_dst_factor: BlendDstFactor::Zero,
+            blend_equation: BlendEquation::Add,
+            blend_color: 0,
+
+            alpha_test_enable: false,
+            alpha_test_func: AlphaFunc::Always,
+            alpha_test_ref: 0,
+
+            stencil_test_enable: false,
+            stencil_func: StencilFunc::Always,
+            stencil_sfail_op: StencilOp::Keep,
+            stencil_zfail_op: StencilOp::Keep,
+            stencil_zpass_op: StencilOp::Keep,
+            stencil_ref: 0,
+            stencil_mask: 0xff,
+            stencil_write_mask: 0xff,
+
+            fog_enable: false,
+            fog_source: FogSource::Z,
+            fog_w_shift: 0,
+            fog_color: 0,
+            fog_table: [0; REG_FOG_TABLE_ENTRIES as usize],
+
+            aa_enable: false,
+
+            clear_color: 0,
+            clear_depth: 0,
+            clear_stencil: 0,
+
+            z_bounds: 0,
+            depth_bounds: vec![(0xffff, 0); hiz_tile_blocks].into_boxed_slice(),
+            hiz_rejected_primitives: 0,
+            hiz_rejected_blocks: 0,
+
+            occlusion_query_active: false,
+            occlusion_query_passed_samples: 0,
 
             w0_min: 0,
             w0_dx: 0,
@@ -130,9 +333,24 @@ impl ColorThrust {
     pub fn write_reg(&mut self, addr: u32, data: u32, mem: &[u128]) {
         match addr {
             REG_START_ADDR => self.rasterize_primitive(mem),
+            REG_TEX_CACHE_INVALIDATE_ADDR => {
+                // Texels are always read straight from memory
+            }
             REG_DEPTH_SETTINGS_ADDR => {
                 self.depth_test_enable = (data & (1 << REG_DEPTH_TEST_ENABLE_BIT)) != 0;
                 self.depth_write_mask_enable = (data & (1 << REG_DEPTH_WRITE_MASK_ENABLE_BIT)) != 0;
+                self.depth_func = match (data >> REG_DEPTH_FUNC_BIT_OFFSET) & ((1 << REG_DEPTH_FUNC_BITS) - 1) {
+                    REG_DEPTH_FUNC_NEVER => DepthFunc::Never,
+                    REG_DEPTH_FUNC_LESS => DepthFunc::Less,
+                    REG_DEPTH_FUNC_EQUAL => DepthFunc::Equal,
+                    REG_DEPTH_FUNC_LEQUAL => DepthFunc::LessOrEqual,
+                    REG_DEPTH_FUNC_GREATER => DepthFunc::Greater,
+                    REG_DEPTH_FUNC_NOTEQUAL => DepthFunc::NotEqual,
+                    REG_DEPTH_FUNC_GEQUAL => DepthFunc::GreaterOrEqual,
+                    REG_DEPTH_FUNC_ALWAYS => DepthFunc::Always,
+                    _ => unreachable!()
+                };
+                self.hiz_enable = (data & (1 << REG_DEPTH_HIZ_ENABLE_BIT)) != 0;
             }
             REG_TEXTURE_SETTINGS_ADDR => {
                 self.texture_filter = match (data >> REG_TEXTURE_SETTINGS_FILTER_SELECT_BIT_OFFSET) & ((1 << REG_TEXTURE_SETTINGS_FILTER_SELECT_BITS) - 1) {
@@ -151,21 +369,151 @@ impl ColorThrust {
             REG_TEXTURE_BASE_ADDR => {
                 self.texture_base = (data >> (6 + 4)) & ((1 << REG_TEXTURE_BASE_BITS) - 1);
             }
+            REG_TEXTURE_ENV_ADDR => {
+                self.texture_env_mode = match (data >> REG_TEXTURE_ENV_MODE_BIT_OFFSET) & ((1 << REG_TEXTURE_ENV_MODE_BITS) - 1) {
+                    REG_TEXTURE_ENV_MODE_MODULATE => TextureEnvMode::Modulate,
+                    REG_TEXTURE_ENV_MODE_REPLACE => TextureEnvMode::Replace,
+                    REG_TEXTURE_ENV_MODE_DECAL => TextureEnvMode::Decal,
+                    REG_TEXTURE_ENV_MODE_ADD => TextureEnvMode::Add,
+                    REG_TEXTURE_ENV_MODE_BLEND => TextureEnvMode::Blend,
+                    REG_TEXTURE_ENV_MODE_UNTEXTURED => TextureEnvMode::Untextured,
+                    // Reserved modes fall back to modulate, same as the hardware
+                    _ => TextureEnvMode::Modulate,
+                };
+            }
+            REG_TEXTURE_ENV_COLOR_ADDR => {
+                self.texture_env_color = data;
+            }
             REG_BLEND_SETTINGS_ADDR => {
                 self.blend_src_factor = match (data >> REG_BLEND_SETTINGS_SRC_FACTOR_BIT_OFFSET) & ((1 << REG_BLEND_SETTINGS_SRC_FACTOR_BITS) - 1) {
                     REG_BLEND_SETTINGS_SRC_FACTOR_ZERO => BlendSrcFactor::Zero,
                     REG_BLEND_SETTINGS_SRC_FACTOR_ONE => BlendSrcFactor::One,
                     REG_BLEND_SETTINGS_SRC_FACTOR_SRC_ALPHA => BlendSrcFactor::SrcAlpha,
                     REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_ALPHA => BlendSrcFactor::OneMinusSrcAlpha,
-                    _ => unreachable!()
+                    REG_BLEND_SETTINGS_SRC_FACTOR_DST_COLOR => BlendSrcFactor::DstColor,
+                    REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_COLOR => BlendSrcFactor::OneMinusDstColor,
+                    REG_BLEND_SETTINGS_SRC_FACTOR_SRC_COLOR => BlendSrcFactor::SrcColor,
+                    REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_COLOR => BlendSrcFactor::OneMinusSrcColor,
+                    REG_BLEND_SETTINGS_SRC_FACTOR_DST_ALPHA => BlendSrcFactor::DstAlpha,
+                    REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_ALPHA => BlendSrcFactor::OneMinusDstAlpha,
+                    REG_BLEND_SETTINGS_SRC_FACTOR_CONSTANT_COLOR => BlendSrcFactor::ConstantColor,
+                    REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_CONSTANT_COLOR => BlendSrcFactor::OneMinusConstantColor,
+                    // Reserved factors select zero, same as the hardware
+                    _ => BlendSrcFactor::Zero,
                 };
                 self.blend_dst_factor = match (data >> REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET) & ((1 << REG_BLEND_SETTINGS_DST_FACTOR_BITS) - 1) {
                     REG_BLEND_SETTINGS_DST_FACTOR_ZERO => BlendDstFactor::Zero,
                     REG_BLEND_SETTINGS_DST_FACTOR_ONE => BlendDstFactor::One,
                     REG_BLEND_SETTINGS_DST_FACTOR_SRC_ALPHA => BlendDstFactor::SrcAlpha,
                     REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_ALPHA => BlendDstFactor::OneMinusSrcAlpha,
+                    REG_BLEND_SETTINGS_DST_FACTOR_DST_COLOR => BlendDstFactor::DstColor,
+                    REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_COLOR => BlendDstFactor::OneMinusDstColor,
+                    REG_BLEND_SETTINGS_DST_FACTOR_SRC_COLOR => BlendDstFactor::SrcColor,
+                    REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_COLOR => BlendDstFactor::OneMinusSrcColor,
+                    REG_BLEND_SETTINGS_DST_FACTOR_DST_ALPHA => BlendDstFactor::DstAlpha,
+                    REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_ALPHA => BlendDstFactor::OneMinusDstAlpha,
+                    REG_BLEND_SETTINGS_DST_FACTOR_CONSTANT_COLOR => BlendDstFactor::ConstantColor,
+                    REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_CONSTANT_COLOR => BlendDstFactor::OneMinusConstantColor,
+                    _ => BlendDstFactor::Zero,
+                };
+                self.blend_equation = match (data >> REG_BLEND_SETTINGS_EQUATION_BIT_OFFSET) & ((1 << REG_BLEND_SETTINGS_EQUATION_BITS) - 1) {
+                    REG_BLEND_SETTINGS_EQUATION_ADD => BlendEquation::Add,
+                    REG_BLEND_SETTINGS_EQUATION_SUBTRACT => BlendEquation::Subtract,
+                    REG_BLEND_SETTINGS_EQUATION_REVERSE_SUBTRACT => BlendEquation::ReverseSubtract,
+                    REG_BLEND_SETTINGS_EQUATION_MIN => BlendEquation::Min,
+                    REG_BLEND_SETTINGS_EQUATION_MAX => BlendEquation::Max,
+                    _ => BlendEquation::Add,
+                };
+            }
+            REG_BLEND_COLOR_ADDR => {
+                self.blend_color = data;
+            }
+            REG_ALPHA_TEST_SETTINGS_ADDR => {
+                self.alpha_test_enable = (data & (1 << REG_ALPHA_TEST_ENABLE_BIT)) != 0;
+                self.alpha_test_func = match (data >> REG_ALPHA_TEST_FUNC_BIT_OFFSET) & ((1 << REG_ALPHA_TEST_FUNC_BITS) - 1) {
+                    REG_ALPHA_TEST_FUNC_NEVER => AlphaFunc::Never,
+                    REG_ALPHA_TEST_FUNC_LESS => AlphaFunc::Less,
+                    REG_ALPHA_TEST_FUNC_EQUAL => AlphaFunc::Equal,
+                    REG_ALPHA_TEST_FUNC_LEQUAL => AlphaFunc::LessOrEqual,
+                    REG_ALPHA_TEST_FUNC_GREATER => AlphaFunc::Greater,
+                    REG_ALPHA_TEST_FUNC_NOTEQUAL => AlphaFunc::NotEqual,
+                    REG_ALPHA_TEST_FUNC_GEQUAL => AlphaFunc::GreaterOrEqual,
+                    REG_ALPHA_TEST_FUNC_ALWAYS => AlphaFunc::Always,
+                    _ => unreachable!()
+                };
+                self.alpha_test_ref = (data >> REG_ALPHA_TEST_REF_BIT_OFFSET) & ((1 << REG_ALPHA_TEST_REF_BITS) - 1);
+            }
+            REG_STENCIL_SETTINGS_ADDR => {
+                self.stencil_test_enable = (data & (1 << REG_STENCIL_TEST_ENABLE_BIT)) != 0;
+                self.stencil_func = match (data >> REG_STENCIL_FUNC_BIT_OFFSET) & ((1 << REG_STENCIL_FUNC_BITS) - 1) {
+                    REG_STENCIL_FUNC_NEVER => StencilFunc::Never,
+                    REG_STENCIL_FUNC_LESS => StencilFunc::Less,
+                    REG_STENCIL_FUNC_EQUAL => StencilFunc::Equal,
+                    REG_STENCIL_FUNC_LEQUAL => StencilFunc::LessOrEqual,
+                    REG_STENCIL_FUNC_GREATER => StencilFunc::Greater,
+                    REG_STENCIL_FUNC_NOTEQUAL => StencilFunc::NotEqual,
+                    REG_STENCIL_FUNC_GEQUAL => StencilFunc::GreaterOrEqual,
+                    REG_STENCIL_FUNC_ALWAYS => StencilFunc::Always,
                     _ => unreachable!()
                 };
+                self.stencil_sfail_op = StencilOp::from_reg(data >> REG_STENCIL_SFAIL_OP_BIT_OFFSET);
+                self.stencil_zfail_op = StencilOp::from_reg(data >> REG_STENCIL_ZFAIL_OP_BIT_OFFSET);
+                self.stencil_zpass_op = StencilOp::from_reg(data >> REG_STENCIL_ZPASS_OP_BIT_OFFSET);
+            }
+            REG_STENCIL_REF_ADDR => {
+                self.stencil_ref = (data >> REG_STENCIL_REF_VALUE_BIT_OFFSET) as _;
+                self.stencil_mask = (data >> REG_STENCIL_REF_MASK_BIT_OFFSET) as _;
+                self.stencil_write_mask = (data >> REG_STENCIL_REF_WRITE_MASK_BIT_OFFSET) as _;
+            }
+            REG_FOG_SETTINGS_ADDR => {
+                self.fog_enable = (data & (1 << REG_FOG_ENABLE_BIT)) != 0;
+                self.fog_source = match (data >> REG_FOG_SOURCE_BIT_OFFSET) & ((1 << REG_FOG_SOURCE_BITS) - 1) {
+                    REG_FOG_SOURCE_Z => FogSource::Z,
+                    REG_FOG_SOURCE_W => FogSource::W,
+                    _ => unreachable!()
+                };
+                self.fog_w_shift = (data >> REG_FOG_W_SHIFT_BIT_OFFSET) & ((1 << REG_FOG_W_SHIFT_BITS) - 1);
+            }
+            REG_FOG_COLOR_ADDR => {
+                self.fog_color = data;
+            }
+            REG_FOG_TABLE_ADDR => {
+                let index = (data >> REG_FOG_TABLE_INDEX_BIT_OFFSET) & ((1 << REG_FOG_TABLE_INDEX_BITS) - 1);
+                self.fog_table[index as usize] = (data >> REG_FOG_TABLE_VALUE_BIT_OFFSET) as _;
+            }
+            REG_CLEAR_COLOR_ADDR => {
+                self.clear_color = data;
+            }
+            REG_CLEAR_DEPTH_STENCIL_ADDR => {
+                self.clear_depth = (data >> REG_CLEAR_DEPTH_BIT_OFFSET) as _;
+                self.clear_stencil = (data >> REG_CLEAR_STENCIL_BIT_OFFSET) as _;
+            }
+            REG_CLEAR_TILE_ADDR => self.clear_tile(data),
+            REG_AA_SETTINGS_ADDR => {
+                self.aa_enable = (data & (1 << REG_AA_ENABLE_BIT)) != 0;
+            }
+            REG_AA_RESOLVE_ADDR => self.resolve(),
+            REG_COLOR_PACK_ADDR => self.pack((data & (1 << REG_COLOR_PACK_RESOLVED_BIT)) != 0),
+            REG_COLOR_UNPACK_ADDR => self.unpack(),
+            REG_COLOR_SWIZZLE_ADDR => self.swizzle((data & (1 << REG_COLOR_SWIZZLE_INVERSE_BIT)) != 0),
+            REG_Z_BOUNDS_ADDR => {
+                self.z_bounds = data;
+            }
+            REG_DEPTH_BOUNDS_RESET_ADDR => {
+                self.depth_bounds.fill((0xffff, 0));
+            }
+            REG_HIZ_REJECTED_PRIMITIVES_ADDR => {
+                self.hiz_rejected_primitives = 0;
+            }
+            REG_HIZ_REJECTED_BLOCKS_ADDR => {
+                self.hiz_rejected_blocks = 0;
+            }
+            REG_OCCLUSION_QUERY_BEGIN_ADDR => {
+                self.occlusion_query_active = true;
+                self.occlusion_query_passed_samples = 0;
+            }
+            REG_OCCLUSION_QUERY_END_ADDR => {
+                self.occlusion_query_active = false;
             }
             REG_W0_MIN_ADDR => { self.w0_min = data; }
             REG_W0_DX_ADDR => { self.w0_dx = data; }
@@ -209,7 +557,18 @@ impl ColorThrust {
             REG_STATUS_ADDR => 0,
             REG_DEPTH_SETTINGS_ADDR => {
                 (if self.depth_test_enable { 1 } else { 0 } << REG_DEPTH_TEST_ENABLE_BIT) |
-                (if self.depth_write_mask_enable { 1 } else { 0 } << REG_DEPTH_WRITE_MASK_ENABLE_BIT)
+                (if self.depth_write_mask_enable { 1 } else { 0 } << REG_DEPTH_WRITE_MASK_ENABLE_BIT) |
+                (match self.depth_func {
+                    DepthFunc::Never => REG_DEPTH_FUNC_NEVER,
+                    DepthFunc::Less => REG_DEPTH_FUNC_LESS,
+                    DepthFunc::Equal => REG_DEPTH_FUNC_EQUAL,
+                    DepthFunc::LessOrEqual => REG_DEPTH_FUNC_LEQUAL,
+                    DepthFunc::Greater => REG_DEPTH_FUNC_GREATER,
+                    DepthFunc::NotEqual => REG_DEPTH_FUNC_NOTEQUAL,
+                    DepthFunc::GreaterOrEqual => REG_DEPTH_FUNC_GEQUAL,
+                    DepthFunc::Always => REG_DEPTH_FUNC_ALWAYS,
+                } << REG_DEPTH_FUNC_BIT_OFFSET) |
+                (if self.hiz_enable { 1 } else { 0 } << REG_DEPTH_HIZ_ENABLE_BIT)
             }
             REG_TEXTURE_SETTINGS_ADDR => {
                 (match self.texture_filter {
@@ -223,14 +582,109 @@ impl ColorThrust {
                     BlendSrcFactor::One => REG_BLEND_SETTINGS_SRC_FACTOR_ONE,
                     BlendSrcFactor::SrcAlpha => REG_BLEND_SETTINGS_SRC_FACTOR_SRC_ALPHA,
                     BlendSrcFactor::OneMinusSrcAlpha => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_ALPHA,
+                    BlendSrcFactor::DstColor => REG_BLEND_SETTINGS_SRC_FACTOR_DST_COLOR,
+                    BlendSrcFactor::OneMinusDstColor => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_COLOR,
+                    BlendSrcFactor::SrcColor => REG_BLEND_SETTINGS_SRC_FACTOR_SRC_COLOR,
+                    BlendSrcFactor::OneMinusSrcColor => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_COLOR,
+                    BlendSrcFactor::DstAlpha => REG_BLEND_SETTINGS_SRC_FACTOR_DST_ALPHA,
+                    BlendSrcFactor::OneMinusDstAlpha => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_ALPHA,
+                    BlendSrcFactor::ConstantColor => REG_BLEND_SETTINGS_SRC_FACTOR_CONSTANT_COLOR,
+                    BlendSrcFactor::OneMinusConstantColor => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_CONSTANT_COLOR,
                 } << REG_BLEND_SETTINGS_SRC_FACTOR_BIT_OFFSET) |
                 (match self.blend_dst_factor {
                     BlendDstFactor::Zero => REG_BLEND_SETTINGS_DST_FACTOR_ZERO,
                     BlendDstFactor::One => REG_BLEND_SETTINGS_DST_FACTOR_ONE,
                     BlendDstFactor::SrcAlpha => REG_BLEND_SETTINGS_DST_FACTOR_SRC_ALPHA,
                     BlendDstFactor::OneMinusSrcAlpha => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_ALPHA,
-                } << REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET)
+                    BlendDstFactor::DstColor => REG_BLEND_SETTINGS_DST_FACTOR_DST_COLOR,
+                    BlendDstFactor::OneMinusDstColor => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_COLOR,
+                    BlendDstFactor::SrcColor => REG_BLEND_SETTINGS_DST_FACTOR_SRC_COLOR,
+                    BlendDstFactor::OneMinusSrcColor => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_COLOR,
+                    BlendDstFactor::DstAlpha => REG_BLEND_SETTINGS_DST_FACTOR_DST_ALPHA,
+                    BlendDstFactor::OneMinusDstAlpha => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_ALPHA,
+                    BlendDstFactor::ConstantColor => REG_BLEND_SETTINGS_DST_FACTOR_CONSTANT_COLOR,
+                    BlendDstFactor::OneMinusConstantColor => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_CONSTANT_COLOR,
+                } << REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET) |
+                (match self.blend_equation {
+                    BlendEquation::Add => REG_BLEND_SETTINGS_EQUATION_ADD,
+                    BlendEquation::Subtract => REG_BLEND_SETTINGS_EQUATION_SUBTRACT,
+                    BlendEquation::ReverseSubtract => REG_BLEND_SETTINGS_EQUATION_REVERSE_SUBTRACT,
+                    BlendEquation::Min => REG_BLEND_SETTINGS_EQUATION_MIN,
+                    BlendEquation::Max => REG_BLEND_SETTINGS_EQUATION_MAX,
+                } << REG_BLEND_SETTINGS_EQUATION_BIT_OFFSET)
+            }
+            REG_BLEND_COLOR_ADDR => self.blend_color,
+            REG_TEXTURE_ENV_ADDR => {
+                (match self.texture_env_mode {
+                    TextureEnvMode::Modulate => REG_TEXTURE_ENV_MODE_MODULATE,
+                    TextureEnvMode::Replace => REG_TEXTURE_ENV_MODE_REPLACE,
+                    TextureEnvMode::Decal => REG_TEXTURE_ENV_MODE_DECAL,
+                    TextureEnvMode::Add => REG_TEXTURE_ENV_MODE_ADD,
+                    TextureEnvMode::Blend => REG_TEXTURE_ENV_MODE_BLEND,
+                    TextureEnvMode::Untextured => REG_TEXTURE_ENV_MODE_UNTEXTURED,
+                }) << REG_TEXTURE_ENV_MODE_BIT_OFFSET
+            }
+            REG_TEXTURE_ENV_COLOR_ADDR => self.texture_env_color,
+            REG_ALPHA_TEST_SETTINGS_ADDR => {
+                (if self.alpha_test_enable { 1 } else { 0 } << REG_ALPHA_TEST_ENABLE_BIT) |
+                (match self.alpha_test_func {
+                    AlphaFunc::Never => REG_ALPHA_TEST_FUNC_NEVER,
+                    AlphaFunc::Less => REG_ALPHA_TEST_FUNC_LESS,
+                    AlphaFunc::Equal => REG_ALPHA_TEST_FUNC_EQUAL,
+                    AlphaFunc::LessOrEqual => REG_ALPHA_TEST_FUNC_LEQUAL,
+                    AlphaFunc::Greater => REG_ALPHA_TEST_FUNC_GREATER,
+                    AlphaFunc::NotEqual => REG_ALPHA_TEST_FUNC_NOTEQUAL,
+                    AlphaFunc::GreaterOrEqual => REG_ALPHA_TEST_FUNC_GEQUAL,
+                    AlphaFunc::Always => REG_ALPHA_TEST_FUNC_ALWAYS,
+                } << REG_ALPHA_TEST_FUNC_BIT_OFFSET) |
+                (self.alpha_test_ref << REG_ALPHA_TEST_REF_BIT_OFFSET)
+            }
+            REG_STENCIL_SETTINGS_ADDR => {
+                (if self.stencil_test_enable { 1 } else { 0 } << REG_STENCIL_TEST_ENABLE_BIT) |
+                (match self.stencil_func {
+                    StencilFunc::Never => REG_STENCIL_FUNC_NEVER,
+                    StencilFunc::Less => REG_STENCIL_FUNC_LESS,
+                    StencilFunc::Equal => REG_STENCIL_FUNC_EQUAL,
+                    StencilFunc::LessOrEqual => REG_STENCIL_FUNC_LEQUAL,
+                    StencilFunc::Greater => REG_STENCIL_FUNC_GREATER,
+                    StencilFunc::NotEqual => REG_STENCIL_FUNC_NOTEQUAL,
+                    StencilFunc::GreaterOrEqual => REG_STENCIL_FUNC_GEQUAL,
+                    StencilFunc::Always => REG_STENCIL_FUNC_ALWAYS,
+                } << REG_STENCIL_FUNC_BIT_OFFSET) |
+                (self.stencil_sfail_op.to_reg() << REG_STENCIL_SFAIL_OP_BIT_OFFSET) |
+                (self.stencil_zfail_op.to_reg() << REG_STENCIL_ZFAIL_OP_BIT_OFFSET) |
+                (self.stencil_zpass_op.to_reg() << REG_STENCIL_ZPASS_OP_BIT_OFFSET)
+            }
+            REG_STENCIL_REF_ADDR => {
+                ((self.stencil_ref as u32) << REG_STENCIL_REF_VALUE_BIT_OFFSET) |
+                ((self.stencil_mask as u32) << REG_STENCIL_REF_MASK_BIT_OFFSET) |
+                ((self.stencil_write_mask as u32) << REG_STENCIL_REF_WRITE_MASK_BIT_OFFSET)
+            }
+            REG_FOG_SETTINGS_ADDR => {
+                (if self.fog_enable { 1 } else { 0 } << REG_FOG_ENABLE_BIT) |
+                (match self.fog_source {
+                    FogSource::Z => REG_FOG_SOURCE_Z,
+                    FogSource::W => REG_FOG_SOURCE_W,
+                } << REG_FOG_SOURCE_BIT_OFFSET) |
+                (self.fog_w_shift << REG_FOG_W_SHIFT_BIT_OFFSET)
+            }
+            REG_FOG_COLOR_ADDR => self.fog_color,
+            REG_CLEAR_COLOR_ADDR => self.clear_color,
+            REG_CLEAR_DEPTH_STENCIL_ADDR => {
+                ((self.clear_depth as u32) << REG_CLEAR_DEPTH_BIT_OFFSET) |
+                ((self.clear_stencil as u32) << REG_CLEAR_STENCIL_BIT_OFFSET)
+            }
+            REG_AA_SETTINGS_ADDR => (if self.aa_enable { 1 } else { 0 }) << REG_AA_ENABLE_BIT,
+            REG_Z_BOUNDS_ADDR => self.z_bounds,
+            REG_CAPS_ADDR => {
+                (self.tile_width_bits << REG_CAPS_TILE_WIDTH_LOG2_BIT_OFFSET) |
+                (self.tile_height_bits << REG_CAPS_TILE_HEIGHT_LOG2_BIT_OFFSET) |
+                // The model processes one pixel at a time
+                (0 << REG_CAPS_PIXEL_PIPES_LOG2_BIT_OFFSET)
             }
+            REG_HIZ_REJECTED_PRIMITIVES_ADDR => self.hiz_rejected_primitives,
+            REG_HIZ_REJECTED_BLOCKS_ADDR => self.hiz_rejected_blocks,
+            REG_OCCLUSION_QUERY_PASSED_SAMPLES_ADDR => self.occlusion_query_passed_samples,
             REG_W0_MIN_ADDR => self.w0_min,
             REG_W0_DX_ADDR => self.w0_dx,
             REG_W0_DY_ADDR => self.w0_dy,
@@ -269,12 +723,14 @@ impl ColorThrust {
     }
 
     pub fn write_color_buffer_word(&mut self, addr: u32, data: u128) {
+        let addr = self.color_buffer_word_index(addr);
         for i in 0..4 {
             self.color_buffer[(addr * 4 + i) as usize] = (data >> (i * 32)) as _;
         }
     }
 
     pub fn read_color_buffer_word(&mut self, addr: u32) -> u128 {
+        let addr = self.color_buffer_word_index(addr);
         let mut ret = 0;
         for i in 0..4 {
             ret |= (self.color_buffer[(addr * 4 + i) as usize] as u128) << (i * 32);
@@ -284,7 +740,9 @@ impl ColorThrust {
 
     pub fn write_depth_buffer_word(&mut self, addr: u32, data: u128) {
         for i in 0..8 {
-            self.depth_buffer[(addr * 8 + i) as usize] = (data >> (i * 16)) as _;
+            let value = (data >> (i * 16)) as u16;
+            self.depth_buffer[(addr * 8 + i) as usize] = value;
+            self.widen_depth_bounds(addr * 8 + i, value);
         }
     }
 
@@ -296,7 +754,176 @@ impl ColorThrust {
         ret
     }
 
+    pub fn write_stencil_buffer_word(&mut self, addr: u32, data: u128) {
+        for i in 0..16 {
+            self.stencil_buffer[(addr * 16 + i) as usize] = (data >> (i * 8)) as _;
+        }
+    }
+
+    pub fn read_stencil_buffer_word(&mut self, addr: u32) -> u128 {
+        let mut ret = 0;
+        for i in 0..16 {
+            ret |= (self.stencil_buffer[(addr * 16 + i) as usize] as u128) << (i * 8);
+        }
+        ret
+    }
+
+    // Words in the swizzled window are ordered by block y, block x, row / 2, then word, instead of by row and word
+    fn color_buffer_word_index(&self, addr: u32) -> u32 {
+        if (addr & (1 << COLOR_BUFFER_BUS_SWIZZLED_WINDOW_BIT)) == 0 {
+            return addr;
+        }
+
+        let word_bits = self.tile_width_bits - 3;
+        let row_bits = self.tile_height_bits - 1;
+        let word = addr & ((1 << word_bits) - 1);
+        let row = (addr >> word_bits) & ((1 << row_bits) - 1);
+        let block_x = (addr >> (word_bits + row_bits)) & 1;
+        let block_y = (addr >> (word_bits + row_bits + 1)) & 1;
+        (((row << 1) | block_y) << (word_bits + 1)) | (word << 1) | block_x
+    }
+
+    fn clear_tile(&mut self, buffers: u32) {
+        if (buffers & (1 << REG_CLEAR_TILE_COLOR_BIT)) != 0 {
+            self.color_buffer.fill(self.clear_color);
+        }
+        if (buffers & (1 << REG_CLEAR_TILE_DEPTH_BIT)) != 0 {
+            self.depth_buffer.fill(self.clear_depth);
+            self.depth_bounds.fill((self.clear_depth, self.clear_depth));
+        }
+        if (buffers & (1 << REG_CLEAR_TILE_STENCIL_BIT)) != 0 {
+            self.stencil_buffer.fill(self.clear_stencil);
+        }
+    }
+
+    fn resolve(&mut self) {
+        // Resolved pixels are packed into the start of the color buffer. Each one lands at or before the first of its
+        //  samples, and after every sample of the pixels before it, so this can be done in place.
+        let tile_width = 1 << self.tile_width_bits;
+        for y in 0..1 << (self.tile_height_bits - 1) {
+            for x in 0..tile_width / 2 {
+                let samples = [
+                    self.color_buffer[(y * 2 + 0) * tile_width + x * 2 + 0],
+                    self.color_buffer[(y * 2 + 0) * tile_width + x * 2 + 1],
+                    self.color_buffer[(y * 2 + 1) * tile_width + x * 2 + 0],
+                    self.color_buffer[(y * 2 + 1) * tile_width + x * 2 + 1],
+                ];
+                let mut color = 0;
+                for channel in 0..4 {
+                    let sum = samples.iter().map(|sample| (sample >> (channel * 8)) & 0xff).sum::<u32>();
+                    color |= ((sum + 2) >> 2) << (channel * 8);
+                }
+                self.color_buffer[y * tile_width / 2 + x] = color;
+            }
+        }
+    }
+
+    fn pack(&mut self, resolved: bool) {
+        // Each element of the packed color buffer holds 2 RGB565 pixels, which only ever come from elements at or
+        //  after it, so this can be done in place
+        let row_width_bits = if resolved { self.tile_width_bits - 1 } else { self.tile_width_bits };
+        for i in 0..self.color_buffer.len() / 2 {
+            let mut packed = 0;
+            for j in 0..2 {
+                let pixel_index = i * 2 + j;
+                let argb = self.color_buffer[pixel_index];
+                let threshold = COLOR_PACK_DITHER_MATRIX[(pixel_index >> row_width_bits) & 3][pixel_index & 3];
+                let dither = |lsb: u32, bits: u32| {
+                    let truncated_bits = 8 - bits;
+                    let sum = ((argb >> lsb) & 0xff) + (threshold >> (4 - truncated_bits));
+                    sum.min(0xff) >> truncated_bits
+                };
+                let rgb565 = (dither(16, 5) << 11) | (dither(8, 6) << 5) | dither(0, 5);
+                packed |= rgb565 << (j * 16);
+            }
+            self.color_buffer[i] = packed;
+        }
+    }
+
+    fn unpack(&mut self) {
+        // Walk backwards, so that packed elements are read before they're overwritten
+        for i in (0..self.color_buffer.len() / 2).rev() {
+            let packed = self.color_buffer[i];
+            for j in 0..2 {
+                let rgb565 = packed >> (j * 16);
+                let r = (rgb565 >> 11) & 0x1f;
+                let g = (rgb565 >> 5) & 0x3f;
+                let b = rgb565 & 0x1f;
+                self.color_buffer[i * 2 + j] =
+                    0xff000000 |
+                    (((r << 3) | (r >> 2)) << 16) |
+                    (((g << 2) | (g >> 4)) << 8) |
+                    ((b << 3) | (b >> 2));
+            }
+        }
+    }
+
+    fn swizzle(&mut self, inverse: bool) {
+        // Each pair of words (8 pixels) is converted on its own, so this can be done in place
+        for pair in self.color_buffer.chunks_exact_mut(8) {
+            let mut pixels = [0; 8];
+            pixels.copy_from_slice(pair);
+            for (x, pixel) in pair.iter_mut().enumerate() {
+                let (word, index) = (x >> 2, x & 3);
+                *pixel = if inverse {
+                    pixels[(index & 1) * 4 + word * 2 + (index >> 1)]
+                } else {
+                    pixels[(index >> 1) * 4 + (index & 1) * 2 + word]
+                };
+            }
+        }
+    }
+
+    fn hiz_block_index(&self, x: u32, y: u32) -> u32 {
+        ((y >> HIZ_BLOCK_DIM_BITS) << (self.tile_width_bits - HIZ_BLOCK_DIM_BITS)) + (x >> HIZ_BLOCK_DIM_BITS)
+    }
+
+    fn widen_depth_bounds(&mut self, buffer_index: u32, value: u16) {
+        let x = buffer_index & ((1 << self.tile_width_bits) - 1);
+        let y = buffer_index >> self.tile_width_bits;
+        let block_index = self.hiz_block_index(x, y);
+        let (min, max) = &mut self.depth_bounds[block_index as usize];
+        *min = (*min).min(value);
+        *max = (*max).max(value);
+    }
+
+    fn hiz_block_reject(&self, block_index: u32) -> bool {
+        let z_min = (self.z_bounds >> REG_Z_BOUNDS_MIN_BIT_OFFSET) as u16;
+        let z_max = (self.z_bounds >> REG_Z_BOUNDS_MAX_BIT_OFFSET) as u16;
+        let (block_min, block_max) = self.depth_bounds[block_index as usize];
+        let less_possible = z_min < block_max;
+        let equal_possible = block_max >= z_min && z_max >= block_min;
+        let greater_possible = block_min < z_max;
+        !match self.depth_func {
+            DepthFunc::Never => false,
+            DepthFunc::Less => less_possible,
+            DepthFunc::Equal => equal_possible,
+            DepthFunc::LessOrEqual => less_possible || equal_possible,
+            DepthFunc::Greater => greater_possible,
+            DepthFunc::NotEqual => less_possible || greater_possible,
+            DepthFunc::GreaterOrEqual => greater_possible || equal_possible,
+            DepthFunc::Always => true,
+        }
+    }
+
     fn rasterize_primitive(&mut self, mem: &[u128]) {
+        let hiz_enable = self.hiz_enable && self.depth_test_enable && !self.stencil_test_enable;
+        if hiz_enable && (0..self.depth_bounds.len() as u32).all(|block_index| self.hiz_block_reject(block_index)) {
+            self.hiz_rejected_primitives += 1;
+            return;
+        }
+
+        // The hardware widens hi-z bounds as depth writes leave the pixel pipe, so a primitive's own writes land
+        //  some time after its later block tests, depending on pipe latency and stalls. Since bounds only ever widen and
+        //  a block is always tested before the primitive writes anything to it, each block is either rejected in every
+        //  row or in none, however late the writes land. So holding them until the primitive is done, as the hardware
+        //  would with its longest possible delay, counts rejections exactly as it does.
+        let mut depth_writes = Vec::new();
+
+        // With multisampling, the edge functions and z are evaluated per sample, but everything else is evaluated once
+        //  per 2x2 sample pixel
+        let aa_shift = if self.aa_enable { 1 } else { 0 };
+
         let mut w0_row = self.w0_min;
         let mut w1_row = self.w1_min;
         let mut w2_row = self.w2_min;
@@ -309,7 +936,7 @@ impl ColorThrust {
         let mut s_row = self.s_min;
         let mut t_row = self.t_min;
 
-        for y in 0..TILE_DIM {
+        for y in 0..1 << self.tile_height_bits {
             let mut w0 = w0_row;
             let mut w1 = w1_row;
             let mut w2 = w2_row;
@@ -322,7 +949,32 @@ impl ColorThrust {
             let mut s = s_row;
             let mut t = t_row;
 
-            for x in 0..TILE_DIM {
+            let mut x = 0;
+            while x < 1 << self.tile_width_bits {
+                if hiz_enable && x % HIZ_BLOCK_DIM == 0 {
+                    let block_index = self.hiz_block_index(x, y);
+                    if self.hiz_block_reject(block_index) {
+                        if y % HIZ_BLOCK_DIM == 0 {
+                            self.hiz_rejected_blocks += 1;
+                        }
+
+                        w0 += self.w0_dx * HIZ_BLOCK_DIM;
+                        w1 += self.w1_dx * HIZ_BLOCK_DIM;
+                        w2 += self.w2_dx * HIZ_BLOCK_DIM;
+                        z += self.z_dx * HIZ_BLOCK_DIM;
+                        let block_pixels = HIZ_BLOCK_DIM >> aa_shift;
+                        r += self.r_dx * block_pixels;
+                        g += self.g_dx * block_pixels;
+                        b += self.b_dx * block_pixels;
+                        a += self.a_dx * block_pixels;
+                        w_inverse += self.w_inverse_dx * block_pixels;
+                        s += self.s_dx * block_pixels;
+                        t += self.t_dx * block_pixels;
+                        x += HIZ_BLOCK_DIM;
+                        continue;
+                    }
+                }
+
                 if (w0 | w1 | w2) as i32 >= 0 {
                     const RESTORED_W_FRACT_BITS: u32 = 8; // Must be less than W_INVERSE_FRACT_BITS and ST_FRACT_BITS
 
@@ -352,62 +1004,69 @@ impl ColorThrust {
 
                     let w = w_approx;
 
-                    let s = (((s as i32) >> RESTORED_W_FRACT_BITS) * (w as i32)) as u32;
-                    let t = (((t as i32) >> RESTORED_W_FRACT_BITS) * (w as i32)) as u32;
-                    let s_floor = s >> ST_FRACT_BITS;
-                    let t_floor = t >> ST_FRACT_BITS;
-                    let mut s_fract = (s >> (ST_FRACT_BITS - ST_FILTER_FRACT_BITS)) & ((1 << ST_FILTER_FRACT_BITS) - 1);
-                    let mut t_fract = (t >> (ST_FRACT_BITS - ST_FILTER_FRACT_BITS)) & ((1 << ST_FILTER_FRACT_BITS) - 1);
-                    let mut one_minus_s_fract = (1 << ST_FILTER_FRACT_BITS) - s_fract;
-                    let mut one_minus_t_fract = (1 << ST_FILTER_FRACT_BITS) - t_fract;
-                    match self.texture_filter {
-                        TextureFilter::Nearest => {
-                            // Lock weights for nearest filtering
-                            let zero = 0;
-                            let one = 1 << ST_FILTER_FRACT_BITS;
-                            s_fract = zero;
-                            one_minus_s_fract = one;
-                            t_fract = zero;
-                            one_minus_t_fract = one;
-                        }
-                        TextureFilter::Bilinear => (), // Do nothing
-                    }
-                    // Swap weights depending on pixel offsets
-                    let (s_fract, one_minus_s_fract) = if (s_floor & 1) == 0 {
-                        (s_fract, one_minus_s_fract)
+                    let (texel_r, texel_g, texel_b, texel_a) = if let TextureEnvMode::Untextured = self.texture_env_mode {
+                        // No texture bound; skip sampling entirely
+                        (0, 0, 0, 0)
                     } else {
-                        (one_minus_s_fract, s_fract)
-                    };
-                    let (t_fract, one_minus_t_fract) = if (t_floor & 1) == 0 {
-                        (t_fract, one_minus_t_fract)
-                    } else {
-                        (one_minus_t_fract, t_fract)
-                    };
+                        let s = (((s as i32) >> RESTORED_W_FRACT_BITS) * (w as i32)) as u32;
+                        let t = (((t as i32) >> RESTORED_W_FRACT_BITS) * (w as i32)) as u32;
+                        let s_floor = s >> ST_FRACT_BITS;
+                        let t_floor = t >> ST_FRACT_BITS;
+                        let mut s_fract = (s >> (ST_FRACT_BITS - ST_FILTER_FRACT_BITS)) & ((1 << ST_FILTER_FRACT_BITS) - 1);
+                        let mut t_fract = (t >> (ST_FRACT_BITS - ST_FILTER_FRACT_BITS)) & ((1 << ST_FILTER_FRACT_BITS) - 1);
+                        let mut one_minus_s_fract = (1 << ST_FILTER_FRACT_BITS) - s_fract;
+                        let mut one_minus_t_fract = (1 << ST_FILTER_FRACT_BITS) - t_fract;
+                        match self.texture_filter {
+                            TextureFilter::Nearest => {
+                                // Lock weights for nearest filtering
+                                let zero = 0;
+                                let one = 1 << ST_FILTER_FRACT_BITS;
+                                s_fract = zero;
+                                one_minus_s_fract = one;
+                                t_fract = zero;
+                                one_minus_t_fract = one;
+                            }
+                            TextureFilter::Bilinear => (), // Do nothing
+                        }
+                        // Swap weights depending on pixel offsets
+                        let (s_fract, one_minus_s_fract) = if (s_floor & 1) == 0 {
+                            (s_fract, one_minus_s_fract)
+                        } else {
+                            (one_minus_s_fract, s_fract)
+                        };
+                        let (t_fract, one_minus_t_fract) = if (t_floor & 1) == 0 {
+                            (t_fract, one_minus_t_fract)
+                        } else {
+                            (one_minus_t_fract, t_fract)
+                        };
 
-                    let buffer0_s = (s_floor.wrapping_add(1) >> 1) & 0x3f;
-                    let buffer0_t = (t_floor.wrapping_add(1) >> 1) & 0x3f;
-                    let buffer1_s = (s_floor >> 1) & 0x3f;
-                    let buffer1_t = buffer0_t;
-                    let buffer2_s = buffer0_s;
-                    let buffer2_t = (t_floor >> 1) & 0x3f;
-                    let buffer3_s = buffer1_s;
-                    let buffer3_t = buffer2_t;
-                    let texel_color0 = self.fetch_texel(buffer0_s, buffer0_t, 0, mem);
-                    let texel_color1 = self.fetch_texel(buffer1_s, buffer1_t, 1, mem);
-                    let texel_color2 = self.fetch_texel(buffer2_s, buffer2_t, 2, mem);
-                    let texel_color3 = self.fetch_texel(buffer3_s, buffer3_t, 3, mem);
-                    let a_r = (texel_color0.0 * one_minus_s_fract + texel_color1.0 * s_fract) >> ST_FILTER_FRACT_BITS;
-                    let a_g = (texel_color0.1 * one_minus_s_fract + texel_color1.1 * s_fract) >> ST_FILTER_FRACT_BITS;
-                    let a_b = (texel_color0.2 * one_minus_s_fract + texel_color1.2 * s_fract) >> ST_FILTER_FRACT_BITS;
-                    let a_a = (texel_color0.3 * one_minus_s_fract + texel_color1.3 * s_fract) >> ST_FILTER_FRACT_BITS;
-                    let b_r = (texel_color2.0 * one_minus_s_fract + texel_color3.0 * s_fract) >> ST_FILTER_FRACT_BITS;
-                    let b_g = (texel_color2.1 * one_minus_s_fract + texel_color3.1 * s_fract) >> ST_FILTER_FRACT_BITS;
-                    let b_b = (texel_color2.2 * one_minus_s_fract + texel_color3.2 * s_fract) >> ST_FILTER_FRACT_BITS;
-                    let b_a = (texel_color2.3 * one_minus_s_fract + texel_color3.3 * s_fract) >> ST_FILTER_FRACT_BITS;
-                    let texel_r = (a_r * one_minus_t_fract + b_r * t_fract) >> ST_FILTER_FRACT_BITS;
-                    let texel_g = (a_g * one_minus_t_fract + b_g * t_fract) >> ST_FILTER_FRACT_BITS;
-                    let texel_b = (a_b * one_minus_t_fract + b_b * t_fract) >> ST_FILTER_FRACT_BITS;
-                    let texel_a = (a_a * one_minus_t_fract + b_a * t_fract) >> ST_FILTER_FRACT_BITS;
+                        let buffer0_s = (s_floor.wrapping_add(1) >> 1) & 0x3f;
+                        let buffer0_t = (t_floor.wrapping_add(1) >> 1) & 0x3f;
+                        let buffer1_s = (s_floor >> 1) & 0x3f;
+                        let buffer1_t = buffer0_t;
+                        let buffer2_s = buffer0_s;
+                        let buffer2_t = (t_floor >> 1) & 0x3f;
+                        let buffer3_s = buffer1_s;
+                        let buffer3_t = buffer2_t;
+                        let texel_color0 = self.fetch_texel(buffer0_s, buffer0_t, 0, mem);
+                        let texel_color1 = self.fetch_texel(buffer1_s, buffer1_t, 1, mem);
+                        let texel_color2 = self.fetch_texel(buffer2_s, buffer2_t, 2, mem);
+                        let texel_color3 = self.fetch_texel(buffer3_s, buffer3_t, 3, mem);
+                        let a_r = (texel_color0.0 * one_minus_s_fract + texel_color1.0 * s_fract) >> ST_FILTER_FRACT_BITS;
+                        let a_g = (texel_color0.1 * one_minus_s_fract + texel_color1.1 * s_fract) >> ST_FILTER_FRACT_BITS;
+                        let a_b = (texel_color0.2 * one_minus_s_fract + texel_color1.2 * s_fract) >> ST_FILTER_FRACT_BITS;
+                        let a_a = (texel_color0.3 * one_minus_s_fract + texel_color1.3 * s_fract) >> ST_FILTER_FRACT_BITS;
+                        let b_r = (texel_color2.0 * one_minus_s_fract + texel_color3.0 * s_fract) >> ST_FILTER_FRACT_BITS;
+                        let b_g = (texel_color2.1 * one_minus_s_fract + texel_color3.1 * s_fract) >> ST_FILTER_FRACT_BITS;
+                        let b_b = (texel_color2.2 * one_minus_s_fract + texel_color3.2 * s_fract) >> ST_FILTER_FRACT_BITS;
+                        let b_a = (texel_color2.3 * one_minus_s_fract + texel_color3.3 * s_fract) >> ST_FILTER_FRACT_BITS;
+                        let texel_r = (a_r * one_minus_t_fract + b_r * t_fract) >> ST_FILTER_FRACT_BITS;
+                        let texel_g = (a_g * one_minus_t_fract + b_g * t_fract) >> ST_FILTER_FRACT_BITS;
+                        let texel_b = (a_b * one_minus_t_fract + b_b * t_fract) >> ST_FILTER_FRACT_BITS;
+                        let texel_a = (a_a * one_minus_t_fract + b_a * t_fract) >> ST_FILTER_FRACT_BITS;
+
+                        (texel_r, texel_g, texel_b, texel_a)
+                    };
 
                     fn clamp_comp(comp: u32) -> u32 {
                         if (comp & (1 << (COLOR_WHOLE_BITS - 1))) != 0 {
@@ -422,43 +1081,140 @@ impl ColorThrust {
                     let b = clamp_comp(b >> COLOR_FRACT_BITS);
                     let a = clamp_comp(a >> COLOR_FRACT_BITS);
 
+                    let saturate_comp = |comp: u32| -> u32 {
+                        if comp >> 9 == 0 {
+                            comp
+                        } else {
+                            0x1ff
+                        }
+                    };
                     let scale_comp = |color_comp: u32, texel_comp: u32| -> u32 {
                         (color_comp * texel_comp) >> 8
                     };
+                    let add_comp = |color_comp: u32, texel_comp: u32| -> u32 {
+                        saturate_comp(color_comp + texel_comp)
+                    };
+                    let lerp_comp = |color_comp: u32, other_comp: u32, weight: u32| -> u32 {
+                        saturate_comp((color_comp * (256 - weight) + other_comp * weight) >> 8)
+                    };
 
-                    let r = scale_comp(r, texel_r);
-                    let g = scale_comp(g, texel_g);
-                    let b = scale_comp(b, texel_b);
-                    let a = scale_comp(a, texel_a);
-
-                    let zero = 0;
-                    let one = 1 << 8;
+                    let env_r = (self.texture_env_color >> 16) & 0xff;
+                    let env_g = (self.texture_env_color >> 8) & 0xff;
+                    let env_b = (self.texture_env_color >> 0) & 0xff;
 
-                    let blend_src_factor = match self.blend_src_factor {
-                        BlendSrcFactor::Zero => zero,
-                        BlendSrcFactor::One => one,
-                        BlendSrcFactor::SrcAlpha => a,
-                        BlendSrcFactor::OneMinusSrcAlpha => one - a,
+                    let (r, g, b, a) = match self.texture_env_mode {
+                        TextureEnvMode::Modulate => (
+                            scale_comp(r, texel_r),
+                            scale_comp(g, texel_g),
+                            scale_comp(b, texel_b),
+                            scale_comp(a, texel_a),
+                        ),
+                        TextureEnvMode::Replace => (texel_r, texel_g, texel_b, texel_a),
+                        TextureEnvMode::Decal => (
+                            lerp_comp(r, texel_r, texel_a),
+                            lerp_comp(g, texel_g, texel_a),
+                            lerp_comp(b, texel_b, texel_a),
+                            a,
+                        ),
+                        TextureEnvMode::Add => (
+                            add_comp(r, texel_r),
+                            add_comp(g, texel_g),
+                            add_comp(b, texel_b),
+                            scale_comp(a, texel_a),
+                        ),
+                        TextureEnvMode::Blend => (
+                            lerp_comp(r, env_r, texel_r),
+                            lerp_comp(g, env_g, texel_g),
+                            lerp_comp(b, env_b, texel_b),
+                            scale_comp(a, texel_a),
+                        ),
+                        TextureEnvMode::Untextured => (r, g, b, a),
                     };
 
-                    let blend_dst_factor = match self.blend_dst_factor {
-                        BlendDstFactor::Zero => zero,
-                        BlendDstFactor::One => one,
-                        BlendDstFactor::SrcAlpha => a,
-                        BlendDstFactor::OneMinusSrcAlpha => one - a,
+                    let fog_index = match self.fog_source {
+                        FogSource::Z => ((z >> (Z_FRACT_BITS - 16)) as u16 >> (16 - REG_FOG_TABLE_INDEX_BITS)) as u32,
+                        FogSource::W => {
+                            if self.fog_w_shift > 32 - REG_FOG_TABLE_INDEX_BITS {
+                                REG_FOG_TABLE_ENTRIES - 1
+                            } else {
+                                (w >> self.fog_w_shift).min(REG_FOG_TABLE_ENTRIES - 1)
+                            }
+                        }
+                    };
+                    let fog_amount = self.fog_table[fog_index as usize] as u32;
+                    // Map [0, 255] to [0, 256] so that a full table entry replaces the color entirely
+                    let fog_weight = if self.fog_enable { fog_amount + (fog_amount >> 7) } else { 0 };
+                    let fog_r = (self.fog_color >> 16) & 0xff;
+                    let fog_g = (self.fog_color >> 8) & 0xff;
+                    let fog_b = (self.fog_color >> 0) & 0xff;
+                    let fog_comp = |comp: u32, fog_color_comp: u32| -> u32 {
+                        ((comp * (256 - fog_weight) + fog_color_comp * fog_weight) >> 8) & 0x1ff
                     };
+                    let r = fog_comp(r, fog_r);
+                    let g = fog_comp(g, fog_g);
+                    let b = fog_comp(b, fog_b);
+
+                    let clamped_a = if a >> 8 == 0 { a } else { 0xff };
+                    let alpha_test_result = match self.alpha_test_func {
+                        AlphaFunc::Never => false,
+                        AlphaFunc::Less => clamped_a < self.alpha_test_ref,
+                        AlphaFunc::Equal => clamped_a == self.alpha_test_ref,
+                        AlphaFunc::LessOrEqual => clamped_a <= self.alpha_test_ref,
+                        AlphaFunc::Greater => clamped_a > self.alpha_test_ref,
+                        AlphaFunc::NotEqual => clamped_a != self.alpha_test_ref,
+                        AlphaFunc::GreaterOrEqual => clamped_a >= self.alpha_test_ref,
+                        AlphaFunc::Always => true,
+                    } || !self.alpha_test_enable;
 
-                    let buffer_index = y as usize * TILE_DIM as usize + x as usize;
+                    let buffer_index = ((y << self.tile_width_bits) + x) as usize;
 
                     let prev_color = self.color_buffer[buffer_index];
 
-                    let r = (r * blend_src_factor) >> 8;
-                    let g = (g * blend_src_factor) >> 8;
-                    let b = (b * blend_src_factor) >> 8;
+                    let prev_r = (prev_color >> 16) & 0xff;
+                    let prev_g = (prev_color >> 8) & 0xff;
+                    let prev_b = (prev_color >> 0) & 0xff;
+                    let prev_a = (prev_color >> 24) & 0xff;
 
-                    let prev_r = (((prev_color >> 16) & 0xff) * blend_dst_factor) >> 9;
-                    let prev_g = (((prev_color >> 8) & 0xff) * blend_dst_factor) >> 9;
-                    let prev_b = (((prev_color >> 0) & 0xff) * blend_dst_factor) >> 9;
+                    let blend_color_r = (self.blend_color >> 16) & 0xff;
+                    let blend_color_g = (self.blend_color >> 8) & 0xff;
+                    let blend_color_b = (self.blend_color >> 0) & 0xff;
+
+                    let zero = 0;
+                    let one = 1 << 8;
+
+                    let blend_src_factor = |comp: u32, prev_comp: u32, blend_color_comp: u32| -> u32 {
+                        match self.blend_src_factor {
+                            BlendSrcFactor::Zero => zero,
+                            BlendSrcFactor::One => one,
+                            BlendSrcFactor::SrcAlpha => a,
+                            BlendSrcFactor::OneMinusSrcAlpha => one.wrapping_sub(a),
+                            BlendSrcFactor::DstColor => prev_comp,
+                            BlendSrcFactor::OneMinusDstColor => one.wrapping_sub(prev_comp),
+                            BlendSrcFactor::SrcColor => comp,
+                            BlendSrcFactor::OneMinusSrcColor => one.wrapping_sub(comp),
+                            BlendSrcFactor::DstAlpha => prev_a,
+                            BlendSrcFactor::OneMinusDstAlpha => one.wrapping_sub(prev_a),
+                            BlendSrcFactor::ConstantColor => blend_color_comp,
+                            BlendSrcFactor::OneMinusConstantColor => one.wrapping_sub(blend_color_comp),
+                        }
+                    };
+
+                    let blend_dst_factor = |comp: u32, prev_comp: u32, blend_color_comp: u32| -> u32 {
+                        match self.blend_dst_factor {
+                            BlendDstFactor::Zero => zero,
+                            BlendDstFactor::One => one,
+                            BlendDstFactor::SrcAlpha => a,
+                            BlendDstFactor::OneMinusSrcAlpha => one.wrapping_sub(a),
+                            BlendDstFactor::DstColor => prev_comp,
+                            BlendDstFactor::OneMinusDstColor => one.wrapping_sub(prev_comp),
+                            BlendDstFactor::SrcColor => comp,
+                            BlendDstFactor::OneMinusSrcColor => one.wrapping_sub(comp),
+                            BlendDstFactor::DstAlpha => prev_a,
+                            BlendDstFactor::OneMinusDstAlpha => one.wrapping_sub(prev_a),
+                            BlendDstFactor::ConstantColor => blend_color_comp,
+                            BlendDstFactor::OneMinusConstantColor => one.wrapping_sub(blend_color_comp),
+                        }
+                    };
 
                     let clamp_comp = |comp: u32| -> u32 {
                         if comp >> 8 == 0 {
@@ -468,20 +1224,88 @@ impl ColorThrust {
                         }
                     };
 
-                    let r = clamp_comp(r + prev_r);
-                    let g = clamp_comp(g + prev_g);
-                    let b = clamp_comp(b + prev_b);
+                    let blend_comp = |comp: u32, prev_comp: u32, blend_color_comp: u32| -> u32 {
+                        // Factors are 9 bits in hardware, so wrap the same way
+                        let src_factor = blend_src_factor(comp, prev_comp, blend_color_comp) & 0x1ff;
+                        let dst_factor = blend_dst_factor(comp, prev_comp, blend_color_comp) & 0x1ff;
+
+                        let src_term = ((comp * src_factor) >> 8) & 0x3ff;
+                        let dst_term = (prev_comp * dst_factor) >> 9;
+
+                        clamp_comp(match self.blend_equation {
+                            BlendEquation::Add => (src_term + dst_term) & 0x3ff,
+                            BlendEquation::Subtract => src_term.saturating_sub(dst_term),
+                            BlendEquation::ReverseSubtract => dst_term.saturating_sub(src_term),
+                            BlendEquation::Min => comp.min(prev_comp),
+                            BlendEquation::Max => comp.max(prev_comp),
+                        })
+                    };
+
+                    let r = blend_comp(r, prev_r, blend_color_r);
+                    let g = blend_comp(g, prev_g, blend_color_g);
+                    let b = blend_comp(b, prev_b, blend_color_b);
                     let a = clamp_comp(a);
 
                     let color = (a << 24) | (r << 16) | (g << 8) | b;
 
                     let z = (z >> (Z_FRACT_BITS - 16)) as u16;
-                    let depth_test_result = z < self.depth_buffer[buffer_index] || !self.depth_test_enable;
+                    let prev_z = self.depth_buffer[buffer_index];
+                    let depth_test_result = match self.depth_func {
+                        DepthFunc::Never => false,
+                        DepthFunc::Less => z < prev_z,
+                        DepthFunc::Equal => z == prev_z,
+                        DepthFunc::LessOrEqual => z <= prev_z,
+                        DepthFunc::Greater => z > prev_z,
+                        DepthFunc::NotEqual => z != prev_z,
+                        DepthFunc::GreaterOrEqual => z >= prev_z,
+                        DepthFunc::Always => true,
+                    } || !self.depth_test_enable;
+
+                    let prev_stencil = self.stencil_buffer[buffer_index];
+                    let masked_stencil_ref = self.stencil_ref & self.stencil_mask;
+                    let masked_prev_stencil = prev_stencil & self.stencil_mask;
+                    let stencil_test_result = match self.stencil_func {
+                        StencilFunc::Never => false,
+                        StencilFunc::Less => masked_stencil_ref < masked_prev_stencil,
+                        StencilFunc::Equal => masked_stencil_ref == masked_prev_stencil,
+                        StencilFunc::LessOrEqual => masked_stencil_ref <= masked_prev_stencil,
+                        StencilFunc::Greater => masked_stencil_ref > masked_prev_stencil,
+                        StencilFunc::NotEqual => masked_stencil_ref != masked_prev_stencil,
+                        StencilFunc::GreaterOrEqual => masked_stencil_ref >= masked_prev_stencil,
+                        StencilFunc::Always => true,
+                    } || !self.stencil_test_enable;
+
+                    // The hardware updates stencil alongside the depth test, so the alpha test doesn't affect it
+                    if self.stencil_test_enable {
+                        let stencil_op = if !stencil_test_result {
+                            self.stencil_sfail_op
+                        } else if !depth_test_result {
+                            self.stencil_zfail_op
+                        } else {
+                            self.stencil_zpass_op
+                        };
+                        let next_stencil = match stencil_op {
+                            StencilOp::Keep => prev_stencil,
+                            StencilOp::Zero => 0,
+                            StencilOp::Replace => self.stencil_ref,
+                            StencilOp::Incr => prev_stencil.saturating_add(1),
+                            StencilOp::Decr => prev_stencil.saturating_sub(1),
+                            StencilOp::Invert => !prev_stencil,
+                            StencilOp::IncrWrap => prev_stencil.wrapping_add(1),
+                            StencilOp::DecrWrap => prev_stencil.wrapping_sub(1),
+                        };
+                        self.stencil_buffer[buffer_index] = (next_stencil & self.stencil_write_mask) | (prev_stencil & !self.stencil_write_mask);
+                    }
+
+                    if stencil_test_result && depth_test_result && alpha_test_result {
+                        if self.occlusion_query_active {
+                            self.occlusion_query_passed_samples = self.occlusion_query_passed_samples.wrapping_add(1);
+                        }
 
-                    if depth_test_result {
                         self.color_buffer[buffer_index] = color;
                         if self.depth_write_mask_enable {
                             self.depth_buffer[buffer_index] = z;
+                            depth_writes.push((buffer_index as u32, z));
                         }
                     }
                 }
@@ -489,27 +1313,36 @@ impl ColorThrust {
                 w0 += self.w0_dx;
                 w1 += self.w1_dx;
                 w2 += self.w2_dx;
-                r += self.r_dx;
-                g += self.g_dx;
-                b += self.b_dx;
-                a += self.a_dx;
-                w_inverse += self.w_inverse_dx;
                 z += self.z_dx;
-                s += self.s_dx;
-                t += self.t_dx;
+                if !self.aa_enable || (x & 1) != 0 {
+                    r += self.r_dx;
+                    g += self.g_dx;
+                    b += self.b_dx;
+                    a += self.a_dx;
+                    w_inverse += self.w_inverse_dx;
+                    s += self.s_dx;
+                    t += self.t_dx;
+                }
+                x += 1;
             }
 
             w0_row += self.w0_dy;
             w1_row += self.w1_dy;
             w2_row += self.w2_dy;
-            r_row += self.r_dy;
-            g_row += self.g_dy;
-            b_row += self.b_dy;
-            a_row += self.a_dy;
-            w_inverse_row += self.w_inverse_dy;
             z_row += self.z_dy;
-            s_row += self.s_dy;
-            t_row += self.t_dy;
+            if !self.aa_enable || (y & 1) != 0 {
+                r_row += self.r_dy;
+                g_row += self.g_dy;
+                b_row += self.b_dy;
+                a_row += self.a_dy;
+                w_inverse_row += self.w_inverse_dy;
+                s_row += self.s_dy;
+                t_row += self.t_dy;
+            }
+        }
+
+        for (buffer_index, z) in depth_writes {
+            self.widen_depth_bounds(buffer_index, z);
         }
     }
 
@@ -0,0 +1,90 @@
+use rtl_meta::scanout::*;
+
+// There's no display to feed, so the model stays in vertical blanking and latches front buffer writes immediately
+pub struct Scanout {
+    enable: bool,
+    front_buffer_base_addr: u32,
+    line_stride: u32,
+    format: u32,
+    timing: [u32; 6],
+    frame_count: u32,
+
+    overlay_enable: bool,
+    overlay_base_addr: u32,
+    overlay_line_stride: u32,
+}
+
+impl Scanout {
+    pub fn new() -> Scanout {
+        Scanout {
+            enable: false,
+            front_buffer_base_addr: 0,
+            line_stride: REG_LINE_STRIDE_DEFAULT,
+            format: REG_FORMAT_ARGB8888,
+            timing: [
+                REG_H_FRONT_PORCH_DEFAULT,
+                REG_H_SYNC_DEFAULT,
+                REG_H_BACK_PORCH_DEFAULT,
+                REG_V_FRONT_PORCH_DEFAULT,
+                REG_V_SYNC_DEFAULT,
+                REG_V_BACK_PORCH_DEFAULT,
+            ],
+            frame_count: 0,
+
+            overlay_enable: false,
+            overlay_base_addr: 0,
+            overlay_line_stride: REG_OVERLAY_LINE_STRIDE_DEFAULT,
+        }
+    }
+
+    pub fn write_reg(&mut self, addr: u32, data: u32) {
+        match addr {
+            REG_ENABLE_ADDR => {
+                self.enable = (data & 1) != 0;
+            }
+            REG_FRONT_BUFFER_BASE_ADDR => {
+                self.front_buffer_base_addr = data & !0xf;
+                self.frame_count = (self.frame_count + 1) & ((1 << REG_FRAME_COUNT_BITS) - 1);
+            }
+            REG_LINE_STRIDE_ADDR => {
+                self.line_stride = data;
+            }
+            REG_H_FRONT_PORCH_ADDR..=REG_V_BACK_PORCH_ADDR => {
+                self.timing[(addr - REG_H_FRONT_PORCH_ADDR) as usize] = data & ((1 << REG_TIMING_BITS) - 1);
+            }
+            REG_FORMAT_ADDR => {
+                self.format = data & ((1 << REG_FORMAT_BITS) - 1);
+            }
+            REG_PALETTE_ADDR | REG_GAMMA_LUT_ADDR => {
+                // Write-only, and nothing's displayed, so there's no need to keep them around
+            }
+            REG_OVERLAY_ENABLE_ADDR => {
+                self.overlay_enable = (data & 1) != 0;
+            }
+            REG_OVERLAY_BASE_ADDR => {
+                self.overlay_base_addr = data & !0xf;
+            }
+            REG_OVERLAY_LINE_STRIDE_ADDR => {
+                self.overlay_line_stride = data;
+            }
+            _ => panic!("Unrecognized addr: {}", addr)
+        }
+    }
+
+    pub fn read_reg(&mut self, addr: u32) -> u32 {
+        match addr {
+            REG_ENABLE_ADDR => self.enable as _,
+            REG_FRONT_BUFFER_BASE_ADDR => self.front_buffer_base_addr,
+            REG_LINE_STRIDE_ADDR => self.line_stride,
+            REG_CURRENT_LINE_ADDR => V_ACTIVE,
+            REG_H_FRONT_PORCH_ADDR..=REG_V_BACK_PORCH_ADDR => self.timing[(addr - REG_H_FRONT_PORCH_ADDR) as usize],
+            REG_STATUS_ADDR => 1 << REG_STATUS_VBLANK_BIT,
+            REG_FRAME_COUNT_ADDR => self.frame_count,
+            REG_FORMAT_ADDR => self.format,
+            REG_OVERLAY_ENABLE_ADDR => self.overlay_enable as _,
+            REG_OVERLAY_BASE_ADDR => self.overlay_base_addr,
+            REG_OVERLAY_LINE_STRIDE_ADDR => self.overlay_line_stride,
+            _ => panic!("Unrecognized addr: {}", addr)
+        }
+    }
+}
@@ -2,24 +2,38 @@ use abstract_environment::*;
 
 use core::fmt::{self, Result};
 
+use std::cell::Cell;
 use std::io::{self, Write};
+use std::rc::Rc;
 use std::time::Instant;
 
 pub struct ModelEnvironment {
     start_time: Instant,
+    cycles: Option<Rc<Cell<u64>>>,
 }
 
 impl ModelEnvironment {
     pub fn new() -> ModelEnvironment {
         ModelEnvironment {
             start_time: Instant::now(),
+            cycles: None,
+        }
+    }
+
+    pub fn with_cycles(cycles: Rc<Cell<u64>>) -> ModelEnvironment {
+        ModelEnvironment {
+            start_time: Instant::now(),
+            cycles: Some(cycles),
         }
     }
 }
 
 impl Environment<Stdout> for ModelEnvironment {
     fn cycles(&self) -> u64 {
-        0 // TODO!
+        match self.cycles {
+            Some(ref cycles) => cycles.get(),
+            _ => 0, // TODO!
+        }
     }
 
     fn stdout(&self) -> Stdout {
@@ -3,9 +3,13 @@ use crate::modules::*;
 
 use abstract_device::*;
 
+use std::cell::Cell;
+use std::rc::Rc;
+
 pub struct SimDevice {
     top: Top,
     mem_allocator: MemAllocator,
+    cycles: Rc<Cell<u64>>,
 }
 
 impl SimDevice {
@@ -14,15 +18,28 @@ impl SimDevice {
         top.reset();
         top.color_buffer_bus_enable = false;
         top.depth_buffer_bus_enable = false;
+        top.stencil_buffer_bus_enable = false;
         top.reg_bus_enable = false;
+        top.scanout_reg_bus_enable = false;
         top.mem_bus_enable = false;
         top.prop();
 
         SimDevice {
             top,
             mem_allocator: MemAllocator::new(),
+            cycles: Rc::new(Cell::new(0)),
         }
     }
+
+    // Shared with the environment so that cycle counts reported by strugl are simulated clock cycles
+    pub fn cycles(&self) -> Rc<Cell<u64>> {
+        self.cycles.clone()
+    }
+
+    fn posedge_clk(&mut self) {
+        self.top.posedge_clk();
+        self.cycles.set(self.cycles.get() + 1);
+    }
 }
 
 impl Device for SimDevice {
@@ -46,7 +63,7 @@ impl Device for SimDevice {
         self.top.prop();
         loop {
             let ready = self.top.mem_bus_ready;
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
             if ready {
                 break;
@@ -66,7 +83,7 @@ impl Device for SimDevice {
         self.top.prop();
         loop {
             let ready = self.top.mem_bus_ready;
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
             if ready {
                 break;
@@ -74,7 +91,7 @@ impl Device for SimDevice {
         }
         self.top.mem_bus_enable = false;
         while !self.top.mem_bus_read_data_valid {
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
         }
         self.top.mem_bus_read_data
@@ -96,7 +113,7 @@ impl Device for SimDevice {
         self.top.prop();
         loop {
             let ready = self.top.reg_bus_ready;
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
             if ready {
                 break;
@@ -112,7 +129,7 @@ impl Device for SimDevice {
         self.top.reg_bus_write = false;
         loop {
             let ready = self.top.reg_bus_ready;
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
             if ready {
                 break;
@@ -120,7 +137,7 @@ impl Device for SimDevice {
         }
         self.top.reg_bus_enable = false;
         while !self.top.reg_bus_read_data_valid {
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
         }
         self.top.reg_bus_read_data as _
@@ -135,7 +152,7 @@ impl Device for SimDevice {
         self.top.prop();
         loop {
             let ready = self.top.color_buffer_bus_ready;
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
             if ready {
                 break;
@@ -152,7 +169,7 @@ impl Device for SimDevice {
         self.top.prop();
         loop {
             let ready = self.top.color_buffer_bus_ready;
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
             if ready {
                 break;
@@ -161,7 +178,7 @@ impl Device for SimDevice {
         self.top.color_buffer_bus_enable = false;
         self.top.prop();
         while !self.top.color_buffer_bus_read_data_valid {
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
         }
         self.top.color_buffer_bus_read_data
@@ -176,7 +193,7 @@ impl Device for SimDevice {
         self.top.prop();
         loop {
             let ready = self.top.depth_buffer_bus_ready;
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
             if ready {
                 break;
@@ -193,7 +210,7 @@ impl Device for SimDevice {
         self.top.prop();
         loop {
             let ready = self.top.depth_buffer_bus_ready;
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
             if ready {
                 break;
@@ -202,9 +219,91 @@ impl Device for SimDevice {
         self.top.depth_buffer_bus_enable = false;
         self.top.prop();
         while !self.top.depth_buffer_bus_read_data_valid {
-            self.top.posedge_clk();
+            self.posedge_clk();
             self.top.prop();
         }
         self.top.depth_buffer_bus_read_data
     }
+
+    fn color_thrust_write_stencil_buffer_word(&mut self, addr: u32, data: u128) {
+        self.top.stencil_buffer_bus_addr = addr;
+        self.top.stencil_buffer_bus_enable = true;
+        self.top.stencil_buffer_bus_write = true;
+        self.top.stencil_buffer_bus_write_byte_enable = 0xffff;
+        self.top.stencil_buffer_bus_write_data = data;
+        self.top.prop();
+        loop {
+            let ready = self.top.stencil_buffer_bus_ready;
+            self.posedge_clk();
+            self.top.prop();
+            if ready {
+                break;
+            }
+        }
+        self.top.stencil_buffer_bus_enable = false;
+        self.top.prop();
+    }
+
+    fn color_thrust_read_stencil_buffer_word(&mut self, addr: u32) -> u128 {
+        self.top.stencil_buffer_bus_addr = addr;
+        self.top.stencil_buffer_bus_enable = true;
+        self.top.stencil_buffer_bus_write = false;
+        self.top.prop();
+        loop {
+            let ready = self.top.stencil_buffer_bus_ready;
+            self.posedge_clk();
+            self.top.prop();
+            if ready {
+                break;
+            }
+        }
+        self.top.stencil_buffer_bus_enable = false;
+        self.top.prop();
+        while !self.top.stencil_buffer_bus_read_data_valid {
+            self.posedge_clk();
+            self.top.prop();
+        }
+        self.top.stencil_buffer_bus_read_data
+    }
+
+    fn scanout_write_reg(&mut self, addr: u32, data: u32) {
+        self.top.scanout_reg_bus_addr = addr;
+        self.top.scanout_reg_bus_enable = true;
+        self.top.scanout_reg_bus_write = true;
+        self.top.scanout_reg_bus_write_byte_enable = 0xffff;
+        self.top.scanout_reg_bus_write_data = data as _;
+        self.top.prop();
+        loop {
+            let ready = self.top.scanout_reg_bus_ready;
+            self.posedge_clk();
+            self.top.prop();
+            if ready {
+                break;
+            }
+        }
+        self.top.scanout_reg_bus_enable = false;
+        self.top.prop();
+    }
+
+    fn scanout_read_reg(&mut self, addr: u32) -> u32 {
+        self.top.scanout_reg_bus_addr = addr;
+        self.top.scanout_reg_bus_enable = true;
+        self.top.scanout_reg_bus_write = false;
+        self.top.prop();
+        loop {
+            let ready = self.top.scanout_reg_bus_ready;
+            self.posedge_clk();
+            self.top.prop();
+            if ready {
+                break;
+            }
+        }
+        self.top.scanout_reg_bus_enable = false;
+        self.top.prop();
+        while !self.top.scanout_reg_bus_read_data_valid {
+            self.posedge_clk();
+            self.top.prop();
+        }
+        self.top.scanout_reg_bus_read_data as _
+    }
 }
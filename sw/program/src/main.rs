@@ -19,9 +19,12 @@ use xw::{stdio, uart};
 
 use core::fmt::Write;
 
+// Rgb565 halves tile store and frame transfer bandwidth, at the cost of dithering
+const BACK_BUFFER_FORMAT: ColorFormat = ColorFormat::Argb8888;
+
 #[no_mangle]
 fn main() -> ! {
-    let mut c = Context::new(NativeDevice::new());
+    let mut c = Context::with_back_buffer_format(NativeDevice::new(), BACK_BUFFER_FORMAT);
     let env = NativeEnvironment;
     let mut strugl_test = StruglTest::new(&mut c, &env);
 
@@ -47,12 +50,25 @@ fn main() -> ! {
                     uart::write_u8(0x03);
                     uart::write_u64_le(elapsed_cycles);
 
+                    // Pixels are sent in the back buffer's format, which is given first
+                    let color_format = c.back_buffer().color_format();
+                    uart::write_u8(match color_format {
+                        ColorFormat::Argb8888 => 0x00,
+                        ColorFormat::Rgb565 => 0x01,
+                    });
                     let back_buffer = c.extract_back_buffer();
                     for y in 0..HEIGHT {
                         for x in 0..WIDTH {
-                            uart::write_u32_le(back_buffer[(y * WIDTH + x) as usize]);
+                            let argb = back_buffer[(y * WIDTH + x) as usize];
+                            match color_format {
+                                ColorFormat::Argb8888 => uart::write_u32_le(argb),
+                                ColorFormat::Rgb565 => uart::write_u16_le(argb8888_to_rgb565(argb)),
+                            }
                         }
                     }
+
+                    // Also put the frame on the display
+                    c.present();
                     break;
                 }
                 command => {
@@ -87,4 +87,28 @@ impl Device for NativeDevice {
         let base_addr = 0x05000000 as *const u128; // TODO: Proper constant
         unsafe { ptr::read_volatile(base_addr.offset(addr as _)) }
     }
+
+    fn color_thrust_write_stencil_buffer_word(&mut self, addr: u32, data: u128) {
+        let base_addr = 0x07000000 as *mut u128; // TODO: Proper constant
+        unsafe {
+            ptr::write_volatile(base_addr.offset(addr as _), data);
+        }
+    }
+
+    fn color_thrust_read_stencil_buffer_word(&mut self, addr: u32) -> u128 {
+        let base_addr = 0x07000000 as *const u128; // TODO: Proper constant
+        unsafe { ptr::read_volatile(base_addr.offset(addr as _)) }
+    }
+
+    fn scanout_write_reg(&mut self, addr: u32, data: u32) {
+        let base_addr = 0x08000000 as *mut u32; // TODO: Proper constant
+        unsafe {
+            ptr::write_volatile(base_addr.offset((addr * 4) as _) as _, data);
+        }
+    }
+
+    fn scanout_read_reg(&mut self, addr: u32) -> u32 {
+        let base_addr = 0x08000000 as *const u32; // TODO: Proper constant
+        unsafe { ptr::read_volatile(base_addr.offset((addr * 4) as _) as *const u32) }
+    }
 }
@@ -0,0 +1,41 @@
+// TODO: Move
+pub const REG_BUS_ADDR_BITS: u32 = 20;
+pub const REG_BUS_ADDR_BIT_WIDTH: u32 = 3;
+
+// Each 128-bit word holds consecutive frames, with the earliest frame in the low bits. Each frame is a signed 16-bit
+//  left sample in its low half, followed by a signed 16-bit right sample.
+pub const FRAMES_PER_WORD_BITS: u32 = 2;
+
+// Playback starts from the beginning of the ring buffer when enabled, and loops until disabled. While disabled, both
+//  outputs idle at the midpoint (silence).
+pub const REG_ENABLE_ADDR: u32 = 0;
+
+// Byte address in mem; must be 16-byte aligned. Like REG_RING_BUFFER_NUM_WORDS_ADDR, it should only be changed while
+//  disabled.
+pub const REG_RING_BUFFER_BASE_ADDR: u32 = 1;
+
+// In 128-bit words; must be even and nonzero, since interrupts are raised per half
+pub const REG_RING_BUFFER_NUM_WORDS_ADDR: u32 = 2;
+pub const REG_RING_BUFFER_NUM_WORDS_BITS: u32 = 16;
+
+// System clock cycles per frame, eg. 100MHz / 48kHz ~= 2083
+pub const REG_SAMPLE_PERIOD_ADDR: u32 = 3;
+pub const REG_SAMPLE_PERIOD_BITS: u32 = 16;
+pub const REG_SAMPLE_PERIOD_DEFAULT: u32 = 2083;
+
+// Read-only; index of the next word to be played
+pub const REG_PLAY_POSITION_ADDR: u32 = 4;
+
+// Bits are set once playback has taken the last word of the corresponding half of the ring buffer, after which that
+//  half is free to be refilled. Writing 1 to a bit clears it. If a half is still unfilled when playback reaches it, the stale
+//  samples are simply played again.
+pub const REG_INTERRUPT_STATUS_ADDR: u32 = 5;
+pub const REG_INTERRUPT_FIRST_HALF_BIT: u32 = 0;
+pub const REG_INTERRUPT_SECOND_HALF_BIT: u32 = 1;
+pub const REG_INTERRUPT_BITS: u32 = 2;
+
+// The irq output is high while any status bit set here is also set in REG_INTERRUPT_STATUS_ADDR
+pub const REG_INTERRUPT_ENABLE_ADDR: u32 = 6;
+
+// Bit 0 is set when a word wasn't fetched in time, and silence was played in its place. Writing 1 clears it.
+pub const REG_UNDERRUN_ADDR: u32 = 7;
@@ -6,9 +6,13 @@ pub const REG_STATUS_ADDR: u32 = 0;
 pub const REG_START_ADDR: u32 = 0;
 
 pub const REG_DIRECTION_ADDR: u32 = 1;
-pub const REG_DIRECTION_BITS: u32 = 1;
+pub const REG_DIRECTION_BITS: u32 = 2;
 pub const REG_DIRECTION_MEM2SYS: u32 = 0;
 pub const REG_DIRECTION_SYS2MEM: u32 = 1;
+// Blit within mem; the sys addr regs describe the source rect, and the mem addr regs the destination rect
+pub const REG_DIRECTION_MEM2MEM: u32 = 2;
+// Writes the fill value to the rect described by the mem addr regs; the sys addr regs are unused
+pub const REG_DIRECTION_FILL: u32 = 3;
 
 pub const REG_NUM_WORDS_ADDR: u32 = 2;
 
@@ -23,3 +27,57 @@ pub const REG_MEM_ADDR_ADDR: u32 = 6;
 pub const REG_MEM_WORDS_PER_SPAN_ADDR: u32 = 7;
 
 pub const REG_MEM_SPAN_STRIDE_ADDR: u32 = 8;
+
+// Writing a descriptor's (byte) address starts executing the chain it heads; the status reg stays busy until the
+//  whole chain is done
+pub const REG_DESCRIPTOR_ADDR_ADDR: u32 = 9;
+
+// Descriptors are DESCRIPTOR_WORDS consecutive 128-bit words in mem, each field being 32 bits wide:
+//  - Word 0: sys addr (or fill value), sys words per span, sys span stride, num words
+//  - Word 1: mem addr, mem words per span, mem span stride, control
+//  - Word 2: status, written back by the hardware once the descriptor's transfer is complete (the rest is zeroed)
+// The control field holds the next descriptor's (16-byte aligned) address, with the low bits reused for flags
+pub const DESCRIPTOR_WORDS: u32 = 3;
+pub const DESCRIPTOR_STATUS_WORD: u32 = 2;
+
+pub const DESCRIPTOR_CONTROL_DIRECTION_BIT: u32 = 0;
+pub const DESCRIPTOR_CONTROL_LAST_BIT: u32 = 2;
+pub const DESCRIPTOR_CONTROL_NEXT_ADDR_BIT_OFFSET: u32 = 4;
+
+pub const DESCRIPTOR_STATUS_COMPLETE: u32 = 1;
+
+// Replicated across each 128-bit word; descriptors load this from their sys addr field
+pub const REG_FILL_VALUE_ADDR: u32 = 10;
+
+// Pixel ops only apply to MEM2MEM and FILL, and treat each word as 4 ARGB8888 pixels. They're applied in order:
+//  - Color key: source pixels whose RGB matches the color key reg are skipped, leaving the destination pixel as-is
+//  - Blend: source pixels are blended over the destination using the source alpha
+//  - Logic op: combines the (possibly blended) source with the destination bitwise, as in glLogicOp
+// The destination is only read when the blend or logic op needs it
+pub const REG_PIXEL_OP_SETTINGS_ADDR: u32 = 11;
+pub const REG_PIXEL_OP_SETTINGS_BITS: u32 = 8;
+pub const REG_PIXEL_OP_SETTINGS_COLOR_KEY_ENABLE_BIT: u32 = 0;
+pub const REG_PIXEL_OP_SETTINGS_BLEND_ENABLE_BIT: u32 = 1;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_BIT_OFFSET: u32 = 4;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_BITS: u32 = 4;
+// Logic ops are truth tables; bit ((src << 1) | dst) holds the result for that pair of source and destination bits
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_CLEAR: u32 = 0x0;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_NOR: u32 = 0x1;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_AND_INVERTED: u32 = 0x2;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_COPY_INVERTED: u32 = 0x3;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_AND_REVERSE: u32 = 0x4;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_INVERT: u32 = 0x5;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_XOR: u32 = 0x6;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_NAND: u32 = 0x7;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_AND: u32 = 0x8;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_EQUIV: u32 = 0x9;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_NOOP: u32 = 0xa;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_OR_INVERTED: u32 = 0xb;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_COPY: u32 = 0xc;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_OR_REVERSE: u32 = 0xd;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_OR: u32 = 0xe;
+pub const REG_PIXEL_OP_SETTINGS_LOGIC_OP_SET: u32 = 0xf;
+
+// RGB only; alpha is ignored when comparing
+pub const REG_COLOR_KEY_ADDR: u32 = 12;
+pub const REG_COLOR_KEY_BITS: u32 = 24;
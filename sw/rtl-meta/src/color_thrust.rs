@@ -1,10 +1,10 @@
 use crate::xenowing::*;
 
-pub const TILE_DIM_BITS: u32 = 5;
-pub const TILE_DIM: u32 = 1 << TILE_DIM_BITS;
-pub const TILE_PIXELS_BITS: u32 = TILE_DIM_BITS * 2;
-pub const TILE_PIXELS: u32 = 1 << TILE_PIXELS_BITS;
-pub const TILE_PIXELS_WORDS_BITS: u32 = TILE_PIXELS_BITS - 2;
+// Tile width/height are generator parameters (in log2 pixels, within these bounds); software reads them from REG_CAPS
+pub const TILE_DIM_BITS_MIN: u32 = 4;
+pub const TILE_DIM_BITS_MAX: u32 = 6;
+// Used for both dimensions unless a build overrides them
+pub const TILE_DIM_BITS_DEFAULT: u32 = 5;
 
 // TODO: Move
 pub const TEX_WORD_ADDR_BITS: u32 = SYSTEM_BUS_ADDR_BITS;
@@ -34,9 +34,23 @@ pub const REG_START_ADDR: u32 = 0;
 pub const REG_TEX_CACHE_INVALIDATE_ADDR: u32 = 1;
 
 pub const REG_DEPTH_SETTINGS_ADDR: u32 = 2;
-pub const REG_DEPTH_SETTINGS_BITS: u32 = 2;
+pub const REG_DEPTH_SETTINGS_BITS: u32 = 6;
 pub const REG_DEPTH_TEST_ENABLE_BIT: u32 = 0;
 pub const REG_DEPTH_WRITE_MASK_ENABLE_BIT: u32 = 1;
+// Bit 0 passes when z < prev, bit 1 when z == prev, bit 2 when z > prev
+pub const REG_DEPTH_FUNC_BIT_OFFSET: u32 = 2;
+pub const REG_DEPTH_FUNC_BITS: u32 = 3;
+pub const REG_DEPTH_FUNC_NEVER: u32 = 0;
+pub const REG_DEPTH_FUNC_LESS: u32 = 1;
+pub const REG_DEPTH_FUNC_EQUAL: u32 = 2;
+pub const REG_DEPTH_FUNC_LEQUAL: u32 = 3;
+pub const REG_DEPTH_FUNC_GREATER: u32 = 4;
+pub const REG_DEPTH_FUNC_NOTEQUAL: u32 = 5;
+pub const REG_DEPTH_FUNC_GEQUAL: u32 = 6;
+pub const REG_DEPTH_FUNC_ALWAYS: u32 = 7;
+// Skip 8x8 blocks (and whole primitives) whose z range (REG_Z_BOUNDS) can't pass the depth test against the tracked
+//  depth bounds of each block. Ignored unless the depth test is enabled and the stencil test is disabled.
+pub const REG_DEPTH_HIZ_ENABLE_BIT: u32 = REG_DEPTH_FUNC_BIT_OFFSET + REG_DEPTH_FUNC_BITS;
 
 pub const REG_TEXTURE_SETTINGS_ADDR: u32 = 3;
 pub const REG_TEXTURE_SETTINGS_BITS: u32 = 3;
@@ -56,19 +70,43 @@ pub const REG_TEXTURE_BASE_ADDR: u32 = 4;
 pub const REG_TEXTURE_BASE_BITS: u32 = TEX_WORD_ADDR_BITS - 6;
 
 pub const REG_BLEND_SETTINGS_ADDR: u32 = 5;
-pub const REG_BLEND_SETTINGS_BITS: u32 = 4;
+pub const REG_BLEND_SETTINGS_BITS: u32 = 11;
 pub const REG_BLEND_SETTINGS_SRC_FACTOR_BIT_OFFSET: u32 = 0;
-pub const REG_BLEND_SETTINGS_SRC_FACTOR_BITS: u32 = 2;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_BITS: u32 = 4;
 pub const REG_BLEND_SETTINGS_SRC_FACTOR_ZERO: u32 = 0;
 pub const REG_BLEND_SETTINGS_SRC_FACTOR_ONE: u32 = 1;
 pub const REG_BLEND_SETTINGS_SRC_FACTOR_SRC_ALPHA: u32 = 2;
 pub const REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_ALPHA: u32 = 3;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_DST_COLOR: u32 = 4;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_COLOR: u32 = 5;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_SRC_COLOR: u32 = 6;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_COLOR: u32 = 7;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_DST_ALPHA: u32 = 8;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_ALPHA: u32 = 9;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_CONSTANT_COLOR: u32 = 10;
+pub const REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_CONSTANT_COLOR: u32 = 11;
 pub const REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET: u32 = REG_BLEND_SETTINGS_SRC_FACTOR_BIT_OFFSET + REG_BLEND_SETTINGS_SRC_FACTOR_BITS;
-pub const REG_BLEND_SETTINGS_DST_FACTOR_BITS: u32 = 2;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_BITS: u32 = 4;
 pub const REG_BLEND_SETTINGS_DST_FACTOR_ZERO: u32 = 0;
 pub const REG_BLEND_SETTINGS_DST_FACTOR_ONE: u32 = 1;
 pub const REG_BLEND_SETTINGS_DST_FACTOR_SRC_ALPHA: u32 = 2;
 pub const REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_ALPHA: u32 = 3;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_DST_COLOR: u32 = 4;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_COLOR: u32 = 5;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_SRC_COLOR: u32 = 6;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_COLOR: u32 = 7;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_DST_ALPHA: u32 = 8;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_ALPHA: u32 = 9;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_CONSTANT_COLOR: u32 = 10;
+pub const REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_CONSTANT_COLOR: u32 = 11;
+pub const REG_BLEND_SETTINGS_EQUATION_BIT_OFFSET: u32 = REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET + REG_BLEND_SETTINGS_DST_FACTOR_BITS;
+pub const REG_BLEND_SETTINGS_EQUATION_BITS: u32 = 3;
+pub const REG_BLEND_SETTINGS_EQUATION_ADD: u32 = 0;
+pub const REG_BLEND_SETTINGS_EQUATION_SUBTRACT: u32 = 1;
+pub const REG_BLEND_SETTINGS_EQUATION_REVERSE_SUBTRACT: u32 = 2;
+// MIN/MAX ignore the blend factors
+pub const REG_BLEND_SETTINGS_EQUATION_MIN: u32 = 3;
+pub const REG_BLEND_SETTINGS_EQUATION_MAX: u32 = 4;
 
 pub const REG_W0_MIN_ADDR: u32 = 6;
 pub const REG_W0_DX_ADDR: u32 = 7;
@@ -103,3 +141,200 @@ pub const REG_S_DY_ADDR: u32 = 35;
 pub const REG_T_MIN_ADDR: u32 = 36;
 pub const REG_T_DX_ADDR: u32 = 37;
 pub const REG_T_DY_ADDR: u32 = 38;
+
+pub const REG_ALPHA_TEST_SETTINGS_ADDR: u32 = 39;
+pub const REG_ALPHA_TEST_SETTINGS_BITS: u32 = 12;
+pub const REG_ALPHA_TEST_ENABLE_BIT: u32 = 0;
+// Same encoding as REG_DEPTH_FUNC_*, comparing fragment alpha against the reference value
+pub const REG_ALPHA_TEST_FUNC_BIT_OFFSET: u32 = 1;
+pub const REG_ALPHA_TEST_FUNC_BITS: u32 = 3;
+pub const REG_ALPHA_TEST_FUNC_NEVER: u32 = 0;
+pub const REG_ALPHA_TEST_FUNC_LESS: u32 = 1;
+pub const REG_ALPHA_TEST_FUNC_EQUAL: u32 = 2;
+pub const REG_ALPHA_TEST_FUNC_LEQUAL: u32 = 3;
+pub const REG_ALPHA_TEST_FUNC_GREATER: u32 = 4;
+pub const REG_ALPHA_TEST_FUNC_NOTEQUAL: u32 = 5;
+pub const REG_ALPHA_TEST_FUNC_GEQUAL: u32 = 6;
+pub const REG_ALPHA_TEST_FUNC_ALWAYS: u32 = 7;
+pub const REG_ALPHA_TEST_REF_BIT_OFFSET: u32 = REG_ALPHA_TEST_FUNC_BIT_OFFSET + REG_ALPHA_TEST_FUNC_BITS;
+pub const REG_ALPHA_TEST_REF_BITS: u32 = 8;
+
+pub const REG_TEXTURE_ENV_ADDR: u32 = 40;
+pub const REG_TEXTURE_ENV_BITS: u32 = 3;
+pub const REG_TEXTURE_ENV_MODE_BIT_OFFSET: u32 = 0;
+pub const REG_TEXTURE_ENV_MODE_BITS: u32 = 3;
+pub const REG_TEXTURE_ENV_MODE_MODULATE: u32 = 0;
+pub const REG_TEXTURE_ENV_MODE_REPLACE: u32 = 1;
+pub const REG_TEXTURE_ENV_MODE_DECAL: u32 = 2;
+pub const REG_TEXTURE_ENV_MODE_ADD: u32 = 3;
+// Interpolates between the vertex color and REG_TEXTURE_ENV_COLOR, weighted per-component by the texel
+pub const REG_TEXTURE_ENV_MODE_BLEND: u32 = 4;
+pub const REG_TEXTURE_ENV_MODE_UNTEXTURED: u32 = 5;
+
+// ARGB8888
+pub const REG_TEXTURE_ENV_COLOR_ADDR: u32 = 41;
+pub const REG_TEXTURE_ENV_COLOR_BITS: u32 = 32;
+
+// ARGB8888
+pub const REG_BLEND_COLOR_ADDR: u32 = 42;
+pub const REG_BLEND_COLOR_BITS: u32 = 32;
+
+pub const REG_STENCIL_SETTINGS_ADDR: u32 = 43;
+pub const REG_STENCIL_SETTINGS_BITS: u32 = 13;
+pub const REG_STENCIL_TEST_ENABLE_BIT: u32 = 0;
+// Same encoding as REG_DEPTH_FUNC_*, comparing (ref & mask) against (stencil & mask)
+pub const REG_STENCIL_FUNC_BIT_OFFSET: u32 = 1;
+pub const REG_STENCIL_FUNC_BITS: u32 = 3;
+pub const REG_STENCIL_FUNC_NEVER: u32 = 0;
+pub const REG_STENCIL_FUNC_LESS: u32 = 1;
+pub const REG_STENCIL_FUNC_EQUAL: u32 = 2;
+pub const REG_STENCIL_FUNC_LEQUAL: u32 = 3;
+pub const REG_STENCIL_FUNC_GREATER: u32 = 4;
+pub const REG_STENCIL_FUNC_NOTEQUAL: u32 = 5;
+pub const REG_STENCIL_FUNC_GEQUAL: u32 = 6;
+pub const REG_STENCIL_FUNC_ALWAYS: u32 = 7;
+// Applied when the stencil test fails
+pub const REG_STENCIL_SFAIL_OP_BIT_OFFSET: u32 = REG_STENCIL_FUNC_BIT_OFFSET + REG_STENCIL_FUNC_BITS;
+// Applied when the stencil test passes but the depth test fails
+pub const REG_STENCIL_ZFAIL_OP_BIT_OFFSET: u32 = REG_STENCIL_SFAIL_OP_BIT_OFFSET + REG_STENCIL_OP_BITS;
+// Applied when both the stencil and depth tests pass
+pub const REG_STENCIL_ZPASS_OP_BIT_OFFSET: u32 = REG_STENCIL_ZFAIL_OP_BIT_OFFSET + REG_STENCIL_OP_BITS;
+pub const REG_STENCIL_OP_BITS: u32 = 3;
+pub const REG_STENCIL_OP_KEEP: u32 = 0;
+pub const REG_STENCIL_OP_ZERO: u32 = 1;
+pub const REG_STENCIL_OP_REPLACE: u32 = 2;
+// INCR/DECR saturate, INCR_WRAP/DECR_WRAP wrap around
+pub const REG_STENCIL_OP_INCR: u32 = 3;
+pub const REG_STENCIL_OP_DECR: u32 = 4;
+pub const REG_STENCIL_OP_INVERT: u32 = 5;
+pub const REG_STENCIL_OP_INCR_WRAP: u32 = 6;
+pub const REG_STENCIL_OP_DECR_WRAP: u32 = 7;
+
+pub const REG_STENCIL_REF_ADDR: u32 = 44;
+pub const REG_STENCIL_REF_BITS: u32 = 24;
+pub const REG_STENCIL_REF_VALUE_BIT_OFFSET: u32 = 0;
+pub const REG_STENCIL_REF_VALUE_BITS: u32 = 8;
+pub const REG_STENCIL_REF_MASK_BIT_OFFSET: u32 = REG_STENCIL_REF_VALUE_BIT_OFFSET + REG_STENCIL_REF_VALUE_BITS;
+pub const REG_STENCIL_REF_MASK_BITS: u32 = 8;
+pub const REG_STENCIL_REF_WRITE_MASK_BIT_OFFSET: u32 = REG_STENCIL_REF_MASK_BIT_OFFSET + REG_STENCIL_REF_MASK_BITS;
+pub const REG_STENCIL_REF_WRITE_MASK_BITS: u32 = 8;
+
+pub const REG_FOG_SETTINGS_ADDR: u32 = 45;
+pub const REG_FOG_SETTINGS_BITS: u32 = 7;
+pub const REG_FOG_ENABLE_BIT: u32 = 0;
+pub const REG_FOG_SOURCE_BIT_OFFSET: u32 = 1;
+pub const REG_FOG_SOURCE_BITS: u32 = 1;
+// Table is indexed by the top bits of z
+pub const REG_FOG_SOURCE_Z: u32 = 0;
+// Table is indexed by (w >> W_SHIFT), saturating to the last entry (w has RESTORED_W_FRACT_BITS fractional bits)
+pub const REG_FOG_SOURCE_W: u32 = 1;
+// Must be at most 32 - REG_FOG_TABLE_INDEX_BITS; larger values always select the last entry
+pub const REG_FOG_W_SHIFT_BIT_OFFSET: u32 = REG_FOG_SOURCE_BIT_OFFSET + REG_FOG_SOURCE_BITS;
+pub const REG_FOG_W_SHIFT_BITS: u32 = 5;
+
+// ARGB8888 (alpha is ignored)
+pub const REG_FOG_COLOR_ADDR: u32 = 46;
+pub const REG_FOG_COLOR_BITS: u32 = 32;
+
+// Write-only; each write sets a single table entry. Entries give the amount of fog to apply,
+//  where 0 leaves the fragment color untouched and 255 replaces it with the fog color entirely.
+pub const REG_FOG_TABLE_ADDR: u32 = 47;
+pub const REG_FOG_TABLE_ENTRIES: u32 = 1 << REG_FOG_TABLE_INDEX_BITS;
+pub const REG_FOG_TABLE_VALUE_BIT_OFFSET: u32 = 0;
+pub const REG_FOG_TABLE_VALUE_BITS: u32 = 8;
+pub const REG_FOG_TABLE_INDEX_BIT_OFFSET: u32 = REG_FOG_TABLE_VALUE_BIT_OFFSET + REG_FOG_TABLE_VALUE_BITS;
+pub const REG_FOG_TABLE_INDEX_BITS: u32 = 6;
+
+// ARGB8888
+pub const REG_CLEAR_COLOR_ADDR: u32 = 48;
+pub const REG_CLEAR_COLOR_BITS: u32 = 32;
+
+pub const REG_CLEAR_DEPTH_STENCIL_ADDR: u32 = 49;
+pub const REG_CLEAR_DEPTH_STENCIL_BITS: u32 = 24;
+pub const REG_CLEAR_DEPTH_BIT_OFFSET: u32 = 0;
+pub const REG_CLEAR_DEPTH_BITS: u32 = 16;
+pub const REG_CLEAR_STENCIL_BIT_OFFSET: u32 = REG_CLEAR_DEPTH_BIT_OFFSET + REG_CLEAR_DEPTH_BITS;
+pub const REG_CLEAR_STENCIL_BITS: u32 = 8;
+
+// Write-only; fills the selected tile buffers with the clear values. Shares the busy bit in REG_STATUS with primitive
+//  rasterization, so it must not be issued while a primitive is in flight (and vice versa).
+pub const REG_CLEAR_TILE_ADDR: u32 = 50;
+pub const REG_CLEAR_TILE_BITS: u32 = 3;
+pub const REG_CLEAR_TILE_COLOR_BIT: u32 = 0;
+pub const REG_CLEAR_TILE_DEPTH_BIT: u32 = 1;
+pub const REG_CLEAR_TILE_STENCIL_BIT: u32 = 2;
+
+pub const HIZ_BLOCK_DIM_BITS: u32 = 3;
+pub const HIZ_BLOCK_DIM: u32 = 1 << HIZ_BLOCK_DIM_BITS;
+
+// Conservative z range of the next primitive (latched on START)
+pub const REG_Z_BOUNDS_ADDR: u32 = 51;
+pub const REG_Z_BOUNDS_BITS: u32 = 32;
+pub const REG_Z_BOUNDS_MIN_BIT_OFFSET: u32 = 0;
+pub const REG_Z_BOUNDS_MIN_BITS: u32 = 16;
+pub const REG_Z_BOUNDS_MAX_BIT_OFFSET: u32 = REG_Z_BOUNDS_MIN_BIT_OFFSET + REG_Z_BOUNDS_MIN_BITS;
+pub const REG_Z_BOUNDS_MAX_BITS: u32 = 16;
+
+// Write-only; empties the depth bounds of every block. Depth buffer writes (over the bus, by the pixel pipe, or by a
+//  tile clear) only ever widen the bounds, so this must be followed by writing the entire depth tile before
+//  hierarchical z is used.
+pub const REG_DEPTH_BOUNDS_RESET_ADDR: u32 = 52;
+
+// Counters; writing either register resets it to 0
+pub const REG_HIZ_REJECTED_PRIMITIVES_ADDR: u32 = 53;
+pub const REG_HIZ_REJECTED_BLOCKS_ADDR: u32 = 54;
+
+// Read-only; the generator parameters this ColorThrust instance was built with
+pub const REG_CAPS_ADDR: u32 = 55;
+pub const REG_CAPS_BITS: u32 = 8;
+pub const REG_CAPS_TILE_WIDTH_LOG2_BIT_OFFSET: u32 = 0;
+pub const REG_CAPS_TILE_WIDTH_LOG2_BITS: u32 = 3;
+pub const REG_CAPS_TILE_HEIGHT_LOG2_BIT_OFFSET: u32 = REG_CAPS_TILE_WIDTH_LOG2_BIT_OFFSET + REG_CAPS_TILE_WIDTH_LOG2_BITS;
+pub const REG_CAPS_TILE_HEIGHT_LOG2_BITS: u32 = 3;
+pub const REG_CAPS_PIXEL_PIPES_LOG2_BIT_OFFSET: u32 = REG_CAPS_TILE_HEIGHT_LOG2_BIT_OFFSET + REG_CAPS_TILE_HEIGHT_LOG2_BITS;
+pub const REG_CAPS_PIXEL_PIPES_LOG2_BITS: u32 = 2;
+
+// 4x multisampling; each pixel covers a 2x2 group of samples in the tile buffers, so a tile covers a quarter as many pixels
+pub const REG_AA_SETTINGS_ADDR: u32 = 56;
+pub const REG_AA_SETTINGS_BITS: u32 = 1;
+pub const REG_AA_ENABLE_BIT: u32 = 0;
+
+// Write-only; averages each pixel's samples in the color buffer, packing the resolved pixels into its first quarter
+pub const REG_AA_RESOLVE_ADDR: u32 = 57;
+
+// Write-only; converts the color buffer to RGB565 with ordered dithering, packing 8 pixels into each word of its first
+//  half. Dithering assumes rows of the full tile width, or half of it (as left by REG_AA_RESOLVE) if the resolved bit is set.
+pub const REG_COLOR_PACK_ADDR: u32 = 58;
+pub const REG_COLOR_PACK_BITS: u32 = 1;
+pub const REG_COLOR_PACK_RESOLVED_BIT: u32 = 0;
+
+// Write-only; expands RGB565 pixels packed into the first half of the color buffer back to ARGB8888 (with opaque alpha)
+pub const REG_COLOR_UNPACK_ADDR: u32 = 59;
+
+// Write-only; begin resets the passed samples counter and starts counting, end stops counting. Only samples
+//  that pass the depth, stencil and alpha tests are counted.
+pub const REG_OCCLUSION_QUERY_BEGIN_ADDR: u32 = 60;
+pub const REG_OCCLUSION_QUERY_END_ADDR: u32 = 61;
+
+// Read-only
+pub const REG_OCCLUSION_QUERY_PASSED_SAMPLES_ADDR: u32 = 62;
+
+// Write-only; converts the color buffer between the linear layout and the block-swizzled texture layout (see strugl's
+//  alloc_texture_data), so render-to-texture tiles can be moved with plain transfers. Each pair of consecutive color
+//  words (8 pixels of a row) becomes one word of the even pixels followed by one of the odd pixels; the inverse bit
+//  converts back. Swizzled tiles are meant to be accessed through the color buffer bus's swizzled window.
+pub const REG_COLOR_SWIZZLE_ADDR: u32 = 63;
+pub const REG_COLOR_SWIZZLE_BITS: u32 = 1;
+pub const REG_COLOR_SWIZZLE_INVERSE_BIT: u32 = 0;
+
+// Color buffer bus word addresses with this bit set see the tile's words reordered by 2x2 block index (y & 1, then
+//  x & 1), then by row and word within it, which lines each block index's words up with the matching texture chunk
+pub const COLOR_BUFFER_BUS_SWIZZLED_WINDOW_BIT: u32 = 19;
+
+// Indexed by [y & 3][x & 3]; added to each 8-bit channel (scaled down to the channel's truncated bits) before truncation
+pub const COLOR_PACK_DITHER_MATRIX: [[u32; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
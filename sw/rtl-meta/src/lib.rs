@@ -1,5 +1,9 @@
 #![no_std]
 
+pub mod audio;
 pub mod bit_pusher;
 pub mod color_thrust;
+pub mod scanout;
+pub mod spi;
+pub mod uart;
 pub mod xenowing;
@@ -0,0 +1,102 @@
+// TODO: Move
+pub const REG_BUS_ADDR_BITS: u32 = 20;
+pub const REG_BUS_ADDR_BIT_WIDTH: u32 = 5;
+
+// Active area is fixed; only the blanking intervals are configurable
+pub const H_ACTIVE: u32 = 640;
+pub const V_ACTIVE: u32 = 480;
+
+// Each 128-bit word holds consecutive pixels on a line, with the leftmost pixel in the low bits
+pub const ARGB8888_PIXELS_PER_WORD_BITS: u32 = 2;
+pub const RGB565_PIXELS_PER_WORD_BITS: u32 = 3;
+pub const INDEXED8_PIXELS_PER_WORD_BITS: u32 = 4;
+pub const MAX_PIXELS_PER_WORD_BITS: u32 = INDEXED8_PIXELS_PER_WORD_BITS;
+
+// Wide enough to count a full line/frame including blanking with all porch/sync regs at their max
+pub const TIMING_COUNTER_BITS: u32 = 11;
+
+// Lines are fetched starting at REG_FRONT_BUFFER_BASE_ADDR and stepping by REG_LINE_STRIDE_ADDR; both (as well as
+//  REG_ENABLE_ADDR, REG_FORMAT_ADDR, and the REG_OVERLAY_* regs) are latched once per frame, at the start of the
+//  vertical back porch, so writes never take effect mid-frame
+pub const REG_ENABLE_ADDR: u32 = 0;
+
+// Byte address in mem; must be 16-byte aligned
+pub const REG_FRONT_BUFFER_BASE_ADDR: u32 = 1;
+
+// Signed, in 128-bit words; negative strides scan the buffer bottom-up
+pub const REG_LINE_STRIDE_ADDR: u32 = 2;
+pub const REG_LINE_STRIDE_DEFAULT: u32 = H_ACTIVE >> ARGB8888_PIXELS_PER_WORD_BITS;
+
+// Read-only; lines [0, V_ACTIVE) are visible, the rest are vertical blanking
+pub const REG_CURRENT_LINE_ADDR: u32 = 3;
+
+// Blanking intervals, in pixels/lines. Each line/frame is laid out as active, front porch, sync, back porch. Sync
+//  outputs are active-low, as 640x480@60 calls for.
+pub const REG_TIMING_BITS: u32 = 8;
+
+pub const REG_H_FRONT_PORCH_ADDR: u32 = 4;
+pub const REG_H_FRONT_PORCH_DEFAULT: u32 = 16;
+
+pub const REG_H_SYNC_ADDR: u32 = 5;
+pub const REG_H_SYNC_DEFAULT: u32 = 96;
+
+pub const REG_H_BACK_PORCH_ADDR: u32 = 6;
+pub const REG_H_BACK_PORCH_DEFAULT: u32 = 48;
+
+pub const REG_V_FRONT_PORCH_ADDR: u32 = 7;
+pub const REG_V_FRONT_PORCH_DEFAULT: u32 = 10;
+
+pub const REG_V_SYNC_ADDR: u32 = 8;
+pub const REG_V_SYNC_DEFAULT: u32 = 2;
+
+pub const REG_V_BACK_PORCH_ADDR: u32 = 9;
+pub const REG_V_BACK_PORCH_DEFAULT: u32 = 33;
+
+// Read-only
+pub const REG_STATUS_ADDR: u32 = 10;
+pub const REG_STATUS_VBLANK_BIT: u32 = 0;
+// Set by writes to REG_FRONT_BUFFER_BASE_ADDR and cleared once the new base is latched. While it's set, the previous
+//  front buffer may still be scanned out, so it isn't safe to render into yet.
+pub const REG_STATUS_FLIP_PENDING_BIT: u32 = 1;
+
+// Read-only; incremented (and wrapping) each time the front buffer regs are latched
+pub const REG_FRAME_COUNT_ADDR: u32 = 11;
+pub const REG_FRAME_COUNT_BITS: u32 = 16;
+
+pub const REG_FORMAT_ADDR: u32 = 12;
+pub const REG_FORMAT_BITS: u32 = 2;
+// Alpha is ignored
+pub const REG_FORMAT_ARGB8888: u32 = 0;
+// Expanded to 8 bits per component by bit replication, the same way ColorThrust loads RGB565 tiles
+pub const REG_FORMAT_RGB565: u32 = 1;
+// Each pixel is an index into the palette
+pub const REG_FORMAT_INDEXED8: u32 = 2;
+
+// Write-only. Each write sets a single RGB888 entry, and takes effect immediately (not at the next frame), so palette
+//  cycling should update entries during vertical blanking. Entries default to a grayscale ramp.
+pub const REG_PALETTE_ADDR: u32 = 13;
+pub const REG_PALETTE_ENTRIES: u32 = 1 << REG_PALETTE_INDEX_BITS;
+pub const REG_PALETTE_VALUE_BIT_OFFSET: u32 = 0;
+pub const REG_PALETTE_VALUE_BITS: u32 = 24;
+pub const REG_PALETTE_INDEX_BIT_OFFSET: u32 = REG_PALETTE_VALUE_BIT_OFFSET + REG_PALETTE_VALUE_BITS;
+pub const REG_PALETTE_INDEX_BITS: u32 = 8;
+
+// Write-only, and only applied to REG_FORMAT_ARGB8888 pixels. Each of R, G, and B is looked up separately; entry
+//  INDEX holds the corrected values (packed as RGB888) for a component value of INDEX. Like the palette, writes take
+//  effect immediately, and entries default to identity.
+pub const REG_GAMMA_LUT_ADDR: u32 = 14;
+pub const REG_GAMMA_LUT_ENTRIES: u32 = 1 << REG_GAMMA_LUT_INDEX_BITS;
+pub const REG_GAMMA_LUT_VALUE_BIT_OFFSET: u32 = 0;
+pub const REG_GAMMA_LUT_VALUE_BITS: u32 = 24;
+pub const REG_GAMMA_LUT_INDEX_BIT_OFFSET: u32 = REG_GAMMA_LUT_VALUE_BIT_OFFSET + REG_GAMMA_LUT_VALUE_BITS;
+pub const REG_GAMMA_LUT_INDEX_BITS: u32 = 8;
+
+// The overlay is a full-screen ARGB8888 plane blended over the (palette/gamma-corrected) front buffer using its alpha,
+//  with the same fixed-point convention as BitPusher's blending (one is 256). Its base and stride work the same way
+//  as the front buffer's, and it's only shown while REG_ENABLE_ADDR is set.
+pub const REG_OVERLAY_ENABLE_ADDR: u32 = 15;
+
+pub const REG_OVERLAY_BASE_ADDR: u32 = 16;
+
+pub const REG_OVERLAY_LINE_STRIDE_ADDR: u32 = 17;
+pub const REG_OVERLAY_LINE_STRIDE_DEFAULT: u32 = H_ACTIVE >> ARGB8888_PIXELS_PER_WORD_BITS;
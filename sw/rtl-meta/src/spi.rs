@@ -0,0 +1,24 @@
+// TODO: Move
+pub const REG_BUS_ADDR_BITS: u32 = 20;
+pub const REG_BUS_ADDR_BIT_WIDTH: u32 = 2;
+
+// Transfers are a byte at a time in SPI mode 0 (SCLK idles low, data is sampled on rising edges), MSB first
+
+// SCLK runs at the system clock / (2 * (divider + 1)). MISO passes through sync FF's, which eat 2 cycles of each half
+//  period, so dividers below REG_CLOCK_DIVIDER_MIN (12.5MHz) leave too little time for the slave's output to settle.
+pub const REG_CLOCK_DIVIDER_ADDR: u32 = 0;
+pub const REG_CLOCK_DIVIDER_BITS: u32 = 8;
+pub const REG_CLOCK_DIVIDER_MIN: u32 = 3;
+// 100MHz / (2 * 125) = 400kHz, which is what SD cards expect until they're initialized
+pub const REG_CLOCK_DIVIDER_DEFAULT: u32 = 124;
+
+// Bit 0 drives CS low (active) when set. Only change it while not busy.
+pub const REG_CHIP_SELECT_ADDR: u32 = 1;
+
+// Writing starts a transfer of the low byte, unless one is already in progress, in which case the write is ignored.
+//  Reading returns the byte received during the last completed transfer.
+pub const REG_DATA_ADDR: u32 = 2;
+
+// Read-only
+pub const REG_STATUS_ADDR: u32 = 3;
+pub const REG_STATUS_BUSY_BIT: u32 = 0;
@@ -0,0 +1,60 @@
+// TODO: Move
+pub const REG_BUS_ADDR_BITS: u32 = 20;
+pub const REG_BUS_ADDR_BIT_WIDTH: u32 = 4;
+
+// Frames are always 8N1
+
+// Both the TX and RX FIFO's hold 1 << FIFO_DEPTH_BITS bytes
+pub const FIFO_DEPTH_BITS: u32 = 8;
+pub const FIFO_DEPTH: u32 = 1 << FIFO_DEPTH_BITS;
+
+// Read-only
+pub const REG_TX_STATUS_ADDR: u32 = 0;
+// Set when the TX FIFO has room for another byte
+pub const REG_TX_STATUS_READY_BIT: u32 = 0;
+// Set when the TX FIFO is empty and the last byte has been completely shifted out
+pub const REG_TX_STATUS_IDLE_BIT: u32 = 1;
+
+// Write-only. Pushes the low byte onto the TX FIFO. If the FIFO is full, the write is dropped and
+//  REG_ERROR_TX_OVERFLOW_BIT is set.
+pub const REG_TX_DATA_ADDR: u32 = 1;
+
+// Read-only
+pub const REG_RX_STATUS_ADDR: u32 = 2;
+// Set when the RX FIFO isn't empty
+pub const REG_RX_STATUS_READY_BIT: u32 = 0;
+
+// Read-only. Reading pops a byte from the RX FIFO; the value read while it's empty is undefined.
+pub const REG_RX_DATA_ADDR: u32 = 3;
+
+// System clock cycles per bit. RX takes 4 samples per bit, so divisors below REG_BAUD_DIVISOR_MIN would leave it
+//  unable to find the middle of each bit; writes below it are clamped to it. A new divisor applies to the next bit on
+//  each side, so it should only be changed while TX is idle and the line is quiet.
+pub const REG_BAUD_DIVISOR_ADDR: u32 = 4;
+pub const REG_BAUD_DIVISOR_BITS: u32 = 16;
+pub const REG_BAUD_DIVISOR_MIN: u32 = 8;
+// 100MHz / 460800 baud, which is what xw-blaster expects
+pub const REG_BAUD_DIVISOR_DEFAULT: u32 = 217;
+
+// RTS/CTS are active high here; the top level inverts them for the pins
+pub const REG_FLOW_CONTROL_ADDR: u32 = 5;
+// When set, TX won't start a new byte while CTS is deasserted, and RTS is deasserted once fewer than
+//  RTS_THRESHOLD entries are free in the RX FIFO, which leaves room for bytes the other side has already committed
+//  to. When clear, CTS is ignored and RTS is always asserted.
+pub const REG_FLOW_CONTROL_ENABLE_BIT: u32 = 0;
+pub const RTS_THRESHOLD: u32 = 16;
+
+// Read-only. Number of bytes currently in each FIFO (0..=FIFO_DEPTH).
+pub const REG_TX_LEVEL_ADDR: u32 = 6;
+pub const REG_RX_LEVEL_ADDR: u32 = 7;
+pub const REG_LEVEL_BITS: u32 = FIFO_DEPTH_BITS + 1;
+
+// Sticky; writing 1 to a bit clears it
+pub const REG_ERROR_ADDR: u32 = 8;
+// A byte was received with its stop bit low. The byte is still pushed onto the RX FIFO.
+pub const REG_ERROR_FRAMING_BIT: u32 = 0;
+// A byte was received while the RX FIFO was full, and was dropped
+pub const REG_ERROR_OVERRUN_BIT: u32 = 1;
+// A byte was written to REG_TX_DATA_ADDR while the TX FIFO was full, and was dropped
+pub const REG_ERROR_TX_OVERFLOW_BIT: u32 = 2;
+pub const REG_ERROR_BITS: u32 = 3;
@@ -1 +1,3 @@
 pub const SYSTEM_BUS_ADDR_BITS: u32 = 24;
+
+pub const SYSTEM_CLOCK_FREQ: u32 = 100_000_000;
@@ -51,6 +51,7 @@ impl StruglTest {
         let frame_time = env.time_seconds() - self.start_time;
 
         let start_cycles = env.cycles();
+        c.clear_color = 0x00ff00;
         c.clear();
         let clear_cycles = env.cycles().wrapping_sub(start_cycles);
 
@@ -109,7 +110,7 @@ impl StruglTest {
 
         let mut total_primitive_assembly_cycles = 0;
         let mut total_binning_cycles = 0;
-        let stats = c.render(&self.cube_verts, &mut total_primitive_assembly_cycles, &mut total_binning_cycles, env);
+        let stats = c.render(PrimitiveType::Triangles, &self.cube_verts, &mut total_primitive_assembly_cycles, &mut total_binning_cycles, env);
 
         writeln!(env.stdout(), "Clear cycles: {}", clear_cycles).unwrap();
         writeln!(env.stdout(), "Vertex transformation cycles: {}", stats.vertex_transformation_cycles).unwrap();
@@ -119,6 +120,8 @@ impl StruglTest {
         writeln!(env.stdout(), "Num nonempty tiles: {}", stats.num_nonempty_tiles).unwrap();
         writeln!(env.stdout(), "Total tile xfer cycles: {}", stats.total_tile_xfer_cycles).unwrap();
         writeln!(env.stdout(), "Total rasterization cycles: {}", stats.total_rasterization_cycles).unwrap();
+        writeln!(env.stdout(), "Hi-z rejected primitives: {}", stats.hiz_rejected_primitives).unwrap();
+        writeln!(env.stdout(), "Hi-z rejected blocks: {}", stats.hiz_rejected_blocks).unwrap();
     }
 }
 
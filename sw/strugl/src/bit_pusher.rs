@@ -1,63 +1,157 @@
+use crate::{BlitOps, LogicOp};
+
 use abstract_device::*;
 
 use rtl_meta::bit_pusher::*;
 
-pub fn mem2sys(
-    device: &mut impl Device,
-    sys_addr: u32,
-    sys_words_per_span: u32,
-    sys_span_stride: u32,
-    mem_addr: u32,
-    mem_words_per_span: u32,
-    mem_span_stride: u32,
-    num_words: u32,
-) {
-    device.bit_pusher_write_reg(REG_DIRECTION_ADDR, REG_DIRECTION_MEM2SYS);
-    device.bit_pusher_write_reg(REG_NUM_WORDS_ADDR, num_words);
-
-    device.bit_pusher_write_reg(REG_SYS_ADDR_ADDR, sys_addr);
-    device.bit_pusher_write_reg(REG_SYS_WORDS_PER_SPAN_ADDR, sys_words_per_span);
-    device.bit_pusher_write_reg(REG_SYS_SPAN_STRIDE_ADDR, sys_span_stride);
-
-    device.bit_pusher_write_reg(REG_MEM_ADDR_ADDR, mem_addr);
-    device.bit_pusher_write_reg(REG_MEM_WORDS_PER_SPAN_ADDR, mem_words_per_span);
-    device.bit_pusher_write_reg(REG_MEM_SPAN_STRIDE_ADDR, mem_span_stride);
-
-    // Dispatch transfer
-    device.bit_pusher_write_reg(REG_START_ADDR, 1);
-
-    // Wait until transfer is complete
-    while device.bit_pusher_read_reg(REG_STATUS_ADDR) != 0 {
-        // Do nothing
+use alloc::vec::Vec;
+
+// Pixel op settings apply to every MEM2MEM and FILL transfer until they're set again
+pub fn set_blit_ops(device: &mut impl Device, ops: &BlitOps) {
+    device.bit_pusher_write_reg(
+        REG_PIXEL_OP_SETTINGS_ADDR,
+        (if ops.color_key.is_some() { 1 } else { 0 } << REG_PIXEL_OP_SETTINGS_COLOR_KEY_ENABLE_BIT) |
+        (if ops.blend_enable { 1 } else { 0 } << REG_PIXEL_OP_SETTINGS_BLEND_ENABLE_BIT) |
+        (match ops.logic_op {
+            LogicOp::Clear => REG_PIXEL_OP_SETTINGS_LOGIC_OP_CLEAR,
+            LogicOp::And => REG_PIXEL_OP_SETTINGS_LOGIC_OP_AND,
+            LogicOp::AndReverse => REG_PIXEL_OP_SETTINGS_LOGIC_OP_AND_REVERSE,
+            LogicOp::Copy => REG_PIXEL_OP_SETTINGS_LOGIC_OP_COPY,
+            LogicOp::AndInverted => REG_PIXEL_OP_SETTINGS_LOGIC_OP_AND_INVERTED,
+            LogicOp::Noop => REG_PIXEL_OP_SETTINGS_LOGIC_OP_NOOP,
+            LogicOp::Xor => REG_PIXEL_OP_SETTINGS_LOGIC_OP_XOR,
+            LogicOp::Or => REG_PIXEL_OP_SETTINGS_LOGIC_OP_OR,
+            LogicOp::Nor => REG_PIXEL_OP_SETTINGS_LOGIC_OP_NOR,
+            LogicOp::Equiv => REG_PIXEL_OP_SETTINGS_LOGIC_OP_EQUIV,
+            LogicOp::Invert => REG_PIXEL_OP_SETTINGS_LOGIC_OP_INVERT,
+            LogicOp::OrReverse => REG_PIXEL_OP_SETTINGS_LOGIC_OP_OR_REVERSE,
+            LogicOp::CopyInverted => REG_PIXEL_OP_SETTINGS_LOGIC_OP_COPY_INVERTED,
+            LogicOp::OrInverted => REG_PIXEL_OP_SETTINGS_LOGIC_OP_OR_INVERTED,
+            LogicOp::Nand => REG_PIXEL_OP_SETTINGS_LOGIC_OP_NAND,
+            LogicOp::Set => REG_PIXEL_OP_SETTINGS_LOGIC_OP_SET,
+        } << REG_PIXEL_OP_SETTINGS_LOGIC_OP_BIT_OFFSET));
+    if let Some(color_key) = ops.color_key {
+        device.bit_pusher_write_reg(REG_COLOR_KEY_ADDR, color_key & ((1 << REG_COLOR_KEY_BITS) - 1));
     }
 }
 
-pub fn sys2mem(
-    device: &mut impl Device,
-    sys_addr: u32,
-    sys_words_per_span: u32,
-    sys_span_stride: u32,
-    mem_addr: u32,
-    mem_words_per_span: u32,
-    mem_span_stride: u32,
-    num_words: u32,
-) {
-    device.bit_pusher_write_reg(REG_DIRECTION_ADDR, REG_DIRECTION_SYS2MEM);
-    device.bit_pusher_write_reg(REG_NUM_WORDS_ADDR, num_words);
-
-    device.bit_pusher_write_reg(REG_SYS_ADDR_ADDR, sys_addr);
-    device.bit_pusher_write_reg(REG_SYS_WORDS_PER_SPAN_ADDR, sys_words_per_span);
-    device.bit_pusher_write_reg(REG_SYS_SPAN_STRIDE_ADDR, sys_span_stride);
-
-    device.bit_pusher_write_reg(REG_MEM_ADDR_ADDR, mem_addr);
-    device.bit_pusher_write_reg(REG_MEM_WORDS_PER_SPAN_ADDR, mem_words_per_span);
-    device.bit_pusher_write_reg(REG_MEM_SPAN_STRIDE_ADDR, mem_span_stride);
-
-    // Dispatch transfer
-    device.bit_pusher_write_reg(REG_START_ADDR, 1);
-
-    // Wait until transfer is complete
-    while device.bit_pusher_read_reg(REG_STATUS_ADDR) != 0 {
-        // Do nothing
+// Spans of words_per_span consecutive words, each starting span_stride words after the last. With 0 words per span,
+//  the words are simply consecutive.
+#[derive(Clone, Copy)]
+pub struct Rect {
+    pub addr: u32,
+    pub words_per_span: u32,
+    pub span_stride: u32,
+}
+
+impl Rect {
+    pub fn contiguous(addr: u32) -> Rect {
+        Rect {
+            addr,
+            words_per_span: 0,
+            span_stride: 0,
+        }
+    }
+}
+
+// Descriptors are staged here and only written to device memory on dispatch, since linking them means
+//  rewriting the previous descriptor's control field
+pub struct TransferChain {
+    base_addr: u32,
+    capacity: u32,
+
+    descriptors: Vec<[u128; 2]>,
+    in_flight_len: u32,
+}
+
+impl TransferChain {
+    pub fn new(device: &mut impl Device, capacity: u32) -> TransferChain {
+        TransferChain {
+            base_addr: device.mem_alloc(capacity * DESCRIPTOR_WORDS, 1),
+            capacity,
+
+            descriptors: Vec::with_capacity(capacity as _),
+            in_flight_len: 0,
+        }
+    }
+
+    pub fn push_mem2sys(&mut self, sys: Rect, mem: Rect, num_words: u32) {
+        self.push(REG_DIRECTION_MEM2SYS, sys, mem, num_words);
+    }
+
+    pub fn push_sys2mem(&mut self, sys: Rect, mem: Rect, num_words: u32) {
+        self.push(REG_DIRECTION_SYS2MEM, sys, mem, num_words);
+    }
+
+    // Source and destination are both in mem
+    pub fn push_mem2mem(&mut self, src: Rect, dst: Rect, num_words: u32) {
+        self.push(REG_DIRECTION_MEM2MEM, src, dst, num_words);
+    }
+
+    pub fn push_fill(&mut self, value: u32, mem: Rect, num_words: u32) {
+        // The fill value takes the place of the sys addr
+        self.push(REG_DIRECTION_FILL, Rect::contiguous(value), mem, num_words);
+    }
+
+    fn push(&mut self, direction: u32, sys: Rect, mem: Rect, num_words: u32) {
+        if self.descriptors.len() as u32 == self.capacity {
+            panic!("Transfer chain capacity ({} descriptors) exceeded", self.capacity);
+        }
+
+        let words = |fields: [u32; 4]| fields.iter().enumerate().fold(0, |acc, (i, &field)| acc | ((field as u128) << (i * 32)));
+        // The control field is filled in on dispatch
+        self.descriptors.push([
+            words([sys.addr, sys.words_per_span, sys.span_stride, num_words]),
+            words([mem.addr, mem.words_per_span, mem.span_stride, direction << DESCRIPTOR_CONTROL_DIRECTION_BIT]),
+        ]);
+    }
+
+    // Returns immediately; the previous chain, if any, is waited on first, as its descriptors are overwritten
+    pub fn dispatch(&mut self, device: &mut impl Device) {
+        self.wait(device);
+
+        if self.descriptors.is_empty() {
+            return;
+        }
+
+        let num_descriptors = self.descriptors.len() as u32;
+        for (i, descriptor) in self.descriptors.iter().enumerate() {
+            let i = i as u32;
+            let addr = self.descriptor_addr(i);
+            let control = if i == num_descriptors - 1 {
+                1 << DESCRIPTOR_CONTROL_LAST_BIT
+            } else {
+                self.descriptor_addr(i + 1)
+            };
+            device.mem_write_word(addr, descriptor[0]);
+            device.mem_write_word(addr + 16, descriptor[1] | ((control as u128) << 96));
+            device.mem_write_word(addr + DESCRIPTOR_STATUS_WORD * 16, 0);
+        }
+
+        self.descriptors.clear();
+
+        device.bit_pusher_write_reg(REG_DESCRIPTOR_ADDR_ADDR, self.base_addr);
+        self.in_flight_len = num_descriptors;
+    }
+
+    // Descriptors complete in order, so the chain is done once its last descriptor's status is written back
+    pub fn is_complete(&self, device: &mut impl Device) -> bool {
+        if self.in_flight_len == 0 {
+            return true;
+        }
+
+        let status_addr = self.descriptor_addr(self.in_flight_len - 1) + DESCRIPTOR_STATUS_WORD * 16;
+        (device.mem_read_word(status_addr) as u32) == DESCRIPTOR_STATUS_COMPLETE
+    }
+
+    pub fn wait(&mut self, device: &mut impl Device) {
+        while !self.is_complete(device) {
+            // Do nothing
+        }
+        self.in_flight_len = 0;
+    }
+
+    fn descriptor_addr(&self, index: u32) -> u32 {
+        self.base_addr + index * DESCRIPTOR_WORDS * 16
     }
 }
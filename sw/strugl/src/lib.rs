@@ -11,13 +11,16 @@ use abstract_device::*;
 use abstract_environment::*;
 
 use rtl_meta::color_thrust::*;
+use rtl_meta::scanout;
 
 use linalg::*;
 
+use alloc::collections::BTreeMap;
 use alloc::rc::Rc;
 use alloc::vec::Vec;
 
 use core::fmt::Write;
+use core::mem;
 
 // TODO: Don't specify this here?
 pub const WIDTH: u32 = 640;
@@ -28,6 +31,15 @@ const DEFAULT_FRACT_BITS: u32 = 16;
 
 const NUM_COLOR_BUFFER_WORDS: u32 = PIXELS * 4 / 16;
 const NUM_DEPTH_BUFFER_WORDS: u32 = PIXELS * 2 / 16;
+const NUM_STENCIL_BUFFER_WORDS: u32 = PIXELS / 16;
+
+const ALL_TILE_BUFFERS: u32 =
+    (1 << REG_CLEAR_TILE_COLOR_BIT) |
+    (1 << REG_CLEAR_TILE_DEPTH_BIT) |
+    (1 << REG_CLEAR_TILE_STENCIL_BIT);
+
+// One transfer per tile buffer, except for render-to-texture color tiles, which take one per 2x2 block index
+const NUM_TILE_TRANSFER_DESCRIPTORS: u32 = 6;
 
 // TODO: Change this..
 #[derive(Clone, Copy)]
@@ -45,6 +57,14 @@ struct TransformedVertex {
     tex_coord: Iv2<DEFAULT_FRACT_BITS>,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PrimitiveType {
+    Triangles,
+    Lines,
+    LineStrip,
+    Points,
+}
+
 pub enum TextureFilter {
     Nearest,
     Bilinear,
@@ -67,6 +87,16 @@ impl TextureDim {
             TextureDim::X128 => 128,
         }
     }
+
+    fn align(&self) -> u32 {
+        let align_bits = 6 + match *self {
+            TextureDim::X16 => 0,
+            TextureDim::X32 => 2,
+            TextureDim::X64 => 4,
+            TextureDim::X128 => 8,
+        };
+        1 << align_bits
+    }
 }
 
 pub struct Texture {
@@ -80,11 +110,173 @@ pub struct TextureData {
     dim: TextureDim,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorFormat {
+    Argb8888,
+    // Dithered as tiles are stored, with opaque alpha when tiles are loaded back
+    Rgb565,
+}
+
+impl ColorFormat {
+    fn bytes_per_pixel(&self) -> u32 {
+        match *self {
+            ColorFormat::Argb8888 => 4,
+            ColorFormat::Rgb565 => 2,
+        }
+    }
+}
+
+// TODO: Properly free memory when dropped
+pub struct RenderTarget {
+    width: u32,
+    height: u32,
+    color_format: ColorFormat,
+
+    color_buffer_base_addr: u32,
+    depth_buffer_base_addr: u32,
+    stencil_buffer_base_addr: u32,
+
+    // Render-to-texture targets store color tiles in the block-swizzled texture layout instead of the linear
+    //  (bottom-up) framebuffer layout, so the color buffer can be bound directly as a texture
+    texture_data: Option<Rc<TextureData>>,
+}
+
+impl RenderTarget {
+    fn new<D: Device>(device: &mut D, width: u32, height: u32, color_format: ColorFormat, texture_dim: Option<TextureDim>) -> RenderTarget {
+        let pixels = width * height;
+        let color_buffer_base_addr = device.mem_alloc(pixels * color_format.bytes_per_pixel() / 16, texture_dim.map(|dim| dim.align()).unwrap_or(1));
+        let depth_buffer_base_addr = device.mem_alloc(pixels * 2 / 16, 1);
+        let stencil_buffer_base_addr = device.mem_alloc(pixels / 16, 1);
+
+        RenderTarget {
+            width,
+            height,
+            color_format,
+
+            color_buffer_base_addr,
+            depth_buffer_base_addr,
+            stencil_buffer_base_addr,
+
+            texture_data: texture_dim.map(|dim| Rc::new(TextureData {
+                base_addr: color_buffer_base_addr,
+                dim,
+            })),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn color_format(&self) -> ColorFormat {
+        self.color_format
+    }
+
+    // Only available for targets allocated with Context::alloc_texture_render_target
+    pub fn texture_data(&self) -> Option<Rc<TextureData>> {
+        self.texture_data.clone()
+    }
+}
+
+pub enum TextureEnv {
+    Modulate,
+    Replace,
+    Decal,
+    Add,
+    Blend,
+}
+
+pub enum DepthFunc {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+pub enum AlphaFunc {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+pub enum StencilFunc {
+    Never,
+    Less,
+    Equal,
+    LessOrEqual,
+    Greater,
+    NotEqual,
+    GreaterOrEqual,
+    Always,
+}
+
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    Incr,
+    Decr,
+    Invert,
+    IncrWrap,
+    DecrWrap,
+}
+
+impl StencilOp {
+    fn to_reg(&self) -> u32 {
+        match *self {
+            StencilOp::Keep => REG_STENCIL_OP_KEEP,
+            StencilOp::Zero => REG_STENCIL_OP_ZERO,
+            StencilOp::Replace => REG_STENCIL_OP_REPLACE,
+            StencilOp::Incr => REG_STENCIL_OP_INCR,
+            StencilOp::Decr => REG_STENCIL_OP_DECR,
+            StencilOp::Invert => REG_STENCIL_OP_INVERT,
+            StencilOp::IncrWrap => REG_STENCIL_OP_INCR_WRAP,
+            StencilOp::DecrWrap => REG_STENCIL_OP_DECR_WRAP,
+        }
+    }
+}
+
+pub enum FogMode {
+    Linear,
+    Exp,
+    Exp2,
+    // Use the contents of Context::fog_table as-is
+    Table,
+}
+
+pub enum FogSource {
+    // Window-space depth, in [0, 1]
+    Depth,
+    // Clip-space w (typically eye-space distance)
+    W,
+}
+
 pub enum BlendSrcFactor {
     Zero,
     One,
     SrcAlpha,
     OneMinusSrcAlpha,
+    DstColor,
+    OneMinusDstColor,
+    SrcColor,
+    OneMinusSrcColor,
+    DstAlpha,
+    OneMinusDstAlpha,
+    ConstantColor,
+    OneMinusConstantColor,
 }
 
 pub enum BlendDstFactor {
@@ -92,6 +284,73 @@ pub enum BlendDstFactor {
     One,
     SrcAlpha,
     OneMinusSrcAlpha,
+    DstColor,
+    OneMinusDstColor,
+    SrcColor,
+    OneMinusSrcColor,
+    DstAlpha,
+    OneMinusDstAlpha,
+    ConstantColor,
+    OneMinusConstantColor,
+}
+
+pub enum BlendEquation {
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+// Same ops as glLogicOp
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LogicOp {
+    Clear,
+    And,
+    AndReverse,
+    Copy,
+    AndInverted,
+    Noop,
+    Xor,
+    Or,
+    Nor,
+    Equiv,
+    Invert,
+    OrReverse,
+    CopyInverted,
+    OrInverted,
+    Nand,
+    Set,
+}
+
+// In pixels, with rows in the same bottom-up order as Context::extract_back_buffer
+#[derive(Clone, Copy)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Per-pixel ops for Context::fill_rect and Context::blit_rect, applied in field order; anything other than the
+//  default (plain copy) requires ARGB8888 targets
+#[derive(Clone, Copy)]
+pub struct BlitOps {
+    // Source pixels with this RGB value (alpha is ignored) leave the destination untouched
+    pub color_key: Option<u32>,
+    // Blends source pixels over the destination by source alpha
+    pub blend_enable: bool,
+    pub logic_op: LogicOp,
+}
+
+impl Default for BlitOps {
+    fn default() -> BlitOps {
+        BlitOps {
+            color_key: None,
+            blend_enable: false,
+            logic_op: LogicOp::Copy,
+        }
+    }
 }
 
 // TODO: Figure out the best representation without duplicating tons of data!!
@@ -131,27 +390,144 @@ struct Triangle {
     t_min: u32,
     t_dx: u32,
     t_dy: u32,
+    z_bounds: u32,
+}
+
+// Clears are deferred per tile; each tile entry holds the REG_CLEAR_TILE_* bits for buffers whose contents in memory
+//  are stale, and whose values should come from the clear values here instead
+struct PendingClears {
+    tiles: Vec<u32>,
+    color: u32,
+    depth: u16,
+    stencil: u8,
+}
+
+impl PendingClears {
+    fn new(num_tiles: usize) -> PendingClears {
+        PendingClears {
+            tiles: vec![0; num_tiles],
+            color: 0,
+            depth: 0,
+            stencil: 0,
+        }
+    }
+}
+
+// Sample-space buffers (2x2 samples per pixel) used when multisampling
+#[derive(Clone, Copy)]
+struct MsaaBuffers {
+    color_buffer_base_addr: u32,
+    depth_buffer_base_addr: u32,
+    stencil_buffer_base_addr: u32,
 }
 
 pub struct Context<D: Device> {
     device: D,
 
-    back_buffer_base_addr: u32,
-    depth_buffer_base_addr: u32,
+    back_buffer: Rc<RenderTarget>,
+    render_target: Rc<RenderTarget>,
+
+    // Swapped with the back buffer by present. Only the color buffers are doubled; depth and stencil buffers are
+    //  shared, since they're never scanned out.
+    front_buffer: Rc<RenderTarget>,
+    // Scanout is left alone until the first present, so contexts that never present don't take over the display
+    scanout_enabled: bool,
+    // Set when a flip is requested, and only waited on once the back buffer (which may still be on screen until the
+    //  flip lands) is about to be written
+    flip_pending: bool,
+
+
+    // Tile stores are left in flight until tile memory or the stored buffers are touched again, so they can
+    //  overlap with whatever the CPU does next (eg. binning the next drawcall)
+    tile_transfers: TransferChain,
+
+    tile_width: u32,
+    tile_height: u32,
+
+    msaa_active: bool,
+    msaa_buffers: Option<MsaaBuffers>,
+
+    // Pending clears of the current render target
+    pending_clears: PendingClears,
+    // Depth and stencil clears left pending on targets when they were unbound, keyed by their depth buffer addr
+    //  (which the front and back buffers share), and picked up again when they're rebound
+    unbound_pending_clears: BTreeMap<u32, PendingClears>,
 
     // TODO: Don't make these public; expose as some kind of register interface instead
+    pub clear_color: u32,
+    pub clear_depth: u16,
+
     pub depth_test_enable: bool,
     pub depth_write_mask_enable: bool,
+    pub depth_func: DepthFunc,
+    pub hiz_enable: bool,
+
+    // 4x multisampling of the back buffer; takes effect at its next clear
+    pub msaa_enable: bool,
+
+    pub stencil_test_enable: bool,
+    pub stencil_func: StencilFunc,
+    pub stencil_ref: u8,
+    pub stencil_mask: u8,
+    pub stencil_write_mask: u8,
+    pub stencil_fail_op: StencilOp,
+    pub stencil_depth_fail_op: StencilOp,
+    pub stencil_depth_pass_op: StencilOp,
+    pub clear_stencil: u8,
 
     pub texture: Option<Rc<Texture>>,
+    pub texture_env: TextureEnv,
+    pub texture_env_color: u32,
 
     pub blend_src_factor: BlendSrcFactor,
     pub blend_dst_factor: BlendDstFactor,
+    pub blend_equation: BlendEquation,
+    pub blend_color: u32,
+
+    pub alpha_test_enable: bool,
+    pub alpha_test_func: AlphaFunc,
+    pub alpha_test_ref: u8,
+
+    pub fog_enable: bool,
+    pub fog_mode: FogMode,
+    pub fog_source: FogSource,
+    pub fog_color: u32,
+    pub fog_start: f32,
+    pub fog_end: f32,
+    pub fog_density: f32,
+    // Only used with FogMode::Table; each entry is the amount of fog (0 = none, 255 = fog color only)
+    //  for its slice of [0, fog_end] (or [0, 1] with FogSource::Depth)
+    pub fog_table: [u8; REG_FOG_TABLE_ENTRIES as usize],
+    // Contents of the hardware fog table, if it's been written yet
+    uploaded_fog_table: Option<[u8; REG_FOG_TABLE_ENTRIES as usize]>,
+
+    // In pixels
+    pub line_width: f32,
+    pub point_size: f32,
 
     pub model_view: Im4<DEFAULT_FRACT_BITS>,
     pub projection: Im4<DEFAULT_FRACT_BITS>,
 
     assembled_triangles: Vec<Vec<Triangle>>,
+
+    // Accumulated across tiles and drawcalls while a query is active
+    occlusion_query: Option<OcclusionQuery>,
+}
+
+// Samples (pixels, without multisampling) that passed the depth and stencil tests while the query was active
+#[derive(Clone, Copy, Default)]
+pub struct OcclusionQuery {
+    passed_samples: u32,
+}
+
+impl OcclusionQuery {
+    pub fn passed_samples(&self) -> u32 {
+        self.passed_samples
+    }
+
+    pub fn any_samples_passed(&self) -> bool {
+        self.passed_samples != 0
+    }
 }
 
 pub struct RenderStats {
@@ -160,32 +536,115 @@ pub struct RenderStats {
     pub num_nonempty_tiles: u32,
     pub total_tile_xfer_cycles: u64,
     pub total_rasterization_cycles: u64,
+    pub hiz_rejected_primitives: u32,
+    pub hiz_rejected_blocks: u32,
 }
 
 impl<D: Device> Context<D> {
-    pub fn new(mut device: D) -> Context<D> {
-        let back_buffer_base_addr = device.mem_alloc(NUM_COLOR_BUFFER_WORDS, 1);
-        let depth_buffer_base_addr = device.mem_alloc(NUM_DEPTH_BUFFER_WORDS, 1);
+    pub fn new(device: D) -> Context<D> {
+        Context::with_back_buffer_format(device, ColorFormat::Argb8888)
+    }
+
+    pub fn with_back_buffer_format(mut device: D, back_buffer_format: ColorFormat) -> Context<D> {
+        let back_buffer = Rc::new(RenderTarget::new(&mut device, WIDTH, HEIGHT, back_buffer_format, None));
+        let front_buffer = Rc::new(RenderTarget {
+            width: WIDTH,
+            height: HEIGHT,
+            color_format: back_buffer_format,
+
+            color_buffer_base_addr: device.mem_alloc(PIXELS * back_buffer_format.bytes_per_pixel() / 16, 1),
+            depth_buffer_base_addr: back_buffer.depth_buffer_base_addr,
+            stencil_buffer_base_addr: back_buffer.stencil_buffer_base_addr,
+
+            texture_data: None,
+        });
+
+        // Tile size is a hardware generator parameter
+        let caps = device.color_thrust_read_reg(REG_CAPS_ADDR);
+        let tile_width = 1 << ((caps >> REG_CAPS_TILE_WIDTH_LOG2_BIT_OFFSET) & ((1 << REG_CAPS_TILE_WIDTH_LOG2_BITS) - 1));
+        let tile_height = 1 << ((caps >> REG_CAPS_TILE_HEIGHT_LOG2_BIT_OFFSET) & ((1 << REG_CAPS_TILE_HEIGHT_LOG2_BITS) - 1));
+        if !WIDTH.is_multiple_of(tile_width) || !HEIGHT.is_multiple_of(tile_height) {
+            panic!("{}x{} tiles don't evenly divide the {}x{} framebuffer", tile_width, tile_height, WIDTH, HEIGHT);
+        }
+        let num_tiles = (PIXELS / (tile_width * tile_height)) as usize;
+
+        let tile_transfers = TransferChain::new(&mut device, NUM_TILE_TRANSFER_DESCRIPTORS);
 
         Context {
             device,
 
-            back_buffer_base_addr,
-            depth_buffer_base_addr,
+            back_buffer: back_buffer.clone(),
+            render_target: back_buffer,
+
+            front_buffer,
+            scanout_enabled: false,
+            flip_pending: false,
+
+
+            tile_transfers,
+
+            tile_width,
+            tile_height,
+
+            msaa_active: false,
+            msaa_buffers: None,
+
+            pending_clears: PendingClears::new(num_tiles),
+            unbound_pending_clears: BTreeMap::new(),
+
+            clear_color: 0,
+            clear_depth: 0xffff,
 
             depth_test_enable: false,
             depth_write_mask_enable: false,
+            depth_func: DepthFunc::Less,
+            hiz_enable: true,
+
+            msaa_enable: false,
+
+            stencil_test_enable: false,
+            stencil_func: StencilFunc::Always,
+            stencil_ref: 0,
+            stencil_mask: 0xff,
+            stencil_write_mask: 0xff,
+            stencil_fail_op: StencilOp::Keep,
+            stencil_depth_fail_op: StencilOp::Keep,
+            stencil_depth_pass_op: StencilOp::Keep,
+            clear_stencil: 0,
 
             texture: None,
+            texture_env: TextureEnv::Modulate,
+            texture_env_color: 0,
 
             blend_src_factor: BlendSrcFactor::One,
             blend_dst_factor: BlendDstFactor::Zero,
+            blend_equation: BlendEquation::Add,
+            blend_color: 0,
+
+            alpha_test_enable: false,
+            alpha_test_func: AlphaFunc::Always,
+            alpha_test_ref: 0,
+
+            fog_enable: false,
+            fog_mode: FogMode::Exp,
+            fog_source: FogSource::W,
+            fog_color: 0,
+            fog_start: 0.0,
+            fog_end: 1.0,
+            fog_density: 1.0,
+            fog_table: [0; REG_FOG_TABLE_ENTRIES as usize],
+            uploaded_fog_table: None,
+
+            line_width: 1.0,
+            point_size: 1.0,
 
             model_view: Im4::identity(),
             projection: Im4::identity(),
 
             // TODO: Fixed capacity and splitting drawcalls on overflow
-            assembled_triangles: vec![Vec::new(); (PIXELS / TILE_PIXELS) as usize],
+            assembled_triangles: vec![Vec::new(); num_tiles],
+
+            occlusion_query: None,
         }
     }
 
@@ -198,13 +657,7 @@ impl<D: Device> Context<D> {
 
     // TODO: Expose failure possibility in type signature
     pub fn alloc_texture_data(&mut self, dim: TextureDim, data: &[u32]) -> Rc<TextureData> {
-        let align_bits = 6 + match dim {
-            TextureDim::X16 => 0,
-            TextureDim::X32 => 2,
-            TextureDim::X64 => 4,
-            TextureDim::X128 => 8,
-        };
-        let base_addr = self.device.mem_alloc(data.len() as u32 / 4, 1 << align_bits);
+        let base_addr = self.device.mem_alloc(data.len() as u32 / 4, dim.align());
         // Upload data
         //  To support reading a filtered texel in one clock cycle, the texture storage organization is a little tricky.
         //  The main idea is to conceptually group texels into 2x2 blocks. For a bilinear-filtered texel, we need to
@@ -244,26 +697,452 @@ impl<D: Device> Context<D> {
         })
     }
 
+    // Offscreen render targets must be a whole number of tiles in each dimension
+    pub fn alloc_render_target(&mut self, width: u32, height: u32, color_format: ColorFormat) -> Rc<RenderTarget> {
+        if width == 0 || height == 0 || !width.is_multiple_of(self.tile_width) || !height.is_multiple_of(self.tile_height) {
+            panic!("{}x{} render target isn't a whole number of {}x{} tiles", width, height, self.tile_width, self.tile_height);
+        }
+
+        Rc::new(RenderTarget::new(&mut self.device, width, height, color_format, None))
+    }
+
+    // Render target whose color buffer can also be bound as a texture (see RenderTarget::texture_data). Texture
+    //  coordinates follow window space, so t = 0 is the bottom row.
+    pub fn alloc_texture_render_target(&mut self, dim: TextureDim) -> Rc<RenderTarget> {
+        let dim_pixels = dim.to_u32();
+        if !dim_pixels.is_multiple_of(self.tile_width) || !dim_pixels.is_multiple_of(self.tile_height) {
+            panic!("{}x{} texture isn't a whole number of {}x{} tiles", dim_pixels, dim_pixels, self.tile_width, self.tile_height);
+        }
+
+        Rc::new(RenderTarget::new(&mut self.device, dim_pixels, dim_pixels, ColorFormat::Argb8888, Some(dim)))
+    }
+
+    pub fn back_buffer(&self) -> Rc<RenderTarget> {
+        self.back_buffer.clone()
+    }
+
+    pub fn render_target(&self) -> Rc<RenderTarget> {
+        self.render_target.clone()
+    }
+
+    pub fn set_render_target(&mut self, render_target: Rc<RenderTarget>) {
+        if Rc::ptr_eq(&render_target, &self.render_target) {
+            return;
+        }
+
+        // The outgoing target's color buffer may be sampled or read back next, so its color clears have to land.
+        //  Depth and stencil clears stay pending with the target, since they're only ever read by its own drawcalls.
+        self.resolve_pending_clears(1 << REG_CLEAR_TILE_COLOR_BIT);
+
+        let pending_clears = mem::replace(&mut self.pending_clears, PendingClears::new(0));
+        self.unbound_pending_clears.insert(self.render_target.depth_buffer_base_addr, pending_clears);
+
+        self.render_target = render_target;
+
+        let num_tiles = self.num_tiles();
+        self.pending_clears = self.unbound_pending_clears.remove(&self.render_target.depth_buffer_base_addr).unwrap_or_else(|| PendingClears::new(num_tiles));
+        self.assembled_triangles = vec![Vec::new(); num_tiles];
+    }
+
     pub fn clear(&mut self) {
-        let clear_color = 0x00ff00; // TODO: Expose this properly
-        let mut clear_color_word = 0;
-        for i in 0..4 {
-            clear_color_word |= (clear_color as u128) << (i * 32);
+        if Rc::ptr_eq(&self.render_target, &self.back_buffer) && self.msaa_enable != self.msaa_active {
+            // Tiles cover a different part of the screen with multisampling, so primitives would need to be binned again
+            if self.assembled_triangles.iter().any(|triangles| !triangles.is_empty()) {
+                panic!("Multisampling can't be toggled while primitives are pending");
+            }
+
+            self.msaa_active = self.msaa_enable;
+            if self.msaa_active && self.msaa_buffers.is_none() {
+                self.msaa_buffers = Some(MsaaBuffers {
+                    color_buffer_base_addr: self.device.mem_alloc(NUM_COLOR_BUFFER_WORDS * 4, 1),
+                    depth_buffer_base_addr: self.device.mem_alloc(NUM_DEPTH_BUFFER_WORDS * 4, 1),
+                    stencil_buffer_base_addr: self.device.mem_alloc(NUM_STENCIL_BUFFER_WORDS * 4, 1),
+                });
+            }
+
+            let num_tiles = self.num_tiles();
+            self.pending_clears = PendingClears::new(num_tiles);
+            self.assembled_triangles = vec![Vec::new(); num_tiles];
         }
-        for i in 0..NUM_COLOR_BUFFER_WORDS {
-            self.device.mem_write_word(self.back_buffer_base_addr + i * 16, clear_color_word);
+
+        // Nothing is written to memory here; tiles are cleared in ColorThrust's tile memory as they're rendered
+        self.pending_clears.color = self.clear_color;
+        self.pending_clears.depth = self.clear_depth;
+        self.pending_clears.stencil = self.clear_stencil;
+        for pending_clears in self.pending_clears.tiles.iter_mut() {
+            *pending_clears = ALL_TILE_BUFFERS;
         }
-        let clear_depth = 0xffff; // TODO: Expose this properly
-        let mut clear_depth_word = 0;
-        for i in 0..8 {
-            clear_depth_word |= (clear_depth as u128) << (i * 16);
+    }
+
+    // Only covers drawcalls rendered between begin and end
+    pub fn begin_occlusion_query(&mut self) {
+        if self.occlusion_query.is_some() {
+            panic!("An occlusion query is already active");
         }
-        for i in 0..NUM_DEPTH_BUFFER_WORDS {
-            self.device.mem_write_word(self.depth_buffer_base_addr + i * 16, clear_depth_word);
+
+        self.occlusion_query = Some(OcclusionQuery::default());
+    }
+
+    pub fn end_occlusion_query(&mut self) -> OcclusionQuery {
+        self.occlusion_query.take().expect("No occlusion query is active")
+    }
+
+    fn clear_tile(&mut self, buffers: u32) {
+        self.tile_transfers.wait(&mut self.device);
+
+        self.device.color_thrust_write_reg(REG_CLEAR_COLOR_ADDR, self.pending_clears.color);
+        self.device.color_thrust_write_reg(
+            REG_CLEAR_DEPTH_STENCIL_ADDR,
+            ((self.pending_clears.depth as u32) << REG_CLEAR_DEPTH_BIT_OFFSET) |
+            ((self.pending_clears.stencil as u32) << REG_CLEAR_STENCIL_BIT_OFFSET));
+        self.device.color_thrust_write_reg(REG_CLEAR_TILE_ADDR, buffers);
+
+        while self.device.color_thrust_read_reg(REG_STATUS_ADDR) != 0 {
+            // Do nothing
         }
     }
 
-    pub fn render<W: Write, E: Environment<W>>(&mut self, verts: &[Vertex], total_primitive_assembly_cycles: &mut u64, total_binning_cycles: &mut u64, env: &E) -> RenderStats {
+    // Only the back buffer is multisampled
+    fn multisampling(&self) -> bool {
+        self.msaa_active && Rc::ptr_eq(&self.render_target, &self.back_buffer)
+    }
+
+    // Dimensions of the surface that's rendered to and tiled; with multisampling, this is in samples rather than pixels
+    fn surface_dims(&self) -> (u32, u32) {
+        if self.multisampling() {
+            (self.render_target.width * 2, self.render_target.height * 2)
+        } else {
+            (self.render_target.width, self.render_target.height)
+        }
+    }
+
+    fn num_tiles(&self) -> usize {
+        let (surface_width, surface_height) = self.surface_dims();
+        ((surface_width / self.tile_width) * (surface_height / self.tile_height)) as usize
+    }
+
+    // Color, depth, and stencil buffers that tiles are loaded from and stored to
+    fn tile_buffer_base_addrs(&self) -> (u32, u32, u32) {
+        match self.msaa_buffers {
+            Some(msaa_buffers) if self.multisampling() => (
+                msaa_buffers.color_buffer_base_addr,
+                msaa_buffers.depth_buffer_base_addr,
+                msaa_buffers.stencil_buffer_base_addr,
+            ),
+            _ => (
+                self.render_target.color_buffer_base_addr,
+                self.render_target.depth_buffer_base_addr,
+                self.render_target.stencil_buffer_base_addr,
+            ),
+        }
+    }
+
+    // Buffers are given as REG_CLEAR_TILE_* bits
+    fn load_tile(&mut self, buffers: u32, tile_min_x: u32, tile_min_y: u32) {
+        self.tile_transfers.wait(&mut self.device);
+
+        let (surface_width, surface_height) = self.surface_dims();
+        let (_, depth_buffer_base_addr, stencil_buffer_base_addr) = self.tile_buffer_base_addrs();
+        if (buffers & (1 << REG_CLEAR_TILE_COLOR_BIT)) != 0 {
+            self.load_color_tile(tile_min_x, tile_min_y);
+        }
+        if (buffers & (1 << REG_CLEAR_TILE_DEPTH_BIT)) != 0 {
+            // Depth bounds are rebuilt as the tile streams in
+            self.device.color_thrust_write_reg(REG_DEPTH_BOUNDS_RESET_ADDR, 1);
+            self.tile_transfers.push_mem2sys(
+                Rect::contiguous(0x05000000), // TODO: Proper constant!!!
+                Rect {
+                    addr: depth_buffer_base_addr + ((surface_height - 1 - tile_min_y) * surface_width + tile_min_x) * 2,
+                    words_per_span: self.tile_width / 8,
+                    span_stride: (-(surface_width as i32) / 8) as _,
+                },
+                self.tile_width * self.tile_height / 8,
+            );
+        }
+        if (buffers & (1 << REG_CLEAR_TILE_STENCIL_BIT)) != 0 {
+            self.tile_transfers.push_mem2sys(
+                Rect::contiguous(0x07000000), // TODO: Proper constant!!!
+                Rect {
+                    addr: stencil_buffer_base_addr + (surface_height - 1 - tile_min_y) * surface_width + tile_min_x,
+                    words_per_span: self.tile_width / 16,
+                    span_stride: (-(surface_width as i32) / 16) as _,
+                },
+                self.tile_width * self.tile_height / 16,
+            );
+        }
+        self.flush_tile_transfers();
+
+        if (buffers & (1 << REG_CLEAR_TILE_COLOR_BIT)) != 0 && self.tile_color_format() == ColorFormat::Rgb565 {
+            self.device.color_thrust_write_reg(REG_COLOR_UNPACK_ADDR, 1);
+            while self.device.color_thrust_read_reg(REG_STATUS_ADDR) != 0 {
+                // Do nothing
+            }
+        }
+        if (buffers & (1 << REG_CLEAR_TILE_COLOR_BIT)) != 0 && self.render_target.texture_data.is_some() {
+            self.swizzle_color_tile(true);
+        }
+    }
+
+    // Buffers are given as REG_CLEAR_TILE_* bits
+    fn store_tile(&mut self, buffers: u32, tile_min_x: u32, tile_min_y: u32) {
+        let (surface_width, surface_height) = self.surface_dims();
+        let (_, depth_buffer_base_addr, stencil_buffer_base_addr) = self.tile_buffer_base_addrs();
+        if (buffers & (1 << REG_CLEAR_TILE_COLOR_BIT)) != 0 {
+            self.store_color_tile(tile_min_x, tile_min_y);
+        }
+        if (buffers & (1 << REG_CLEAR_TILE_DEPTH_BIT)) != 0 {
+            self.tile_transfers.push_sys2mem(
+                Rect::contiguous(0x05000000), // TODO: Proper constant!!!
+                Rect {
+                    addr: depth_buffer_base_addr + ((surface_height - 1 - tile_min_y) * surface_width + tile_min_x) * 2,
+                    words_per_span: self.tile_width / 8,
+                    span_stride: (-(surface_width as i32) / 8) as _,
+                },
+                self.tile_width * self.tile_height / 8,
+            );
+        }
+        if (buffers & (1 << REG_CLEAR_TILE_STENCIL_BIT)) != 0 {
+            self.tile_transfers.push_sys2mem(
+                Rect::contiguous(0x07000000), // TODO: Proper constant!!!
+                Rect {
+                    addr: stencil_buffer_base_addr + (surface_height - 1 - tile_min_y) * surface_width + tile_min_x,
+                    words_per_span: self.tile_width / 16,
+                    span_stride: (-(surface_width as i32) / 16) as _,
+                },
+                self.tile_width * self.tile_height / 16,
+            );
+        }
+        self.tile_transfers.dispatch(&mut self.device);
+    }
+
+    fn flush_tile_transfers(&mut self) {
+        self.tile_transfers.dispatch(&mut self.device);
+        self.tile_transfers.wait(&mut self.device);
+    }
+
+    // Samples are always kept in full precision
+    fn tile_color_format(&self) -> ColorFormat {
+        if self.multisampling() { ColorFormat::Argb8888 } else { self.render_target.color_format }
+    }
+
+    fn load_color_tile(&mut self, tile_min_x: u32, tile_min_y: u32) {
+        if let Some(texture_data) = self.render_target.texture_data.clone() {
+            for (sys, mem) in self.swizzled_color_tile_rects(&texture_data, tile_min_x, tile_min_y) {
+                self.tile_transfers.push_mem2sys(sys, mem, self.tile_width * self.tile_height / 16);
+            }
+            return;
+        }
+
+        let (surface_width, surface_height) = self.surface_dims();
+        let (color_buffer_base_addr, _, _) = self.tile_buffer_base_addrs();
+        let bytes_per_pixel = self.tile_color_format().bytes_per_pixel();
+        self.tile_transfers.push_mem2sys(
+            Rect::contiguous(0x04000000), // TODO: Proper constant!!!
+            Rect {
+                addr: color_buffer_base_addr + ((surface_height - 1 - tile_min_y) * surface_width + tile_min_x) * bytes_per_pixel,
+                words_per_span: self.tile_width * bytes_per_pixel / 16,
+                span_stride: (-((surface_width * bytes_per_pixel / 16) as i32)) as _,
+            },
+            self.tile_width * self.tile_height * bytes_per_pixel / 16,
+        );
+    }
+
+    fn store_color_tile(&mut self, tile_min_x: u32, tile_min_y: u32) {
+        if Rc::ptr_eq(&self.render_target, &self.back_buffer) {
+            self.wait_for_flip();
+        }
+
+        if let Some(texture_data) = self.render_target.texture_data.clone() {
+            self.swizzle_color_tile(false);
+            for (sys, mem) in self.swizzled_color_tile_rects(&texture_data, tile_min_x, tile_min_y) {
+                self.tile_transfers.push_sys2mem(sys, mem, self.tile_width * self.tile_height / 16);
+            }
+            return;
+        }
+
+        let bytes_per_pixel = self.render_target.color_format.bytes_per_pixel();
+
+        if self.multisampling() {
+            // The samples are kept in full precision for later drawcalls, but the back buffer gets the resolved pixels
+            let (surface_width, surface_height) = self.surface_dims();
+            let (color_buffer_base_addr, _, _) = self.tile_buffer_base_addrs();
+            self.tile_transfers.push_sys2mem(
+                Rect::contiguous(0x04000000), // TODO: Proper constant!!!
+                Rect {
+                    addr: color_buffer_base_addr + ((surface_height - 1 - tile_min_y) * surface_width + tile_min_x) * 4,
+                    words_per_span: self.tile_width / 4,
+                    span_stride: (-(surface_width as i32) / 4) as _,
+                },
+                self.tile_width * self.tile_height / 4,
+            );
+            // Resolving happens in place
+            self.flush_tile_transfers();
+
+            self.device.color_thrust_write_reg(REG_AA_RESOLVE_ADDR, 1);
+            while self.device.color_thrust_read_reg(REG_STATUS_ADDR) != 0 {
+                // Do nothing
+            }
+            self.pack_color_tile(true);
+
+            self.tile_transfers.push_sys2mem(
+                Rect::contiguous(0x04000000), // TODO: Proper constant!!!
+                Rect {
+                    addr: self.back_buffer.color_buffer_base_addr + ((HEIGHT - 1 - tile_min_y / 2) * WIDTH + tile_min_x / 2) * bytes_per_pixel,
+                    words_per_span: self.tile_width / 2 * bytes_per_pixel / 16,
+                    span_stride: (-((WIDTH * bytes_per_pixel / 16) as i32)) as _,
+                },
+                self.tile_width * self.tile_height / 4 * bytes_per_pixel / 16,
+            );
+            return;
+        }
+
+        self.pack_color_tile(false);
+
+        let (surface_width, surface_height) = self.surface_dims();
+        let (color_buffer_base_addr, _, _) = self.tile_buffer_base_addrs();
+        self.tile_transfers.push_sys2mem(
+            Rect::contiguous(0x04000000), // TODO: Proper constant!!!
+            Rect {
+                addr: color_buffer_base_addr + ((surface_height - 1 - tile_min_y) * surface_width + tile_min_x) * bytes_per_pixel,
+                words_per_span: self.tile_width * bytes_per_pixel / 16,
+                span_stride: (-((surface_width * bytes_per_pixel / 16) as i32)) as _,
+            },
+            self.tile_width * self.tile_height * bytes_per_pixel / 16,
+        );
+    }
+
+    // Converts the tile's color buffer in place if the current target is stored as RGB565; this clobbers the tile
+    fn pack_color_tile(&mut self, resolved: bool) {
+        if self.render_target.color_format != ColorFormat::Rgb565 {
+            return;
+        }
+
+        self.device.color_thrust_write_reg(REG_COLOR_PACK_ADDR, (if resolved { 1 } else { 0 }) << REG_COLOR_PACK_RESOLVED_BIT);
+        while self.device.color_thrust_read_reg(REG_STATUS_ADDR) != 0 {
+            // Do nothing
+        }
+    }
+
+    // Converts the tile's color buffer in place between the linear and swizzled texture layouts; swizzling clobbers the tile
+    fn swizzle_color_tile(&mut self, inverse: bool) {
+        self.device.color_thrust_write_reg(REG_COLOR_SWIZZLE_ADDR, (if inverse { 1 } else { 0 }) << REG_COLOR_SWIZZLE_INVERSE_BIT);
+        while self.device.color_thrust_read_reg(REG_STATUS_ADDR) != 0 {
+            // Do nothing
+        }
+    }
+
+    // Swizzled tiles are moved through color thrust's swizzled window, where each 2x2 block index's words form a
+    //  contiguous half-height tile that maps to a rect of the matching texture chunk (see alloc_texture_data).
+    //  Texture rows are bottom-up, like tile rows.
+    fn swizzled_color_tile_rects(&self, texture_data: &TextureData, tile_min_x: u32, tile_min_y: u32) -> [(Rect, Rect); 4] {
+        let dim = texture_data.dim.to_u32();
+        let chunk_row_words = dim / 2 / 4;
+        let chunk_words = dim / 2 * chunk_row_words;
+        let tile_chunk_words = self.tile_width * self.tile_height / 16;
+        let swizzled_window_base_addr = 0x04000000 + (1 << COLOR_BUFFER_BUS_SWIZZLED_WINDOW_BIT) * 16; // TODO: Proper constant!!!
+        let rects = |chunk: u32| (
+            Rect::contiguous(swizzled_window_base_addr + chunk * tile_chunk_words * 16),
+            Rect {
+                addr: texture_data.base_addr + (chunk * chunk_words + tile_min_y / 2 * chunk_row_words + tile_min_x / 8) * 16,
+                words_per_span: self.tile_width / 8,
+                span_stride: chunk_row_words,
+            },
+        );
+        [rects(0), rects(1), rects(2), rects(3)]
+    }
+
+    // Tiles that weren't rendered since the last clear still need their clear values to land in memory
+    fn resolve_pending_clears(&mut self, buffers: u32) {
+        let (surface_width, surface_height) = self.surface_dims();
+
+        for tile_index_y in 0..surface_height / self.tile_height {
+            let tile_min_y = tile_index_y * self.tile_height;
+
+            for tile_index_x in 0..surface_width / self.tile_width {
+                let tile_min_x = tile_index_x * self.tile_width;
+
+                let tile_index = tile_index_y * (surface_width / self.tile_width) + tile_index_x;
+                let pending_clears = self.pending_clears.tiles[tile_index as usize] & buffers;
+                if pending_clears == 0 {
+                    continue;
+                }
+
+                self.clear_tile(pending_clears);
+                self.store_tile(pending_clears, tile_min_x, tile_min_y);
+
+                self.pending_clears.tiles[tile_index as usize] &= !pending_clears;
+            }
+        }
+    }
+
+    fn write_fog_regs(&mut self) {
+        // Pick a shift for w so that the table covers the whole fog range
+        let w_shift = match self.fog_source {
+            FogSource::Depth => 0,
+            FogSource::W => {
+                let range = match self.fog_mode {
+                    FogMode::Linear | FogMode::Table => self.fog_end,
+                    // Distances where fog reaches 255/256
+                    FogMode::Exp => 5.545 / self.fog_density,
+                    FogMode::Exp2 => 2.355 / self.fog_density,
+                };
+                let range = (range * (1 << RESTORED_W_FRACT_BITS) as f32) as u32;
+                let mut w_shift = 0;
+                while w_shift < 32 - REG_FOG_TABLE_INDEX_BITS && (range >> w_shift) > REG_FOG_TABLE_ENTRIES {
+                    w_shift += 1;
+                }
+                w_shift
+            }
+        };
+
+        self.device.color_thrust_write_reg(
+            REG_FOG_SETTINGS_ADDR,
+            (if self.fog_enable { 1 } else { 0 } << REG_FOG_ENABLE_BIT) |
+            (match self.fog_source {
+                FogSource::Depth => REG_FOG_SOURCE_Z,
+                FogSource::W => REG_FOG_SOURCE_W,
+            } << REG_FOG_SOURCE_BIT_OFFSET) |
+            (w_shift << REG_FOG_W_SHIFT_BIT_OFFSET));
+        self.device.color_thrust_write_reg(REG_FOG_COLOR_ADDR, self.fog_color);
+
+        if !self.fog_enable {
+            return;
+        }
+
+        // Sample each entry at the center of the range it covers
+        let step = match self.fog_source {
+            FogSource::Depth => 1.0 / REG_FOG_TABLE_ENTRIES as f32,
+            FogSource::W => (1 << w_shift) as f32 / (1 << RESTORED_W_FRACT_BITS) as f32,
+        };
+        let mut table = [0; REG_FOG_TABLE_ENTRIES as usize];
+        for (i, amount) in table.iter_mut().enumerate() {
+            let d = (i as f32 + 0.5) * step;
+            let f = match self.fog_mode {
+                FogMode::Linear => (self.fog_end - d) / (self.fog_end - self.fog_start),
+                FogMode::Exp => exp(-self.fog_density * d),
+                FogMode::Exp2 => exp(-(self.fog_density * d) * (self.fog_density * d)),
+                FogMode::Table => 1.0 - self.fog_table[i] as f32 / 255.0,
+            };
+            let f = f.clamp(0.0, 1.0);
+            *amount = ((1.0 - f) * 255.0 + 0.5) as u8;
+        }
+
+        // Fog settings rarely change between drawcalls, so only entries that differ from what's already in the
+        //  hardware table are written
+        for (i, &amount) in table.iter().enumerate() {
+            if self.uploaded_fog_table.map(|uploaded_table| uploaded_table[i] == amount).unwrap_or(false) {
+                continue;
+            }
+
+            self.device.color_thrust_write_reg(
+                REG_FOG_TABLE_ADDR,
+                ((i as u32) << REG_FOG_TABLE_INDEX_BIT_OFFSET) |
+                ((amount as u32) << REG_FOG_TABLE_VALUE_BIT_OFFSET));
+        }
+        self.uploaded_fog_table = Some(table);
+    }
+
+    pub fn render<W: Write, E: Environment<W>>(&mut self, primitive_type: PrimitiveType, verts: &[Vertex], total_primitive_assembly_cycles: &mut u64, total_binning_cycles: &mut u64, env: &E) -> RenderStats {
         // Transformation
         let start_cycles = env.cycles();
         let verts = verts.iter().map(|vert| {
@@ -280,8 +1159,27 @@ impl<D: Device> Context<D> {
 
         // Primitive assembly
         let start_cycles = env.cycles();
-        for i in (0..verts.len()).step_by(3) {
-            self.assemble_triangle([verts[i + 0], verts[i + 1], verts[i + 2]], total_primitive_assembly_cycles, total_binning_cycles, env);
+        match primitive_type {
+            PrimitiveType::Triangles => {
+                for i in (0..verts.len()).step_by(3) {
+                    self.assemble_triangle([verts[i + 0], verts[i + 1], verts[i + 2]], total_primitive_assembly_cycles, total_binning_cycles, env);
+                }
+            }
+            PrimitiveType::Lines => {
+                for line in verts.chunks_exact(2) {
+                    self.assemble_line([line[0], line[1]], total_primitive_assembly_cycles, total_binning_cycles, env);
+                }
+            }
+            PrimitiveType::LineStrip => {
+                for i in 1..verts.len() {
+                    self.assemble_line([verts[i - 1], verts[i]], total_primitive_assembly_cycles, total_binning_cycles, env);
+                }
+            }
+            PrimitiveType::Points => {
+                for &vert in verts.iter() {
+                    self.assemble_point(vert, total_primitive_assembly_cycles, total_binning_cycles, env);
+                }
+            }
         }
         let primitive_assembly_and_binning_cycles = env.cycles().wrapping_sub(start_cycles);
 
@@ -289,7 +1187,44 @@ impl<D: Device> Context<D> {
         self.device.color_thrust_write_reg(
             REG_DEPTH_SETTINGS_ADDR,
             (if self.depth_test_enable { 1 } else { 0 } << REG_DEPTH_TEST_ENABLE_BIT) |
-            (if self.depth_write_mask_enable { 1 } else { 0 } << REG_DEPTH_WRITE_MASK_ENABLE_BIT));
+            (if self.depth_write_mask_enable { 1 } else { 0 } << REG_DEPTH_WRITE_MASK_ENABLE_BIT) |
+            (match self.depth_func {
+                DepthFunc::Never => REG_DEPTH_FUNC_NEVER,
+                DepthFunc::Less => REG_DEPTH_FUNC_LESS,
+                DepthFunc::Equal => REG_DEPTH_FUNC_EQUAL,
+                DepthFunc::LessOrEqual => REG_DEPTH_FUNC_LEQUAL,
+                DepthFunc::Greater => REG_DEPTH_FUNC_GREATER,
+                DepthFunc::NotEqual => REG_DEPTH_FUNC_NOTEQUAL,
+                DepthFunc::GreaterOrEqual => REG_DEPTH_FUNC_GEQUAL,
+                DepthFunc::Always => REG_DEPTH_FUNC_ALWAYS,
+            } << REG_DEPTH_FUNC_BIT_OFFSET) |
+            (if self.hiz_enable { 1 } else { 0 } << REG_DEPTH_HIZ_ENABLE_BIT));
+
+        self.device.color_thrust_write_reg(
+            REG_STENCIL_SETTINGS_ADDR,
+            (if self.stencil_test_enable { 1 } else { 0 } << REG_STENCIL_TEST_ENABLE_BIT) |
+            (match self.stencil_func {
+                StencilFunc::Never => REG_STENCIL_FUNC_NEVER,
+                StencilFunc::Less => REG_STENCIL_FUNC_LESS,
+                StencilFunc::Equal => REG_STENCIL_FUNC_EQUAL,
+                StencilFunc::LessOrEqual => REG_STENCIL_FUNC_LEQUAL,
+                StencilFunc::Greater => REG_STENCIL_FUNC_GREATER,
+                StencilFunc::NotEqual => REG_STENCIL_FUNC_NOTEQUAL,
+                StencilFunc::GreaterOrEqual => REG_STENCIL_FUNC_GEQUAL,
+                StencilFunc::Always => REG_STENCIL_FUNC_ALWAYS,
+            } << REG_STENCIL_FUNC_BIT_OFFSET) |
+            (self.stencil_fail_op.to_reg() << REG_STENCIL_SFAIL_OP_BIT_OFFSET) |
+            (self.stencil_depth_fail_op.to_reg() << REG_STENCIL_ZFAIL_OP_BIT_OFFSET) |
+            (self.stencil_depth_pass_op.to_reg() << REG_STENCIL_ZPASS_OP_BIT_OFFSET));
+        self.device.color_thrust_write_reg(
+            REG_STENCIL_REF_ADDR,
+            ((self.stencil_ref as u32) << REG_STENCIL_REF_VALUE_BIT_OFFSET) |
+            ((self.stencil_mask as u32) << REG_STENCIL_REF_MASK_BIT_OFFSET) |
+            ((self.stencil_write_mask as u32) << REG_STENCIL_REF_WRITE_MASK_BIT_OFFSET));
+
+        self.write_fog_regs();
+
+        self.device.color_thrust_write_reg(REG_AA_SETTINGS_ADDR, (if self.multisampling() { 1 } else { 0 }) << REG_AA_ENABLE_BIT);
 
         if let Some(texture) = self.texture.as_ref() {
             self.device.color_thrust_write_reg(
@@ -307,6 +1242,21 @@ impl<D: Device> Context<D> {
             self.device.color_thrust_write_reg(REG_TEXTURE_BASE_ADDR, texture.data.base_addr);
         }
 
+        self.device.color_thrust_write_reg(
+            REG_TEXTURE_ENV_ADDR,
+            (if self.texture.is_some() {
+                match self.texture_env {
+                    TextureEnv::Modulate => REG_TEXTURE_ENV_MODE_MODULATE,
+                    TextureEnv::Replace => REG_TEXTURE_ENV_MODE_REPLACE,
+                    TextureEnv::Decal => REG_TEXTURE_ENV_MODE_DECAL,
+                    TextureEnv::Add => REG_TEXTURE_ENV_MODE_ADD,
+                    TextureEnv::Blend => REG_TEXTURE_ENV_MODE_BLEND,
+                }
+            } else {
+                REG_TEXTURE_ENV_MODE_UNTEXTURED
+            }) << REG_TEXTURE_ENV_MODE_BIT_OFFSET);
+        self.device.color_thrust_write_reg(REG_TEXTURE_ENV_COLOR_ADDR, self.texture_env_color);
+
         self.device.color_thrust_write_reg(
             REG_BLEND_SETTINGS_ADDR,
             (match self.blend_src_factor {
@@ -314,59 +1264,97 @@ impl<D: Device> Context<D> {
                 BlendSrcFactor::One => REG_BLEND_SETTINGS_SRC_FACTOR_ONE,
                 BlendSrcFactor::SrcAlpha => REG_BLEND_SETTINGS_SRC_FACTOR_SRC_ALPHA,
                 BlendSrcFactor::OneMinusSrcAlpha => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_ALPHA,
+                BlendSrcFactor::DstColor => REG_BLEND_SETTINGS_SRC_FACTOR_DST_COLOR,
+                BlendSrcFactor::OneMinusDstColor => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_COLOR,
+                BlendSrcFactor::SrcColor => REG_BLEND_SETTINGS_SRC_FACTOR_SRC_COLOR,
+                BlendSrcFactor::OneMinusSrcColor => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_SRC_COLOR,
+                BlendSrcFactor::DstAlpha => REG_BLEND_SETTINGS_SRC_FACTOR_DST_ALPHA,
+                BlendSrcFactor::OneMinusDstAlpha => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_DST_ALPHA,
+                BlendSrcFactor::ConstantColor => REG_BLEND_SETTINGS_SRC_FACTOR_CONSTANT_COLOR,
+                BlendSrcFactor::OneMinusConstantColor => REG_BLEND_SETTINGS_SRC_FACTOR_ONE_MINUS_CONSTANT_COLOR,
             } << REG_BLEND_SETTINGS_SRC_FACTOR_BIT_OFFSET) |
             (match self.blend_dst_factor {
                 BlendDstFactor::Zero => REG_BLEND_SETTINGS_DST_FACTOR_ZERO,
                 BlendDstFactor::One => REG_BLEND_SETTINGS_DST_FACTOR_ONE,
                 BlendDstFactor::SrcAlpha => REG_BLEND_SETTINGS_DST_FACTOR_SRC_ALPHA,
                 BlendDstFactor::OneMinusSrcAlpha => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_ALPHA,
-            } << REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET));
+                BlendDstFactor::DstColor => REG_BLEND_SETTINGS_DST_FACTOR_DST_COLOR,
+                BlendDstFactor::OneMinusDstColor => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_COLOR,
+                BlendDstFactor::SrcColor => REG_BLEND_SETTINGS_DST_FACTOR_SRC_COLOR,
+                BlendDstFactor::OneMinusSrcColor => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_SRC_COLOR,
+                BlendDstFactor::DstAlpha => REG_BLEND_SETTINGS_DST_FACTOR_DST_ALPHA,
+                BlendDstFactor::OneMinusDstAlpha => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_DST_ALPHA,
+                BlendDstFactor::ConstantColor => REG_BLEND_SETTINGS_DST_FACTOR_CONSTANT_COLOR,
+                BlendDstFactor::OneMinusConstantColor => REG_BLEND_SETTINGS_DST_FACTOR_ONE_MINUS_CONSTANT_COLOR,
+            } << REG_BLEND_SETTINGS_DST_FACTOR_BIT_OFFSET) |
+            (match self.blend_equation {
+                BlendEquation::Add => REG_BLEND_SETTINGS_EQUATION_ADD,
+                BlendEquation::Subtract => REG_BLEND_SETTINGS_EQUATION_SUBTRACT,
+                BlendEquation::ReverseSubtract => REG_BLEND_SETTINGS_EQUATION_REVERSE_SUBTRACT,
+                BlendEquation::Min => REG_BLEND_SETTINGS_EQUATION_MIN,
+                BlendEquation::Max => REG_BLEND_SETTINGS_EQUATION_MAX,
+            } << REG_BLEND_SETTINGS_EQUATION_BIT_OFFSET));
+        self.device.color_thrust_write_reg(REG_BLEND_COLOR_ADDR, self.blend_color);
+
+        self.device.color_thrust_write_reg(
+            REG_ALPHA_TEST_SETTINGS_ADDR,
+            (if self.alpha_test_enable { 1 } else { 0 } << REG_ALPHA_TEST_ENABLE_BIT) |
+            (match self.alpha_test_func {
+                AlphaFunc::Never => REG_ALPHA_TEST_FUNC_NEVER,
+                AlphaFunc::Less => REG_ALPHA_TEST_FUNC_LESS,
+                AlphaFunc::Equal => REG_ALPHA_TEST_FUNC_EQUAL,
+                AlphaFunc::LessOrEqual => REG_ALPHA_TEST_FUNC_LEQUAL,
+                AlphaFunc::Greater => REG_ALPHA_TEST_FUNC_GREATER,
+                AlphaFunc::NotEqual => REG_ALPHA_TEST_FUNC_NOTEQUAL,
+                AlphaFunc::GreaterOrEqual => REG_ALPHA_TEST_FUNC_GEQUAL,
+                AlphaFunc::Always => REG_ALPHA_TEST_FUNC_ALWAYS,
+            } << REG_ALPHA_TEST_FUNC_BIT_OFFSET) |
+            ((self.alpha_test_ref as u32) << REG_ALPHA_TEST_REF_BIT_OFFSET));
 
         let mut num_nonempty_tiles = 0;
         let mut total_tile_xfer_cycles = 0;
         let mut total_rasterization_cycles = 0;
+        self.device.color_thrust_write_reg(REG_HIZ_REJECTED_PRIMITIVES_ADDR, 0);
+        self.device.color_thrust_write_reg(REG_HIZ_REJECTED_BLOCKS_ADDR, 0);
 
         // Primitive rendering
-        for tile_index_y in 0..HEIGHT / TILE_DIM {
-            let tile_min_y = tile_index_y * TILE_DIM;
+        let (surface_width, surface_height) = self.surface_dims();
+        for tile_index_y in 0..surface_height / self.tile_height {
+            let tile_min_y = tile_index_y * self.tile_height;
 
-            for tile_index_x in 0..WIDTH / TILE_DIM {
-                let tile_min_x = tile_index_x * TILE_DIM;
+            for tile_index_x in 0..surface_width / self.tile_width {
+                let tile_min_x = tile_index_x * self.tile_width;
 
-                let tile_index = tile_index_y * (WIDTH / TILE_DIM) + tile_index_x;
-                let assembled_triangles = &mut self.assembled_triangles[tile_index as usize];
-                if assembled_triangles.is_empty() {
+                let tile_index = tile_index_y * (surface_width / self.tile_width) + tile_index_x;
+                if self.assembled_triangles[tile_index as usize].is_empty() {
                     continue;
                 }
 
                 num_nonempty_tiles += 1;
 
-                // Copy tile into rasterizer memory
+                // Copy tile into rasterizer memory, clearing it in place instead where a clear is pending
                 let start_cycles = env.cycles();
-                mem2sys(
-                    &mut self.device,
-                    0x04000000, // TODO: Proper constant!!!
-                    0,
-                    0,
-                    self.back_buffer_base_addr + ((HEIGHT - 1 - tile_min_y) * WIDTH + tile_min_x) * 4,
-                    TILE_DIM / 4,
-                    (-(WIDTH as i32) / 4) as _,
-                    TILE_DIM * TILE_DIM / 4,
-                );
+                let pending_clears = self.pending_clears.tiles[tile_index as usize];
+                if pending_clears != 0 {
+                    self.clear_tile(pending_clears);
+                }
+                let mut used_buffers = 1 << REG_CLEAR_TILE_COLOR_BIT;
                 if self.depth_test_enable || self.depth_write_mask_enable {
-                    mem2sys(
-                        &mut self.device,
-                        0x05000000, // TODO: Proper constant!!!
-                        0,
-                        0,
-                        self.depth_buffer_base_addr + ((HEIGHT - 1 - tile_min_y) * WIDTH + tile_min_x) * 2,
-                        TILE_DIM / 8,
-                        (-(WIDTH as i32) / 8) as _,
-                        TILE_DIM * TILE_DIM / 8,
-                    );
+                    used_buffers |= 1 << REG_CLEAR_TILE_DEPTH_BIT;
+                }
+                if self.stencil_test_enable {
+                    used_buffers |= 1 << REG_CLEAR_TILE_STENCIL_BIT;
                 }
+                self.load_tile(used_buffers & !pending_clears, tile_min_x, tile_min_y);
                 total_tile_xfer_cycles += env.cycles().wrapping_sub(start_cycles);
 
+                // The hardware counter only covers one tile at a time
+                if self.occlusion_query.is_some() {
+                    self.device.color_thrust_write_reg(REG_OCCLUSION_QUERY_BEGIN_ADDR, 1);
+                }
+
+                let assembled_triangles = &mut self.assembled_triangles[tile_index as usize];
+
                 let start_cycles = env.cycles();
                 for triangle in assembled_triangles.iter() {
                     self.device.color_thrust_write_reg(REG_W0_MIN_ADDR, triangle.w0_min);
@@ -402,6 +1390,7 @@ impl<D: Device> Context<D> {
                     self.device.color_thrust_write_reg(REG_T_MIN_ADDR, triangle.t_min);
                     self.device.color_thrust_write_reg(REG_T_DX_ADDR, triangle.t_dx);
                     self.device.color_thrust_write_reg(REG_T_DY_ADDR, triangle.t_dy);
+                    self.device.color_thrust_write_reg(REG_Z_BOUNDS_ADDR, triangle.z_bounds);
 
                     // Ensure previous primitive is complete, if any
                     while self.device.color_thrust_read_reg(REG_STATUS_ADDR) != 0 {
@@ -417,53 +1406,100 @@ impl<D: Device> Context<D> {
                 }
                 total_rasterization_cycles += env.cycles().wrapping_sub(start_cycles);
 
+                if let Some(occlusion_query) = self.occlusion_query.as_mut() {
+                    self.device.color_thrust_write_reg(REG_OCCLUSION_QUERY_END_ADDR, 1);
+                    occlusion_query.passed_samples += self.device.color_thrust_read_reg(REG_OCCLUSION_QUERY_PASSED_SAMPLES_ADDR);
+                }
+
                 // Copy rasterizer memory back to tile
                 let start_cycles = env.cycles();
-                sys2mem(
-                    &mut self.device,
-                    0x04000000, // TODO: Proper constant!!!
-                    0,
-                    0,
-                    self.back_buffer_base_addr + ((HEIGHT - 1 - tile_min_y) * WIDTH + tile_min_x) * 4,
-                    TILE_DIM / 4,
-                    (-(WIDTH as i32) / 4) as _,
-                    TILE_DIM * TILE_DIM / 4,
-                );
+                let mut written_buffers = 1 << REG_CLEAR_TILE_COLOR_BIT;
                 if self.depth_write_mask_enable {
-                    sys2mem(
-                        &mut self.device,
-                        0x05000000, // TODO: Proper constant!!!
-                        0,
-                        0,
-                        self.depth_buffer_base_addr + ((HEIGHT - 1 - tile_min_y) * WIDTH + tile_min_x) * 2,
-                        TILE_DIM / 8,
-                        (-(WIDTH as i32) / 8) as _,
-                        TILE_DIM * TILE_DIM / 8,
-                    );
+                    written_buffers |= 1 << REG_CLEAR_TILE_DEPTH_BIT;
                 }
+                if self.stencil_test_enable {
+                    written_buffers |= 1 << REG_CLEAR_TILE_STENCIL_BIT;
+                }
+                self.store_tile(written_buffers, tile_min_x, tile_min_y);
+                self.pending_clears.tiles[tile_index as usize] &= !written_buffers;
                 total_tile_xfer_cycles += env.cycles().wrapping_sub(start_cycles);
 
-                assembled_triangles.clear();
+                self.assembled_triangles[tile_index as usize].clear();
             }
         }
 
+        // The target may be sampled as a texture in a later drawcall
+        if self.render_target.texture_data.is_some() && num_nonempty_tiles > 0 {
+            self.device.color_thrust_write_reg(REG_TEX_CACHE_INVALIDATE_ADDR, 1);
+        }
+
         RenderStats {
             vertex_transformation_cycles,
             primitive_assembly_and_binning_cycles,
             num_nonempty_tiles,
             total_tile_xfer_cycles,
             total_rasterization_cycles,
+            hiz_rejected_primitives: self.device.color_thrust_read_reg(REG_HIZ_REJECTED_PRIMITIVES_ADDR),
+            hiz_rejected_blocks: self.device.color_thrust_read_reg(REG_HIZ_REJECTED_BLOCKS_ADDR),
         }
     }
 
+    // Lines and points are expanded into screen-aligned quads, so they're rasterized with the same fill rules and
+    //  attribute interpolation as triangles
+    fn assemble_line<W: Write, E: Environment<W>>(&mut self, verts: [TransformedVertex; 2], total_primitive_assembly_cycles: &mut u64, total_binning_cycles: &mut u64, env: &E) {
+        // TODO: Clipping
+        if verts.iter().any(|vert| vert.position.w <= 0.0.into()) {
+            return;
+        }
+
+        let (width, height) = (self.render_target.width, self.render_target.height);
+        let window_dir = window_offset(verts[1].position, width, height) - window_offset(verts[0].position, width, height);
+        if window_dir.dot(window_dir) == 0.0 {
+            return;
+        }
+        // Extend to the line's left so that the quad's winding is counter-clockwise (front-facing)
+        let half_width = V2::new(-window_dir.y, window_dir.x).normalize() * (self.line_width / 2.0);
+
+        self.assemble_quad([
+            offset_vertex(verts[0], half_width * -1.0, width, height),
+            offset_vertex(verts[1], half_width * -1.0, width, height),
+            offset_vertex(verts[1], half_width, width, height),
+            offset_vertex(verts[0], half_width, width, height),
+        ], total_primitive_assembly_cycles, total_binning_cycles, env);
+    }
+
+    fn assemble_point<W: Write, E: Environment<W>>(&mut self, vert: TransformedVertex, total_primitive_assembly_cycles: &mut u64, total_binning_cycles: &mut u64, env: &E) {
+        // TODO: Clipping
+        if vert.position.w <= 0.0.into() {
+            return;
+        }
+
+        let (width, height) = (self.render_target.width, self.render_target.height);
+        let half_size = self.point_size / 2.0;
+
+        self.assemble_quad([
+            offset_vertex(vert, V2::new(-half_size, -half_size), width, height),
+            offset_vertex(vert, V2::new(half_size, -half_size), width, height),
+            offset_vertex(vert, V2::new(half_size, half_size), width, height),
+            offset_vertex(vert, V2::new(-half_size, half_size), width, height),
+        ], total_primitive_assembly_cycles, total_binning_cycles, env);
+    }
+
+    // Corners must be in counter-clockwise order
+    fn assemble_quad<W: Write, E: Environment<W>>(&mut self, verts: [TransformedVertex; 4], total_primitive_assembly_cycles: &mut u64, total_binning_cycles: &mut u64, env: &E) {
+        self.assemble_triangle([verts[0], verts[1], verts[2]], total_primitive_assembly_cycles, total_binning_cycles, env);
+        self.assemble_triangle([verts[0], verts[2], verts[3]], total_primitive_assembly_cycles, total_binning_cycles, env);
+    }
+
     fn assemble_triangle<W: Write, E: Environment<W>>(&mut self, mut verts: [TransformedVertex; 3], total_primitive_assembly_cycles: &mut u64, total_binning_cycles: &mut u64, env: &E) {
         let start_cycles = env.cycles();
 
         // TODO: Proper viewport
         let viewport_x = 0;
         let viewport_y = 0;
-        let viewport_width = WIDTH as i32;
-        let viewport_height = HEIGHT as i32;
+        let (surface_width, surface_height) = self.surface_dims();
+        let viewport_width = surface_width as i32;
+        let viewport_height = surface_height as i32;
 
         // TODO: Clipping, culling, ...
         for vert in verts.iter() {
@@ -553,8 +1589,8 @@ impl<D: Device> Context<D> {
         ));
         bb_min = bb_min.max(Iv2::zero());
         bb_max = bb_max.min(Iv2::new(
-            Fixed::from_raw(WIDTH as i32 - 1, 0),
-            Fixed::from_raw(HEIGHT as i32 - 1, 0),
+            Fixed::from_raw(surface_width as i32 - 1, 0),
+            Fixed::from_raw(surface_height as i32 - 1, 0),
         ));
         let bb_min_x = bb_min.x.floor().into_raw(0);
         let bb_min_y = bb_min.y.floor().into_raw(0);
@@ -580,6 +1616,12 @@ impl<D: Device> Context<D> {
         // TODO: Don't divide, reciprocal multiply
         let w_dx: Iv3<DEFAULT_FRACT_BITS> = Iv3::new(w0_dx, w1_dx, w2_dx).div_mixed(scaled_area);
         let w_dy: Iv3<DEFAULT_FRACT_BITS> = Iv3::new(w0_dy, w1_dy, w2_dy).div_mixed(scaled_area);
+        // With multisampling, everything but z is shaded once per pixel, so it steps by 2 samples at a time
+        let (shade_w_dx, shade_w_dy) = if self.multisampling() {
+            (w_dx + w_dx, w_dy + w_dy)
+        } else {
+            (w_dx, w_dy)
+        };
 
         let r = Iv3::new(verts[0].color.x, verts[1].color.x, verts[2].color.x);
         let g = Iv3::new(verts[0].color.y, verts[1].color.y, verts[2].color.y);
@@ -587,14 +1629,14 @@ impl<D: Device> Context<D> {
         let a = Iv3::new(verts[0].color.w, verts[1].color.w, verts[2].color.w);
         // TODO: Move this?
         const COLOR_COMP_BITS: u32 = COLOR_WHOLE_BITS + COLOR_FRACT_BITS - 2;
-        let r_dx: Fixed<COLOR_COMP_BITS> = r.dot_mixed(w_dx);
-        let g_dx: Fixed<COLOR_COMP_BITS> = g.dot_mixed(w_dx);
-        let b_dx: Fixed<COLOR_COMP_BITS> = b.dot_mixed(w_dx);
-        let a_dx: Fixed<COLOR_COMP_BITS> = a.dot_mixed(w_dx);
-        let r_dy: Fixed<COLOR_COMP_BITS> = r.dot_mixed(w_dy);
-        let g_dy: Fixed<COLOR_COMP_BITS> = g.dot_mixed(w_dy);
-        let b_dy: Fixed<COLOR_COMP_BITS> = b.dot_mixed(w_dy);
-        let a_dy: Fixed<COLOR_COMP_BITS> = a.dot_mixed(w_dy);
+        let r_dx: Fixed<COLOR_COMP_BITS> = r.dot_mixed(shade_w_dx);
+        let g_dx: Fixed<COLOR_COMP_BITS> = g.dot_mixed(shade_w_dx);
+        let b_dx: Fixed<COLOR_COMP_BITS> = b.dot_mixed(shade_w_dx);
+        let a_dx: Fixed<COLOR_COMP_BITS> = a.dot_mixed(shade_w_dx);
+        let r_dy: Fixed<COLOR_COMP_BITS> = r.dot_mixed(shade_w_dy);
+        let g_dy: Fixed<COLOR_COMP_BITS> = g.dot_mixed(shade_w_dy);
+        let b_dy: Fixed<COLOR_COMP_BITS> = b.dot_mixed(shade_w_dy);
+        let a_dy: Fixed<COLOR_COMP_BITS> = a.dot_mixed(shade_w_dy);
         triangle.r_dx = r_dx.into_raw(COLOR_COMP_BITS) as _;
         triangle.g_dx = g_dx.into_raw(COLOR_COMP_BITS) as _;
         triangle.b_dx = b_dx.into_raw(COLOR_COMP_BITS) as _;
@@ -610,8 +1652,8 @@ impl<D: Device> Context<D> {
             Fixed::from(1.0) / verts[1].position.w,
             Fixed::from(1.0) / verts[2].position.w,
         );
-        let w_inverse_dx: Fixed<W_INVERSE_FRACT_BITS> = w_inverse.dot_mixed(w_dx);
-        let w_inverse_dy: Fixed<W_INVERSE_FRACT_BITS> = w_inverse.dot_mixed(w_dy);
+        let w_inverse_dx: Fixed<W_INVERSE_FRACT_BITS> = w_inverse.dot_mixed(shade_w_dx);
+        let w_inverse_dy: Fixed<W_INVERSE_FRACT_BITS> = w_inverse.dot_mixed(shade_w_dy);
         triangle.w_inverse_dx = w_inverse_dx.into_raw(W_INVERSE_FRACT_BITS) as _;
         triangle.w_inverse_dy = w_inverse_dy.into_raw(W_INVERSE_FRACT_BITS) as _;
 
@@ -621,12 +1663,19 @@ impl<D: Device> Context<D> {
         triangle.z_dx = z_dx.into_raw(Z_FRACT_BITS) as _;
         triangle.z_dy = z_dy.into_raw(Z_FRACT_BITS) as _;
 
+        // Interpolated z stays within the vertices' range, give or take rounding, so pad by one in each direction
+        let z_bounds_min = window_verts.iter().map(|v| v.z.into_raw(16)).min().unwrap();
+        let z_bounds_max = window_verts.iter().map(|v| v.z.into_raw(16)).max().unwrap();
+        let z_bounds_min = (z_bounds_min - 1).clamp(0, 0xffff) as u32;
+        let z_bounds_max = (z_bounds_max + 1).clamp(0, 0xffff) as u32;
+        triangle.z_bounds = (z_bounds_min << REG_Z_BOUNDS_MIN_BIT_OFFSET) | (z_bounds_max << REG_Z_BOUNDS_MAX_BIT_OFFSET);
+
         let s = Iv3::new(verts[0].tex_coord.x, verts[1].tex_coord.x, verts[2].tex_coord.x);
         let t = Iv3::new(verts[0].tex_coord.y, verts[1].tex_coord.y, verts[2].tex_coord.y);
-        let s_dx: Fixed<ST_FRACT_BITS> = s.dot_mixed(w_dx);
-        let t_dx: Fixed<ST_FRACT_BITS> = t.dot_mixed(w_dx);
-        let s_dy: Fixed<ST_FRACT_BITS> = s.dot_mixed(w_dy);
-        let t_dy: Fixed<ST_FRACT_BITS> = t.dot_mixed(w_dy);
+        let s_dx: Fixed<ST_FRACT_BITS> = s.dot_mixed(shade_w_dx);
+        let t_dx: Fixed<ST_FRACT_BITS> = t.dot_mixed(shade_w_dx);
+        let s_dy: Fixed<ST_FRACT_BITS> = s.dot_mixed(shade_w_dy);
+        let t_dy: Fixed<ST_FRACT_BITS> = t.dot_mixed(shade_w_dy);
         triangle.s_dx = s_dx.into_raw(ST_FRACT_BITS) as _;
         triangle.t_dx = t_dx.into_raw(ST_FRACT_BITS) as _;
         triangle.s_dy = s_dy.into_raw(ST_FRACT_BITS) as _;
@@ -646,17 +1695,17 @@ impl<D: Device> Context<D> {
 
         let start_cycles = env.cycles();
 
-        for tile_index_y in 0..HEIGHT / TILE_DIM {
-            let tile_min_y = (tile_index_y * TILE_DIM) as i32;
-            let tile_max_y = tile_min_y + TILE_DIM as i32 - 1;
+        for tile_index_y in 0..surface_height / self.tile_height {
+            let tile_min_y = (tile_index_y * self.tile_height) as i32;
+            let tile_max_y = tile_min_y + self.tile_height as i32 - 1;
 
             if bb_max_y < tile_min_y || bb_min_y > tile_max_y {
                 continue;
             }
 
-            for tile_index_x in 0..WIDTH / TILE_DIM {
-                let tile_min_x = (tile_index_x * TILE_DIM) as i32;
-                let tile_max_x = tile_min_x + TILE_DIM as i32 - 1;
+            for tile_index_x in 0..surface_width / self.tile_width {
+                let tile_min_x = (tile_index_x * self.tile_width) as i32;
+                let tile_max_x = tile_min_x + self.tile_width as i32 - 1;
 
                 if bb_max_x < tile_min_x || bb_min_x > tile_max_x {
                     continue;
@@ -697,27 +1746,42 @@ impl<D: Device> Context<D> {
                 // TODO: Don't divide, reciprocal multiply
                 let w_min: Iv3<DEFAULT_FRACT_BITS> = Iv3::new(w0_min, w1_min, w2_min).div_mixed(scaled_area);
 
-                let r_min: Fixed<COLOR_COMP_BITS> = r.dot_mixed(w_min);
-                let g_min: Fixed<COLOR_COMP_BITS> = g.dot_mixed(w_min);
-                let b_min: Fixed<COLOR_COMP_BITS> = b.dot_mixed(w_min);
-                let a_min: Fixed<COLOR_COMP_BITS> = a.dot_mixed(w_min);
+                // With multisampling, pixel centers sit between each pixel's 2x2 samples
+                let shade_w_min = if self.multisampling() {
+                    let p = p + Fixed::from(0.5);
+                    let w0_min_raw = orient2d_raw(Iv2::new(window_verts[1].x, window_verts[1].y), Iv2::new(window_verts[2].x, window_verts[2].y), p);
+                    let w1_min_raw = orient2d_raw(Iv2::new(window_verts[2].x, window_verts[2].y), Iv2::new(window_verts[0].x, window_verts[0].y), p);
+                    let w2_min_raw = orient2d_raw(Iv2::new(window_verts[0].x, window_verts[0].y), Iv2::new(window_verts[1].x, window_verts[1].y), p);
+                    let w0_min: Fixed<EDGE_FRACT_BITS> = Fixed::from_raw((w0_min_raw >> EDGE_FRACT_BITS) as _, EDGE_FRACT_BITS);
+                    let w1_min: Fixed<EDGE_FRACT_BITS> = Fixed::from_raw((w1_min_raw >> EDGE_FRACT_BITS) as _, EDGE_FRACT_BITS);
+                    let w2_min: Fixed<EDGE_FRACT_BITS> = Fixed::from_raw((w2_min_raw >> EDGE_FRACT_BITS) as _, EDGE_FRACT_BITS);
+                    // TODO: Don't divide, reciprocal multiply
+                    Iv3::new(w0_min, w1_min, w2_min).div_mixed(scaled_area)
+                } else {
+                    w_min
+                };
+
+                let r_min: Fixed<COLOR_COMP_BITS> = r.dot_mixed(shade_w_min);
+                let g_min: Fixed<COLOR_COMP_BITS> = g.dot_mixed(shade_w_min);
+                let b_min: Fixed<COLOR_COMP_BITS> = b.dot_mixed(shade_w_min);
+                let a_min: Fixed<COLOR_COMP_BITS> = a.dot_mixed(shade_w_min);
                 triangle.r_min = r_min.into_raw(COLOR_COMP_BITS) as _;
                 triangle.g_min = g_min.into_raw(COLOR_COMP_BITS) as _;
                 triangle.b_min = b_min.into_raw(COLOR_COMP_BITS) as _;
                 triangle.a_min = a_min.into_raw(COLOR_COMP_BITS) as _;
 
-                let w_inverse_min: Fixed<W_INVERSE_FRACT_BITS> = w_inverse.dot_mixed(w_min);
+                let w_inverse_min: Fixed<W_INVERSE_FRACT_BITS> = w_inverse.dot_mixed(shade_w_min);
                 triangle.w_inverse_min = w_inverse_min.into_raw(W_INVERSE_FRACT_BITS) as _;
 
                 let z_min: Fixed<Z_FRACT_BITS> = z.dot_mixed(w_min);
                 triangle.z_min = z_min.into_raw(Z_FRACT_BITS) as _;
 
-                let s_min: Fixed<ST_FRACT_BITS> = s.dot_mixed(w_min);
-                let t_min: Fixed<ST_FRACT_BITS> = t.dot_mixed(w_min);
+                let s_min: Fixed<ST_FRACT_BITS> = s.dot_mixed(shade_w_min);
+                let t_min: Fixed<ST_FRACT_BITS> = t.dot_mixed(shade_w_min);
                 triangle.s_min = s_min.into_raw(ST_FRACT_BITS) as _;
                 triangle.t_min = t_min.into_raw(ST_FRACT_BITS) as _;
 
-                let tile_index = tile_index_y * (WIDTH / TILE_DIM) + tile_index_x;
+                let tile_index = tile_index_y * (surface_width / self.tile_width) + tile_index_x;
                 self.assembled_triangles[tile_index as usize].push(triangle.clone());
             }
         }
@@ -725,14 +1789,143 @@ impl<D: Device> Context<D> {
         *total_binning_cycles += env.cycles().wrapping_sub(start_cycles);
     }
 
+    // With multisampling, the back buffer is overwritten with resolved samples wherever later drawcalls touch it
+    pub fn fill_rect(&mut self, target: &Rc<RenderTarget>, rect: PixelRect, color: u32, ops: BlitOps) {
+        let value = match target.color_format {
+            ColorFormat::Argb8888 => color,
+            ColorFormat::Rgb565 => {
+                let color = argb8888_to_rgb565(color) as u32;
+                (color << 16) | color
+            }
+        };
+        let mem_rect = self.prepare_blit_rect(target, rect, &ops);
+
+        set_blit_ops(&mut self.device, &ops);
+        self.tile_transfers.push_fill(value, mem_rect, mem_rect.words_per_span * rect.height);
+        self.flush_tile_transfers();
+    }
+
+    // The destination rect has the same dimensions as the source rect. Overlapping source and destination rects are
+    //  only supported when the destination is lower in memory.
+    pub fn blit_rect(&mut self, src: &Rc<RenderTarget>, src_rect: PixelRect, dst: &Rc<RenderTarget>, dst_x: u32, dst_y: u32, ops: BlitOps) {
+        if src.color_format != dst.color_format {
+            panic!("Blits can't convert between color formats");
+        }
+        let dst_rect = PixelRect {
+            x: dst_x,
+            y: dst_y,
+            ..src_rect
+        };
+        let src_mem_rect = self.prepare_blit_rect(src, src_rect, &ops);
+        let dst_mem_rect = self.prepare_blit_rect(dst, dst_rect, &ops);
+
+        set_blit_ops(&mut self.device, &ops);
+        self.tile_transfers.push_mem2mem(src_mem_rect, dst_mem_rect, dst_mem_rect.words_per_span * src_rect.height);
+        self.flush_tile_transfers();
+    }
+
+    // Lands any pending work on the target in memory, and returns where the rect's words are
+    fn prepare_blit_rect(&mut self, target: &Rc<RenderTarget>, rect: PixelRect, ops: &BlitOps) -> Rect {
+        let PixelRect { x, y, width, height } = rect;
+        if target.texture_data.is_some() {
+            panic!("Render-to-texture targets can't be blitted");
+        }
+        if x + width > target.width || y + height > target.height {
+            panic!("Blit rect ({}, {}, {}x{}) exceeds the {}x{} target", x, y, width, height, target.width, target.height);
+        }
+        let bytes_per_pixel = target.color_format.bytes_per_pixel();
+        let pixels_per_word = 16 / bytes_per_pixel;
+        if x % pixels_per_word != 0 || width % pixels_per_word != 0 {
+            panic!("Blit rect x and width must be multiples of {} pixels", pixels_per_word);
+        }
+        if (ops.color_key.is_some() || ops.blend_enable || ops.logic_op != LogicOp::Copy) && target.color_format != ColorFormat::Argb8888 {
+            panic!("Blit ops require ARGB8888 targets");
+        }
+
+        // Pending clears are only tracked for the current target
+        if Rc::ptr_eq(target, &self.render_target) {
+            self.resolve_pending_clears(1 << REG_CLEAR_TILE_COLOR_BIT);
+        }
+        self.tile_transfers.wait(&mut self.device);
+        if Rc::ptr_eq(target, &self.back_buffer) {
+            self.wait_for_flip();
+        }
+
+        Rect {
+            addr: target.color_buffer_base_addr + (y * target.width + x) * bytes_per_pixel,
+            words_per_span: width / pixels_per_word,
+            span_stride: target.width / pixels_per_word,
+        }
+    }
+
+    // Queues the back buffer to be scanned out from the next frame on, and swaps it with the front buffer. Rendering
+    //  into the new back buffer waits for the flip to land, so the displayed image never tears. The new back buffer
+    //  holds whatever was presented before the last frame, so it should be cleared before it's rendered to again.
+    pub fn present(&mut self) {
+        let is_render_target = Rc::ptr_eq(&self.render_target, &self.back_buffer);
+        if is_render_target {
+            self.resolve_pending_clears(1 << REG_CLEAR_TILE_COLOR_BIT);
+        }
+        self.tile_transfers.wait(&mut self.device);
+
+        // Presenting again before the last flip lands would start scanning out the buffer that's about to become
+        //  the back buffer, so wait for that first
+        self.wait_for_flip();
+
+        let bytes_per_pixel = self.back_buffer.color_format.bytes_per_pixel();
+        if !self.scanout_enabled {
+            self.device.scanout_write_reg(scanout::REG_FORMAT_ADDR, match self.back_buffer.color_format {
+                ColorFormat::Argb8888 => scanout::REG_FORMAT_ARGB8888,
+                ColorFormat::Rgb565 => scanout::REG_FORMAT_RGB565,
+            });
+            // Rows are stored bottom-up
+            self.device.scanout_write_reg(scanout::REG_LINE_STRIDE_ADDR, (-((WIDTH * bytes_per_pixel / 16) as i32)) as _);
+        }
+        self.device.scanout_write_reg(scanout::REG_FRONT_BUFFER_BASE_ADDR, self.back_buffer.color_buffer_base_addr + (HEIGHT - 1) * WIDTH * bytes_per_pixel);
+        if !self.scanout_enabled {
+            self.device.scanout_write_reg(scanout::REG_ENABLE_ADDR, 1);
+            self.scanout_enabled = true;
+        }
+        self.flip_pending = true;
+
+        mem::swap(&mut self.back_buffer, &mut self.front_buffer);
+        if is_render_target {
+            self.render_target = self.back_buffer.clone();
+        }
+    }
+
+    fn wait_for_flip(&mut self) {
+        if !self.flip_pending {
+            return;
+        }
+
+        while (self.device.scanout_read_reg(scanout::REG_STATUS_ADDR) & (1 << scanout::REG_STATUS_FLIP_PENDING_BIT)) != 0 {
+            // Do nothing
+        }
+        self.flip_pending = false;
+    }
+
     pub fn extract_back_buffer(&mut self) -> Vec<u32> {
+        // Pending clears of other targets were already resolved when switching away from them
+        if Rc::ptr_eq(&self.render_target, &self.back_buffer) {
+            self.resolve_pending_clears(1 << REG_CLEAR_TILE_COLOR_BIT);
+        }
+
+        self.tile_transfers.wait(&mut self.device);
+
         let mut ret = Vec::with_capacity(PIXELS as _);
 
+        let color_format = self.back_buffer.color_format;
+        let bytes_per_pixel = color_format.bytes_per_pixel();
+        let pixels_per_word = 16 / bytes_per_pixel;
         for y in 0..HEIGHT {
-            for x in 0..WIDTH / 4 {
-                let word = self.device.mem_read_word(self.back_buffer_base_addr + ((y * WIDTH) + x * 4) * 4);
-                for i in 0..4 {
-                    ret.push((word >> (i * 32)) as _);
+            for x in 0..WIDTH / pixels_per_word {
+                let word = self.device.mem_read_word(self.back_buffer.color_buffer_base_addr + ((y * WIDTH) + x * pixels_per_word) * bytes_per_pixel);
+                for i in 0..pixels_per_word {
+                    ret.push(match color_format {
+                        ColorFormat::Argb8888 => (word >> (i * 32)) as _,
+                        ColorFormat::Rgb565 => rgb565_to_argb8888((word >> (i * 16)) as _),
+                    });
                 }
             }
         }
@@ -740,3 +1933,63 @@ impl<D: Device> Context<D> {
         ret
     }
 }
+
+// Offset from the center of the viewport in window space, in pixels
+fn window_offset(clip: Iv4<DEFAULT_FRACT_BITS>, width: u32, height: u32) -> V2 {
+    let w = f32::from(clip.w);
+    V2::new(
+        f32::from(clip.x) / w * (width / 2) as f32,
+        f32::from(clip.y) / w * (height / 2) as f32,
+    )
+}
+
+// Moves a vertex by an offset in window space, in pixels
+fn offset_vertex(mut vert: TransformedVertex, offset: V2, width: u32, height: u32) -> TransformedVertex {
+    vert.position.x += Fixed::from(offset.x * 2.0 / width as f32) * vert.position.w;
+    vert.position.y += Fixed::from(offset.y * 2.0 / height as f32) * vert.position.w;
+    vert
+}
+
+// Expands with the same bit replication ColorThrust uses when loading RGB565 tiles
+pub fn rgb565_to_argb8888(rgb565: u16) -> u32 {
+    let rgb565 = rgb565 as u32;
+    let r = (rgb565 >> 11) & 0x1f;
+    let g = (rgb565 >> 5) & 0x3f;
+    let b = rgb565 & 0x1f;
+    0xff000000 |
+    (((r << 3) | (r >> 2)) << 16) |
+    (((g << 2) | (g >> 4)) << 8) |
+    ((b << 3) | (b >> 2))
+}
+
+// Truncates without dithering; exactly undoes rgb565_to_argb8888
+pub fn argb8888_to_rgb565(argb: u32) -> u16 {
+    (((argb >> 8) & 0xf800) | ((argb >> 5) & 0x07e0) | ((argb >> 3) & 0x001f)) as _
+}
+
+// Good enough for building fog tables; core doesn't provide exp in no_std
+fn exp(x: f32) -> f32 {
+    if x > 0.0 {
+        return 1.0 / exp(-x);
+    }
+    if x < -20.0 {
+        return 0.0;
+    }
+
+    // exp(x) = exp(-n) * exp(x + n), where x + n is in (-1, 0]
+    let n = (-x) as u32;
+    let r = x + n as f32;
+
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for i in 1..10 {
+        term *= r / i as f32;
+        sum += term;
+    }
+
+    for _ in 0..n {
+        sum *= 1.0 / core::f32::consts::E;
+    }
+
+    sum
+}
@@ -0,0 +1,349 @@
+#![no_std]
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+mod module;
+mod player;
+
+pub use module::*;
+pub use player::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::vec::Vec;
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    const SINE_SAMPLE: u8 = 1;
+    const SINE_LEN: usize = 64;
+    const SAW_SAMPLE: u8 = 2;
+    const SAW_LEN: usize = 3000;
+    const SAW_VOLUME: u8 = 48;
+
+    const NUM_PATTERNS: usize = 2;
+    const SONG_LENGTH: usize = 3;
+    const ORDERS: [u8; SONG_LENGTH] = [0, 1, 0];
+
+    struct ModuleBuilder {
+        data: Vec<u8>,
+    }
+
+    impl ModuleBuilder {
+        fn new(title: &[u8]) -> ModuleBuilder {
+            let mut data = vec![0; 1084 + NUM_PATTERNS * NUM_ROWS * 4 * 4];
+            data[..title.len()].copy_from_slice(title);
+            data[950] = SONG_LENGTH as _;
+            data[951] = 127;
+            data[952..952 + SONG_LENGTH].copy_from_slice(&ORDERS);
+            data[1080..1084].copy_from_slice(b"M.K.");
+            ModuleBuilder {
+                data,
+            }
+        }
+
+        fn sample(&mut self, index: u8, data: &[i8], volume: u8, loop_start: usize, loop_length: usize) {
+            let header = &mut self.data[20 + (index as usize - 1) * 30..][..30];
+            header[22..24].copy_from_slice(&((data.len() / 2) as u16).to_be_bytes());
+            header[25] = volume;
+            header[26..28].copy_from_slice(&((loop_start / 2) as u16).to_be_bytes());
+            header[28..30].copy_from_slice(&((loop_length / 2) as u16).to_be_bytes());
+            // Samples must be added in order
+            self.data.extend(data.iter().map(|&x| x as u8));
+        }
+
+        fn note(&mut self, pattern: usize, row: usize, channel: usize, sample: u8, period: u16, effect: u8, param: u8) {
+            let bytes = &mut self.data[1084 + ((pattern * NUM_ROWS + row) * 4 + channel) * 4..][..4];
+            bytes[0] = (sample & 0xf0) | (period >> 8) as u8;
+            bytes[1] = period as u8;
+            bytes[2] = (sample << 4) | effect;
+            bytes[3] = param;
+        }
+    }
+
+    fn sine_sample() -> Vec<i8> {
+        (0..SINE_LEN).map(|i| ((i as f64 / SINE_LEN as f64 * core::f64::consts::TAU).sin() * 127.0) as i8).collect()
+    }
+
+    fn saw_sample() -> Vec<i8> {
+        (0..SAW_LEN).map(|i| (((i * 7) % 256) as i32 - 128) as i8).collect()
+    }
+
+    fn build_test_module() -> Vec<u8> {
+        let mut b = ModuleBuilder::new(b"test song");
+        b.sample(SINE_SAMPLE, &sine_sample(), 64, 0, SINE_LEN);
+        b.sample(SAW_SAMPLE, &saw_sample(), SAW_VOLUME, 0, 0);
+
+        // Pattern 0: a bass line and some one-shots, a volume slide, a speed change, then a break into pattern 1
+        for (i, &period) in [428, 381, 339, 320, 285, 254].iter().cycle().take(7).enumerate() {
+            b.note(0, i * 8, 0, SINE_SAMPLE, period, 0, 0);
+        }
+        b.note(0, 0, 1, SAW_SAMPLE, 214, 0, 0);
+        b.note(0, 16, 1, SAW_SAMPLE, 170, 0xc, 0x20);
+        b.note(0, 40, 1, SAW_SAMPLE, 254, 0, 0);
+        b.note(0, 4, 2, SINE_SAMPLE, 856, 0, 0);
+        for row in 5..12 {
+            b.note(0, row, 2, 0, 0, 0xa, 0x03);
+        }
+        b.note(0, 20, 3, SAW_SAMPLE, 302, 0, 0);
+        b.note(0, 28, 3, 0, 0, 0xc, 0x10);
+        b.note(0, 32, 0, 0, 0, 0xf, 0x04);
+        b.note(0, 50, 2, 0, 0, 0xd, 0x10);
+
+        // Pattern 1: a few notes, then a jump back to the start of the song
+        b.note(1, 10, 0, SINE_SAMPLE, 202, 0, 0);
+        b.note(1, 12, 1, SAW_SAMPLE, 160, 0, 0);
+        b.note(1, 14, 3, SINE_SAMPLE, 113, 0xc, 0x30);
+        b.note(1, 20, 2, 0, 0, 0xb, 0x00);
+
+        b.data
+    }
+
+    // A straightforward floating-point render of just the features the test module uses. Steps are quantized the same
+    //  way the player does it, since otherwise tiny pitch differences would add up to large phase differences over long
+    //  notes and swamp everything else we want to compare.
+    fn reference_render(data: &[u8], num_frames: usize) -> Vec<(i16, i16)> {
+        struct RefChannel {
+            sample: usize,
+            position: Option<f64>,
+            step: f64,
+            volume: i32,
+        }
+
+        let sample_data = |index: usize| -> &[u8] {
+            let mut offset = 1084 + NUM_PATTERNS * NUM_ROWS * 4 * 4;
+            for i in 1..index {
+                let header = &data[20 + (i - 1) * 30..];
+                offset += u16::from_be_bytes([header[22], header[23]]) as usize * 2;
+            }
+            let header = &data[20 + (index - 1) * 30..];
+            &data[offset..offset + u16::from_be_bytes([header[22], header[23]]) as usize * 2]
+        };
+        let is_looped = |index: usize| index == SINE_SAMPLE as usize;
+
+        let mut channels = (0..4).map(|_| RefChannel { sample: 0, position: None, step: 0.0, volume: 0 }).collect::<Vec<_>>();
+        let mut speed = 6;
+        let tempo = 125;
+        let mut order = 0;
+        let mut row = 0;
+        let mut tick_frames_remainder = 0;
+
+        let mut ret = Vec::new();
+        while ret.len() < num_frames {
+            let pattern = ORDERS[order] as usize;
+            let mut next = None;
+            // Speed can change on tick 0, so it has to be checked each tick
+            let mut tick = 0;
+            while tick < speed {
+                for (channel_index, channel) in channels.iter_mut().enumerate() {
+                    let note = &data[1084 + ((pattern * NUM_ROWS + row) * 4 + channel_index) * 4..][..4];
+                    let sample = ((note[0] & 0xf0) | (note[2] >> 4)) as usize;
+                    let period = (((note[0] & 0x0f) as u64) << 8) | note[1] as u64;
+                    let effect = note[2] & 0x0f;
+                    let param = note[3];
+                    if tick == 0 {
+                        if sample != 0 {
+                            channel.sample = sample;
+                            channel.volume = if sample == SAW_SAMPLE as usize { SAW_VOLUME as _ } else { 64 };
+                        }
+                        if period != 0 {
+                            channel.position = Some(0.0);
+                            let denominator = period * SAMPLE_RATE as u64;
+                            channel.step = (((3546895u64 << 14) + denominator / 2) / denominator) as f64 / 16384.0;
+                        }
+                        match effect {
+                            0xb => next = Some((param as usize, 0)),
+                            0xc => channel.volume = param as _,
+                            0xd => next = Some((order + 1, (param >> 4) as usize * 10 + (param & 0x0f) as usize)),
+                            0xf => speed = param as _,
+                            _ => (),
+                        }
+                    } else if effect == 0xa {
+                        channel.volume = (channel.volume - (param & 0x0f) as i32).max(0);
+                    }
+                }
+
+                let numerator = SAMPLE_RATE * 5 + tick_frames_remainder;
+                tick_frames_remainder = numerator % (tempo * 2);
+                for _ in 0..numerator / (tempo * 2) {
+                    let mut left = 0.0;
+                    let mut right = 0.0;
+                    for (channel_index, channel) in channels.iter_mut().enumerate() {
+                        let position = match channel.position {
+                            Some(position) => position,
+                            _ => continue,
+                        };
+                        let data = sample_data(channel.sample);
+                        let value = |index: usize| {
+                            let index = if is_looped(channel.sample) { index % data.len() } else { index };
+                            data.get(index).map(|&x| x as i8 as f64 * 256.0).unwrap_or(0.0)
+                        };
+                        let index = position.floor();
+                        let fract = position - index;
+                        let value = (value(index as usize) * (1.0 - fract) + value(index as usize + 1) * fract) * channel.volume as f64 / 64.0;
+                        if channel_index == 0 || channel_index == 3 {
+                            left += value;
+                        } else {
+                            right += value;
+                        }
+
+                        let mut position = position + channel.step;
+                        if is_looped(channel.sample) {
+                            position %= data.len() as f64;
+                        }
+                        channel.position = if position < data.len() as f64 { Some(position) } else { None };
+                    }
+                    let scale = |value: f64| (value / 2.0).round().clamp(i16::MIN as _, i16::MAX as _) as i16;
+                    ret.push((scale(left), scale(right)));
+                }
+
+                tick += 1;
+            }
+
+            match next {
+                Some((next_order, next_row)) => {
+                    order = next_order;
+                    row = next_row;
+                }
+                None => {
+                    row += 1;
+                    if row == NUM_ROWS {
+                        row = 0;
+                        order += 1;
+                    }
+                }
+            }
+            if order >= SONG_LENGTH {
+                order = 0;
+            }
+        }
+        ret.truncate(num_frames);
+
+        ret
+    }
+
+    fn render(player: &mut Player, num_frames: usize) -> Vec<(i16, i16)> {
+        let mut frames = vec![0; num_frames];
+        player.render(&mut frames);
+        frames.into_iter().map(|frame| (frame as i16, (frame >> 16) as i16)).collect()
+    }
+
+    #[test]
+    fn parse() {
+        let data = build_test_module();
+        let module = Module::parse(&data).unwrap();
+
+        assert_eq!(module.title(), b"test song");
+        assert_eq!(module.num_channels(), 4);
+        assert_eq!(module.song_length(), SONG_LENGTH);
+        assert_eq!(module.restart_position(), 0);
+        assert_eq!(module.sample(SINE_SAMPLE).len(), SINE_LEN as u32);
+        assert!(module.sample(SINE_SAMPLE).is_looped());
+        assert_eq!(module.sample(SAW_SAMPLE).len(), SAW_LEN as u32);
+        assert_eq!(module.sample(SAW_SAMPLE).volume, SAW_VOLUME);
+        assert!(!module.sample(SAW_SAMPLE).is_looped());
+        assert_eq!(module.note(0, 16, 1), Note {
+            sample: SAW_SAMPLE,
+            period: 170,
+            effect: 0xc,
+            param: 0x20,
+        });
+    }
+
+    #[test]
+    fn parse_errors() {
+        let data = build_test_module();
+
+        assert_eq!(Module::parse(&data[..1000]).err(), Some(ParseError::TooShort));
+        // Cut off partway through the patterns
+        assert_eq!(Module::parse(&data[..1084 + NUM_ROWS * 4 * 4]).err(), Some(ParseError::TooShort));
+
+        let mut bad_tag = data.clone();
+        bad_tag[1080..1084].copy_from_slice(b"ABCD");
+        assert_eq!(Module::parse(&bad_tag).err(), Some(ParseError::UnrecognizedFormat));
+
+        let mut bad_song_length = data.clone();
+        bad_song_length[950] = 0;
+        assert_eq!(Module::parse(&bad_song_length).err(), Some(ParseError::InvalidSongLength));
+        bad_song_length[950] = 129;
+        assert_eq!(Module::parse(&bad_song_length).err(), Some(ParseError::InvalidSongLength));
+    }
+
+    #[test]
+    fn matches_reference_render() {
+        let data = build_test_module();
+        let module = Module::parse(&data).unwrap();
+        let mut player = Player::new(&module, SAMPLE_RATE);
+
+        // Long enough to cover the break, the jump, and some of the second time around
+        let num_frames = SAMPLE_RATE as usize * 8;
+
+        player.start();
+        let actual = render(&mut player, num_frames);
+        let expected = reference_render(&data, num_frames);
+
+        // Only rounding differences are expected
+        const TOLERANCE: i32 = 8;
+        for (i, (actual, expected)) in actual.iter().zip(expected.iter()).enumerate() {
+            assert!(
+                (actual.0 as i32 - expected.0 as i32).abs() <= TOLERANCE && (actual.1 as i32 - expected.1 as i32).abs() <= TOLERANCE,
+                "frame {} mismatch: expected {:?}, got {:?}", i, expected, actual);
+        }
+    }
+
+    #[test]
+    fn position() {
+        let data = build_test_module();
+        let module = Module::parse(&data).unwrap();
+        let mut player = Player::new(&module, SAMPLE_RATE);
+
+        // 125 BPM means 960 frames per tick at this sample rate
+        const FRAMES_PER_TICK: usize = 960;
+
+        player.start();
+        assert_eq!(player.position(), Position { order: 0, pattern: 0, row: 0 });
+
+        // Last frame of row 0 at speed 6
+        render(&mut player, FRAMES_PER_TICK * 6);
+        assert_eq!(player.position(), Position { order: 0, pattern: 0, row: 0 });
+        render(&mut player, 1);
+        assert_eq!(player.position(), Position { order: 0, pattern: 0, row: 1 });
+
+        // Speed drops to 4 at row 32, and pattern 0 breaks to row 10 of pattern 1 after row 50
+        render(&mut player, FRAMES_PER_TICK * (6 * 31 + 4 * 19) - 1);
+        assert_eq!(player.position(), Position { order: 0, pattern: 0, row: 50 });
+        render(&mut player, 1);
+        assert_eq!(player.position(), Position { order: 1, pattern: 1, row: 10 });
+
+        // Pattern 1 jumps back to the start after row 20
+        render(&mut player, FRAMES_PER_TICK * 4 * 11);
+        assert_eq!(player.position(), Position { order: 0, pattern: 0, row: 0 });
+    }
+
+    #[test]
+    fn start_stop() {
+        let data = build_test_module();
+        let module = Module::parse(&data).unwrap();
+        let mut player = Player::new(&module, SAMPLE_RATE);
+
+        // Nothing plays until started
+        assert!(!player.is_playing());
+        assert!(render(&mut player, 1000).iter().all(|&frame| frame == (0, 0)));
+
+        player.start();
+        let first = render(&mut player, 10000);
+        assert!(first.iter().any(|&frame| frame != (0, 0)));
+
+        player.stop();
+        assert!(!player.is_playing());
+        assert!(render(&mut player, 1000).iter().all(|&frame| frame == (0, 0)));
+
+        // Starting again starts over
+        player.start();
+        assert_eq!(render(&mut player, 10000), first);
+        assert_eq!(player.position().order, 0);
+    }
+}
@@ -0,0 +1,177 @@
+use core::array;
+
+pub const NUM_SAMPLES: usize = 31;
+pub const MAX_NUM_CHANNELS: usize = 9;
+pub const NUM_ROWS: usize = 64;
+pub const MAX_NUM_ORDERS: usize = 128;
+
+const TITLE_LEN: usize = 20;
+const SAMPLE_HEADER_LEN: usize = 30;
+const SAMPLE_HEADERS_OFFSET: usize = TITLE_LEN;
+const SONG_LENGTH_OFFSET: usize = SAMPLE_HEADERS_OFFSET + NUM_SAMPLES * SAMPLE_HEADER_LEN;
+const RESTART_POSITION_OFFSET: usize = SONG_LENGTH_OFFSET + 1;
+const ORDERS_OFFSET: usize = RESTART_POSITION_OFFSET + 1;
+const TAG_OFFSET: usize = ORDERS_OFFSET + MAX_NUM_ORDERS;
+const PATTERNS_OFFSET: usize = TAG_OFFSET + 4;
+
+const NOTE_LEN: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Note {
+    // 1-based; 0 means no sample change
+    pub sample: u8,
+    // Amiga period (finetune 0); 0 means no note
+    pub period: u16,
+    pub effect: u8,
+    pub param: u8,
+}
+
+pub struct Sample<'a> {
+    pub data: &'a [u8],
+    // -8..=7, in 1/8ths of a semitone
+    pub finetune: i8,
+    // 0..=64
+    pub volume: u8,
+    // In bytes; a loop length of 2 bytes or less means the sample doesn't loop
+    pub loop_start: u32,
+    pub loop_length: u32,
+}
+
+impl<'a> Sample<'a> {
+    pub fn is_looped(&self) -> bool {
+        self.loop_length > 2
+    }
+
+    pub fn len(&self) -> u32 {
+        self.data.len() as _
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    // Cut off before the end of the header or pattern data
+    TooShort,
+    // Only the 31-sample format is supported (M.K., M!K!, FLT4, and xCHN tags)
+    UnrecognizedFormat,
+    InvalidSongLength,
+}
+
+// Borrows everything (including sample data) straight from the file, so no allocation is needed
+pub struct Module<'a> {
+    data: &'a [u8],
+    num_channels: usize,
+    samples: [Sample<'a>; NUM_SAMPLES],
+    song_length: usize,
+    restart_position: usize,
+}
+
+impl<'a> Module<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Module<'a>, ParseError> {
+        if data.len() < PATTERNS_OFFSET {
+            return Err(ParseError::TooShort);
+        }
+
+        let num_channels = match &data[TAG_OFFSET..TAG_OFFSET + 4] {
+            b"M.K." | b"M!K!" | b"FLT4" => 4,
+            &[n @ b'1'..=b'9', b'C', b'H', b'N'] => (n - b'0') as usize,
+            _ => return Err(ParseError::UnrecognizedFormat),
+        };
+
+        let song_length = data[SONG_LENGTH_OFFSET] as usize;
+        if !(1..=MAX_NUM_ORDERS).contains(&song_length) {
+            return Err(ParseError::InvalidSongLength);
+        }
+        // Many trackers store 127 here to mean "no restart position"
+        let restart_position = data[RESTART_POSITION_OFFSET] as usize;
+        let restart_position = if restart_position < song_length { restart_position } else { 0 };
+
+        // All orders count here, even the ones past song_length
+        let num_patterns = data[ORDERS_OFFSET..ORDERS_OFFSET + MAX_NUM_ORDERS].iter().fold(0, |acc, &order| acc.max(order)) as usize + 1;
+        let pattern_len = NUM_ROWS * num_channels * NOTE_LEN;
+
+        let mut sample_data_offset = PATTERNS_OFFSET + num_patterns * pattern_len;
+        if data.len() < sample_data_offset {
+            return Err(ParseError::TooShort);
+        }
+        let samples = array::from_fn(|i| {
+            let header = &data[SAMPLE_HEADERS_OFFSET + i * SAMPLE_HEADER_LEN..][..SAMPLE_HEADER_LEN];
+            let read_words = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]) as u32 * 2;
+            let len = read_words(22);
+            // Lower nibble, sign-extended
+            let finetune = ((header[24] << 4) as i8) >> 4;
+            let volume = header[25].min(64);
+            let loop_start = read_words(26);
+            let loop_length = read_words(28);
+
+            // Truncated files are common enough that we just play what's there
+            let start = sample_data_offset.min(data.len());
+            let end = (sample_data_offset + len as usize).min(data.len());
+            sample_data_offset += len as usize;
+            let sample_data = &data[start..end];
+
+            let (loop_start, loop_length) = if loop_length > 2 && loop_start < sample_data.len() as u32 {
+                (loop_start, loop_length.min(sample_data.len() as u32 - loop_start))
+            } else {
+                (0, 0)
+            };
+
+            Sample {
+                data: sample_data,
+                finetune,
+                volume,
+                loop_start,
+                loop_length,
+            }
+        });
+
+        Ok(Module {
+            data,
+            num_channels,
+            samples,
+            song_length,
+            restart_position,
+        })
+    }
+
+    pub fn title(&self) -> &'a [u8] {
+        let title = &self.data[..TITLE_LEN];
+        let len = title.iter().position(|&c| c == 0).unwrap_or(TITLE_LEN);
+        &title[..len]
+    }
+
+    pub fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    // Index is 1-based, like in patterns
+    pub fn sample(&self, index: u8) -> &Sample<'a> {
+        &self.samples[index as usize - 1]
+    }
+
+    pub fn song_length(&self) -> usize {
+        self.song_length
+    }
+
+    pub fn restart_position(&self) -> usize {
+        self.restart_position
+    }
+
+    pub fn order(&self, position: usize) -> u8 {
+        self.data[ORDERS_OFFSET + position]
+    }
+
+    pub fn note(&self, pattern: u8, row: usize, channel: usize) -> Note {
+        let offset = PATTERNS_OFFSET + ((pattern as usize * NUM_ROWS + row) * self.num_channels + channel) * NOTE_LEN;
+        let bytes = &self.data[offset..offset + NOTE_LEN];
+        Note {
+            sample: (bytes[0] & 0xf0) | (bytes[2] >> 4),
+            period: (((bytes[0] & 0x0f) as u16) << 8) | bytes[1] as u16,
+            effect: bytes[2] & 0x0f,
+            param: bytes[3],
+        }
+    }
+}
@@ -0,0 +1,669 @@
+use crate::module::*;
+
+use linalg::*;
+
+use core::array;
+
+// Sample positions need 17 integer bits to cover the longest possible samples (128KB), which leaves 14 for the fraction
+const POSITION_FRACT_BITS: u32 = 14;
+type SamplePosition = Fixed<POSITION_FRACT_BITS>;
+
+// PAL Amiga clock / 2; a sample plays back at this / period Hz
+const PAULA_CLOCK: u64 = 3546895;
+
+const DEFAULT_SPEED: u32 = 6;
+const DEFAULT_TEMPO: u32 = 125;
+
+const MIN_PERIOD: u16 = 113;
+const MAX_PERIOD: u16 = 856;
+
+const NUM_OCTAVES: usize = 3;
+const NOTES_PER_OCTAVE: usize = 12;
+
+// Periods for each note at finetune 0, as stored in patterns
+const PERIODS: [u16; NUM_OCTAVES * NOTES_PER_OCTAVE] = [
+    856, 808, 762, 720, 678, 640, 604, 570, 538, 508, 480, 453,
+    428, 404, 381, 360, 339, 320, 302, 285, 269, 254, 240, 226,
+    214, 202, 190, 180, 170, 160, 151, 143, 135, 127, 120, 113,
+];
+
+// 2^(-finetune / 96) for each finetune from -8 to 7, with 16 fractional bits
+const FINETUNE_SCALES: [i32; 16] = [
+    69433, 68933, 68438, 67945, 67456, 66971, 66489, 66011,
+    65536, 65065, 64596, 64132, 63670, 63212, 62757, 62306,
+];
+const FINETUNE_SCALE_FRACT_BITS: u32 = 16;
+
+// First half of a sine period, scaled to 255, as used for vibrato and tremolo
+const VIBRATO_TABLE: [u8; 32] = [
+    0, 24, 49, 74, 97, 120, 141, 161, 180, 197, 212, 224, 235, 244, 250, 253,
+    255, 253, 250, 244, 235, 224, 212, 197, 180, 161, 141, 120, 97, 74, 49, 24,
+];
+
+const EFFECT_ARPEGGIO: u8 = 0x0;
+const EFFECT_PORTA_UP: u8 = 0x1;
+const EFFECT_PORTA_DOWN: u8 = 0x2;
+const EFFECT_TONE_PORTA: u8 = 0x3;
+const EFFECT_VIBRATO: u8 = 0x4;
+const EFFECT_TONE_PORTA_VOLUME_SLIDE: u8 = 0x5;
+const EFFECT_VIBRATO_VOLUME_SLIDE: u8 = 0x6;
+const EFFECT_TREMOLO: u8 = 0x7;
+const EFFECT_SET_PANNING: u8 = 0x8;
+const EFFECT_SAMPLE_OFFSET: u8 = 0x9;
+const EFFECT_VOLUME_SLIDE: u8 = 0xa;
+const EFFECT_POSITION_JUMP: u8 = 0xb;
+const EFFECT_SET_VOLUME: u8 = 0xc;
+const EFFECT_PATTERN_BREAK: u8 = 0xd;
+const EFFECT_EXTENDED: u8 = 0xe;
+const EFFECT_SET_SPEED: u8 = 0xf;
+
+const EXTENDED_FINE_PORTA_UP: u8 = 0x1;
+const EXTENDED_FINE_PORTA_DOWN: u8 = 0x2;
+const EXTENDED_SET_FINETUNE: u8 = 0x5;
+const EXTENDED_PATTERN_LOOP: u8 = 0x6;
+const EXTENDED_RETRIGGER: u8 = 0x9;
+const EXTENDED_FINE_VOLUME_SLIDE_UP: u8 = 0xa;
+const EXTENDED_FINE_VOLUME_SLIDE_DOWN: u8 = 0xb;
+const EXTENDED_NOTE_CUT: u8 = 0xc;
+const EXTENDED_NOTE_DELAY: u8 = 0xd;
+const EXTENDED_PATTERN_DELAY: u8 = 0xe;
+
+// Hard left/right, in the classic Amiga LRRL arrangement
+const PANNING_LEFT: u8 = 0x00;
+const PANNING_RIGHT: u8 = 0xff;
+
+fn finetune_period(period: u16, finetune: i8) -> u16 {
+    let scale = Fixed::<FINETUNE_SCALE_FRACT_BITS>::from_raw(FINETUNE_SCALES[(finetune + 8) as usize], FINETUNE_SCALE_FRACT_BITS);
+    let period = Fixed::<FINETUNE_SCALE_FRACT_BITS>::from_raw(period as _, 0);
+    let half = Fixed::<FINETUNE_SCALE_FRACT_BITS>::from_raw(1, 1);
+    (period * scale + half).into_raw(0) as _
+}
+
+fn note_index(period: u16) -> Option<usize> {
+    // Some trackers write periods that are slightly off, so take the closest one
+    PERIODS.iter().enumerate().min_by_key(|(_, &p)| (p as i32 - period as i32).abs()).map(|(i, _)| i)
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Position {
+    // Index into the order list
+    pub order: usize,
+    pub pattern: u8,
+    pub row: usize,
+}
+
+struct Channel {
+    // 1-based, 0 if none has been set yet
+    sample: u8,
+    // None when the channel isn't playing anything
+    sample_position: Option<SamplePosition>,
+    finetune: i8,
+
+    note: Note,
+
+    step: SamplePosition,
+    period: u16,
+    // Includes arpeggio and vibrato, so this is what's actually played
+    output_period: u16,
+    volume: u8,
+    output_volume: u8,
+    panning: u8,
+
+    tone_porta_target: u16,
+    tone_porta_speed: u8,
+    vibrato_speed: u8,
+    vibrato_depth: u8,
+    vibrato_position: u8,
+    tremolo_speed: u8,
+    tremolo_depth: u8,
+    tremolo_position: u8,
+    sample_offset: u8,
+    pattern_loop_row: usize,
+    pattern_loop_count: u8,
+}
+
+impl Channel {
+    fn new(panning: u8) -> Channel {
+        Channel {
+            sample: 0,
+            sample_position: None,
+            finetune: 0,
+
+            note: Note::default(),
+
+            step: SamplePosition::zero(),
+            period: 0,
+            output_period: 0,
+            volume: 0,
+            output_volume: 0,
+            panning,
+
+            tone_porta_target: 0,
+            tone_porta_speed: 0,
+            vibrato_speed: 0,
+            vibrato_depth: 0,
+            vibrato_position: 0,
+            tremolo_speed: 0,
+            tremolo_depth: 0,
+            tremolo_position: 0,
+            sample_offset: 0,
+            pattern_loop_row: 0,
+            pattern_loop_count: 0,
+        }
+    }
+
+    fn trigger(&mut self, module: &Module) {
+        self.sample_position = if self.sample != 0 && !module.sample(self.sample).is_empty() {
+            Some(SamplePosition::zero())
+        } else {
+            None
+        };
+        self.vibrato_position = 0;
+        self.tremolo_position = 0;
+    }
+
+    fn slide_volume(&mut self, param: u8) {
+        let up = param >> 4;
+        let down = param & 0x0f;
+        self.volume = if up != 0 {
+            (self.volume + up).min(64)
+        } else {
+            self.volume.saturating_sub(down)
+        };
+    }
+
+    fn tone_porta(&mut self) {
+        let speed = self.tone_porta_speed as u16;
+        if self.period < self.tone_porta_target {
+            self.period = (self.period + speed).min(self.tone_porta_target);
+        } else if self.period > self.tone_porta_target {
+            self.period = self.period.saturating_sub(speed).max(self.tone_porta_target);
+        }
+    }
+
+    fn vibrato(&mut self) {
+        let delta = (VIBRATO_TABLE[(self.vibrato_position & 31) as usize] as u16 * self.vibrato_depth as u16) >> 7;
+        self.output_period = if (self.vibrato_position & 32) == 0 {
+            self.period + delta
+        } else {
+            self.period.saturating_sub(delta)
+        };
+        self.vibrato_position = (self.vibrato_position + self.vibrato_speed) & 63;
+    }
+
+    fn tremolo(&mut self) {
+        let delta = ((VIBRATO_TABLE[(self.tremolo_position & 31) as usize] as u16 * self.tremolo_depth as u16) >> 6) as u8;
+        self.output_volume = if (self.tremolo_position & 32) == 0 {
+            (self.volume + delta).min(64)
+        } else {
+            self.volume.saturating_sub(delta)
+        };
+        self.tremolo_position = (self.tremolo_position + self.tremolo_speed) & 63;
+    }
+
+    fn update_step(&mut self, sample_rate: u32) {
+        if self.output_period == 0 {
+            return;
+        }
+        // Round to nearest so that pitch drift stays small over long notes
+        let denominator = self.output_period as u64 * sample_rate as u64;
+        let step_raw = ((PAULA_CLOCK << POSITION_FRACT_BITS) + denominator / 2) / denominator;
+        self.step = SamplePosition::from_raw(step_raw as _, POSITION_FRACT_BITS);
+    }
+
+    // Returns the next sample, scaled by volume, in 16-bit range
+    fn next(&mut self, module: &Module) -> i32 {
+        let position = match self.sample_position {
+            Some(position) if self.output_period != 0 => position,
+            _ => return 0,
+        };
+        let sample = module.sample(self.sample);
+
+        let index = position.into_raw(0) as u32;
+        let next_index = if sample.is_looped() && index + 1 >= sample.loop_start + sample.loop_length {
+            sample.loop_start
+        } else {
+            index + 1
+        };
+        let value = |index: u32| (sample.data.get(index as usize).copied().unwrap_or(0) as i8 as i32) << 8;
+        let fract = (position - position.floor()).into_raw(POSITION_FRACT_BITS);
+        let current = value(index);
+        let interpolated = current + (((value(next_index) - current) * fract) >> POSITION_FRACT_BITS);
+
+        let mut position = position + self.step;
+        if sample.is_looped() {
+            let loop_end = SamplePosition::from_raw((sample.loop_start + sample.loop_length) as _, 0);
+            let loop_length = SamplePosition::from_raw(sample.loop_length as _, 0);
+            while position >= loop_end {
+                position = position - loop_length;
+            }
+            self.sample_position = Some(position);
+        } else if position >= SamplePosition::from_raw(sample.len() as _, 0) {
+            self.sample_position = None;
+        } else {
+            self.sample_position = Some(position);
+        }
+
+        (interpolated * self.output_volume as i32) >> 6
+    }
+}
+
+pub struct Player<'a> {
+    module: &'a Module<'a>,
+    sample_rate: u32,
+
+    is_playing: bool,
+
+    channels: [Channel; MAX_NUM_CHANNELS],
+
+    speed: u32,
+    tempo: u32,
+    tick: u32,
+    frames_left_in_tick: u32,
+    tick_frames_remainder: u32,
+
+    position: Position,
+    pattern_delay: u8,
+    pattern_delay_count: u8,
+    next_order: Option<usize>,
+    next_row: Option<usize>,
+}
+
+impl<'a> Player<'a> {
+    pub fn new(module: &'a Module<'a>, sample_rate: u32) -> Player<'a> {
+        let mut ret = Player {
+            module,
+            sample_rate,
+
+            is_playing: false,
+
+            channels: array::from_fn(|_| Channel::new(PANNING_LEFT)),
+
+            speed: DEFAULT_SPEED,
+            tempo: DEFAULT_TEMPO,
+            tick: 0,
+            frames_left_in_tick: 0,
+            tick_frames_remainder: 0,
+
+            position: Position::default(),
+            pattern_delay: 0,
+            pattern_delay_count: 0,
+            next_order: None,
+            next_row: None,
+        };
+        ret.reset();
+        ret
+    }
+
+    fn reset(&mut self) {
+        self.channels = array::from_fn(|i| Channel::new(if (i & 3) == 0 || (i & 3) == 3 { PANNING_LEFT } else { PANNING_RIGHT }));
+
+        self.speed = DEFAULT_SPEED;
+        self.tempo = DEFAULT_TEMPO;
+        self.tick = 0;
+        self.frames_left_in_tick = 0;
+        self.tick_frames_remainder = 0;
+
+        self.position = Position {
+            order: 0,
+            pattern: self.module.order(0),
+            row: 0,
+        };
+        self.pattern_delay = 0;
+        self.pattern_delay_count = 0;
+        self.next_order = None;
+        self.next_row = None;
+    }
+
+    // Always starts from the beginning of the song
+    pub fn start(&mut self) {
+        self.reset();
+        self.is_playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.is_playing = false;
+    }
+
+    // The song loops (from its restart position) once it reaches the end, so this is only false after stop is called
+    //  or the song stops itself with F00
+    pub fn is_playing(&self) -> bool {
+        self.is_playing
+    }
+
+    // The row currently being played, for syncing visuals to the music
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    // Fills frames in the same format the audio ring buffer uses: left sample in the low half, right in the high half.
+    //  Silence is rendered while stopped.
+    pub fn render(&mut self, frames: &mut [u32]) {
+        for frame in frames.iter_mut() {
+            if !self.is_playing {
+                *frame = 0;
+                continue;
+            }
+
+            if self.frames_left_in_tick == 0 {
+                self.process_tick();
+                if !self.is_playing {
+                    *frame = 0;
+                    continue;
+                }
+
+                // A tick lasts 2.5 / tempo seconds; carry the remainder so tempo stays exact over time
+                let numerator = self.sample_rate * 5 + self.tick_frames_remainder;
+                let denominator = self.tempo * 2;
+                self.frames_left_in_tick = numerator / denominator;
+                self.tick_frames_remainder = numerator % denominator;
+            }
+            self.frames_left_in_tick -= 1;
+
+            let mut left = 0;
+            let mut right = 0;
+            for channel in self.channels[..self.module.num_channels()].iter_mut() {
+                let value = channel.next(self.module);
+                // Stretch 0..=255 to 0..=256 so hard left/right are exact
+                let right_gain = channel.panning as i32 + (channel.panning as i32 >> 7);
+                left += (value * (256 - right_gain)) >> 8;
+                right += (value * right_gain) >> 8;
+            }
+
+            // Scaled so that 4 channels at full volume (2 per side) just fit
+            let scale = |value: i32| (value * 2 / self.module.num_channels() as i32).clamp(i16::MIN as _, i16::MAX as _) as u16 as u32;
+            *frame = (scale(right) << 16) | scale(left);
+        }
+    }
+
+    fn process_tick(&mut self) {
+        // Rows only advance once their last tick has been played, so position always reports what's audible
+        if self.tick >= self.speed {
+            self.tick = 0;
+            if self.pattern_delay_count < self.pattern_delay {
+                self.pattern_delay_count += 1;
+            } else {
+                self.pattern_delay = 0;
+                self.pattern_delay_count = 0;
+                self.advance_row();
+            }
+        }
+
+        if self.tick == 0 {
+            if self.pattern_delay_count == 0 {
+                self.process_row();
+            } else {
+                // Delayed rows replay their tick 0 effects, but not their notes
+                for channel_index in 0..self.module.num_channels() {
+                    self.process_tick_0_effects(channel_index);
+                }
+            }
+        } else {
+            for channel_index in 0..self.module.num_channels() {
+                self.process_tick_n_effects(channel_index);
+            }
+        }
+
+        for channel in self.channels[..self.module.num_channels()].iter_mut() {
+            channel.update_step(self.sample_rate);
+        }
+
+        self.tick += 1;
+    }
+
+    fn advance_row(&mut self) {
+        let Position { mut order, mut row, .. } = self.position;
+
+        match (self.next_order.take(), self.next_row.take()) {
+            (None, None) => {
+                row += 1;
+                if row >= NUM_ROWS {
+                    row = 0;
+                    order += 1;
+                }
+            }
+            (Some(next_order), next_row) => {
+                order = next_order;
+                row = next_row.unwrap_or(0);
+            }
+            (None, Some(next_row)) => {
+                order += 1;
+                row = next_row;
+            }
+        }
+
+        if order >= self.module.song_length() {
+            order = self.module.restart_position();
+        }
+
+        self.position = Position {
+            order,
+            pattern: self.module.order(order),
+            row,
+        };
+    }
+
+    fn process_row(&mut self) {
+        for channel_index in 0..self.module.num_channels() {
+            let note = self.module.note(self.position.pattern, self.position.row, channel_index);
+            let channel = &mut self.channels[channel_index];
+            channel.note = note;
+
+            let is_tone_porta = note.effect == EFFECT_TONE_PORTA || note.effect == EFFECT_TONE_PORTA_VOLUME_SLIDE;
+            let is_delayed = note.effect == EFFECT_EXTENDED && (note.param >> 4) == EXTENDED_NOTE_DELAY && (note.param & 0x0f) != 0;
+
+            if note.sample != 0 && note.sample as usize <= NUM_SAMPLES {
+                let sample = self.module.sample(note.sample);
+                channel.sample = note.sample;
+                channel.finetune = sample.finetune;
+                channel.volume = sample.volume;
+            }
+
+            if note.period != 0 {
+                if note.effect == EFFECT_EXTENDED && (note.param >> 4) == EXTENDED_SET_FINETUNE {
+                    channel.finetune = (((note.param & 0x0f) << 4) as i8) >> 4;
+                }
+                let period = finetune_period(note.period, channel.finetune);
+                if is_tone_porta {
+                    channel.tone_porta_target = period;
+                } else if !is_delayed {
+                    channel.period = period;
+                    channel.trigger(self.module);
+                }
+            }
+
+            self.process_tick_0_effects(channel_index);
+        }
+    }
+
+    fn process_tick_0_effects(&mut self, channel_index: usize) {
+        let channel = &mut self.channels[channel_index];
+        let Note { period, effect, param, .. } = channel.note;
+
+        channel.output_period = channel.period;
+        channel.output_volume = channel.volume;
+
+        match effect {
+            EFFECT_TONE_PORTA if param != 0 => {
+                channel.tone_porta_speed = param;
+            }
+            EFFECT_VIBRATO => {
+                if (param >> 4) != 0 {
+                    channel.vibrato_speed = param >> 4;
+                }
+                if (param & 0x0f) != 0 {
+                    channel.vibrato_depth = param & 0x0f;
+                }
+            }
+            EFFECT_TREMOLO => {
+                if (param >> 4) != 0 {
+                    channel.tremolo_speed = param >> 4;
+                }
+                if (param & 0x0f) != 0 {
+                    channel.tremolo_depth = param & 0x0f;
+                }
+            }
+            EFFECT_SET_PANNING => {
+                channel.panning = param;
+            }
+            EFFECT_SAMPLE_OFFSET => {
+                if param != 0 {
+                    channel.sample_offset = param;
+                }
+                if period != 0 && channel.sample_position.is_some() {
+                    let offset = channel.sample_offset as u32 * 256;
+                    let sample = self.module.sample(channel.sample);
+                    // Past the end of a one-shot sample just means silence
+                    channel.sample_position = if offset < sample.len() {
+                        Some(SamplePosition::from_raw(offset as _, 0))
+                    } else {
+                        None
+                    };
+                }
+            }
+            EFFECT_POSITION_JUMP => {
+                self.next_order = Some(param as _);
+            }
+            EFFECT_SET_VOLUME => {
+                channel.volume = param.min(64);
+                channel.output_volume = channel.volume;
+            }
+            EFFECT_PATTERN_BREAK => {
+                // Row is BCD
+                let row = (param >> 4) as usize * 10 + (param & 0x0f) as usize;
+                self.next_row = Some(if row < NUM_ROWS { row } else { 0 });
+            }
+            EFFECT_EXTENDED => {
+                let x = param & 0x0f;
+                match param >> 4 {
+                    EXTENDED_FINE_PORTA_UP => {
+                        channel.period = channel.period.saturating_sub(x as _).max(MIN_PERIOD);
+                        channel.output_period = channel.period;
+                    }
+                    EXTENDED_FINE_PORTA_DOWN => {
+                        channel.period = (channel.period + x as u16).min(MAX_PERIOD);
+                        channel.output_period = channel.period;
+                    }
+                    EXTENDED_PATTERN_LOOP if self.pattern_delay_count == 0 => {
+                        if x == 0 {
+                            channel.pattern_loop_row = self.position.row;
+                        } else if channel.pattern_loop_count == 0 {
+                            channel.pattern_loop_count = x;
+                            self.next_order = Some(self.position.order);
+                            self.next_row = Some(channel.pattern_loop_row);
+                        } else {
+                            channel.pattern_loop_count -= 1;
+                            if channel.pattern_loop_count != 0 {
+                                self.next_order = Some(self.position.order);
+                                self.next_row = Some(channel.pattern_loop_row);
+                            }
+                        }
+                    }
+                    EXTENDED_FINE_VOLUME_SLIDE_UP => {
+                        channel.volume = (channel.volume + x).min(64);
+                        channel.output_volume = channel.volume;
+                    }
+                    EXTENDED_FINE_VOLUME_SLIDE_DOWN => {
+                        channel.volume = channel.volume.saturating_sub(x);
+                        channel.output_volume = channel.volume;
+                    }
+                    EXTENDED_NOTE_CUT if x == 0 => {
+                        channel.volume = 0;
+                        channel.output_volume = 0;
+                    }
+                    EXTENDED_PATTERN_DELAY if self.pattern_delay_count == 0 => {
+                        self.pattern_delay = x;
+                    }
+                    _ => (),
+                }
+            }
+            EFFECT_SET_SPEED => {
+                if param == 0 {
+                    self.is_playing = false;
+                } else if param < 32 {
+                    self.speed = param as _;
+                } else {
+                    self.tempo = param as _;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn process_tick_n_effects(&mut self, channel_index: usize) {
+        let tick = self.tick;
+        let module = self.module;
+        let channel = &mut self.channels[channel_index];
+        let Note { period, effect, param, .. } = channel.note;
+
+        channel.output_period = channel.period;
+        channel.output_volume = channel.volume;
+
+        match effect {
+            EFFECT_ARPEGGIO if param != 0 => {
+                let offset = match tick % 3 {
+                    0 => 0,
+                    1 => param >> 4,
+                    _ => param & 0x0f,
+                } as usize;
+                // Arpeggio is relative to the (unfinetuned) note being played, clamped to the top of the table
+                if let Some(index) = note_index(finetune_period(channel.period, -channel.finetune)) {
+                    let index = (index + offset).min(PERIODS.len() - 1);
+                    channel.output_period = finetune_period(PERIODS[index], channel.finetune);
+                }
+            }
+            EFFECT_PORTA_UP => {
+                channel.period = channel.period.saturating_sub(param as _).max(MIN_PERIOD);
+                channel.output_period = channel.period;
+            }
+            EFFECT_PORTA_DOWN => {
+                channel.period = (channel.period + param as u16).min(MAX_PERIOD);
+                channel.output_period = channel.period;
+            }
+            EFFECT_TONE_PORTA => {
+                channel.tone_porta();
+                channel.output_period = channel.period;
+            }
+            EFFECT_VIBRATO => {
+                channel.vibrato();
+            }
+            EFFECT_TONE_PORTA_VOLUME_SLIDE => {
+                channel.tone_porta();
+                channel.output_period = channel.period;
+                channel.slide_volume(param);
+                channel.output_volume = channel.volume;
+            }
+            EFFECT_VIBRATO_VOLUME_SLIDE => {
+                channel.vibrato();
+                channel.slide_volume(param);
+                channel.output_volume = channel.volume;
+            }
+            EFFECT_TREMOLO => {
+                channel.tremolo();
+            }
+            EFFECT_VOLUME_SLIDE => {
+                channel.slide_volume(param);
+                channel.output_volume = channel.volume;
+            }
+            EFFECT_EXTENDED => {
+                let x = (param & 0x0f) as u32;
+                match param >> 4 {
+                    EXTENDED_RETRIGGER if x != 0 && tick.is_multiple_of(x) => {
+                        channel.trigger(module);
+                    }
+                    EXTENDED_NOTE_CUT if tick == x => {
+                        channel.volume = 0;
+                        channel.output_volume = 0;
+                    }
+                    EXTENDED_NOTE_DELAY if tick == x && period != 0 => {
+                        channel.period = finetune_period(period, channel.finetune);
+                        channel.output_period = channel.period;
+                        channel.trigger(module);
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -3,6 +3,8 @@ use kaze::*;
 use rtl::uart::*;
 use rtl::xenowing::*;
 
+use rtl_meta::uart::*;
+
 use std::env;
 use std::fs::File;
 use std::io::Result;
@@ -21,19 +23,27 @@ fn main() -> Result<()> {
 
     m.output("leds", xenowing.leds);
 
-    let clock_freq = 100000000;
-    let uart_baud_rate = 460800;
+    // Programs that change the baud divisor won't be able to talk to us anymore
+    let uart_baud_divisor = m.lit(REG_BAUD_DIVISOR_DEFAULT, REG_BAUD_DIVISOR_BITS);
 
-    let uart_rx = UartRx::new("uart_rx", clock_freq, uart_baud_rate, m);
+    let uart_rx = UartRx::new("uart_rx", m);
     uart_rx.rx.drive(xenowing.tx);
+    uart_rx.baud_divisor.drive(uart_baud_divisor);
     m.output("uart_tx_data", uart_rx.data);
     m.output("uart_tx_data_valid", uart_rx.data_valid);
 
-    let uart_tx = UartTx::new("uart_tx", clock_freq, uart_baud_rate, m);
+    let uart_tx = UartTx::new("uart_tx", m);
     xenowing.rx.drive(uart_tx.tx);
+    uart_tx.baud_divisor.drive(uart_baud_divisor);
     m.output("uart_rx_ready", uart_tx.ready);
     uart_tx.data.drive(m.input("uart_rx_data", 8));
     uart_tx.enable.drive(m.input("uart_rx_enable", 1));
+    xenowing.cts_n.drive(m.low());
+
+    // No SD card inserted
+    xenowing.sd_miso.drive(m.high());
+    // No flash attached, so the boot ROM will always fall back to UART
+    xenowing.flash_miso.drive(m.high());
 
     xenowing.ddr3.forward("ddr3", m);
 
@@ -50,7 +60,14 @@ fn main() -> Result<()> {
     inner.uart_tx_ready.drive(m.high());
     inner.uart_rx_data.drive(m.input("uart_rx_data", 8));
     inner.uart_rx_data_valid.drive(m.input("uart_rx_data_valid", 1));
+    inner.uart_rx_framing_error.drive(m.low());
     m.output("uart_rx_ready", inner.uart_rx_ready);
+    inner.uart_cts.drive(m.high());
+
+    // No SD card inserted
+    inner.sd_miso.drive(m.high());
+    // No flash attached, so the boot ROM will always fall back to UART
+    inner.flash_miso.drive(m.high());
 
     inner.ddr3.forward("ddr3", m);
 
@@ -9,7 +9,7 @@ use modules::*;
 use minifb::{Scale, ScaleMode, Window, WindowOptions};
 use rtl::buster_mig_ui_bridge::*;
 use serialport::prelude::*;
-use strugl::{PIXELS, HEIGHT, WIDTH};
+use strugl::{rgb565_to_argb8888, PIXELS, HEIGHT, WIDTH};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 use std::env;
@@ -71,6 +71,15 @@ trait Device {
         Ok(ret)
     }
 
+    fn read_u16(&mut self) -> Result<u16, Error> {
+        let mut ret = 0x00;
+        for i in 0..2 {
+            ret |= (self.read_byte()? as u16) << i * 8;
+        }
+
+        Ok(ret)
+    }
+
     fn write_u32(&mut self, value: u32) -> Result<(), Error> {
         self.write_byte((value >> 0) as _)?;
         self.write_byte((value >> 8) as _)?;
@@ -464,6 +473,14 @@ impl Device for SerialDevice {
     }
 }
 
+// Replies to the boot ROM's boot mode query
+#[derive(Clone, Copy)]
+enum BootMode {
+    Flash = 0x00,
+    Uart = 0x01,
+    ProgramFlash = 0x02,
+}
+
 fn main() -> Result<(), Error> {
     let device_type = env::args().nth(1).expect("Missing device type arg");
 
@@ -483,6 +500,18 @@ fn main() -> Result<(), Error> {
         }
         _ => panic!("Invalid device type argument")
     };
+
+    // Optional; follows the device args
+    let boot_mode_arg_index = if device_type == "serial" { 3 } else { 2 };
+    let boot_mode = match env::args().nth(boot_mode_arg_index).as_deref() {
+        // Serve the program over UART, even if there's one in flash
+        None | Some("uart") => BootMode::Uart,
+        // Boot whatever's in flash, falling back to UART if there's nothing valid there
+        Some("flash") => BootMode::Flash,
+        // Serve the program over UART and have the boot ROM write it to flash as well
+        Some("program-flash") => BootMode::ProgramFlash,
+        _ => panic!("Invalid boot mode argument")
+    };
     println!();
 
     let mut back_buffer = vec![0xffff00ff; PIXELS as usize];
@@ -548,9 +577,14 @@ fn main() -> Result<(), Error> {
                 }
                 writeln!(&mut stdout, "  elapsed cycles: {}", elapsed_cycles)?;
 
+                let color_format = device.read_byte()?;
                 for y in 0..HEIGHT {
                     for x in 0..WIDTH {
-                        back_buffer[((HEIGHT - 1 - y) * WIDTH + x) as usize] = device.read_u32()?;
+                        back_buffer[((HEIGHT - 1 - y) * WIDTH + x) as usize] = match color_format {
+                            0x00 => device.read_u32()?,
+                            0x01 => rgb565_to_argb8888(device.read_u16()?),
+                            _ => return Err(format!("Invalid frame color format received: 0x{:02x}", color_format).into()),
+                        };
                     }
                 }
 
@@ -560,6 +594,20 @@ fn main() -> Result<(), Error> {
 
                 stdout.reset()?;
             }
+            0x04 => {
+                let mut stdout = StandardStream::stdout(ColorChoice::Always);
+                stdout.set_color(ColorSpec::new().set_fg(Some(Color::White)).set_intense(true))?;
+
+                writeln!(&mut stdout, "boot mode requested, replying with {}", match boot_mode {
+                    BootMode::Flash => "flash",
+                    BootMode::Uart => "UART",
+                    BootMode::ProgramFlash => "program flash",
+                })?;
+
+                device.write_byte(boot_mode as _)?;
+
+                stdout.reset()?;
+            }
             command_byte => {
                 return Err(format!("Invalid UART command byte received: 0x{:02x}", command_byte).into());
             }
@@ -0,0 +1,59 @@
+use crate::regs::RegBlock;
+
+use rtl_meta::audio::*;
+use rtl_meta::xenowing::SYSTEM_CLOCK_FREQ;
+
+const REGS: RegBlock = RegBlock::new(0x09000000);
+
+// Addr is in bytes and must be 16-byte aligned; num_words is in 16-byte words and must be even. Should only be called
+//  while disabled.
+pub fn set_ring_buffer(addr: u32, num_words: u32) {
+    REGS.write(REG_RING_BUFFER_BASE_ADDR, addr);
+    REGS.write(REG_RING_BUFFER_NUM_WORDS_ADDR, num_words);
+}
+
+pub fn set_sample_period(cycles: u32) {
+    REGS.write(REG_SAMPLE_PERIOD_ADDR, cycles);
+}
+
+// Rounds to the nearest period the hw can represent
+pub fn set_sample_rate(hz: u32) {
+    set_sample_period((SYSTEM_CLOCK_FREQ + hz / 2) / hz);
+}
+
+// Playback always starts from the beginning of the ring buffer
+pub fn enable() {
+    REGS.write(REG_ENABLE_ADDR, 1);
+}
+
+pub fn disable() {
+    REGS.write(REG_ENABLE_ADDR, 0);
+}
+
+// Index of the next word to be played
+pub fn play_position() -> u32 {
+    REGS.read(REG_PLAY_POSITION_ADDR)
+}
+
+// Bits are REG_INTERRUPT_*_BIT; the returned value can be passed straight to clear_interrupt_status
+pub fn interrupt_status() -> u32 {
+    REGS.read(REG_INTERRUPT_STATUS_ADDR)
+}
+
+pub fn clear_interrupt_status(bits: u32) {
+    REGS.write(REG_INTERRUPT_STATUS_ADDR, bits);
+}
+
+// There's no CPU interrupt line yet, so this only masks the peripheral's irq output; poll interrupt_status meanwhile
+pub fn set_interrupt_enable(bits: u32) {
+    REGS.write(REG_INTERRUPT_ENABLE_ADDR, bits);
+}
+
+// Sticky until cleared with clear_underrun
+pub fn has_underrun() -> bool {
+    (REGS.read(REG_UNDERRUN_ADDR) & 1) != 0
+}
+
+pub fn clear_underrun() {
+    REGS.write(REG_UNDERRUN_ADDR, 1);
+}
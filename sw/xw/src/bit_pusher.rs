@@ -0,0 +1,88 @@
+use crate::regs::RegBlock;
+
+use rtl_meta::bit_pusher::*;
+
+const REGS: RegBlock = RegBlock::new(0x06000000);
+
+pub fn is_busy() -> bool {
+    REGS.read(REG_STATUS_ADDR) != 0
+}
+
+pub fn wait() {
+    while is_busy() {
+        // Do nothing
+    }
+}
+
+// Logic ops are REG_PIXEL_OP_SETTINGS_LOGIC_OP_* values
+#[derive(Clone, Copy)]
+pub struct PixelOps {
+    pub color_key: Option<u32>,
+    pub blend_enable: bool,
+    pub logic_op: u32,
+}
+
+impl Default for PixelOps {
+    fn default() -> PixelOps {
+        PixelOps {
+            color_key: None,
+            blend_enable: false,
+            logic_op: REG_PIXEL_OP_SETTINGS_LOGIC_OP_COPY,
+        }
+    }
+}
+
+// Applies to all subsequent fills and blits
+pub fn set_pixel_ops(ops: PixelOps) {
+    wait();
+
+    REGS.write(
+        REG_PIXEL_OP_SETTINGS_ADDR,
+        (if ops.color_key.is_some() { 1 } else { 0 } << REG_PIXEL_OP_SETTINGS_COLOR_KEY_ENABLE_BIT) |
+        (if ops.blend_enable { 1 } else { 0 } << REG_PIXEL_OP_SETTINGS_BLEND_ENABLE_BIT) |
+        (ops.logic_op << REG_PIXEL_OP_SETTINGS_LOGIC_OP_BIT_OFFSET));
+    if let Some(color_key) = ops.color_key {
+        REGS.write(REG_COLOR_KEY_ADDR, color_key);
+    }
+}
+
+// Addrs are in bytes and must be 16-byte aligned; spans and strides are in 16-byte words. Both return as soon as the
+//  transfer is dispatched, so the CPU can keep working until it calls wait.
+pub fn fill(value: u32, addr: u32, words_per_span: u32, span_stride: u32, num_words: u32) {
+    wait();
+
+    REGS.write(REG_DIRECTION_ADDR, REG_DIRECTION_FILL);
+    REGS.write(REG_NUM_WORDS_ADDR, num_words);
+    REGS.write(REG_FILL_VALUE_ADDR, value);
+
+    REGS.write(REG_MEM_ADDR_ADDR, addr);
+    REGS.write(REG_MEM_WORDS_PER_SPAN_ADDR, words_per_span);
+    REGS.write(REG_MEM_SPAN_STRIDE_ADDR, span_stride);
+
+    REGS.write(REG_START_ADDR, 1);
+}
+
+pub fn blit(
+    src_addr: u32,
+    src_words_per_span: u32,
+    src_span_stride: u32,
+    dst_addr: u32,
+    dst_words_per_span: u32,
+    dst_span_stride: u32,
+    num_words: u32,
+) {
+    wait();
+
+    REGS.write(REG_DIRECTION_ADDR, REG_DIRECTION_MEM2MEM);
+    REGS.write(REG_NUM_WORDS_ADDR, num_words);
+
+    REGS.write(REG_SYS_ADDR_ADDR, src_addr);
+    REGS.write(REG_SYS_WORDS_PER_SPAN_ADDR, src_words_per_span);
+    REGS.write(REG_SYS_SPAN_STRIDE_ADDR, src_span_stride);
+
+    REGS.write(REG_MEM_ADDR_ADDR, dst_addr);
+    REGS.write(REG_MEM_WORDS_PER_SPAN_ADDR, dst_words_per_span);
+    REGS.write(REG_MEM_SPAN_STRIDE_ADDR, dst_span_stride);
+
+    REGS.write(REG_START_ADDR, 1);
+}
@@ -0,0 +1,110 @@
+use crate::spi::{self, Spi};
+
+use rtl_meta::spi::*;
+
+// Standard SPI NOR commands with 3-byte addresses, which everything from 128Mbit down supports
+
+pub const PAGE_SIZE: u32 = 256;
+pub const SECTOR_SIZE: u32 = 4096;
+
+const SPI: Spi = spi::FLASH;
+
+const CMD_READ: u8 = 0x03;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_SECTOR_ERASE: u8 = 0x20;
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_READ_ID: u8 = 0x9f;
+
+const STATUS_WRITE_IN_PROGRESS_BIT: u32 = 0;
+
+pub fn init() {
+    SPI.deselect();
+    // Plain reads are good up to at least 50MHz on any flash we'd use, so this is nowhere near the limit
+    SPI.set_clock_divider(REG_CLOCK_DIVIDER_MIN);
+    // SCLK goes through STARTUPE2 on the board, which drops the first few edges after configuration, so clock out a
+    //  byte with CS high to get them out of the way
+    SPI.transfer(0xff);
+}
+
+// Manufacturer ID in the high byte, followed by memory type and capacity
+pub fn read_id() -> u32 {
+    SPI.select();
+    SPI.transfer(CMD_READ_ID);
+    let mut ret = 0;
+    for _ in 0..3 {
+        ret = (ret << 8) | SPI.transfer(0xff) as u32;
+    }
+    SPI.deselect();
+    ret
+}
+
+pub fn read(addr: u32, buf: &mut [u8]) {
+    command_with_addr(CMD_READ, addr);
+    for byte in buf.iter_mut() {
+        *byte = SPI.transfer(0xff);
+    }
+    SPI.deselect();
+}
+
+// Sets the whole sector containing addr to 0xff
+pub fn erase_sector(addr: u32) {
+    write_enable();
+    command_with_addr(CMD_SECTOR_ERASE, addr);
+    SPI.deselect();
+    wait_while_busy();
+}
+
+// Programming can only clear bits, so the target area should be erased first. Writes wrap around within the page
+//  containing addr, so data must not cross a page boundary.
+pub fn program_page(addr: u32, data: &[u8]) {
+    assert!((addr % PAGE_SIZE) as usize + data.len() <= PAGE_SIZE as usize, "Data crosses a page boundary");
+
+    write_enable();
+    command_with_addr(CMD_PAGE_PROGRAM, addr);
+    for &byte in data {
+        SPI.transfer(byte);
+    }
+    SPI.deselect();
+    wait_while_busy();
+}
+
+// Erases every sector data touches and programs it in. Addr must be sector-aligned, and anything else in the last
+//  sector is lost.
+pub fn write(addr: u32, data: &[u8]) {
+    assert!((addr & (SECTOR_SIZE - 1)) == 0, "Address must be sector-aligned");
+
+    let mut sector_addr = addr;
+    while sector_addr < addr + data.len() as u32 {
+        erase_sector(sector_addr);
+        sector_addr += SECTOR_SIZE;
+    }
+
+    for (i, page) in data.chunks(PAGE_SIZE as _).enumerate() {
+        program_page(addr + i as u32 * PAGE_SIZE, page);
+    }
+}
+
+// Leaves the flash selected
+fn command_with_addr(command: u8, addr: u32) {
+    SPI.select();
+    SPI.transfer(command);
+    SPI.transfer((addr >> 16) as _);
+    SPI.transfer((addr >> 8) as _);
+    SPI.transfer(addr as _);
+}
+
+// Needed before every program or erase, since the flash clears it again once they finish
+fn write_enable() {
+    SPI.select();
+    SPI.transfer(CMD_WRITE_ENABLE);
+    SPI.deselect();
+}
+
+fn wait_while_busy() {
+    SPI.select();
+    SPI.transfer(CMD_READ_STATUS);
+    // The status reg is sent repeatedly for as long as we keep clocking
+    while (SPI.transfer(0xff) & (1 << STATUS_WRITE_IN_PROGRESS_BIT)) != 0 {}
+    SPI.deselect();
+}
@@ -0,0 +1,46 @@
+use crate::sdcard::{self, SdCard};
+
+use fat32::{BlockDevice, FileSystem};
+
+use core::ptr;
+
+pub use fat32::File;
+
+pub type Error = fat32::Error<sdcard::Error>;
+
+impl BlockDevice for SdCard {
+    type Error = sdcard::Error;
+
+    fn read_block(&mut self, index: u32, data: &mut [u8; fat32::BLOCK_SIZE]) -> Result<(), sdcard::Error> {
+        SdCard::read_block(self, index, data)
+    }
+}
+
+static mut FILE_SYSTEM: Option<FileSystem<SdCard>> = None;
+
+// Initializes the card and mounts the first FAT32 partition on it. Called automatically by open if needed; call it
+//  directly to remount after swapping cards.
+pub fn mount() -> Result<(), Error> {
+    let file_system = FileSystem::mount(SdCard::init()?)?;
+    unsafe {
+        FILE_SYSTEM = Some(file_system);
+    }
+    Ok(())
+}
+
+fn file_system() -> Result<&'static mut FileSystem<SdCard>, Error> {
+    if unsafe { (*ptr::addr_of!(FILE_SYSTEM)).is_none() } {
+        mount()?;
+    }
+    Ok(unsafe { (*ptr::addr_of_mut!(FILE_SYSTEM)).as_mut().unwrap() })
+}
+
+// Paths are absolute, separated by '/', and matched case-insensitively, eg. "/assets/sound.mod"
+pub fn open(path: &str) -> Result<File, Error> {
+    file_system()?.open(path)
+}
+
+// Returns the number of bytes read, which is only less than buf.len() at the end of the file
+pub fn read(file: &mut File, buf: &mut [u8]) -> Result<usize, Error> {
+    file_system()?.read(file, buf)
+}
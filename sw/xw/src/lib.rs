@@ -6,9 +6,17 @@ extern crate alloc;
 #[macro_use]
 extern crate static_assertions;
 
+pub mod audio;
+pub mod bit_pusher;
+pub mod flash;
+pub mod fs;
 pub mod leds;
 mod heap;
 pub mod marv;
+mod regs;
+pub mod scanout;
+pub mod sdcard;
+pub mod spi;
 pub mod stdio;
 pub mod uart;
 
@@ -0,0 +1,26 @@
+use core::ptr;
+
+// Peripheral regs are 32 bits wide and 16 bytes apart, starting at the peripheral's base address. Reg addrs are the
+//  REG_*_ADDR indices from rtl_meta.
+#[derive(Clone, Copy)]
+pub struct RegBlock {
+    base: *mut u32,
+}
+
+impl RegBlock {
+    pub const fn new(base_addr: u32) -> RegBlock {
+        RegBlock {
+            base: base_addr as _,
+        }
+    }
+
+    pub fn write(&self, addr: u32, data: u32) {
+        unsafe {
+            ptr::write_volatile(self.base.offset((addr * 4) as _), data);
+        }
+    }
+
+    pub fn read(&self, addr: u32) -> u32 {
+        unsafe { ptr::read_volatile(self.base.offset((addr * 4) as _)) }
+    }
+}
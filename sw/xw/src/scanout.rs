@@ -0,0 +1,75 @@
+use crate::regs::RegBlock;
+
+use rtl_meta::scanout::*;
+
+const REGS: RegBlock = RegBlock::new(0x08000000);
+
+// Addr is in bytes and must be 16-byte aligned; stride is in 16-byte words. Like enable and format, these take effect
+//  from the next frame on; see is_flip_pending.
+pub fn set_front_buffer(addr: u32, line_stride: i32) {
+    // Base goes last, since writing it is what marks the flip as pending
+    REGS.write(REG_LINE_STRIDE_ADDR, line_stride as _);
+    REGS.write(REG_FRONT_BUFFER_BASE_ADDR, addr);
+}
+
+// One of REG_FORMAT_*
+pub fn set_format(format: u32) {
+    REGS.write(REG_FORMAT_ADDR, format);
+}
+
+pub fn enable() {
+    REGS.write(REG_ENABLE_ADDR, 1);
+}
+
+pub fn disable() {
+    REGS.write(REG_ENABLE_ADDR, 0);
+}
+
+pub fn current_line() -> u32 {
+    REGS.read(REG_CURRENT_LINE_ADDR)
+}
+
+pub fn in_vblank() -> bool {
+    (REGS.read(REG_STATUS_ADDR) & (1 << REG_STATUS_VBLANK_BIT)) != 0
+}
+
+// True until the front buffer given to the last set_front_buffer call is latched; until then, the previous front
+//  buffer may still be on screen
+pub fn is_flip_pending() -> bool {
+    (REGS.read(REG_STATUS_ADDR) & (1 << REG_STATUS_FLIP_PENDING_BIT)) != 0
+}
+
+pub fn wait_for_flip() {
+    while is_flip_pending() {
+        // Do nothing
+    }
+}
+
+// Wraps at 1 << REG_FRAME_COUNT_BITS
+pub fn frame_count() -> u32 {
+    REGS.read(REG_FRAME_COUNT_ADDR)
+}
+
+// Takes effect immediately; see REG_PALETTE_ADDR
+pub fn set_palette_entry(index: u8, rgb: u32) {
+    REGS.write(REG_PALETTE_ADDR, ((index as u32) << REG_PALETTE_INDEX_BIT_OFFSET) | ((rgb & 0xffffff) << REG_PALETTE_VALUE_BIT_OFFSET));
+}
+
+// Corrected RGB for component values of index; takes effect immediately
+pub fn set_gamma_lut_entry(index: u8, rgb: u32) {
+    REGS.write(REG_GAMMA_LUT_ADDR, ((index as u32) << REG_GAMMA_LUT_INDEX_BIT_OFFSET) | ((rgb & 0xffffff) << REG_GAMMA_LUT_VALUE_BIT_OFFSET));
+}
+
+// Same units as set_front_buffer, and likewise takes effect from the next frame on
+pub fn set_overlay(addr: u32, line_stride: i32) {
+    REGS.write(REG_OVERLAY_LINE_STRIDE_ADDR, line_stride as _);
+    REGS.write(REG_OVERLAY_BASE_ADDR, addr);
+}
+
+pub fn enable_overlay() {
+    REGS.write(REG_OVERLAY_ENABLE_ADDR, 1);
+}
+
+pub fn disable_overlay() {
+    REGS.write(REG_OVERLAY_ENABLE_ADDR, 0);
+}
@@ -0,0 +1,276 @@
+use crate::spi::{self, Spi};
+
+use rtl_meta::spi::*;
+
+pub const BLOCK_SIZE: usize = 512;
+
+const SPI: Spi = spi::SD_CARD;
+
+// ~25MHz, as fast as cards in SPI mode are guaranteed to go
+const FAST_CLOCK_DIVIDER: u32 = REG_CLOCK_DIVIDER_MIN;
+
+// Timeouts are counted in bytes transferred, since that's the only clock we have. At 400kHz a byte takes 20us, so
+//  these are generous, if loose, upper bounds on the times the spec allows.
+const COMMAND_RESPONSE_TIMEOUT: u32 = 8;
+const INIT_TIMEOUT: u32 = 50000;
+const READ_TIMEOUT: u32 = 100000;
+const WRITE_TIMEOUT: u32 = 500000;
+
+const CMD_GO_IDLE_STATE: u8 = 0;
+const CMD_SEND_IF_COND: u8 = 8;
+const CMD_SET_BLOCKLEN: u8 = 16;
+const CMD_READ_SINGLE_BLOCK: u8 = 17;
+const CMD_WRITE_BLOCK: u8 = 24;
+const CMD_APP_CMD: u8 = 55;
+const CMD_READ_OCR: u8 = 58;
+const ACMD_SD_SEND_OP_COND: u8 = 41;
+
+const R1_IDLE: u8 = 0x01;
+const R1_ILLEGAL_COMMAND: u8 = 0x04;
+
+// 2.7-3.6V, with the check pattern the card should echo back
+const IF_COND_ARG: u32 = 0x1aa;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+
+const DATA_START_TOKEN: u8 = 0xfe;
+const DATA_RESPONSE_MASK: u8 = 0x1f;
+const DATA_RESPONSE_ACCEPTED: u8 = 0x05;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Error {
+    // Nothing answered; usually means there's no card in the slot. The mimas_a7 top doesn't route the slot yet, so
+    //  that's always the case there.
+    NoResponse,
+    // The card doesn't support our voltage range or didn't echo the check pattern
+    UnsupportedCard,
+    // The card never left the idle state
+    InitTimeout,
+    // Holds the command index and the R1 response, which has at least one error bit set
+    Command(u8, u8),
+    DataTimeout,
+    // Holds the data error token the card sent in place of a block
+    ReadFailed(u8),
+    // Holds the data response token
+    WriteRejected(u8),
+    WriteTimeout,
+}
+
+// Only one card is supported, and only single-block transfers in SPI mode
+pub struct SdCard {
+    // SDHC/SDXC cards are addressed in blocks; older cards are addressed in bytes
+    is_high_capacity: bool,
+}
+
+impl SdCard {
+    pub fn init() -> Result<SdCard, Error> {
+        SPI.set_clock_divider(REG_CLOCK_DIVIDER_DEFAULT);
+
+        // At least 74 clocks with CS high to put the card in its native mode, ready for CMD0
+        SPI.deselect();
+        for _ in 0..10 {
+            SPI.transfer(0xff);
+        }
+
+        let ret = init_card();
+
+        SPI.deselect();
+        SPI.transfer(0xff);
+
+        let card = ret?;
+        SPI.set_clock_divider(FAST_CLOCK_DIVIDER);
+        Ok(card)
+    }
+
+    pub fn is_high_capacity(&self) -> bool {
+        self.is_high_capacity
+    }
+
+    pub fn read_block(&mut self, index: u32, data: &mut [u8; BLOCK_SIZE]) -> Result<(), Error> {
+        let addr = self.block_addr(index);
+        transaction(|| {
+            expect_ready(CMD_READ_SINGLE_BLOCK, command(CMD_READ_SINGLE_BLOCK, addr)?)?;
+
+            let mut token = 0xff;
+            for _ in 0..READ_TIMEOUT {
+                token = SPI.transfer(0xff);
+                if token != 0xff {
+                    break;
+                }
+            }
+            match token {
+                DATA_START_TOKEN => (),
+                0xff => return Err(Error::DataTimeout),
+                _ => return Err(Error::ReadFailed(token)),
+            }
+
+            for byte in data.iter_mut() {
+                *byte = SPI.transfer(0xff);
+            }
+            // CRC checking is off in SPI mode by default, so the CRC is skipped
+            SPI.transfer(0xff);
+            SPI.transfer(0xff);
+
+            Ok(())
+        })
+    }
+
+    pub fn write_block(&mut self, index: u32, data: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+        let addr = self.block_addr(index);
+        transaction(|| {
+            expect_ready(CMD_WRITE_BLOCK, command(CMD_WRITE_BLOCK, addr)?)?;
+
+            SPI.transfer(0xff);
+            SPI.transfer(DATA_START_TOKEN);
+            for &byte in data.iter() {
+                SPI.transfer(byte);
+            }
+            // Dummy CRC
+            SPI.transfer(0xff);
+            SPI.transfer(0xff);
+
+            let response = SPI.transfer(0xff) & DATA_RESPONSE_MASK;
+            if response != DATA_RESPONSE_ACCEPTED {
+                return Err(Error::WriteRejected(response));
+            }
+
+            // The card holds MISO low while it programs the block
+            for _ in 0..WRITE_TIMEOUT {
+                if SPI.transfer(0xff) == 0xff {
+                    return Ok(());
+                }
+            }
+            Err(Error::WriteTimeout)
+        })
+    }
+
+    fn block_addr(&self, index: u32) -> u32 {
+        if self.is_high_capacity {
+            index
+        } else {
+            index * BLOCK_SIZE as u32
+        }
+    }
+}
+
+fn init_card() -> Result<SdCard, Error> {
+    SPI.select();
+
+    let r1 = command(CMD_GO_IDLE_STATE, 0)?;
+    if r1 != R1_IDLE {
+        return Err(Error::Command(CMD_GO_IDLE_STATE, r1));
+    }
+
+    // v1 cards don't know CMD8
+    let r1 = command(CMD_SEND_IF_COND, IF_COND_ARG)?;
+    let is_v2 = if (r1 & R1_ILLEGAL_COMMAND) != 0 {
+        false
+    } else {
+        expect_idle(CMD_SEND_IF_COND, r1)?;
+        if read_u32() & 0xfff != IF_COND_ARG {
+            return Err(Error::UnsupportedCard);
+        }
+        true
+    };
+
+    let mut is_initialized = false;
+    for _ in 0..INIT_TIMEOUT {
+        expect_idle(CMD_APP_CMD, command(CMD_APP_CMD, 0)?)?;
+        let r1 = command(ACMD_SD_SEND_OP_COND, if is_v2 { OCR_HIGH_CAPACITY } else { 0 })?;
+        expect_idle(ACMD_SD_SEND_OP_COND, r1)?;
+        if r1 == 0 {
+            is_initialized = true;
+            break;
+        }
+    }
+    if !is_initialized {
+        return Err(Error::InitTimeout);
+    }
+
+    let is_high_capacity = if is_v2 {
+        expect_ready(CMD_READ_OCR, command(CMD_READ_OCR, 0)?)?;
+        (read_u32() & OCR_HIGH_CAPACITY) != 0
+    } else {
+        false
+    };
+
+    // Byte-addressed cards may default to some other block size
+    if !is_high_capacity {
+        expect_ready(CMD_SET_BLOCKLEN, command(CMD_SET_BLOCKLEN, BLOCK_SIZE as _)?)?;
+    }
+
+    Ok(SdCard {
+        is_high_capacity,
+    })
+}
+
+// Runs f with the card selected, making sure it's deselected afterwards even if f fails
+fn transaction<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    SPI.select();
+    let ret = f();
+    SPI.deselect();
+    // The card only releases MISO after seeing a clock with CS high
+    SPI.transfer(0xff);
+    ret
+}
+
+// Expects the card to be selected. Returns the R1 response; any additional response bytes are left for the caller.
+fn command(index: u8, arg: u32) -> Result<u8, Error> {
+    let bytes = [
+        0x40 | index,
+        (arg >> 24) as u8,
+        (arg >> 16) as u8,
+        (arg >> 8) as u8,
+        arg as u8,
+    ];
+
+    SPI.transfer(0xff);
+    for &byte in bytes.iter() {
+        SPI.transfer(byte);
+    }
+    SPI.transfer((crc7(&bytes) << 1) | 1);
+
+    for _ in 0..COMMAND_RESPONSE_TIMEOUT {
+        let r1 = SPI.transfer(0xff);
+        if (r1 & 0x80) == 0 {
+            return Ok(r1);
+        }
+    }
+
+    Err(Error::NoResponse)
+}
+
+fn expect_ready(index: u8, r1: u8) -> Result<(), Error> {
+    if r1 == 0 {
+        Ok(())
+    } else {
+        Err(Error::Command(index, r1))
+    }
+}
+
+// Like expect_ready, but also allows the idle bit
+fn expect_idle(index: u8, r1: u8) -> Result<(), Error> {
+    expect_ready(index, r1 & !R1_IDLE)
+}
+
+fn read_u32() -> u32 {
+    let mut ret = 0;
+    for _ in 0..4 {
+        ret = (ret << 8) | SPI.transfer(0xff) as u32;
+    }
+    ret
+}
+
+// x^7 + x^3 + 1, MSB first
+fn crc7(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        for i in (0..8).rev() {
+            let bit = ((byte >> i) & 1) ^ (crc >> 6);
+            crc = (crc << 1) & 0x7f;
+            if bit != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
@@ -0,0 +1,40 @@
+use crate::regs::RegBlock;
+
+use rtl_meta::spi::*;
+
+// There's one master per device, each with the same regs
+pub struct Spi {
+    regs: RegBlock,
+}
+
+pub const SD_CARD: Spi = Spi { regs: RegBlock::new(0x0a000000) };
+pub const FLASH: Spi = Spi { regs: RegBlock::new(0x0b000000) };
+
+impl Spi {
+    // SCLK will be 100MHz / (2 * (divider + 1)); clamped to what the hw can support
+    pub fn set_clock_divider(&self, divider: u32) {
+        self.regs.write(REG_CLOCK_DIVIDER_ADDR, divider.clamp(REG_CLOCK_DIVIDER_MIN, (1 << REG_CLOCK_DIVIDER_BITS) - 1));
+    }
+
+    fn is_busy(&self) -> bool {
+        (self.regs.read(REG_STATUS_ADDR) & (1 << REG_STATUS_BUSY_BIT)) != 0
+    }
+
+    pub fn select(&self) {
+        while self.is_busy() {}
+        self.regs.write(REG_CHIP_SELECT_ADDR, 1);
+    }
+
+    pub fn deselect(&self) {
+        while self.is_busy() {}
+        self.regs.write(REG_CHIP_SELECT_ADDR, 0);
+    }
+
+    // Sends a byte and returns the byte received at the same time
+    pub fn transfer(&self, data: u8) -> u8 {
+        while self.is_busy() {}
+        self.regs.write(REG_DATA_ADDR, data as _);
+        while self.is_busy() {}
+        self.regs.read(REG_DATA_ADDR) as _
+    }
+}
@@ -1,24 +1,67 @@
-use core::ptr;
+use crate::regs::RegBlock;
 
-#[repr(C)]
-struct Regs {
-    tx_status: u8, _padding0: [u8; 15],
-    tx_write: u8, _padding1: [u8; 15],
+use rtl_meta::uart::*;
+use rtl_meta::xenowing::SYSTEM_CLOCK_FREQ;
 
-    rx_status: u8, _padding2: [u8; 15],
-    rx_read: u8, _padding3: [u8; 15],
+const REGS: RegBlock = RegBlock::new(0x02000000);
+
+// Each side picks up the new divisor from its next bit, so a byte in flight would be sent or received at mixed rates;
+//  call flush first if anything has been written. The other side obviously has to switch too.
+pub fn set_baud_divisor(divisor: u32) {
+    REGS.write(REG_BAUD_DIVISOR_ADDR, divisor.clamp(REG_BAUD_DIVISOR_MIN, (1 << REG_BAUD_DIVISOR_BITS) - 1));
+}
+
+// Rounds to the nearest divisor
+pub fn set_baud_rate(baud_rate: u32) {
+    set_baud_divisor((SYSTEM_CLOCK_FREQ + baud_rate / 2) / baud_rate);
+}
+
+// RTS/CTS aren't routed to pins on the mimas_a7 build, so this has no effect there
+pub fn set_flow_control(enable: bool) {
+    REGS.write(REG_FLOW_CONTROL_ADDR, (enable as u32) << REG_FLOW_CONTROL_ENABLE_BIT);
+}
+
+// Number of bytes waiting to be sent
+pub fn tx_level() -> u32 {
+    REGS.read(REG_TX_LEVEL_ADDR)
+}
+
+// Number of bytes received but not yet read
+pub fn rx_level() -> u32 {
+    REGS.read(REG_RX_LEVEL_ADDR)
+}
+
+// Blocks until everything written so far has left the wire
+pub fn flush() {
+    while (REGS.read(REG_TX_STATUS_ADDR) & (1 << REG_TX_STATUS_IDLE_BIT)) == 0 {
+        // Do nothing
+    }
 }
 
-const REGS: *mut Regs = 0x02000000 as _;
+// Sticky REG_ERROR_*_BIT flags, set since they were last cleared
+pub fn error_flags() -> u32 {
+    REGS.read(REG_ERROR_ADDR)
+}
+
+pub fn clear_error_flags(flags: u32) {
+    REGS.write(REG_ERROR_ADDR, flags);
+}
 
 pub fn read_u8() -> u8 {
-    unsafe {
-        while (ptr::read_volatile(&(*REGS).rx_status) & 1) == 0 {
-            // Do nothing
-        }
+    while (REGS.read(REG_RX_STATUS_ADDR) & (1 << REG_RX_STATUS_READY_BIT)) == 0 {
+        // Do nothing
+    }
+
+    REGS.read(REG_RX_DATA_ADDR) as _
+}
 
-        ptr::read_volatile(&(*REGS).rx_read)
+// Returns None right away if nothing has been received
+pub fn try_read_u8() -> Option<u8> {
+    if (REGS.read(REG_RX_STATUS_ADDR) & (1 << REG_RX_STATUS_READY_BIT)) == 0 {
+        return None;
     }
+
+    Some(REGS.read(REG_RX_DATA_ADDR) as _)
 }
 
 pub fn read_u32_le() -> u32 {
@@ -45,13 +88,18 @@ pub fn read_u128_le() -> u128 {
     ret
 }
 
+// Only blocks while the TX FIFO is full
 pub fn write_u8(x: u8) {
-    unsafe {
-        while (ptr::read_volatile(&(*REGS).tx_status) & 1) == 0 {
-            // Do nothing
-        }
+    while (REGS.read(REG_TX_STATUS_ADDR) & (1 << REG_TX_STATUS_READY_BIT)) == 0 {
+        // Do nothing
+    }
+
+    REGS.write(REG_TX_DATA_ADDR, x as _);
+}
 
-        ptr::write_volatile(&mut (*REGS).tx_write, x);
+pub fn write_u16_le(x: u16) {
+    for i in 0..2 {
+        write_u8((x >> (i * 8)) as _);
     }
 }
 